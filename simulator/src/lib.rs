@@ -0,0 +1,112 @@
+//! The firmware modules that do not touch the hardware, shared as they are,
+//! and a host HTTP connection for the InfluxDB client. Their tests run with
+//! `cargo test` here, next to the end-to-end tests in `tests/`.
+
+use error::Context;
+
+#[path = "../../src/bmp280.rs"]
+pub mod bmp280;
+#[path = "../../src/broadcast.rs"]
+pub mod broadcast;
+#[path = "../../src/cbor.rs"]
+pub mod cbor;
+#[path = "../../src/coap.rs"]
+pub mod coap;
+#[path = "../../src/ct.rs"]
+pub mod ct;
+#[path = "../../src/deadband.rs"]
+pub mod deadband;
+#[path = "../../src/dht.rs"]
+pub mod dht;
+#[path = "../../src/ds18b20.rs"]
+pub mod ds18b20;
+#[path = "../../src/error.rs"]
+pub mod error;
+#[path = "../../src/exposure.rs"]
+pub mod exposure;
+#[path = "../../src/forecast.rs"]
+pub mod forecast;
+#[path = "../../src/gp2y1010.rs"]
+pub mod gp2y1010;
+#[path = "../../src/hx711.rs"]
+pub mod hx711;
+#[path = "../../src/influx.rs"]
+pub mod influx;
+#[path = "../../src/isopleth.rs"]
+pub mod isopleth;
+#[path = "../../src/knxnet.rs"]
+pub mod knxnet;
+#[path = "../../src/layout.rs"]
+pub mod layout;
+#[path = "../../src/line_proto.rs"]
+pub mod line_proto;
+#[path = "../../src/metrics.rs"]
+pub mod metrics;
+#[path = "../../src/modbus.rs"]
+pub mod modbus;
+#[path = "../../src/mqtt.rs"]
+pub mod mqtt;
+#[path = "../../src/nats.rs"]
+pub mod nats;
+#[path = "../../src/onewire.rs"]
+pub mod onewire;
+#[path = "../../src/otlp.rs"]
+pub mod otlp;
+#[path = "../../src/outbox.rs"]
+pub mod outbox;
+#[path = "../../src/point.rs"]
+pub mod point;
+#[path = "../../src/reading.rs"]
+pub mod reading;
+#[path = "../../src/ready.rs"]
+pub mod ready;
+#[path = "../../src/rollout.rs"]
+pub mod rollout;
+#[path = "../../src/sdp810.rs"]
+pub mod sdp810;
+#[path = "../../src/segments.rs"]
+pub mod segments;
+#[path = "../../src/sha256.rs"]
+pub mod sha256;
+#[path = "../../src/spl.rs"]
+pub mod spl;
+#[path = "../../src/state.rs"]
+pub mod state;
+#[path = "../../src/statsd.rs"]
+pub mod statsd;
+#[path = "../../src/thing.rs"]
+pub mod thing;
+
+/// Stands in for the ESP-IDF client of the firmware. Status codes outside
+/// 2xx are responses too, so only transport errors are errors. The host
+/// has the RAM to collect the body before sending it.
+impl influx::Connection for ureq::Agent {
+    fn post(
+        &mut self,
+        addr: &str,
+        headers: &[(&str, &str)],
+        body: &mut dyn influx::Body,
+    ) -> error::Result<u16> {
+        let mut bytes = Vec::with_capacity(body.content_length());
+        body.write_to(&mut |piece| {
+            bytes.extend_from_slice(piece);
+            Ok(())
+        })?;
+        let mut request = ureq::Agent::post(self, addr);
+        for (name, value) in headers {
+            // ureq counts the body itself.
+            if !name.eq_ignore_ascii_case("content-length") {
+                request = request.set(name, value);
+            }
+        }
+        match request.send_bytes(&bytes) {
+            Ok(response) => Ok(response.status()),
+            Err(ureq::Error::Status(status, response)) => {
+                let text = response.into_string().unwrap_or_default();
+                log::info!("Response body: {:?}", text);
+                Ok(status)
+            }
+            Err(err) => Err(err).context("do post request"),
+        }
+    }
+}
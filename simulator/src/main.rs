@@ -0,0 +1,142 @@
+//! Host build of the firmware's send pipeline. A fake DHT22 produces sine
+//! waves, which go through the same broadcast channel, point building, line
+//! protocol encoding and sender metrics as on the device, and are written
+//! to InfluxDB over real HTTP.
+
+use std::{
+    env, f32::consts::PI, io::Write, thread, time::Duration, time::Instant, time::SystemTime,
+};
+
+use esp_sensor_simulator::{
+    broadcast,
+    error::{self, Context},
+    influx, metrics, point, reading,
+};
+use reading::SensorData;
+
+const USAGE: &str = "usage: esp_sensor_simulator [write-url] [token]
+
+Without a write url the line protocol is printed instead, e.g.
+  esp_sensor_simulator 'http://localhost:8086/api/v2/write?org=home&bucket=sensors' 'Token ...'
+
+Environment:
+  SIM_INTERVAL_SECS  seconds between readings, default 1
+  SIM_PERIOD_SECS    period of the fake sine waves, default 600
+  SIM_MEASUREMENT    measurement of the readings, default `simulator`
+  RUST_LOG           log levels, default info";
+
+/// Readings and metric points between two metric points.
+const METRICS_EVERY: u32 = 10;
+
+struct Options {
+    addr: Option<String>,
+    token: String,
+    interval: Duration,
+    period: Duration,
+    measurement: String,
+}
+
+fn options() -> error::Result<Options> {
+    let mut args = env::args().skip(1);
+    let addr = args.next();
+    if matches!(addr.as_deref(), Some("-h" | "--help")) {
+        println!("{}", USAGE);
+        std::process::exit(0);
+    }
+    let secs = |name: &str, default: u64| -> error::Result<Duration> {
+        let secs = match env::var(name) {
+            Ok(value) => value.parse().with_context(|| format!("parse {}", name))?,
+            Err(_) => default,
+        };
+        Ok(Duration::from_secs(secs))
+    };
+    Ok(Options {
+        addr,
+        token: args.next().unwrap_or_default(),
+        interval: secs("SIM_INTERVAL_SECS", 1)?,
+        period: secs("SIM_PERIOD_SECS", 600)?,
+        measurement: env::var("SIM_MEASUREMENT").unwrap_or_else(|_| "simulator".into()),
+    })
+}
+
+/// Temperature around 21 °C and humidity around 50 % in opposite phase, as
+/// a heated room would show.
+fn fake_reading(elapsed: Duration, period: Duration) -> SensorData {
+    let phase = 2. * PI * elapsed.as_secs_f32() / period.as_secs_f32().max(1.);
+    SensorData {
+        temperature: 21. + 3. * phase.sin(),
+        humidity: 50. - 10. * phase.sin(),
+    }
+}
+
+fn read_sensor(readings: &broadcast::Sender<SensorData>, options: &Options) {
+    let started = Instant::now();
+    loop {
+        let value = fake_reading(started.elapsed(), options.period);
+        if value.is_correct() {
+            log::info!("read_sensor: data={}", value);
+            readings.send(value);
+        } else {
+            log::error!("read_sensor: got invalid data={}", value);
+        }
+        thread::sleep(options.interval);
+    }
+}
+
+fn data_sender(mut sub: broadcast::Receiver<SensorData>, options: &Options) -> error::Result<()> {
+    let tags = vec![("device".to_string(), "simulator".to_string())];
+    let agent = ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(30))
+        .build();
+    let mut client = influx::Client::new(|| Ok(agent.clone()))?;
+    let mut metrics = metrics::SenderMetrics::default();
+    let mut sent = 0;
+    loop {
+        let data = match sub.recv_timeout(Duration::from_secs(60)) {
+            Ok(data) => data,
+            Err(broadcast::RecvError::Closed) => return Ok(()),
+            Err(err) => {
+                log::warn!("data_sender: no reading error={:?}", err);
+                continue;
+            }
+        };
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?;
+        let mut points = vec![reading::sensor_point(
+            &options.measurement,
+            "temperature",
+            "humidity",
+            &tags,
+            data,
+            Some(now.as_nanos() as i64),
+        )];
+        sent += 1;
+        if sent % METRICS_EVERY == 0 {
+            points.push(metrics.point(&tags));
+        }
+        let body = point::encode(&points);
+
+        let Some(addr) = &options.addr else {
+            std::io::stdout().write_all(&body)?;
+            continue;
+        };
+        if let Err(err) = client.write(&mut metrics, addr, &options.token, &body) {
+            log::error!("http post failed error={:?}", err);
+            metrics.record_error(err.code());
+        }
+    }
+}
+
+fn main() -> error::Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let options = options()?;
+    if options.addr.is_none() {
+        log::info!("no write url, printing line protocol");
+    }
+
+    let readings = broadcast::Sender::new(16);
+    let sub = readings.subscribe();
+    thread::scope(|s| {
+        s.spawn(|| read_sensor(&readings, &options));
+        data_sender(sub, &options)
+    })
+}
@@ -0,0 +1,329 @@
+//! Writes through `influx::Client` and the host connection to a fake
+//! InfluxDB that records every request.
+
+use std::{
+    cell::Cell,
+    collections::VecDeque,
+    io::{BufRead, BufReader, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use esp_sensor_simulator::{influx, metrics::SenderMetrics, otlp, point, point::Point, reading};
+
+const TOKEN: &str = "Token secret";
+
+/// What the fake server does with a request.
+#[derive(Debug, Clone, Copy)]
+enum Reply {
+    Status(u16),
+    /// Closes the connection without a response, as a server dropping an
+    /// idle kept-alive connection does.
+    Hangup,
+}
+
+#[derive(Debug)]
+struct Request {
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+struct FakeInflux {
+    addr: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl FakeInflux {
+    /// Answers requests with `replies` in order, then with 204.
+    fn start(replies: &[Reply]) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = format!(
+            "http://{}/api/v2/write?org=home&bucket=sensors&precision=ns",
+            listener.local_addr().unwrap()
+        );
+        let replies = Arc::new(Mutex::new(VecDeque::from(replies.to_vec())));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let server_requests = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let (replies, requests) = (replies.clone(), server_requests.clone());
+                thread::spawn(move || serve(stream.unwrap(), &replies, &requests));
+            }
+        });
+        FakeInflux { addr, requests }
+    }
+
+    fn requests(&self) -> std::sync::MutexGuard<'_, Vec<Request>> {
+        self.requests.lock().unwrap()
+    }
+}
+
+/// Serves the requests of one kept-alive connection.
+fn serve(stream: TcpStream, replies: &Mutex<VecDeque<Reply>>, requests: &Mutex<Vec<Request>>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut stream = stream;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+            return;
+        }
+        let mut parts = line.split_whitespace();
+        let (method, path) = (parts.next().unwrap().into(), parts.next().unwrap().into());
+
+        let mut headers = Vec::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let Some((name, value)) = line.trim_end().split_once(':') else {
+                break;
+            };
+            headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+        }
+        let mut request = Request {
+            method,
+            path,
+            headers,
+            body: String::new(),
+        };
+        let length = request
+            .header("content-length")
+            .map_or(0, |v| v.parse().unwrap());
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        request.body = String::from_utf8(body).unwrap();
+        requests.lock().unwrap().push(request);
+
+        match replies
+            .lock()
+            .unwrap()
+            .pop_front()
+            .unwrap_or(Reply::Status(204))
+        {
+            Reply::Status(status) => {
+                let response = format!("HTTP/1.1 {} Fake\r\ncontent-length: 0\r\n\r\n", status);
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+            Reply::Hangup => {
+                let _ = stream.shutdown(Shutdown::Both);
+                return;
+            }
+        }
+    }
+}
+
+fn agent() -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(5))
+        .build()
+}
+
+fn reading_body() -> Vec<u8> {
+    let tags = [("device".to_string(), "sim".to_string())];
+    let data = reading::SensorData {
+        temperature: 21.5,
+        humidity: 40.25,
+    };
+    let point = reading::sensor_point("room", "t", "h", &tags, data, Some(1_000_000_000));
+    point::encode(&[point])
+}
+
+/// The `sender` point of `metrics` as line protocol.
+fn metrics_line(metrics: &SenderMetrics) -> String {
+    String::from_utf8(point::encode(&[metrics.point(&[])])).unwrap()
+}
+
+#[test]
+fn sends_token_and_line_protocol() {
+    let server = FakeInflux::start(&[]);
+    let agent = agent();
+    let mut client = influx::Client::new(|| Ok(agent.clone())).unwrap();
+    let mut metrics = SenderMetrics::default();
+
+    let body = reading_body();
+    let status = client.write(&mut metrics, &server.addr, TOKEN, &body);
+    assert_eq!(status.unwrap(), 204);
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 1);
+    let request = &requests[0];
+    assert_eq!(request.method, "POST");
+    assert_eq!(
+        request.path,
+        "/api/v2/write?org=home&bucket=sensors&precision=ns"
+    );
+    assert_eq!(request.header("authorization"), Some(TOKEN));
+    assert_eq!(request.header("content-type"), Some("text/plain"));
+    assert_eq!(request.header("accept"), Some("application/json"));
+    assert_eq!(
+        request.body,
+        "room,sensor=dht22,device=sim h=40.25,t=21.5 1000000000\n"
+    );
+}
+
+#[test]
+fn sends_otlp_json_without_token() {
+    let server = FakeInflux::start(&[]);
+    let agent = agent();
+    let mut client = influx::Client::new(|| Ok(agent.clone()))
+        .unwrap()
+        .with_content_type("application/json");
+
+    let point = Point::new("room").field("t", 21.5f32);
+    let body = otlp::encode(&[point], 1_000_000_000);
+    let status = client.write(&mut SenderMetrics::default(), &server.addr, "", &body);
+    assert_eq!(status.unwrap(), 204);
+
+    let request = &server.requests()[0];
+    assert_eq!(request.header("authorization"), None);
+    assert_eq!(request.header("content-type"), Some("application/json"));
+    assert!(request.body.contains(r#""name":"room.t""#));
+}
+
+#[test]
+fn escapes_names_tags_and_strings() {
+    let server = FakeInflux::start(&[]);
+    let agent = agent();
+    let mut client = influx::Client::new(|| Ok(agent.clone())).unwrap();
+
+    let point = Point::new("living room,1")
+        .tag("room name", "a,b=c")
+        .field("message", r#"say "hi" \o/"#);
+    let body = point::encode(&[point]);
+    client
+        .write(&mut SenderMetrics::default(), &server.addr, TOKEN, &body)
+        .unwrap();
+
+    assert_eq!(
+        server.requests()[0].body,
+        r#"living\ room\,1,room\ name=a\,b\=c message="say \"hi\" \\o/""#.to_string() + "\n"
+    );
+}
+
+#[test]
+fn batches_points_in_one_request() {
+    let server = FakeInflux::start(&[]);
+    let agent = agent();
+    let mut client = influx::Client::new(|| Ok(agent.clone())).unwrap();
+    let mut metrics = SenderMetrics::default();
+
+    client
+        .write(&mut metrics, &server.addr, TOKEN, &reading_body())
+        .unwrap();
+    let mut body = reading_body();
+    body.extend(point::encode(&[metrics.point(&[])]));
+    client
+        .write(&mut metrics, &server.addr, TOKEN, &body)
+        .unwrap();
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 2);
+    let lines: Vec<_> = requests[1].body.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("room,"));
+    assert!(lines[1].starts_with("sender requests=1u,failures=0u,status_2xx=1u,"));
+    assert_eq!(
+        requests[1].header("content-length"),
+        Some(&*body.len().to_string())
+    );
+}
+
+#[test]
+fn streams_points_line_by_line() {
+    let server = FakeInflux::start(&[Reply::Hangup]);
+    let agent = agent();
+    let mut client = influx::Client::new(|| Ok(agent.clone())).unwrap();
+    let mut metrics = SenderMetrics::default();
+
+    let points = [
+        Point::new("room").tag("device", "sim").field("t", 21.5f32),
+        // Skipped, the protocol needs a field.
+        Point::new("empty"),
+        Point::new("sender").field("requests", 1u32).timestamp(5),
+    ];
+    let mut line = Vec::new();
+    let body = influx::LineProtocol::new(&points, &mut line);
+    let status = client.write(&mut metrics, &server.addr, TOKEN, body);
+    assert_eq!(status.unwrap(), 204);
+
+    let expected = String::from_utf8(point::encode(&points)).unwrap();
+    let requests = server.requests();
+    // The retry encodes the points again.
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[1].body, expected);
+    assert_eq!(
+        requests[1].header("content-length"),
+        Some(&*point::encoded_len(&points).to_string())
+    );
+}
+
+#[test]
+fn retries_once_on_fresh_connection() {
+    let server = FakeInflux::start(&[Reply::Hangup]);
+    let agent = agent();
+    let connects = Cell::new(0);
+    let mut client = influx::Client::new(|| {
+        connects.set(connects.get() + 1);
+        Ok(agent.clone())
+    })
+    .unwrap();
+    let mut metrics = SenderMetrics::default();
+
+    let body = reading_body();
+    let status = client.write(&mut metrics, &server.addr, TOKEN, &body);
+    assert_eq!(status.unwrap(), 204);
+    assert_eq!(connects.get(), 2);
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 2);
+    // The retry repeats the same body.
+    assert_eq!(requests[0].body, requests[1].body);
+    assert!(metrics_line(&metrics).contains("requests=2u,failures=1u,status_2xx=1u,"));
+}
+
+#[test]
+fn gives_up_after_retry() {
+    let server = FakeInflux::start(&[Reply::Hangup, Reply::Hangup]);
+    let agent = agent();
+    let mut client = influx::Client::new(|| Ok(agent.clone())).unwrap();
+    let mut metrics = SenderMetrics::default();
+
+    let result = client.write(&mut metrics, &server.addr, TOKEN, &reading_body());
+    assert!(result.is_err());
+    assert_eq!(server.requests().len(), 2);
+    assert!(metrics_line(&metrics).contains("requests=2u,failures=2u,"));
+}
+
+#[test]
+fn does_not_retry_error_status() {
+    let server = FakeInflux::start(&[Reply::Status(401)]);
+    let agent = agent();
+    let mut client = influx::Client::new(|| Ok(agent.clone())).unwrap();
+    let mut metrics = SenderMetrics::default();
+
+    let status = client.write(&mut metrics, &server.addr, TOKEN, &reading_body());
+    assert_eq!(status.unwrap(), 401);
+    assert_eq!(server.requests().len(), 1);
+    assert!(metrics_line(&metrics).contains("status_4xx=1u,"));
+}
+
+#[test]
+fn builds_headers_up_to_their_capacity() {
+    let token = influx::header(format_args!("Token {}", "secret")).unwrap();
+    assert_eq!(token.as_str(), TOKEN);
+
+    let long = "x".repeat(influx::MAX_HEADER_LEN);
+    assert!(influx::header(format_args!("Token {}", long)).is_err());
+}
@@ -0,0 +1,180 @@
+//! Checks `line_proto` against the examples of the line protocol
+//! specification, a golden file and the `influxdb-line-protocol` builder
+//! behind `point::encode`, so the crate can be dropped without the bodies
+//! changing.
+//!
+//! Run with `UPDATE_GOLDEN=1` to rewrite `golden/points.lp` after an
+//! intended change.
+
+use std::{env, fs, path::Path};
+
+use esp_sensor_simulator::{line_proto, point, point::Point};
+
+/// Lines from the specification and the points spelling them. Floats are
+/// written as Rust displays them, `100` rather than `100.0`.
+fn spec_examples() -> Vec<(&'static str, Point)> {
+    vec![
+        (
+            r#"myMeasurement,tag1=value1,tag2=value2 fieldKey="fieldValue" 1556813561098000000"#,
+            Point::new("myMeasurement")
+                .tag("tag1", "value1")
+                .tag("tag2", "value2")
+                .field("fieldKey", "fieldValue")
+                .timestamp(1556813561098000000),
+        ),
+        (
+            r#"my\ Measurement fieldKey="string value""#,
+            Point::new("my Measurement").field("fieldKey", "string value"),
+        ),
+        (
+            r#"myMeasurement fieldKey="\"string\" within a string""#,
+            Point::new("myMeasurement").field("fieldKey", r#""string" within a string"#),
+        ),
+        (
+            r#"myMeasurement,tag\ Key1=tag\ Value1,tag\ Key2=tag\ Value2 fieldKey=100"#,
+            Point::new("myMeasurement")
+                .tag("tag Key1", "tag Value1")
+                .tag("tag Key2", "tag Value2")
+                .field("fieldKey", 100.),
+        ),
+        (
+            r#"myMeasurement,tagKey=🍭 fieldKey="Launch 🚀" 1556813561098000000"#,
+            Point::new("myMeasurement")
+                .tag("tagKey", "🍭")
+                .field("fieldKey", "Launch 🚀")
+                .timestamp(1556813561098000000),
+        ),
+        (
+            r#"my\,Measurement,tag\,key=tag\,value field\,key=1i"#,
+            Point::new("my,Measurement")
+                .tag("tag,key", "tag,value")
+                .field("field,key", 1i64),
+        ),
+        (
+            r#"myMeasurement,tag\=key=tag\=value field\=key="a=b""#,
+            Point::new("myMeasurement")
+                .tag("tag=key", "tag=value")
+                .field("field=key", "a=b"),
+        ),
+        (
+            r#"myMeasurement fieldKey="C:\\Program Files""#,
+            Point::new("myMeasurement").field("fieldKey", r"C:\Program Files"),
+        ),
+        (
+            "my=Measurement fieldKey=true",
+            Point::new("my=Measurement").field("fieldKey", true),
+        ),
+    ]
+}
+
+/// Every field type and timestamp shape, plus the spec examples, as the
+/// firmware batches them into one body.
+fn batch() -> Vec<Point> {
+    let mut points: Vec<Point> = spec_examples().into_iter().map(|(_, p)| p).collect();
+    points.extend([
+        Point::new("dht22")
+            .tag("sensor", "dht22")
+            .tag("location", "living room")
+            .field("temperature", 21.5f32)
+            .field("humidity", 40.1f32)
+            .timestamp(1700000000000000000),
+        Point::new("types")
+            .field("float", -2.25)
+            .field("whole", 3.)
+            .field("tiny", 1e-7)
+            .field("huge", 1e21)
+            .field("int", -7i64)
+            .field("int_max", i64::MAX)
+            .field("uint", 7u64)
+            .field("uint_max", u64::MAX)
+            .field("yes", true)
+            .field("no", false)
+            .field("empty", ""),
+        Point::new("before_epoch")
+            .field("value", 1i64)
+            .timestamp(-1),
+        Point::new("epoch").field("value", 1i64).timestamp(0),
+        // Skipped, the protocol requires a field.
+        Point::new("no_fields").tag("sensor", "dht22"),
+        Point::new("last").field("value", 0u64),
+    ]);
+    points
+}
+
+fn text(body: &[u8]) -> &str {
+    std::str::from_utf8(body).unwrap()
+}
+
+#[test]
+fn matches_spec_examples() {
+    for (line, point) in spec_examples() {
+        let body = line_proto::encode(&[point]);
+        assert_eq!(text(&body), format!("{}\n", line));
+    }
+}
+
+#[test]
+fn matches_golden_file() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden/points.lp");
+    let body = line_proto::encode(&batch());
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&path, &body).unwrap();
+    }
+    assert_eq!(text(&body), fs::read_to_string(&path).unwrap());
+}
+
+#[test]
+fn matches_influxdb_line_protocol() {
+    let points = batch();
+    assert_eq!(
+        text(&line_proto::encode(&points)),
+        text(&point::encode(&points))
+    );
+    for one in points.chunks(1) {
+        assert_eq!(text(&line_proto::encode(one)), text(&point::encode(one)));
+    }
+}
+
+#[test]
+fn skips_points_without_fields() {
+    let points = [
+        Point::new("empty"),
+        Point::new("full").field("value", 1i64),
+        Point::new("empty").tag("sensor", "dht22").timestamp(1),
+    ];
+    assert_eq!(text(&line_proto::encode(&points)), "full value=1i\n");
+    assert!(line_proto::encode(&points[..1]).is_empty());
+}
+
+#[test]
+fn write_point_appends() {
+    let mut body = b"first value=1i\n".to_vec();
+    line_proto::write_point(&mut body, &Point::new("second").field("value", 2i64));
+    assert_eq!(text(&body), "first value=1i\nsecond value=2i\n");
+}
+
+#[test]
+fn estimates_the_exact_length() {
+    let points = batch();
+    assert_eq!(
+        point::encoded_len(&points),
+        line_proto::encode(&points).len()
+    );
+    for (_, point) in spec_examples() {
+        let point = [point];
+        assert_eq!(point::encoded_len(&point), line_proto::encode(&point).len());
+    }
+}
+
+#[test]
+fn encode_into_reuses_the_buffer() {
+    let points = batch();
+    let mut body = b"stale".to_vec();
+    point::encode_into(&points, &mut body);
+    assert_eq!(text(&body), text(&point::encode(&points)));
+
+    let capacity = body.capacity();
+    point::encode_into(&points[..1], &mut body);
+    assert_eq!(text(&body), text(&point::encode(&points[..1])));
+    assert_eq!(body.capacity(), capacity);
+}
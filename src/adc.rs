@@ -0,0 +1,166 @@
+use std::sync::Mutex;
+
+use crate::error::{self, Context};
+/// Oneshot driver of each ADC unit in use, as an address. A unit has a
+/// single driver, shared by its channels.
+static UNITS: Mutex<Vec<(esp_idf_sys::adc_unit_t, usize)>> = Mutex::new(Vec::new());
+
+const ATTENUATION: esp_idf_sys::adc_atten_t = esp_idf_sys::adc_atten_t_ADC_ATTEN_DB_11;
+
+/// Nominal mV at a raw reading of 4095 with `ATTENUATION`, for chips
+/// without calibration in eFuse.
+#[cfg(any(esp32, esp32s2, esp32s3))]
+const NOMINAL_MV: i32 = 3100;
+#[cfg(esp32c3)]
+const NOMINAL_MV: i32 = 2500;
+#[cfg(not(any(esp32, esp32s2, esp32s3, esp32c3)))]
+const NOMINAL_MV: i32 = 3300;
+
+/// ADC input read one sample at a time, at 11 dB attenuation so that it
+/// covers 0 V to about 2.5 V on the ESP32-C3 and 3.1 V on the ESP32. The
+/// range and linearity differ from chip to chip, millivolts come from the
+/// calibration in eFuse, or from the nominal range on chips without it.
+pub struct Channel {
+    unit: esp_idf_sys::adc_oneshot_unit_handle_t,
+    channel: esp_idf_sys::adc_channel_t,
+    /// `None` when the chip has no calibration, the nominal range is used.
+    calibration: Option<esp_idf_sys::adc_cali_handle_t>,
+}
+
+// SAFETY: the oneshot driver is thread safe.
+unsafe impl Send for Channel {}
+
+impl Channel {
+    pub fn new(pin: i32) -> error::Result<Channel> {
+        use esp_idf_sys::*;
+
+        let mut unit_id = 0;
+        let mut channel = 0;
+        unsafe {
+            esp!(adc_oneshot_io_to_channel(pin, &mut unit_id, &mut channel))
+                .with_context(|| format!("gpio{} is not an adc pin", pin))?;
+        }
+
+        let mut units = UNITS.lock().unwrap();
+        let unit = match units.iter().find(|(id, _)| *id == unit_id) {
+            Some(&(_, unit)) => unit as adc_oneshot_unit_handle_t,
+            None => {
+                let mut unit = std::ptr::null_mut();
+                unsafe {
+                    esp!(adc_oneshot_new_unit(
+                        &adc_oneshot_unit_init_cfg_t {
+                            unit_id,
+                            ..Default::default()
+                        },
+                        &mut unit
+                    ))
+                    .context("create adc unit")?;
+                }
+                units.push((unit_id, unit as usize));
+                unit
+            }
+        };
+        unsafe {
+            esp!(adc_oneshot_config_channel(
+                unit,
+                channel,
+                &adc_oneshot_chan_cfg_t {
+                    atten: ATTENUATION,
+                    bitwidth: adc_bitwidth_t_ADC_BITWIDTH_DEFAULT,
+                }
+            ))?;
+        }
+        let calibration = match calibrate(unit_id) {
+            Ok(calibration) => Some(calibration),
+            Err(err) => {
+                log::warn!(
+                    "adc: gpio{} without calibration, readings use the nominal range error={:?}",
+                    pin,
+                    err
+                );
+                None
+            }
+        };
+        Ok(Channel {
+            unit,
+            channel,
+            calibration,
+        })
+    }
+
+    /// Raw reading, 0 to 4095.
+    pub fn read(&self) -> error::Result<i32> {
+        let mut raw = 0;
+        unsafe {
+            esp_idf_sys::esp!(esp_idf_sys::adc_oneshot_read(
+                self.unit,
+                self.channel,
+                &mut raw
+            ))?
+        };
+        Ok(raw)
+    }
+
+    /// Calibrated reading in mV at the pin.
+    pub fn read_mv(&self) -> error::Result<i32> {
+        self.mv(self.read()?)
+    }
+
+    /// Converts a raw reading, or an average of them, to mV at the pin.
+    pub fn mv(&self, raw: i32) -> error::Result<i32> {
+        let Some(calibration) = self.calibration else {
+            return Ok(raw * NOMINAL_MV / 4095);
+        };
+        let mut mv = 0;
+        unsafe {
+            esp_idf_sys::esp!(esp_idf_sys::adc_cali_raw_to_voltage(
+                calibration,
+                raw,
+                &mut mv
+            ))?
+        };
+        Ok(mv)
+    }
+}
+
+/// The curve fitting scheme of the newer chips, from the eFuse values.
+#[cfg(not(esp32))]
+fn calibrate(unit_id: esp_idf_sys::adc_unit_t) -> error::Result<esp_idf_sys::adc_cali_handle_t> {
+    use esp_idf_sys::*;
+
+    let mut calibration = std::ptr::null_mut();
+    unsafe {
+        esp!(adc_cali_create_scheme_curve_fitting(
+            &adc_cali_curve_fitting_config_t {
+                unit_id,
+                atten: ATTENUATION,
+                bitwidth: adc_bitwidth_t_ADC_BITWIDTH_DEFAULT,
+                ..Default::default()
+            },
+            &mut calibration
+        ))
+        .context("create adc calibration")?;
+    }
+    Ok(calibration)
+}
+
+/// The ESP32 only has the line fitting scheme.
+#[cfg(esp32)]
+fn calibrate(unit_id: esp_idf_sys::adc_unit_t) -> error::Result<esp_idf_sys::adc_cali_handle_t> {
+    use esp_idf_sys::*;
+
+    let mut calibration = std::ptr::null_mut();
+    unsafe {
+        esp!(adc_cali_create_scheme_line_fitting(
+            &adc_cali_line_fitting_config_t {
+                unit_id,
+                atten: ATTENUATION,
+                bitwidth: adc_bitwidth_t_ADC_BITWIDTH_DEFAULT,
+                ..Default::default()
+            },
+            &mut calibration
+        ))
+        .context("create adc calibration")?;
+    }
+    Ok(calibration)
+}
@@ -0,0 +1,468 @@
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use esp_idf_hal::gpio::{self, Output, PinDriver};
+
+use crate::{
+    error::{self, bail, Context},
+    health,
+    point::Point,
+    settings::Store,
+    state::State,
+    trace,
+    watchdog::Watchdog,
+    SensorData,
+};
+
+/// Events not yet picked up by the sender are dropped beyond this.
+const MAX_PENDING_EVENTS: usize = 32;
+
+/// Reading field a rule is evaluated on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Temperature,
+    Humidity,
+    /// Water temperature of the aquarium.
+    Water,
+    Ph,
+    /// Total dissolved solids in ppm.
+    Tds,
+}
+
+impl Field {
+    pub fn name(self) -> &'static str {
+        match self {
+            Field::Temperature => "temperature",
+            Field::Humidity => "humidity",
+            Field::Water => "water",
+            Field::Ph => "ph",
+            Field::Tds => "tds",
+        }
+    }
+}
+
+/// Readings rules are evaluated on, a rule on a field without a value is
+/// skipped.
+pub trait Values {
+    fn get(&self, field: Field) -> Option<f32>;
+}
+
+impl Values for SensorData {
+    fn get(&self, field: Field) -> Option<f32> {
+        match field {
+            Field::Temperature => Some(self.temperature),
+            Field::Humidity => Some(self.humidity),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Field {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "temperature" => Ok(Field::Temperature),
+            "humidity" => Ok(Field::Humidity),
+            "water" => Ok(Field::Water),
+            "ph" => Ok(Field::Ph),
+            "tds" => Ok(Field::Tds),
+            _ => bail!("unknown field {:?}", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Above,
+    Below,
+}
+
+/// Threshold rule, written as `name=field>threshold~hysteresis@seconds`.
+///
+/// `<` is accepted instead of `>`. Hysteresis and minimum duration are
+/// optional and default to zero. For example `freezer=temperature>-10~1@600`
+/// raises `freezer` once the temperature stayed above -10 for ten minutes
+/// and clears it when it drops below -11.
+///
+/// A trailing `!` marks the alert as urgent, it then ignores quiet hours.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub name: String,
+    pub urgent: bool,
+    field: Field,
+    op: Op,
+    threshold: f32,
+    hysteresis: f32,
+    min_duration: Duration,
+}
+
+impl Rule {
+    #[cfg_attr(not(feature = "encoder"), allow(dead_code))]
+    pub fn threshold(&self) -> f32 {
+        self.threshold
+    }
+
+    #[cfg_attr(not(feature = "encoder"), allow(dead_code))]
+    pub fn with_threshold(self, threshold: f32) -> Rule {
+        Rule { threshold, ..self }
+    }
+
+    fn triggered(&self, value: f32) -> bool {
+        match self.op {
+            Op::Above => value > self.threshold,
+            Op::Below => value < self.threshold,
+        }
+    }
+
+    fn cleared(&self, value: f32) -> bool {
+        match self.op {
+            Op::Above => value < self.threshold - self.hysteresis,
+            Op::Below => value > self.threshold + self.hysteresis,
+        }
+    }
+}
+
+impl FromStr for Rule {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((name, condition)) = s.split_once('=') else {
+            bail!("alert rule {:?} has no name", s);
+        };
+        let name = name.trim();
+        let (condition, urgent) = match condition.trim().strip_suffix('!') {
+            Some(condition) => (condition, true),
+            None => (condition, false),
+        };
+        if name.is_empty() {
+            bail!("alert rule {:?} has an empty name", s);
+        }
+
+        let Some(at) = condition.find(['<', '>']) else {
+            bail!("alert rule {:?} has no < or > comparison", s);
+        };
+        let field = condition[..at].trim().parse()?;
+        let op = if condition[at..].starts_with('>') {
+            Op::Above
+        } else {
+            Op::Below
+        };
+
+        let rest = &condition[at + 1..];
+        let (rest, secs) = match rest.split_once('@') {
+            Some((rest, secs)) => (rest, secs.trim().parse().context("parse duration")?),
+            None => (rest, 0),
+        };
+        let (threshold, hysteresis) = match rest.split_once('~') {
+            Some((threshold, hysteresis)) => (
+                threshold,
+                hysteresis.trim().parse().context("parse hysteresis")?,
+            ),
+            None => (rest, 0.),
+        };
+        let threshold = threshold.trim().parse().context("parse threshold")?;
+        if hysteresis < 0. {
+            bail!("alert rule {:?} has a negative hysteresis", s);
+        }
+
+        Ok(Rule {
+            name: name.into(),
+            urgent,
+            field,
+            op,
+            threshold,
+            hysteresis,
+            min_duration: Duration::from_secs(secs),
+        })
+    }
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let op = match self.op {
+            Op::Above => '>',
+            Op::Below => '<',
+        };
+        write!(
+            f,
+            "{}={}{}{}",
+            self.name,
+            self.field.name(),
+            op,
+            self.threshold
+        )?;
+        if self.hysteresis > 0. {
+            write!(f, "~{}", self.hysteresis)?;
+        }
+        if !self.min_duration.is_zero() {
+            write!(f, "@{}", self.min_duration.as_secs())?;
+        }
+        if self.urgent {
+            write!(f, "!")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses comma separated rules, see [`Rule`].
+pub fn parse_rules(spec: &str) -> error::Result<Vec<Rule>> {
+    let mut rules: Vec<Rule> = Vec::new();
+    for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let rule: Rule = entry.parse()?;
+        if rules.iter().any(|r| r.name == rule.name) {
+            bail!("alert {:?} is defined more than once", rule.name);
+        }
+        rules.push(rule);
+    }
+    Ok(rules)
+}
+
+/// An alert was raised or cleared.
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub name: String,
+    pub urgent: bool,
+    pub raised: bool,
+    pub value: f32,
+    /// Already sent as a push message, see [`Alerts::set_safety`].
+    pub notified: bool,
+}
+
+impl Event {
+    pub fn point(&self, tags: &[(String, String)]) -> Point {
+        Point::new("alert")
+            .tag("alert", &self.name)
+            .tags(tags)
+            .field("active", self.raised)
+            .field("value", self.value)
+    }
+}
+
+impl Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = if self.raised { "raised" } else { "cleared" };
+        write!(f, "{} {} at {:.1}", self.name, state, self.value)
+    }
+}
+
+#[derive(Default)]
+struct RuleState {
+    active: bool,
+    /// When the condition started to hold, while waiting for the minimum duration.
+    since: Option<Instant>,
+}
+
+/// Evaluates rules against readings.
+pub struct Engine {
+    rules: Vec<(Rule, RuleState)>,
+}
+
+impl Engine {
+    pub fn new(rules: Vec<Rule>) -> Self {
+        Self {
+            rules: rules
+                .into_iter()
+                .map(|r| (r, RuleState::default()))
+                .collect(),
+        }
+    }
+
+    pub fn update(&mut self, values: &impl Values) -> Vec<Event> {
+        let mut events = Vec::new();
+        for (rule, state) in &mut self.rules {
+            let Some(value) = values.get(rule.field) else {
+                continue;
+            };
+            let raised = if state.active {
+                if !rule.cleared(value) {
+                    continue;
+                }
+                false
+            } else if rule.triggered(value) {
+                let since = *state.since.get_or_insert_with(trace::now);
+                if trace::now().duration_since(since) < rule.min_duration {
+                    continue;
+                }
+                true
+            } else {
+                state.since = None;
+                continue;
+            };
+
+            state.active = raised;
+            state.since = None;
+            events.push(Event {
+                name: rule.name.clone(),
+                urgent: rule.urgent,
+                raised,
+                value,
+                notified: false,
+            });
+        }
+        events
+    }
+
+    /// Active alerts and whether they are urgent.
+    pub fn active(&self) -> Vec<(String, bool)> {
+        self.rules
+            .iter()
+            .filter(|(_, state)| state.active)
+            .map(|(rule, _)| (rule.name.clone(), rule.urgent))
+            .collect()
+    }
+}
+
+/// Alert state shared with the sender, display and HTTP server.
+#[derive(Default)]
+pub struct Alerts {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// Active alerts of each rule engine, whether they are urgent.
+    active: Vec<(&'static str, String, bool)>,
+    /// Raised with [`Alerts::set_external`], always urgent.
+    external: Vec<String>,
+    events: VecDeque<Event>,
+}
+
+impl Inner {
+    fn push_event(&mut self, event: Event) {
+        if self.events.len() == MAX_PENDING_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+}
+
+impl Alerts {
+    pub fn active(&self) -> Vec<String> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .active
+            .iter()
+            .map(|(_, name, _)| name)
+            .chain(&inner.external)
+            .cloned()
+            .collect()
+    }
+
+    /// Active alerts that may use audible and push outputs, only urgent
+    /// ones during quiet hours.
+    pub fn audible(&self, quiet: bool) -> Vec<String> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .active
+            .iter()
+            .filter(|(_, _, urgent)| *urgent || !quiet)
+            .map(|(_, name, _)| name)
+            .chain(&inner.external)
+            .cloned()
+            .collect()
+    }
+
+    /// Raises or clears an urgent alert that is not driven by a rule, e.g.
+    /// from the leak probe. Returns `true` if its state changed.
+    #[cfg_attr(not(any(feature = "leak", feature = "frost")), allow(dead_code))]
+    pub fn set_external(&self, name: &str, raised: bool, value: f32) -> bool {
+        self.set(name, raised, value, false)
+    }
+
+    /// Like [`Alerts::set_external`], for safety alerts whose task sends
+    /// its own push messages so they do not wait for the sender. The
+    /// sender still records their events.
+    #[cfg_attr(not(feature = "co"), allow(dead_code))]
+    pub fn set_safety(&self, name: &str, raised: bool, value: f32) -> bool {
+        self.set(name, raised, value, true)
+    }
+
+    fn set(&self, name: &str, raised: bool, value: f32, notified: bool) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.external.iter().any(|n| n == name) == raised {
+            return false;
+        }
+        if raised {
+            inner.external.push(name.into());
+        } else {
+            inner.external.retain(|n| n != name);
+        }
+        inner.push_event(Event {
+            name: name.into(),
+            urgent: true,
+            raised,
+            value,
+            notified,
+        });
+        true
+    }
+
+    /// Takes the events that were not published yet.
+    pub fn take_events(&self) -> Vec<Event> {
+        self.inner.lock().unwrap().events.drain(..).collect()
+    }
+
+    /// Replaces the active alerts of the rule engine `source` and queues
+    /// its events.
+    pub fn publish(&self, source: &'static str, active: Vec<(String, bool)>, events: Vec<Event>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.active.retain(|(s, _, _)| *s != source);
+        inner.active.extend(
+            active
+                .into_iter()
+                .map(|(name, urgent)| (source, name, urgent)),
+        );
+        for event in events {
+            inner.push_event(event);
+        }
+    }
+}
+
+/// Evaluates the `alert_rules` setting on each new reading and lights the
+/// status LED while any alert is active.
+pub fn run<P: gpio::OutputPin>(
+    state: &State,
+    store: &Store,
+    alerts: &Alerts,
+    mut led: PinDriver<'_, P, Output>,
+) {
+    let watchdog = Watchdog::subscribe("alert");
+    let health = health::register("alert");
+    let mut spec = String::new();
+    let mut engine = Engine::new(Vec::new());
+    let mut seen = None;
+
+    loop {
+        let snapshot = watchdog.next(state, seen);
+        seen = Some(snapshot.sequence);
+        health.tick();
+        let settings = store.get();
+        if settings.alert_rules != spec {
+            spec = settings.alert_rules;
+            // The store only accepts valid rules.
+            engine = Engine::new(parse_rules(&spec).unwrap_or_default());
+            log::info!("alert: loaded rules {:?}", spec);
+        }
+
+        let events = engine.update(&snapshot.data);
+        for event in &events {
+            log::warn!("alert: {}", event);
+        }
+
+        alerts.publish("alert_rules", engine.active(), events);
+        let result = if alerts.active().is_empty() {
+            led.set_low()
+        } else {
+            led.set_high()
+        };
+        if let Err(err) = result {
+            log::error!("alert: setting status led error={:?}", err);
+        }
+    }
+}
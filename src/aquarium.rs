@@ -0,0 +1,296 @@
+use std::sync::Mutex;
+#[cfg(feature = "aquarium")]
+use std::time::Duration;
+
+#[cfg(feature = "aquarium")]
+use embedded_hal::{
+    blocking::delay::DelayUs,
+    digital::v2::{InputPin, OutputPin},
+};
+
+#[cfg(feature = "aquarium")]
+use crate::{
+    adc,
+    alert::{parse_rules, Alerts, Engine},
+    broadcast, ds18b20,
+    error::Context,
+    health,
+    onewire::{self, OneWire},
+    settings::{Key, Store},
+    watchdog::Watchdog,
+    SensorData,
+};
+use crate::{
+    alert::{Field, Values},
+    error::{self, bail},
+    point::Point,
+    settings::Settings,
+};
+
+#[cfg(feature = "aquarium")]
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
+/// ADC samples averaged per reading of the pH and TDS probes.
+#[cfg(feature = "aquarium")]
+const ADC_SAMPLES: i32 = 16;
+/// Temperature the TDS probe formula is for.
+const TDS_REFERENCE: f32 = 25.;
+
+/// Water readings the `aquarium_alerts` rules are evaluated on.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Water {
+    pub temperature: Option<f32>,
+    pub ph: Option<f32>,
+    /// Total dissolved solids in ppm.
+    pub tds: Option<f32>,
+}
+
+impl Values for Water {
+    fn get(&self, field: Field) -> Option<f32> {
+        match field {
+            Field::Water => self.temperature,
+            Field::Ph => self.ph,
+            Field::Tds => self.tds,
+            _ => None,
+        }
+    }
+}
+
+/// Latest readings of the aquarium probes, shared with the sender and the
+/// HTTP server.
+#[derive(Default)]
+pub struct Aquarium {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    water: Water,
+    ph_raw: Option<i32>,
+    tds_raw: Option<i32>,
+    /// Whether the readings changed since the previous point.
+    fresh: bool,
+}
+
+impl Aquarium {
+    /// An `aquarium` point with the `water_temperature`, and the `ph` and
+    /// `tds` in ppm with the `ec` in µS/cm, each once its probe has been
+    /// read. `None` without new readings.
+    pub fn point(&self, tags: &[(String, String)]) -> Option<Point> {
+        let mut state = self.state.lock().unwrap();
+        if !state.fresh {
+            return None;
+        }
+        state.fresh = false;
+
+        let mut point = Point::new("aquarium").tags(tags);
+        if let Some(temperature) = state.water.temperature {
+            point = point.field("water_temperature", temperature);
+        }
+        if let Some(raw) = state.ph_raw {
+            point = point.field("ph_raw", i64::from(raw));
+        }
+        if let Some(ph) = state.water.ph {
+            point = point.field("ph", ph);
+        }
+        if let Some(raw) = state.tds_raw {
+            point = point.field("tds_raw", i64::from(raw));
+        }
+        if let Some(tds) = state.water.tds {
+            // The probe's formula assumes EC in µS/cm is twice the ppm.
+            point = point.field("tds", tds).field("ec", tds * 2.);
+        }
+        (!point.fields.is_empty()).then_some(point)
+    }
+
+    /// Adds the current pH probe reading as the calibration point of a
+    /// buffer solution, replacing the older of two points.
+    #[cfg(feature = "aquarium")]
+    pub fn calibrate_ph(&self, store: &Store, buffer: f32) -> error::Result<String> {
+        if !(0.0..=14.).contains(&buffer) {
+            bail!("buffer pH {} is out of range", buffer);
+        }
+        let raw = self.state.lock().unwrap().ph_raw;
+        let raw = raw.context("the pH probe has not been read yet")?;
+        let mut points = parse_calibration(&store.get().ph_calibration)?;
+        points.retain(|&(ph, _)| ph != buffer);
+        if points.len() == 2 {
+            points.remove(0);
+        }
+        points.push((buffer, raw));
+        let spec = format_calibration(&points);
+        store.set(Key::PhCalibration, &spec)?;
+        log::info!("aquarium: calibrated ph={} raw={}", buffer, raw);
+        Ok(spec)
+    }
+}
+
+/// Parses the pH calibration, up to two `ph:raw` points of buffer
+/// solutions, e.g. `7:1860,4:2320`.
+pub fn parse_calibration(spec: &str) -> error::Result<Vec<(f32, i32)>> {
+    let mut points: Vec<(f32, i32)> = Vec::new();
+    for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let Some((ph, raw)) = entry.split_once(':') else {
+            bail!("ph calibration {:?} must be written as ph:raw", entry);
+        };
+        let (Ok(ph), Ok(raw)) = (ph.trim().parse::<f32>(), raw.trim().parse::<i32>()) else {
+            bail!("ph calibration {:?} must be written as ph:raw", entry);
+        };
+        if points.iter().any(|&(p, r)| p == ph || r == raw) {
+            bail!("ph calibration {:?} repeats a pH or reading", spec);
+        }
+        points.push((ph, raw));
+    }
+    if points.len() > 2 {
+        bail!("ph calibration {:?} has more than two points", spec);
+    }
+    Ok(points)
+}
+
+#[cfg_attr(not(feature = "aquarium"), allow(dead_code))]
+fn format_calibration(points: &[(f32, i32)]) -> String {
+    let points: Vec<_> = points
+        .iter()
+        .map(|(ph, raw)| format!("{}:{}", ph, raw))
+        .collect();
+    points.join(",")
+}
+
+/// pH of a raw reading, on the line through both calibration points.
+#[cfg_attr(not(feature = "aquarium"), allow(dead_code))]
+fn ph(settings: &Settings, raw: i32) -> Option<f32> {
+    // The store only accepts valid calibrations.
+    let points = parse_calibration(&settings.ph_calibration).ok()?;
+    let &[(ph1, raw1), (ph2, raw2)] = points.as_slice() else {
+        return None;
+    };
+    let slope = (ph2 - ph1) / (raw2 - raw1) as f32;
+    Some(ph1 + (raw - raw1) as f32 * slope)
+}
+
+/// TDS in ppm of the probe output in mV with the cubic of the DFRobot
+/// Gravity probe, compensated to 25 °C and scaled by `tds_factor`.
+#[cfg_attr(not(feature = "aquarium"), allow(dead_code))]
+fn tds(settings: &Settings, mv: i32, temperature: Option<f32>) -> f32 {
+    let volts = mv as f32 / 1000.;
+    let coefficient = 1. + 0.02 * (temperature.unwrap_or(TDS_REFERENCE) - TDS_REFERENCE);
+    let v = volts / coefficient;
+    let ppm = (133.42 * v * v * v - 255.86 * v * v + 857.39 * v) * 0.5;
+    ppm * settings.tds_factor as f32 / 1000.
+}
+
+#[cfg(feature = "aquarium")]
+fn average(channel: &adc::Channel) -> error::Result<i32> {
+    let mut sum = 0;
+    for _ in 0..ADC_SAMPLES {
+        sum += channel.read()?;
+    }
+    Ok(sum / ADC_SAMPLES)
+}
+
+/// Reads the DS18B20, pH and TDS probes every 10 seconds and evaluates the
+/// `aquarium_alerts` rules on them. A raised or cleared alert wakes the
+/// sender.
+#[cfg(feature = "aquarium")]
+#[allow(clippy::too_many_arguments)]
+pub fn run<P, D, E>(
+    store: &Store,
+    alerts: &Alerts,
+    readings: &broadcast::Sender<SensorData>,
+    aquarium: &Aquarium,
+    bus: &Mutex<OneWire<P, D>>,
+    ph_pin: i32,
+    tds_pin: i32,
+) where
+    P: InputPin<Error = E> + OutputPin<Error = E>,
+    D: DelayUs<u16>,
+    E: std::fmt::Debug,
+{
+    let watchdog = Watchdog::subscribe("aquarium");
+    let health = health::register("aquarium");
+    let channel = |pin| match adc::Channel::new(pin) {
+        Ok(channel) => Some(channel),
+        Err(err) => {
+            log::error!("aquarium: adc init error={:?}", err);
+            None
+        }
+    };
+    let (ph_channel, tds_channel) = (channel(ph_pin), channel(tds_pin));
+    let mut spec = String::new();
+    let mut engine = Engine::new(Vec::new());
+
+    loop {
+        health.tick();
+        // The store only accepts a valid ROM code.
+        let probe = onewire::parse_rom(&store.get().aquarium_probe).ok();
+        let temperature = {
+            // Held through the conversion, which also starts the rack sensors.
+            let mut bus = bus.lock().unwrap();
+            match ds18b20::start_conversion(&mut bus) {
+                Ok(()) => {
+                    watchdog.sleep(Duration::from_millis(ds18b20::CONVERSION_MS));
+                    ds18b20::read(&mut bus, probe.as_ref())
+                }
+                Err(err) => Err(err),
+            }
+        };
+        let temperature = match temperature {
+            Ok(temperature) => temperature,
+            Err(err) => {
+                log::error!("aquarium: reading ds18b20 error={:?}", err);
+                None
+            }
+        };
+        let read = |channel: &Option<adc::Channel>| match channel.as_ref().map(average) {
+            Some(Ok(raw)) => Some(raw),
+            Some(Err(err)) => {
+                log::error!("aquarium: reading probe error={:?}", err);
+                None
+            }
+            None => None,
+        };
+        let (ph_raw, tds_raw) = (read(&ph_channel), read(&tds_channel));
+        // The pH calibration is on raw readings, the TDS formula is in volts.
+        let tds_mv = match (&tds_channel, tds_raw) {
+            (Some(channel), Some(raw)) => match channel.mv(raw) {
+                Ok(mv) => Some(mv),
+                Err(err) => {
+                    log::error!("aquarium: converting tds reading error={:?}", err);
+                    None
+                }
+            },
+            _ => None,
+        };
+
+        let settings = store.get();
+        let water = Water {
+            temperature,
+            ph: ph_raw.and_then(|raw| ph(&settings, raw)),
+            tds: tds_mv.map(|mv| tds(&settings, mv, temperature)),
+        };
+        log::debug!("aquarium: {:?}", water);
+        *aquarium.state.lock().unwrap() = State {
+            water,
+            ph_raw,
+            tds_raw,
+            fresh: true,
+        };
+
+        if settings.aquarium_alerts != spec {
+            spec = settings.aquarium_alerts;
+            // The store only accepts valid rules.
+            engine = Engine::new(parse_rules(&spec).unwrap_or_default());
+            log::info!("aquarium: loaded rules {:?}", spec);
+        }
+        let events = engine.update(&water);
+        for event in &events {
+            log::warn!("aquarium: {}", event);
+        }
+        let changed = !events.is_empty();
+        alerts.publish("aquarium", engine.active(), events);
+        if changed {
+            readings.wake();
+        }
+
+        watchdog.sleep(SAMPLE_INTERVAL);
+    }
+}
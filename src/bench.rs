@@ -0,0 +1,168 @@
+use std::{
+    convert::Infallible,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::{self, bail},
+    health, influx,
+    metrics::SenderMetrics,
+    point::{self, Point},
+    schedule,
+    settings::{Settings, Store},
+    watchdog::Watchdog,
+};
+
+/// Summaries are logged this often.
+const SUMMARY_EVERY: Duration = Duration::from_secs(10);
+
+/// Requests since the last summary.
+struct Window {
+    started: Instant,
+    points: u32,
+    requests: u32,
+    failures: u32,
+    bytes: u64,
+    largest: usize,
+    duration: Duration,
+}
+
+impl Window {
+    fn new() -> Self {
+        Window {
+            started: Instant::now(),
+            points: 0,
+            requests: 0,
+            failures: 0,
+            bytes: 0,
+            largest: 0,
+            duration: Duration::ZERO,
+        }
+    }
+
+    fn record(&mut self, points: usize, bytes: usize, duration: Duration, ok: bool) {
+        self.points += points as u32;
+        self.requests += 1;
+        self.failures += u32::from(!ok);
+        self.bytes += bytes as u64;
+        self.largest = self.largest.max(bytes);
+        self.duration += duration;
+    }
+
+    fn point(&self, tags: &[(String, String)]) -> Point {
+        let secs = self.started.elapsed().as_secs_f32();
+        let requests = self.requests.max(1);
+        Point::new("bench")
+            .tags(tags)
+            .field("points_per_sec", self.points as f32 / secs)
+            .field("bytes_per_sec", self.bytes as f32 / secs)
+            .field("requests", self.requests)
+            .field("failures", self.failures)
+            .field("payload_avg", (self.bytes / u64::from(requests)) as u32)
+            .field("payload_max", self.largest as u32)
+            .field(
+                "latency_ms_avg",
+                (self.duration / requests).as_millis() as u32,
+            )
+            .field("free_heap", unsafe {
+                esp_idf_sys::esp_get_free_heap_size()
+            })
+            .field("min_free_heap", unsafe {
+                esp_idf_sys::esp_get_minimum_free_heap_size()
+            })
+    }
+}
+
+/// Points shaped like sensor readings, numbered from `seq`.
+fn synthetic(count: u32, seq: &mut u64, tags: &[(String, String)]) -> Vec<Point> {
+    let now = schedule::unix_time().map(|now| now.as_nanos() as i64);
+    (0..count)
+        .map(|i| {
+            *seq += 1;
+            let point = Point::new("bench_data")
+                .tag("sensor", "synthetic")
+                .tags(tags)
+                .field("temperature", 20. + (*seq % 100) as f32 / 10.)
+                .field("humidity", 40. + (*seq % 300) as f32 / 10.)
+                .field("seq", *seq);
+            // Distinct timestamps, so points of a batch do not overwrite
+            // each other.
+            match now {
+                Some(now) => point.timestamp(now + i64::from(i)),
+                None => point,
+            }
+        })
+        .collect()
+}
+
+/// Sends `bench_rate` synthetic points per second in batches of
+/// `bench_batch` instead of readings, until the settings change.
+///
+/// Every summary is logged with throughput, payload sizes, latency, failures
+/// and free heap, and written as a `bench` point with `bench_report` set.
+/// Failures count towards the summary rather than dropping Wi-Fi.
+#[allow(clippy::too_many_arguments)]
+pub fn run<C, F>(
+    client: &mut influx::Client<C, F>,
+    metrics: &mut SenderMetrics,
+    addr: &str,
+    token: &str,
+    tags: &[(String, String)],
+    settings: &Settings,
+    store: &Store,
+    health: &health::Task,
+    watchdog: &Watchdog,
+) -> error::Result<Infallible>
+where
+    C: influx::Connection,
+    F: FnMut() -> error::Result<C>,
+{
+    let revision = store.revision();
+    let batch = settings.bench_batch.max(1);
+    let interval = Duration::from_secs_f32(batch as f32 / settings.bench_rate as f32);
+    log::warn!(
+        "bench: sending {} points/s in batches of {}, not sending readings",
+        settings.bench_rate,
+        batch
+    );
+
+    let mut seq = 0;
+    let mut window = Window::new();
+    let mut next = Instant::now();
+    loop {
+        health.tick();
+        watchdog.feed();
+        let points = synthetic(batch, &mut seq, tags);
+        let body = point::encode(&points);
+        let started = Instant::now();
+        let result = client.write(metrics, addr, token, &body);
+        if let Err(err) = &result {
+            log::debug!("bench: write failed error={:?}", err);
+        }
+        let ok = matches!(result, Ok(200..=299));
+        window.record(points.len(), body.len(), started.elapsed(), ok);
+
+        if window.started.elapsed() >= SUMMARY_EVERY {
+            let summary = point::encode(&[window.point(tags)]);
+            log::info!("bench: {}", String::from_utf8_lossy(&summary).trim_end());
+            if settings.bench_report != 0 {
+                if let Err(err) = client.write(metrics, addr, token, &summary) {
+                    log::error!("bench: writing summary error={:?}", err);
+                }
+            }
+            window = Window::new();
+        }
+
+        if store.revision() != revision {
+            bail!("settings changed, reconnecting");
+        }
+        next += interval;
+        match next.checked_duration_since(Instant::now()) {
+            Some(wait) => watchdog.sleep(wait),
+            None => {
+                log::debug!("bench: falling behind the rate");
+                next = Instant::now();
+            }
+        }
+    }
+}
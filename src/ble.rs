@@ -0,0 +1,41 @@
+use std::{ffi::c_void, thread, time::Duration};
+
+use esp_idf_sys::esp;
+
+use crate::error::{self, bail};
+
+/// Starts the NimBLE host and waits until it synced with the controller.
+///
+/// Needs Bluetooth enabled in the ESP-IDF configuration, see
+/// `sdkconfig.ble`.
+pub fn init() -> error::Result<()> {
+    unsafe {
+        esp!(esp_idf_sys::nimble_port_init())?;
+        esp_idf_sys::nimble_port_freertos_init(Some(host_task));
+    }
+
+    for _ in 0..50 {
+        if unsafe { esp_idf_sys::ble_hs_synced() } != 0 {
+            log::info!("ble: host synced");
+            return Ok(());
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    bail!("nimble host did not sync");
+}
+
+extern "C" fn host_task(_: *mut c_void) {
+    unsafe {
+        // Returns only after `nimble_port_stop`.
+        esp_idf_sys::nimble_port_run();
+        esp_idf_sys::nimble_port_freertos_deinit();
+    }
+}
+
+/// Turns a NimBLE host return code into a result.
+pub fn check(rc: i32) -> error::Result<()> {
+    if rc != 0 {
+        bail!("nimble rc={}", rc);
+    }
+    Ok(())
+}
@@ -0,0 +1,213 @@
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+
+/// Address with SDO to ground, SDO to VDDIO selects [`ADDRESS_HIGH`].
+pub const ADDRESS_LOW: u8 = 0x76;
+pub const ADDRESS_HIGH: u8 = 0x77;
+
+const REG_CALIBRATION: u8 = 0x88;
+const REG_ID: u8 = 0xD0;
+const REG_CTRL_MEAS: u8 = 0xF4;
+const REG_CONFIG: u8 = 0xF5;
+const REG_DATA: u8 = 0xF7;
+const CHIP_ID: u8 = 0x58;
+/// Temperature oversampling x2, pressure x16, normal mode.
+const CTRL_MEAS: u8 = 0b010 << 5 | 0b101 << 2 | 0b11;
+/// 0.5 ms standby, IIR filter coefficient 16.
+const CONFIG: u8 = 0b100 << 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error<E> {
+    I2c(E),
+    /// Something else answered at the address, e.g. a BME280 (0x60).
+    WrongChip(u8),
+}
+
+/// Trimming parameters, programmed into each chip at the factory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Calibration {
+    t1: u16,
+    t2: i16,
+    t3: i16,
+    p1: u16,
+    p: [i16; 8],
+}
+
+impl Calibration {
+    fn from_bytes(bytes: [u8; 24]) -> Calibration {
+        let word = |i: usize| u16::from_le_bytes([bytes[i * 2], bytes[i * 2 + 1]]);
+        let mut p = [0; 8];
+        for (i, value) in p.iter_mut().enumerate() {
+            *value = word(i + 4) as i16;
+        }
+        Calibration {
+            t1: word(0),
+            t2: word(1) as i16,
+            t3: word(2) as i16,
+            p1: word(3),
+            p,
+        }
+    }
+
+    /// Temperature in °C and pressure in Pa of the raw readings, the
+    /// integer compensation of the datasheet.
+    pub fn compensate(&self, adc_t: i32, adc_p: i32) -> (f32, f32) {
+        let t1 = i32::from(self.t1);
+        let var1 = (((adc_t >> 3) - (t1 << 1)) * i32::from(self.t2)) >> 11;
+        let var2 = (((((adc_t >> 4) - t1) * ((adc_t >> 4) - t1)) >> 12) * i32::from(self.t3)) >> 14;
+        let t_fine = var1 + var2;
+        let centi_degrees = (t_fine * 5 + 128) >> 8;
+
+        let [p2, p3, p4, p5, p6, p7, p8, p9] = self.p.map(i64::from);
+        let mut var1 = i64::from(t_fine) - 128000;
+        let mut var2 = var1 * var1 * p6;
+        var2 += (var1 * p5) << 17;
+        var2 += p4 << 35;
+        var1 = ((var1 * var1 * p3) >> 8) + ((var1 * p2) << 12);
+        var1 = (((1i64 << 47) + var1) * i64::from(self.p1)) >> 33;
+        let pascal = if var1 == 0 {
+            0.
+        } else {
+            let mut p = 1048576 - i64::from(adc_p);
+            p = (((p << 31) - var2) * 3125) / var1;
+            let var1 = (p9 * (p >> 13) * (p >> 13)) >> 25;
+            let var2 = (p8 * p) >> 19;
+            p = ((p + var1 + var2) >> 8) + (p7 << 4);
+            // Q24.8 fixed point.
+            p as f32 / 256.
+        };
+        (centi_degrees as f32 / 100., pascal)
+    }
+}
+
+/// BMP280 barometric pressure sensor on I2C, measuring continuously.
+///
+/// It does not own the bus, so several sensors and other devices can share
+/// one: each call takes the bus it is on.
+#[derive(Debug, Clone, Copy)]
+pub struct Bmp280 {
+    address: u8,
+    calibration: Calibration,
+}
+
+impl Bmp280 {
+    /// Checks the chip id, reads the calibration and starts continuous
+    /// measurements.
+    pub fn new<I, E>(i2c: &mut I, address: u8) -> Result<Bmp280, Error<E>>
+    where
+        I: Write<Error = E> + WriteRead<Error = E>,
+    {
+        let mut id = [0u8; 1];
+        i2c.write_read(address, &[REG_ID], &mut id)
+            .map_err(Error::I2c)?;
+        if id[0] != CHIP_ID {
+            return Err(Error::WrongChip(id[0]));
+        }
+
+        let mut bytes = [0u8; 24];
+        i2c.write_read(address, &[REG_CALIBRATION], &mut bytes)
+            .map_err(Error::I2c)?;
+        // The configuration is only written reliably in sleep mode.
+        i2c.write(address, &[REG_CONFIG, CONFIG])
+            .map_err(Error::I2c)?;
+        i2c.write(address, &[REG_CTRL_MEAS, CTRL_MEAS])
+            .map_err(Error::I2c)?;
+        Ok(Bmp280 {
+            address,
+            calibration: Calibration::from_bytes(bytes),
+        })
+    }
+
+    /// Temperature in °C and pressure in Pa of the latest measurement.
+    pub fn read<I, E>(&self, i2c: &mut I) -> Result<(f32, f32), Error<E>>
+    where
+        I: WriteRead<Error = E>,
+    {
+        let mut data = [0u8; 6];
+        i2c.write_read(self.address, &[REG_DATA], &mut data)
+            .map_err(Error::I2c)?;
+        let adc = |b: &[u8]| i32::from(b[0]) << 12 | i32::from(b[1]) << 4 | i32::from(b[2]) >> 4;
+        Ok(self
+            .calibration
+            .compensate(adc(&data[3..]), adc(&data[..3])))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal_mock::eh0::i2c::{Mock, Transaction};
+
+    /// The example of the datasheet.
+    const CALIBRATION: [u16; 12] = [
+        27504,
+        26435,
+        -1000i16 as u16,
+        36477,
+        -10685i16 as u16,
+        3024,
+        2855,
+        140,
+        -7i16 as u16,
+        15500,
+        -14600i16 as u16,
+        6000,
+    ];
+    const ADC_T: u32 = 519888;
+    const ADC_P: u32 = 415148;
+
+    fn init(address: u8) -> Vec<Transaction> {
+        let bytes = CALIBRATION.iter().flat_map(|w| w.to_le_bytes()).collect();
+        vec![
+            Transaction::write_read(address, vec![REG_ID], vec![CHIP_ID]),
+            Transaction::write_read(address, vec![REG_CALIBRATION], bytes),
+            Transaction::write(address, vec![REG_CONFIG, CONFIG]),
+            Transaction::write(address, vec![REG_CTRL_MEAS, CTRL_MEAS]),
+        ]
+    }
+
+    fn raw(adc: u32) -> [u8; 3] {
+        [(adc >> 12) as u8, (adc >> 4) as u8, (adc << 4) as u8]
+    }
+
+    #[test]
+    fn compensates_the_datasheet_example() {
+        let mut data = raw(ADC_P).to_vec();
+        data.extend(raw(ADC_T));
+        let mut transactions = init(ADDRESS_LOW);
+        transactions.push(Transaction::write_read(ADDRESS_LOW, vec![REG_DATA], data));
+        let mut i2c = Mock::new(&transactions);
+
+        let sensor = Bmp280::new(&mut i2c, ADDRESS_LOW).unwrap();
+        let (temperature, pressure) = sensor.read(&mut i2c).unwrap();
+        i2c.done();
+        assert_eq!(temperature, 25.08);
+        assert!((pressure - 100653.25).abs() < 0.01);
+    }
+
+    #[test]
+    fn shares_the_bus() {
+        let mut transactions = init(ADDRESS_LOW);
+        transactions.extend(init(ADDRESS_HIGH));
+        let mut i2c = Mock::new(&transactions);
+
+        let upstream = Bmp280::new(&mut i2c, ADDRESS_LOW).unwrap();
+        let downstream = Bmp280::new(&mut i2c, ADDRESS_HIGH).unwrap();
+        i2c.done();
+        assert_eq!(upstream.calibration, downstream.calibration);
+        assert_ne!(upstream.address, downstream.address);
+    }
+
+    #[test]
+    fn rejects_other_chips() {
+        let mut i2c = Mock::new(&[Transaction::write_read(
+            ADDRESS_HIGH,
+            vec![REG_ID],
+            vec![0x60],
+        )]);
+        assert_eq!(
+            Bmp280::new(&mut i2c, ADDRESS_HIGH).unwrap_err(),
+            Error::WrongChip(0x60)
+        );
+        i2c.done();
+    }
+}
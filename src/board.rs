@@ -0,0 +1,537 @@
+use esp_idf_hal::gpio::{AnyIOPin, AnyInputPin, AnyOutputPin};
+
+use crate::error::{self, bail, Context};
+
+const _: () = assert!(
+    cfg!(feature = "board-esp32-wroom") as u8
+        + cfg!(feature = "board-m5stickc") as u8
+        + cfg!(feature = "board-xiao-esp32c3") as u8
+        + cfg!(feature = "board-esp32s3-devkit") as u8
+        + cfg!(feature = "board-esp32c6-devkit") as u8
+        <= 1,
+    "select at most one board-* feature"
+);
+
+/// GPIO numbers of the DHT22, TM1637, button, status LED, relay, buzzer,
+/// rotary encoder, PIR sensor, leak probe, weather station, gas sensor, hive
+/// scale, aquarium probes, CT clamp, dust sensor, I2S microphone, CO
+/// sensor, fan, LoRa module and I2C bus on a board.
+/// The `pins` setting overrides them at boot.
+#[derive(Debug, Clone)]
+pub struct Board {
+    pub name: &'static str,
+    pub dht22: i32,
+    pub button: i32,
+    /// Input only pads have no internal pull-up, the board provides one.
+    pub button_pull_up: bool,
+    pub status_led: i32,
+    #[cfg_attr(not(feature = "display"), allow(dead_code))]
+    pub display_clk: i32,
+    #[cfg_attr(not(feature = "display"), allow(dead_code))]
+    pub display_dio: i32,
+    #[cfg_attr(not(feature = "relay"), allow(dead_code))]
+    pub relay: i32,
+    #[cfg_attr(not(feature = "buzzer"), allow(dead_code))]
+    pub buzzer: i32,
+    #[cfg_attr(not(feature = "encoder"), allow(dead_code))]
+    pub encoder_a: i32,
+    #[cfg_attr(not(feature = "encoder"), allow(dead_code))]
+    pub encoder_b: i32,
+    /// Push switch of the encoder, active low.
+    #[cfg_attr(not(feature = "encoder"), allow(dead_code))]
+    pub encoder_sw: i32,
+    #[cfg_attr(not(feature = "pir"), allow(dead_code))]
+    pub pir: i32,
+    /// ADC capable, so the probe can be read either way.
+    #[cfg_attr(not(feature = "leak"), allow(dead_code))]
+    pub leak: i32,
+    #[cfg_attr(not(feature = "weather"), allow(dead_code))]
+    pub anemometer: i32,
+    #[cfg_attr(not(feature = "weather"), allow(dead_code))]
+    pub rain_gauge: i32,
+    /// ADC capable.
+    #[cfg_attr(not(feature = "weather"), allow(dead_code))]
+    pub wind_vane: i32,
+    /// ADC capable.
+    #[cfg_attr(not(feature = "gas"), allow(dead_code))]
+    pub gas: i32,
+    #[cfg_attr(not(feature = "hive"), allow(dead_code))]
+    pub hx711_dout: i32,
+    #[cfg_attr(not(feature = "hive"), allow(dead_code))]
+    pub hx711_sck: i32,
+    /// 1-Wire bus of the DS18B20 sensors, with a 4.7 kΩ pull-up.
+    #[cfg_attr(not(feature = "aquarium"), allow(dead_code))]
+    pub onewire: i32,
+    /// ADC capable.
+    #[cfg_attr(not(feature = "aquarium"), allow(dead_code))]
+    pub ph_probe: i32,
+    /// ADC capable.
+    #[cfg_attr(not(feature = "aquarium"), allow(dead_code))]
+    pub tds_probe: i32,
+    /// ADC capable, on ADC1 as ADC2 is taken by Wi-Fi.
+    #[cfg_attr(not(feature = "energy"), allow(dead_code))]
+    pub ct_clamp: i32,
+    #[cfg_attr(not(feature = "dust"), allow(dead_code))]
+    pub dust_led: i32,
+    /// ADC capable.
+    #[cfg_attr(not(feature = "dust"), allow(dead_code))]
+    pub dust: i32,
+    #[cfg_attr(not(feature = "noise"), allow(dead_code))]
+    pub i2s_sck: i32,
+    #[cfg_attr(not(feature = "noise"), allow(dead_code))]
+    pub i2s_ws: i32,
+    /// Input only pads are fine.
+    #[cfg_attr(not(feature = "noise"), allow(dead_code))]
+    pub i2s_sd: i32,
+    /// ADC capable.
+    #[cfg_attr(not(feature = "co"), allow(dead_code))]
+    pub co: i32,
+    #[cfg_attr(not(feature = "fan"), allow(dead_code))]
+    pub fan_pwm: i32,
+    /// Open collector, pulled up internally.
+    #[cfg_attr(not(feature = "fan"), allow(dead_code))]
+    pub fan_tach: i32,
+    #[cfg_attr(not(feature = "lora"), allow(dead_code))]
+    pub lora_sck: i32,
+    #[cfg_attr(not(feature = "lora"), allow(dead_code))]
+    pub lora_mosi: i32,
+    #[cfg_attr(not(feature = "lora"), allow(dead_code))]
+    pub lora_miso: i32,
+    #[cfg_attr(not(feature = "lora"), allow(dead_code))]
+    pub lora_nss: i32,
+    /// Bus of the DS3231 and the HVAC pressure sensors.
+    #[cfg_attr(not(any(feature = "rtc", feature = "hvac")), allow(dead_code))]
+    pub i2c_sda: i32,
+    #[cfg_attr(not(any(feature = "rtc", feature = "hvac")), allow(dead_code))]
+    pub i2c_scl: i32,
+}
+
+/// ESP32-C3-DevKitM-1 and similar, the default.
+#[cfg(not(any(
+    feature = "board-esp32-wroom",
+    feature = "board-m5stickc",
+    feature = "board-xiao-esp32c3",
+    feature = "board-esp32s3-devkit",
+    feature = "board-esp32c6-devkit"
+)))]
+pub const BOARD: Board = Board {
+    name: "esp32c3-devkit",
+    dht22: 3,
+    button: 9,
+    button_pull_up: true,
+    status_led: 8,
+    display_clk: 1,
+    display_dio: 10,
+    relay: 5,
+    buzzer: 4,
+    encoder_a: 18,
+    encoder_b: 19,
+    encoder_sw: 2,
+    pir: 6,
+    leak: 0,
+    anemometer: 6,
+    rain_gauge: 7,
+    wind_vane: 1,
+    gas: 4,
+    hx711_dout: 18,
+    hx711_sck: 19,
+    onewire: 10,
+    ph_probe: 0,
+    tds_probe: 1,
+    ct_clamp: 2,
+    dust_led: 7,
+    dust: 4,
+    i2s_sck: 6,
+    i2s_ws: 7,
+    i2s_sd: 10,
+    co: 2,
+    fan_pwm: 6,
+    fan_tach: 7,
+    lora_sck: 0,
+    lora_mosi: 2,
+    lora_miso: 18,
+    lora_nss: 19,
+    i2c_sda: 20,
+    i2c_scl: 21,
+};
+
+/// ESP32-DevKitC and other ESP32-WROOM boards, LED on GPIO2 and the buzzer
+/// on GPIO25 as the DHT22 takes GPIO4.
+#[cfg(feature = "board-esp32-wroom")]
+pub const BOARD: Board = Board {
+    name: "esp32-wroom",
+    dht22: 4,
+    button: 0,
+    button_pull_up: true,
+    status_led: 2,
+    display_clk: 18,
+    display_dio: 19,
+    relay: 5,
+    buzzer: 25,
+    encoder_a: 32,
+    encoder_b: 33,
+    encoder_sw: 27,
+    pir: 26,
+    leak: 34,
+    anemometer: 14,
+    rain_gauge: 13,
+    wind_vane: 35,
+    gas: 39,
+    hx711_dout: 16,
+    hx711_sck: 17,
+    onewire: 21,
+    ph_probe: 36,
+    tds_probe: 33,
+    ct_clamp: 32,
+    dust_led: 23,
+    dust: 34,
+    i2s_sck: 14,
+    i2s_ws: 15,
+    i2s_sd: 13,
+    co: 35,
+    fan_pwm: 23,
+    fan_tach: 27,
+    lora_sck: 14,
+    lora_mosi: 13,
+    lora_miso: 12,
+    lora_nss: 15,
+    i2c_sda: 21,
+    i2c_scl: 22,
+};
+
+/// M5StickC: DHT22 on the Grove port, TM1637 on the hat header and
+/// button A.
+#[cfg(feature = "board-m5stickc")]
+pub const BOARD: Board = Board {
+    name: "m5stickc",
+    dht22: 33,
+    button: 37,
+    button_pull_up: false,
+    status_led: 10,
+    display_clk: 26,
+    display_dio: 0,
+    relay: 5,
+    buzzer: 4,
+    encoder_a: 32,
+    encoder_b: 36,
+    encoder_sw: 39,
+    pir: 36,
+    leak: 36,
+    anemometer: 32,
+    rain_gauge: 26,
+    wind_vane: 36,
+    gas: 36,
+    hx711_dout: 36,
+    hx711_sck: 26,
+    onewire: 0,
+    ph_probe: 36,
+    tds_probe: 32,
+    ct_clamp: 36,
+    dust_led: 26,
+    dust: 36,
+    i2s_sck: 26,
+    i2s_ws: 0,
+    i2s_sd: 36,
+    co: 36,
+    fan_pwm: 26,
+    fan_tach: 32,
+    lora_sck: 0,
+    lora_mosi: 26,
+    lora_miso: 36,
+    lora_nss: 32,
+    i2c_sda: 0,
+    i2c_scl: 26,
+};
+
+/// Seeed Studio XIAO ESP32C3: DHT22 on D0, TM1637 on D1/D10, LED on D8 and
+/// the boot button.
+#[cfg(feature = "board-xiao-esp32c3")]
+pub const BOARD: Board = Board {
+    name: "xiao-esp32c3",
+    dht22: 2,
+    button: 9,
+    button_pull_up: true,
+    status_led: 8,
+    display_clk: 3,
+    display_dio: 10,
+    relay: 5,
+    buzzer: 4,
+    encoder_a: 20,
+    encoder_b: 21,
+    encoder_sw: 7,
+    pir: 6,
+    leak: 4,
+    anemometer: 6,
+    rain_gauge: 7,
+    wind_vane: 3,
+    gas: 3,
+    hx711_dout: 20,
+    hx711_sck: 21,
+    onewire: 10,
+    ph_probe: 3,
+    tds_probe: 4,
+    ct_clamp: 3,
+    dust_led: 7,
+    dust: 4,
+    i2s_sck: 6,
+    i2s_ws: 7,
+    i2s_sd: 10,
+    co: 3,
+    fan_pwm: 20,
+    fan_tach: 21,
+    lora_sck: 20,
+    lora_mosi: 21,
+    lora_miso: 6,
+    lora_nss: 7,
+    i2c_sda: 6,
+    i2c_scl: 7,
+};
+
+/// ESP32-S3-DevKitC-1, LED on the RGB LED's data pin.
+#[cfg(feature = "board-esp32s3-devkit")]
+pub const BOARD: Board = Board {
+    name: "esp32s3-devkit",
+    dht22: 15,
+    button: 0,
+    button_pull_up: true,
+    status_led: 48,
+    display_clk: 16,
+    display_dio: 17,
+    relay: 5,
+    buzzer: 4,
+    encoder_a: 1,
+    encoder_b: 2,
+    encoder_sw: 42,
+    pir: 6,
+    leak: 7,
+    anemometer: 8,
+    rain_gauge: 9,
+    wind_vane: 10,
+    gas: 11,
+    hx711_dout: 12,
+    hx711_sck: 13,
+    onewire: 14,
+    ph_probe: 3,
+    tds_probe: 9,
+    ct_clamp: 4,
+    dust_led: 18,
+    dust: 5,
+    i2s_sck: 39,
+    i2s_ws: 40,
+    i2s_sd: 41,
+    co: 6,
+    fan_pwm: 38,
+    fan_tach: 14,
+    lora_sck: 12,
+    lora_mosi: 11,
+    lora_miso: 13,
+    lora_nss: 10,
+    i2c_sda: 47,
+    i2c_scl: 21,
+};
+
+/// ESP32-C6-DevKitC-1, LED on the RGB LED's data pin.
+#[cfg(feature = "board-esp32c6-devkit")]
+pub const BOARD: Board = Board {
+    name: "esp32c6-devkit",
+    dht22: 3,
+    button: 9,
+    button_pull_up: true,
+    status_led: 8,
+    display_clk: 22,
+    display_dio: 23,
+    relay: 5,
+    buzzer: 4,
+    encoder_a: 10,
+    encoder_b: 11,
+    encoder_sw: 15,
+    pir: 14,
+    leak: 1,
+    anemometer: 18,
+    rain_gauge: 19,
+    wind_vane: 2,
+    gas: 6,
+    hx711_dout: 20,
+    hx711_sck: 21,
+    onewire: 7,
+    ph_probe: 0,
+    tds_probe: 1,
+    ct_clamp: 5,
+    dust_led: 18,
+    dust: 6,
+    i2s_sck: 18,
+    i2s_ws: 19,
+    i2s_sd: 20,
+    co: 2,
+    fan_pwm: 21,
+    fan_tach: 20,
+    lora_sck: 6,
+    lora_mosi: 7,
+    lora_miso: 2,
+    lora_nss: 10,
+    i2c_sda: 16,
+    i2c_scl: 17,
+};
+
+impl Board {
+    /// Applies comma separated `name=gpio` overrides, e.g. `dht22=4,relay=6`.
+    pub fn with_pins(&self, spec: &str) -> error::Result<Board> {
+        let mut board = self.clone();
+        for entry in spec.split(',').filter(|entry| !entry.trim().is_empty()) {
+            let Some((name, gpio)) = entry.split_once('=') else {
+                bail!("pin {:?} must be written as name=gpio", entry.trim());
+            };
+            let gpio: i32 = gpio
+                .trim()
+                .parse()
+                .with_context(|| format!("parse gpio of {:?}", name.trim()))?;
+            if !(0..esp_idf_sys::gpio_num_t_GPIO_NUM_MAX as i32).contains(&gpio) {
+                bail!("gpio{} does not exist on this chip", gpio);
+            }
+
+            let field = match name.trim() {
+                "dht22" => &mut board.dht22,
+                "button" => &mut board.button,
+                "status_led" => &mut board.status_led,
+                "display_clk" => &mut board.display_clk,
+                "display_dio" => &mut board.display_dio,
+                "relay" => &mut board.relay,
+                "buzzer" => &mut board.buzzer,
+                "encoder_a" => &mut board.encoder_a,
+                "encoder_b" => &mut board.encoder_b,
+                "encoder_sw" => &mut board.encoder_sw,
+                "pir" => &mut board.pir,
+                "leak" => &mut board.leak,
+                "anemometer" => &mut board.anemometer,
+                "rain_gauge" => &mut board.rain_gauge,
+                "wind_vane" => &mut board.wind_vane,
+                "gas" => &mut board.gas,
+                "hx711_dout" => &mut board.hx711_dout,
+                "hx711_sck" => &mut board.hx711_sck,
+                "onewire" => &mut board.onewire,
+                "ph_probe" => &mut board.ph_probe,
+                "tds_probe" => &mut board.tds_probe,
+                "ct_clamp" => &mut board.ct_clamp,
+                "dust_led" => &mut board.dust_led,
+                "dust" => &mut board.dust,
+                "i2s_sck" => &mut board.i2s_sck,
+                "i2s_ws" => &mut board.i2s_ws,
+                "i2s_sd" => &mut board.i2s_sd,
+                "co" => &mut board.co,
+                "fan_pwm" => &mut board.fan_pwm,
+                "fan_tach" => &mut board.fan_tach,
+                "lora_sck" => &mut board.lora_sck,
+                "lora_mosi" => &mut board.lora_mosi,
+                "lora_miso" => &mut board.lora_miso,
+                "lora_nss" => &mut board.lora_nss,
+                "i2c_sda" => &mut board.i2c_sda,
+                "i2c_scl" => &mut board.i2c_scl,
+                other => bail!(
+                    "unknown pin {:?}, expected dht22, button, status_led, display_clk, \
+                     display_dio, relay, buzzer, encoder_a, encoder_b, encoder_sw, pir, leak, \
+                     anemometer, rain_gauge, wind_vane, gas, hx711_dout, hx711_sck, onewire, \
+                     ph_probe, tds_probe, ct_clamp, dust_led, dust, i2s_sck, i2s_ws, i2s_sd, co, \
+                     fan_pwm, fan_tach, lora_sck, lora_mosi, lora_miso, lora_nss, i2c_sda or \
+                     i2c_scl",
+                    other
+                ),
+            };
+            *field = gpio;
+        }
+        Ok(board)
+    }
+
+    // SAFETY (all below): each board pin is created once, at boot, and the
+    // board pins are not taken from `Peripherals`. Overlaps with each other
+    // and with optional hardware are reported by the pin validation.
+
+    pub fn dht22_pin(&self) -> AnyIOPin {
+        unsafe { AnyIOPin::new(self.dht22) }
+    }
+
+    pub fn button_pin(&self) -> AnyInputPin {
+        unsafe { AnyInputPin::new(self.button) }
+    }
+
+    pub fn status_led_pin(&self) -> AnyOutputPin {
+        unsafe { AnyOutputPin::new(self.status_led) }
+    }
+
+    #[cfg(feature = "relay")]
+    pub fn relay_pin(&self) -> AnyOutputPin {
+        unsafe { AnyOutputPin::new(self.relay) }
+    }
+
+    #[cfg(feature = "buzzer")]
+    pub fn buzzer_pin(&self) -> AnyOutputPin {
+        unsafe { AnyOutputPin::new(self.buzzer) }
+    }
+
+    #[cfg(feature = "encoder")]
+    pub fn encoder_sw_pin(&self) -> AnyInputPin {
+        unsafe { AnyInputPin::new(self.encoder_sw) }
+    }
+
+    #[cfg(feature = "pir")]
+    pub fn pir_pin(&self) -> AnyInputPin {
+        unsafe { AnyInputPin::new(self.pir) }
+    }
+
+    #[cfg(feature = "hive")]
+    pub fn hx711_pins(&self) -> (AnyInputPin, AnyOutputPin) {
+        unsafe {
+            (
+                AnyInputPin::new(self.hx711_dout),
+                AnyOutputPin::new(self.hx711_sck),
+            )
+        }
+    }
+
+    #[cfg(feature = "aquarium")]
+    pub fn onewire_pin(&self) -> AnyIOPin {
+        unsafe { AnyIOPin::new(self.onewire) }
+    }
+
+    #[cfg(feature = "dust")]
+    pub fn dust_led_pin(&self) -> AnyOutputPin {
+        unsafe { AnyOutputPin::new(self.dust_led) }
+    }
+
+    #[cfg(feature = "fan")]
+    pub fn fan_pins(&self) -> (AnyOutputPin, AnyInputPin) {
+        unsafe {
+            (
+                AnyOutputPin::new(self.fan_pwm),
+                AnyInputPin::new(self.fan_tach),
+            )
+        }
+    }
+
+    /// SCK, MOSI, MISO and NSS.
+    #[cfg(feature = "lora")]
+    pub fn lora_pins(&self) -> (AnyOutputPin, AnyOutputPin, AnyInputPin, AnyOutputPin) {
+        unsafe {
+            (
+                AnyOutputPin::new(self.lora_sck),
+                AnyOutputPin::new(self.lora_mosi),
+                AnyInputPin::new(self.lora_miso),
+                AnyOutputPin::new(self.lora_nss),
+            )
+        }
+    }
+
+    #[cfg(any(feature = "rtc", feature = "hvac"))]
+    pub fn i2c_pins(&self) -> (AnyIOPin, AnyIOPin) {
+        unsafe { (AnyIOPin::new(self.i2c_sda), AnyIOPin::new(self.i2c_scl)) }
+    }
+
+    #[cfg(feature = "display")]
+    pub fn display_pins(&self) -> (AnyIOPin, AnyIOPin) {
+        unsafe {
+            (
+                AnyIOPin::new(self.display_clk),
+                AnyIOPin::new(self.display_dio),
+            )
+        }
+    }
+}
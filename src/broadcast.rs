@@ -0,0 +1,153 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Single producer broadcast channel for sensor readings.
+///
+/// The sender never blocks. It keeps the last `capacity` values, and every
+/// receiver sees each of them once. A receiver that falls more than
+/// `capacity` values behind loses the oldest ones. Its next receive returns
+/// [`RecvError::Lagged`] with the number of skipped values and then continues
+/// with the oldest value still kept. Waiting receivers sleep on a condition
+/// variable instead of polling, [`Sender::wake`] interrupts them without a
+/// value.
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    /// Sequence number of the next value this receiver returns.
+    next: u64,
+    /// Wakes already returned as [`RecvError::Woken`].
+    wakes: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvError {
+    /// No value arrived in time.
+    Timeout,
+    /// The receiver fell behind and this many values were dropped.
+    Lagged(u64),
+    /// The sender is gone and every kept value was received.
+    Closed,
+    /// [`Sender::wake`] was called, e.g. to send an event right away.
+    Woken,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    ready: Condvar,
+}
+
+struct State<T> {
+    values: VecDeque<T>,
+    /// Sequence number of `values[0]`.
+    head: u64,
+    capacity: usize,
+    closed: bool,
+    /// Number of [`Sender::wake`] calls.
+    wakes: u64,
+}
+
+impl<T: Clone> Sender<T> {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "broadcast capacity must not be zero");
+        Self {
+            shared: Arc::new(Shared {
+                state: Mutex::new(State {
+                    values: VecDeque::with_capacity(capacity),
+                    head: 0,
+                    capacity,
+                    closed: false,
+                    wakes: 0,
+                }),
+                ready: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Creates a receiver that gets values sent from now on.
+    pub fn subscribe(&self) -> Receiver<T> {
+        let state = self.shared.state.lock().unwrap();
+        Receiver {
+            shared: self.shared.clone(),
+            next: state.head + state.values.len() as u64,
+            wakes: state.wakes,
+        }
+    }
+
+    /// Publishes a value, dropping the oldest one when full.
+    pub fn send(&self, value: T) {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.values.len() == state.capacity {
+            state.values.pop_front();
+            state.head += 1;
+        }
+        state.values.push_back(value);
+        drop(state);
+
+        self.shared.ready.notify_all();
+    }
+
+    /// Makes every waiting receiver return [`RecvError::Woken`] once.
+    /// Receivers that are not waiting get it on their next receive.
+    pub fn wake(&self) {
+        self.shared.state.lock().unwrap().wakes += 1;
+        self.shared.ready.notify_all();
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().closed = true;
+        self.shared.ready.notify_all();
+    }
+}
+
+impl<T: Clone> Receiver<T> {
+    /// Receives the next value, waiting at most `timeout` for it.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<T, RecvError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            if self.next < state.head {
+                let skipped = state.head - self.next;
+                self.next = state.head;
+                return Err(RecvError::Lagged(skipped));
+            }
+
+            let index = (self.next - state.head) as usize;
+            if let Some(value) = state.values.get(index) {
+                self.next += 1;
+                return Ok(value.clone());
+            }
+            if self.wakes != state.wakes {
+                self.wakes = state.wakes;
+                return Err(RecvError::Woken);
+            }
+            if state.closed {
+                return Err(RecvError::Closed);
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(RecvError::Timeout);
+            }
+            state = self.shared.ready.wait_timeout(state, remaining).unwrap().0;
+        }
+    }
+
+    /// Receives the next value if one is available.
+    pub fn try_recv(&mut self) -> Result<T, RecvError> {
+        self.recv_timeout(Duration::ZERO)
+    }
+
+    /// Number of values sent on the channel so far, received or not.
+    pub fn sent(&self) -> u64 {
+        let state = self.shared.state.lock().unwrap();
+        state.head + state.values.len() as u64
+    }
+}
@@ -0,0 +1,121 @@
+#[cfg(feature = "ble")]
+use std::ptr;
+use std::str::FromStr;
+
+use crate::error::{self, bail};
+#[cfg(feature = "ble")]
+use crate::{ble, health, state::State, watchdog::Watchdog, SensorData};
+
+/// BTHome service UUID 0xFCD2, little endian.
+#[cfg(feature = "ble")]
+const SERVICE_UUID: [u8; 2] = [0xD2, 0xFC];
+/// BTHome v2, not encrypted, advertising at a regular interval.
+#[cfg(feature = "ble")]
+const DEVICE_INFO: u8 = 0x40;
+#[cfg(feature = "ble")]
+const NAME: &[u8] = b"esp-sensor";
+/// One second in 0.625 ms units.
+#[cfg(feature = "ble")]
+const ADV_INTERVAL: u16 = 1600;
+
+/// Whether readings are broadcast as BTHome advertisements.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Mode {
+    #[default]
+    Off,
+    On,
+    /// Advertise only, Wi-Fi is never started.
+    Only,
+}
+
+impl Mode {
+    pub fn uses_wifi(self) -> bool {
+        self != Mode::Only
+    }
+}
+
+impl FromStr for Mode {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Mode::Off),
+            "on" => Ok(Mode::On),
+            "only" => Ok(Mode::Only),
+            _ => bail!("bthome mode {:?} is not one of off, on, only", s),
+        }
+    }
+}
+
+/// Raw advertising data carrying the reading as BTHome v2 objects.
+#[cfg(feature = "ble")]
+pub fn encode(data: &SensorData, packet_id: u8) -> Vec<u8> {
+    let temperature = (data.temperature * 100.).round() as i16;
+    let humidity = (data.humidity * 100.).round() as u16;
+
+    // Objects are sorted by id: packet id, temperature (0.01 °C),
+    // humidity (0.01 %).
+    let mut service_data = vec![0x16, SERVICE_UUID[0], SERVICE_UUID[1], DEVICE_INFO];
+    service_data.extend_from_slice(&[0x00, packet_id, 0x02]);
+    service_data.extend_from_slice(&temperature.to_le_bytes());
+    service_data.push(0x03);
+    service_data.extend_from_slice(&humidity.to_le_bytes());
+
+    // Flags: LE general discoverable, BR/EDR not supported.
+    let mut adv = vec![0x02, 0x01, 0x06];
+    adv.push(service_data.len() as u8);
+    adv.extend(service_data);
+    adv.push(NAME.len() as u8 + 1);
+    adv.push(0x09);
+    adv.extend_from_slice(NAME);
+    adv
+}
+
+/// Advertises every new reading as a non-connectable BTHome packet.
+#[cfg(feature = "ble")]
+pub fn run(state: &State) {
+    let watchdog = Watchdog::subscribe("bthome");
+    let health = health::register("bthome");
+    let mut packet_id: u8 = 0;
+    let mut seen = None;
+
+    loop {
+        let snapshot = watchdog.next(state, seen);
+        seen = Some(snapshot.sequence);
+        health.tick();
+        // Receivers drop packets with a repeated id.
+        packet_id = packet_id.wrapping_add(1);
+        if let Err(err) = advertise(&encode(&snapshot.data, packet_id)) {
+            log::error!("bthome: advertising error={:?}", err);
+        }
+    }
+}
+
+#[cfg(feature = "ble")]
+fn advertise(adv: &[u8]) -> error::Result<()> {
+    unsafe {
+        ble::check(esp_idf_sys::ble_gap_adv_set_data(
+            adv.as_ptr(),
+            adv.len() as i32,
+        ))?;
+        if esp_idf_sys::ble_gap_adv_active() != 0 {
+            return Ok(());
+        }
+
+        let params = esp_idf_sys::ble_gap_adv_params {
+            conn_mode: esp_idf_sys::BLE_GAP_CONN_MODE_NON as u8,
+            disc_mode: esp_idf_sys::BLE_GAP_DISC_MODE_GEN as u8,
+            itvl_min: ADV_INTERVAL,
+            itvl_max: ADV_INTERVAL,
+            ..Default::default()
+        };
+        ble::check(esp_idf_sys::ble_gap_adv_start(
+            esp_idf_sys::BLE_OWN_ADDR_PUBLIC as u8,
+            ptr::null(),
+            i32::MAX,
+            &params,
+            None,
+            ptr::null_mut(),
+        ))
+    }
+}
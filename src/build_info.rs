@@ -0,0 +1,87 @@
+//! What was built and how much room it takes: the version, git hash and
+//! features embedded by `build.rs`, the running image against its flash
+//! partition and the heap left next to the static data. Sent with the
+//! `device` telemetry and shown on `/status`, to follow the size of the
+//! firmware across releases.
+
+use std::sync::OnceLock;
+
+use serde_json::{json, Value as Json};
+
+use crate::point::Point;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Short hash of the commit, `-dirty` with uncommitted changes.
+pub const GIT_HASH: &str = env!("BUILD_GIT_HASH");
+/// Enabled cargo features, comma separated.
+pub const FEATURES: &str = env!("BUILD_FEATURES");
+
+/// Flash and RAM use in bytes.
+#[derive(Debug, Clone, Copy)]
+pub struct Usage {
+    /// The running image and the app partition holding it.
+    pub app_size: u32,
+    pub app_partition_size: u32,
+    /// Heap of the internal RAM, what the static data leaves of it.
+    pub heap_total: u32,
+    pub heap_free: u32,
+}
+
+pub fn usage() -> Usage {
+    // Reading the image metadata hashes the whole image, it is done once.
+    static APP: OnceLock<(u32, u32)> = OnceLock::new();
+    let (app_size, app_partition_size) = *APP.get_or_init(app_size);
+    let caps = esp_idf_sys::MALLOC_CAP_INTERNAL | esp_idf_sys::MALLOC_CAP_8BIT;
+    Usage {
+        app_size,
+        app_partition_size,
+        heap_total: unsafe { esp_idf_sys::heap_caps_get_total_size(caps) } as u32,
+        heap_free: unsafe { esp_idf_sys::heap_caps_get_free_size(caps) } as u32,
+    }
+}
+
+/// Size of the running image and of its partition, 0 for what is unknown.
+fn app_size() -> (u32, u32) {
+    let partition = unsafe { esp_idf_sys::esp_ota_get_running_partition() };
+    if partition.is_null() {
+        log::error!("build_info: no running partition");
+        return (0, 0);
+    }
+    let partition = unsafe { &*partition };
+    let position = esp_idf_sys::esp_partition_pos_t {
+        offset: partition.address,
+        size: partition.size,
+    };
+    let mut metadata: esp_idf_sys::esp_image_metadata_t = unsafe { std::mem::zeroed() };
+    let err = unsafe { esp_idf_sys::esp_image_get_metadata(&position, &mut metadata) };
+    if err != esp_idf_sys::ESP_OK {
+        log::error!("build_info: reading image metadata error={}", err);
+        return (0, partition.size);
+    }
+    (metadata.image_len, partition.size)
+}
+
+/// Adds the build and usage fields to the `device` point.
+pub fn fields(point: Point) -> Point {
+    let usage = usage();
+    point
+        .field("git_hash", GIT_HASH)
+        .field("features", FEATURES)
+        .field("app_size", usage.app_size)
+        .field("app_partition_size", usage.app_partition_size)
+        .field("heap_total", usage.heap_total)
+}
+
+/// The build and usage for the status page.
+pub fn json() -> Json {
+    let usage = usage();
+    json!({
+        "version": VERSION,
+        "git_hash": GIT_HASH,
+        "features": FEATURES.split(',').filter(|f| !f.is_empty()).collect::<Vec<_>>(),
+        "app_size": usage.app_size,
+        "app_partition_size": usage.app_partition_size,
+        "heap_total": usage.heap_total,
+        "heap_free": usage.heap_free,
+    })
+}
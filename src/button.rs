@@ -0,0 +1,24 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use esp_idf_hal::gpio::{self, PinDriver};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Returns how long the (active low) button is held down, measuring at most
+/// `limit`. `on_tick` is called with the elapsed time while it is held. Used
+/// right after boot to select maintenance actions.
+pub fn held_for<P: gpio::InputPin>(
+    pin: &PinDriver<'_, P, gpio::Input>,
+    limit: Duration,
+    mut on_tick: impl FnMut(Duration),
+) -> Duration {
+    let start = Instant::now();
+    while pin.is_low() && start.elapsed() < limit {
+        on_tick(start.elapsed());
+        thread::sleep(POLL_INTERVAL);
+    }
+    start.elapsed()
+}
@@ -0,0 +1,105 @@
+use std::time::Duration;
+#[cfg(feature = "buzzer")]
+use std::time::Instant;
+
+#[cfg(feature = "buzzer")]
+use esp_idf_hal::{
+    gpio::{self, Input, PinDriver},
+    ledc::LedcDriver,
+};
+
+use crate::error::{self, bail, Context};
+#[cfg(feature = "buzzer")]
+use crate::{alert::Alerts, health, schedule, settings::Store, watchdog::Watchdog};
+
+/// Resonant frequency of common piezo buzzers.
+#[cfg(feature = "buzzer")]
+pub const FREQUENCY_HZ: u32 = 2700;
+#[cfg(feature = "buzzer")]
+const TICK: Duration = Duration::from_millis(50);
+
+/// Parses a beep pattern of comma separated on/off durations in
+/// milliseconds, starting with on, e.g. `200,200,200,1000`.
+pub fn parse_pattern(spec: &str) -> error::Result<Vec<Duration>> {
+    let pattern = spec
+        .split(',')
+        .map(|ms| {
+            ms.trim()
+                .parse()
+                .map(Duration::from_millis)
+                .with_context(|| format!("parse duration {:?}", ms))
+        })
+        .collect::<error::Result<Vec<_>>>()?;
+
+    if pattern.len() % 2 != 0 {
+        bail!("buzzer pattern needs pairs of on and off durations");
+    }
+    if pattern.iter().all(Duration::is_zero) {
+        bail!("buzzer pattern must not be all zero");
+    }
+    Ok(pattern)
+}
+
+/// Beeps the `buzzer_pattern` while an alert is active, outside of quiet
+/// hours only for urgent alerts. Pressing the mute
+/// button silences the active alerts, and a newly raised alert re-arms it.
+#[cfg(feature = "buzzer")]
+pub fn run<P: gpio::InputPin>(
+    store: &Store,
+    alerts: &Alerts,
+    mute: PinDriver<'_, P, Input>,
+    mut buzzer: LedcDriver<'_>,
+) {
+    let watchdog = Watchdog::subscribe("buzzer");
+    let health = health::register("buzzer");
+    let mut muted: Option<Vec<String>> = None;
+    let mut pattern = Vec::new();
+    let mut step = 0;
+    let mut step_started = Instant::now();
+    let mut on = false;
+
+    loop {
+        health.tick();
+        let active = alerts.audible(schedule::is_quiet(&store.get()));
+        if let Some(silenced) = &muted {
+            if active.iter().any(|name| !silenced.contains(name)) {
+                log::info!("buzzer: new alert, re-armed");
+                muted = None;
+            } else if active.is_empty() {
+                muted = None;
+            }
+        }
+        if !active.is_empty() && muted.is_none() && mute.is_low() {
+            log::info!("buzzer: muted {:?}", active);
+            muted = Some(active.clone());
+        }
+
+        let sounding = !active.is_empty() && muted.is_none();
+        if !sounding {
+            step = 0;
+            pattern.clear();
+        } else if pattern.is_empty() || step_started.elapsed() >= pattern[step] {
+            step = if pattern.is_empty() {
+                0
+            } else {
+                (step + 1) % pattern.len()
+            };
+            if step == 0 {
+                // The store only accepts valid patterns.
+                pattern = parse_pattern(&store.get().buzzer_pattern).unwrap_or_default();
+            }
+            step_started = Instant::now();
+        }
+
+        let want_on = sounding && step % 2 == 0 && !pattern.is_empty();
+        if want_on != on {
+            on = want_on;
+            let duty = if on { buzzer.get_max_duty() / 2 } else { 0 };
+            if let Err(err) = buzzer.set_duty(duty) {
+                log::error!("buzzer: setting duty error={:?}", err);
+            }
+        }
+
+        watchdog.sleep(TICK);
+    }
+}
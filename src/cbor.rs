@@ -0,0 +1,94 @@
+//! CBOR (RFC 8949) encoding of readings, a third of the size of the same
+//! JSON for gateways behind cellular or LoRa links.
+//!
+//! A reading is a map of small integer keys to single precision floats:
+//!
+//! | Key | Value                |
+//! |-----|----------------------|
+//! | 1   | temperature in °C    |
+//! | 2   | humidity in %        |
+//!
+//! Decoders should skip keys they do not know, later keys may be added.
+
+use crate::reading::SensorData;
+
+pub const TEMPERATURE: u8 = 1;
+pub const HUMIDITY: u8 = 2;
+
+const UNSIGNED: u8 = 0;
+const MAP: u8 = 5;
+/// Major type 7 with additional information 26, a single precision float.
+const FLOAT32: u8 = 0xFA;
+
+/// Encodes a reading as the map above.
+pub fn reading(data: SensorData) -> Vec<u8> {
+    let mut out = Vec::with_capacity(13);
+    head(&mut out, MAP, 2);
+    head(&mut out, UNSIGNED, TEMPERATURE.into());
+    float(&mut out, data.temperature);
+    head(&mut out, UNSIGNED, HUMIDITY.into());
+    float(&mut out, data.humidity);
+    out
+}
+
+/// Writes the initial byte of a data item and its argument in as few bytes
+/// as it takes.
+fn head(out: &mut Vec<u8>, major: u8, value: u64) {
+    let major = major << 5;
+    match value {
+        0..=23 => out.push(major | value as u8),
+        24..=0xFF => out.extend_from_slice(&[major | 24, value as u8]),
+        0x100..=0xFFFF => {
+            out.push(major | 25);
+            out.extend_from_slice(&(value as u16).to_be_bytes());
+        }
+        0x1_0000..=0xFFFF_FFFF => {
+            out.push(major | 26);
+            out.extend_from_slice(&(value as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(major | 27);
+            out.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+}
+
+fn float(out: &mut Vec<u8>, value: f32) {
+    out.push(FLOAT32);
+    out.extend_from_slice(&value.to_bits().to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_a_reading() {
+        let data = SensorData {
+            temperature: 21.5,
+            humidity: 40.,
+        };
+        // {1: 21.5, 2: 40.0}
+        assert_eq!(
+            reading(data),
+            [0xA2, 0x01, 0xFA, 0x41, 0xAC, 0x00, 0x00, 0x02, 0xFA, 0x42, 0x20, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn encodes_heads_in_the_shortest_form() {
+        let encode = |value| {
+            let mut out = Vec::new();
+            head(&mut out, UNSIGNED, value);
+            out
+        };
+        assert_eq!(encode(23), [0x17]);
+        assert_eq!(encode(24), [0x18, 0x18]);
+        assert_eq!(encode(1000), [0x19, 0x03, 0xE8]);
+        assert_eq!(encode(1_000_000), [0x1A, 0x00, 0x0F, 0x42, 0x40]);
+        assert_eq!(
+            encode(1 << 32),
+            [0x1B, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00]
+        );
+    }
+}
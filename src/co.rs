@@ -0,0 +1,164 @@
+use std::sync::Mutex;
+#[cfg(feature = "co")]
+use std::time::{Duration, Instant};
+
+use crate::point::Point;
+#[cfg(feature = "co")]
+use crate::{
+    adc, alert::Alerts, broadcast, exposure, health, notify, settings::Store, watchdog::Watchdog,
+    SensorData,
+};
+
+#[cfg(feature = "co")]
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+/// A push message that failed, e.g. without Wi-Fi, is retried this often.
+#[cfg(feature = "co")]
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+/// Name of the alert raised by the exposure alarm.
+#[cfg(feature = "co")]
+const ALERT: &str = "co";
+
+/// Readings of the CO sensor since the previous point, shared with the
+/// sender.
+#[derive(Default)]
+pub struct Co {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Sum and count of the concentrations in ppm.
+    ppm_sum: f32,
+    samples: u32,
+    /// Highest concentration since the previous point.
+    peak: f32,
+    alarm: bool,
+}
+
+impl Co {
+    /// A `co` point with the average and `peak` concentration in `ppm` and
+    /// whether the `alarm` is sounding. `None` without the CO sensor.
+    pub fn point(&self, tags: &[(String, String)]) -> Option<Point> {
+        let mut state = self.state.lock().unwrap();
+        if state.samples == 0 {
+            return None;
+        }
+        let ppm = state.ppm_sum / state.samples as f32;
+        let peak = state.peak;
+        state.ppm_sum = 0.;
+        state.samples = 0;
+        state.peak = 0.;
+
+        Some(
+            Point::new("co")
+                .tags(tags)
+                .field("ppm", ppm)
+                .field("peak", peak)
+                .field("alarm", state.alarm),
+        )
+    }
+}
+
+/// Reads the electrochemical CO sensor every second and runs the exposure
+/// alarm of `co_levels` on it.
+///
+/// This is the safety path: the task runs above the others, raises the
+/// `co` alert for the buzzer and sends its push messages itself, right away
+/// and again every `co_repeat` seconds while the alarm sounds, so neither
+/// quiet hours nor a sender stuck retrying InfluxDB hold them back.
+#[cfg(feature = "co")]
+pub fn run(
+    store: &Store,
+    co: &Co,
+    alerts: &Alerts,
+    readings: &broadcast::Sender<SensorData>,
+    pin: i32,
+) {
+    let watchdog = Watchdog::subscribe("co");
+    let health = health::register("co");
+    let channel = match adc::Channel::new(pin) {
+        Ok(channel) => channel,
+        Err(err) => {
+            log::error!("co: init error={:?}", err);
+            return;
+        }
+    };
+
+    let mut spec = String::new();
+    let mut alarm = exposure::Alarm::new(Vec::new());
+    // When the next push message is due, `None` once the all clear went out.
+    let mut next_push: Option<Instant> = None;
+    let mut last = Instant::now();
+    loop {
+        health.tick();
+        watchdog.sleep(SAMPLE_INTERVAL);
+
+        let mv = match channel.read_mv() {
+            Ok(mv) => mv as f32,
+            Err(err) => {
+                log::error!("co: reading sensor error={:?}", err);
+                continue;
+            }
+        };
+        let settings = store.get();
+        if settings.co_levels != spec {
+            spec = settings.co_levels.clone();
+            // The store only accepts valid levels.
+            alarm = exposure::Alarm::new(exposure::parse_levels(&spec).unwrap_or_default());
+            log::info!("co: loaded levels {:?}", spec);
+        }
+
+        let ppm =
+            ((mv - settings.co_zero_mv as f32) * 1000. / settings.co_uv_per_ppm as f32).max(0.);
+        let elapsed = last.elapsed();
+        last = Instant::now();
+        let was_active = alarm.is_active();
+        let active = alarm.update(ppm, elapsed);
+        {
+            let mut state = co.state.lock().unwrap();
+            state.ppm_sum += ppm;
+            state.samples += 1;
+            state.peak = state.peak.max(ppm);
+            state.alarm = active;
+        }
+
+        if active != was_active {
+            if active {
+                log::warn!("co: alarm at {} ppm", ppm);
+            } else {
+                log::info!("co: alarm cleared at {} ppm", ppm);
+            }
+            if alerts.set_safety(ALERT, active, ppm) {
+                readings.wake();
+            }
+            next_push = Some(Instant::now());
+        }
+
+        if !next_push.is_some_and(|due| Instant::now() >= due) {
+            continue;
+        }
+        let message = if active {
+            format!(
+                "{}: carbon monoxide at {:.0} ppm, get everyone to fresh air",
+                settings.device_id(),
+                ppm
+            )
+        } else {
+            format!(
+                "{}: carbon monoxide cleared at {:.0} ppm",
+                settings.device_id(),
+                ppm
+            )
+        };
+        next_push = match notify::send(&settings, active, &message) {
+            Ok(()) if active => {
+                Some(Instant::now() + Duration::from_secs(u64::from(settings.co_repeat_secs)))
+            }
+            Ok(()) => None,
+            Err(err) => {
+                log::error!("co: sending {:?} error={:?}", message, err);
+                Some(Instant::now() + RETRY_INTERVAL)
+            }
+        };
+    }
+}
@@ -0,0 +1,275 @@
+//! CoAP messages (RFC 7252) with the Observe option (RFC 7641), as far as
+//! a server of a few resources needs them.
+
+use crate::error::{self, bail, format_err};
+pub const PORT: u16 = 5683;
+
+pub const OBSERVE: u16 = 6;
+pub const URI_PATH: u16 = 11;
+pub const CONTENT_FORMAT: u16 = 12;
+pub const ACCEPT: u16 = 17;
+
+/// `application/link-format`, `application/json` and `application/cbor`.
+pub const LINK_FORMAT: u32 = 40;
+pub const JSON: u32 = 50;
+pub const CBOR: u32 = 60;
+
+pub const EMPTY: u8 = 0;
+pub const GET: u8 = code(0, 1);
+pub const CONTENT: u8 = code(2, 5);
+pub const NOT_FOUND: u8 = code(4, 4);
+pub const METHOD_NOT_ALLOWED: u8 = code(4, 5);
+pub const NOT_ACCEPTABLE: u8 = code(4, 6);
+pub const SERVICE_UNAVAILABLE: u8 = code(5, 3);
+
+/// Code of a class and detail, 2.05 is `code(2, 5)`.
+pub const fn code(class: u8, detail: u8) -> u8 {
+    class << 5 | detail
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Confirmable = 0,
+    NonConfirmable = 1,
+    Acknowledgement = 2,
+    Reset = 3,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub kind: Kind,
+    pub code: u8,
+    pub id: u16,
+    pub token: Vec<u8>,
+    /// Option numbers and values, in the order they were added.
+    pub options: Vec<(u16, Vec<u8>)>,
+    pub payload: Vec<u8>,
+}
+
+impl Message {
+    pub fn new(kind: Kind, code: u8, id: u16) -> Message {
+        Message {
+            kind,
+            code,
+            id,
+            token: Vec::new(),
+            options: Vec::new(),
+            payload: Vec::new(),
+        }
+    }
+
+    pub fn token(mut self, token: &[u8]) -> Self {
+        self.token = token.to_vec();
+        self
+    }
+
+    /// Adds an unsigned integer option, in as few bytes as it takes.
+    pub fn uint_option(mut self, number: u16, value: u32) -> Self {
+        let bytes = value.to_be_bytes();
+        let skip = bytes.iter().take_while(|&&b| b == 0).count();
+        self.options.push((number, bytes[skip..].to_vec()));
+        self
+    }
+
+    pub fn payload(mut self, payload: impl Into<Vec<u8>>) -> Self {
+        self.payload = payload.into();
+        self
+    }
+
+    pub fn option(&self, number: u16) -> Option<&[u8]> {
+        self.options
+            .iter()
+            .find(|(n, _)| *n == number)
+            .map(|(_, value)| value.as_slice())
+    }
+
+    /// Value of an unsigned integer option, `None` without it or if it is
+    /// longer than 4 bytes.
+    pub fn uint(&self, number: u16) -> Option<u32> {
+        let value = self.option(number)?;
+        (value.len() <= 4).then(|| value.iter().fold(0, |n, &b| n << 8 | u32::from(b)))
+    }
+
+    /// The Uri-Path options joined with `/`, without a leading one.
+    pub fn path(&self) -> String {
+        let segments: Vec<_> = self
+            .options
+            .iter()
+            .filter(|(n, _)| *n == URI_PATH)
+            .map(|(_, value)| String::from_utf8_lossy(value))
+            .collect();
+        segments.join("/")
+    }
+
+    pub fn parse(bytes: &[u8]) -> error::Result<Message> {
+        let [first, code, id_high, id_low, rest @ ..] = bytes else {
+            bail!("coap message of {} bytes is too short", bytes.len());
+        };
+        if first >> 6 != 1 {
+            bail!("coap version {} is not 1", first >> 6);
+        }
+        let kind = match first >> 4 & 0b11 {
+            0 => Kind::Confirmable,
+            1 => Kind::NonConfirmable,
+            2 => Kind::Acknowledgement,
+            _ => Kind::Reset,
+        };
+        let token_len = usize::from(first & 0x0F);
+        if token_len > 8 || rest.len() < token_len {
+            bail!("coap token length {} is invalid", token_len);
+        }
+        let (token, mut rest) = rest.split_at(token_len);
+
+        let mut options = Vec::new();
+        let mut number = 0u16;
+        while let Some((&byte, tail)) = rest.split_first() {
+            if byte == 0xFF {
+                if tail.is_empty() {
+                    bail!("coap payload marker without payload");
+                }
+                break;
+            }
+            rest = tail;
+            let delta = extended(byte >> 4, &mut rest)?;
+            let len = usize::from(extended(byte & 0x0F, &mut rest)?);
+            if rest.len() < len {
+                bail!("coap option {} is cut off", number.saturating_add(delta));
+            }
+            number = number
+                .checked_add(delta)
+                .ok_or_else(|| format_err!("coap option number is too large"))?;
+            options.push((number, rest[..len].to_vec()));
+            rest = &rest[len..];
+        }
+        let payload = rest.get(1..).unwrap_or_default().to_vec();
+
+        Ok(Message {
+            kind,
+            code: *code,
+            id: u16::from_be_bytes([*id_high, *id_low]),
+            token: token.to_vec(),
+            options,
+            payload,
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![
+            1 << 6 | (self.kind as u8) << 4 | self.token.len() as u8,
+            self.code,
+        ];
+        out.extend_from_slice(&self.id.to_be_bytes());
+        out.extend_from_slice(&self.token);
+
+        let mut options: Vec<_> = self.options.iter().collect();
+        // Stable, so repeated options keep their order.
+        options.sort_by_key(|(number, _)| *number);
+        let mut previous = 0;
+        for (number, value) in options {
+            let (delta, delta_ext) = nibble(number - previous);
+            let (len, len_ext) = nibble(value.len() as u16);
+            out.push(delta << 4 | len);
+            out.extend_from_slice(&delta_ext);
+            out.extend_from_slice(&len_ext);
+            out.extend_from_slice(value);
+            previous = *number;
+        }
+        if !self.payload.is_empty() {
+            out.push(0xFF);
+            out.extend_from_slice(&self.payload);
+        }
+        out
+    }
+}
+
+/// Option delta or length of a nibble, followed by 1 or 2 extended bytes
+/// for 13 and 14.
+fn extended(nibble: u8, rest: &mut &[u8]) -> error::Result<u16> {
+    let (value, len) = match nibble {
+        0..=12 => return Ok(u16::from(nibble)),
+        13 => (rest.first().map(|&b| u16::from(b) + 13), 1),
+        14 => (
+            rest.get(..2)
+                .and_then(|b| u16::from_be_bytes([b[0], b[1]]).checked_add(269)),
+            2,
+        ),
+        _ => bail!("coap option nibble 15 is reserved"),
+    };
+    let Some(value) = value else {
+        bail!("coap option is cut off");
+    };
+    *rest = &rest[len..];
+    Ok(value)
+}
+
+/// Nibble and extended bytes of an option delta or length.
+fn nibble(value: u16) -> (u8, Vec<u8>) {
+    match value {
+        0..=12 => (value as u8, Vec::new()),
+        13..=268 => (13, vec![(value - 13) as u8]),
+        _ => (14, (value - 269).to_be_bytes().to_vec()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_an_observe_request() {
+        // CON GET /readings with Observe 0 and a 2 byte token.
+        let mut bytes = vec![0x42, 0x01, 0x12, 0x34, 0xAB, 0xCD, 0x60, 0x58];
+        bytes.extend_from_slice(b"readings");
+        let message = Message::parse(&bytes).unwrap();
+
+        assert_eq!(message.kind, Kind::Confirmable);
+        assert_eq!(message.code, GET);
+        assert_eq!(message.id, 0x1234);
+        assert_eq!(message.token, [0xAB, 0xCD]);
+        assert_eq!(message.uint(OBSERVE), Some(0));
+        assert_eq!(message.path(), "readings");
+        assert!(message.payload.is_empty());
+    }
+
+    #[test]
+    fn round_trips_long_options_and_payload() {
+        let message = Message::new(Kind::NonConfirmable, CONTENT, 7)
+            .token(&[1, 2, 3])
+            .uint_option(CONTENT_FORMAT, JSON)
+            .uint_option(OBSERVE, 70000)
+            .uint_option(300, 1)
+            .payload(vec![b'x'; 300]);
+        let bytes = message.encode();
+        let parsed = Message::parse(&bytes).unwrap();
+
+        assert_eq!(parsed.uint(OBSERVE), Some(70000));
+        assert_eq!(parsed.uint(CONTENT_FORMAT), Some(JSON));
+        assert_eq!(parsed.uint(300), Some(1));
+        // Options come back sorted by number.
+        assert_eq!(parsed.options[0].0, OBSERVE);
+        assert_eq!(parsed.payload, message.payload);
+    }
+
+    #[test]
+    fn encodes_an_empty_ack() {
+        let ack = Message::new(Kind::Acknowledgement, EMPTY, 0x1234);
+        assert_eq!(ack.encode(), [0x60, 0x00, 0x12, 0x34]);
+        assert_eq!(
+            Message::new(Kind::Reset, EMPTY, 1).encode(),
+            [0x70, 0, 0, 1]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_messages() {
+        assert!(Message::parse(&[0x40, 0x01, 0x00]).is_err());
+        // Version 2.
+        assert!(Message::parse(&[0x80, 0x01, 0x00, 0x01]).is_err());
+        // Token length 9.
+        assert!(Message::parse(&[0x49, 0x01, 0x00, 0x01]).is_err());
+        // Payload marker without payload.
+        assert!(Message::parse(&[0x40, 0x01, 0x00, 0x01, 0xFF]).is_err());
+        // Option longer than the message.
+        assert!(Message::parse(&[0x40, 0x01, 0x00, 0x01, 0xB8, b'r']).is_err());
+    }
+}
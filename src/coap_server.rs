@@ -0,0 +1,217 @@
+use std::{
+    io::ErrorKind,
+    net::{SocketAddr, UdpSocket},
+    time::Duration,
+};
+
+use crate::{
+    broadcast, cbor,
+    coap::{self, Kind, Message},
+    health,
+    watchdog::Watchdog,
+    SensorData,
+};
+
+/// How long a receive waits before new readings are checked.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+const MAX_OBSERVERS: usize = 8;
+/// Every this many notifications is confirmable, so gone observers are
+/// noticed.
+const CONFIRM_EVERY: u32 = 8;
+/// Largest request, CoAP over UDP should stay below 1152 bytes.
+const MAX_MESSAGE: usize = 1152;
+const RESOURCE: &str = "readings";
+
+/// A client observing the readings.
+struct Observer {
+    addr: SocketAddr,
+    token: Vec<u8>,
+    /// Message id of the last confirmable notification while it is not
+    /// acknowledged.
+    unacked: Option<u16>,
+    /// Id of the last notification, a reset to it cancels the observation.
+    last_id: u16,
+    /// Content format the observer accepts.
+    format: u32,
+}
+
+/// Serves the latest reading as `/readings` over CoAP on UDP port 5683 and
+/// notifies up to 8 observers of every new one.
+pub fn run(mut sub: broadcast::Receiver<SensorData>) {
+    let watchdog = Watchdog::subscribe("coap");
+    let health = health::register("coap");
+    let socket = match UdpSocket::bind(("0.0.0.0", coap::PORT)) {
+        Ok(socket) => socket,
+        Err(err) => {
+            log::error!("coap: binding port {} error={:?}", coap::PORT, err);
+            return;
+        }
+    };
+    if let Err(err) = socket.set_read_timeout(Some(POLL_INTERVAL)) {
+        log::error!("coap: setting timeout error={:?}", err);
+        return;
+    }
+    log::info!("coap: listening on port {}", coap::PORT);
+
+    let mut latest = None;
+    let mut observers: Vec<Observer> = Vec::new();
+    // Observe sequence number, 24 bits on the wire.
+    let mut sequence = 0u32;
+    let mut next_id = 0u16;
+    let mut buf = [0u8; MAX_MESSAGE];
+    loop {
+        health.tick();
+        watchdog.feed();
+
+        let mut changed = false;
+        while let Ok(data) = sub.try_recv() {
+            latest = Some(data);
+            changed = true;
+        }
+        if let (true, Some(data)) = (changed, latest) {
+            sequence = sequence.wrapping_add(1) & 0xFF_FFFF;
+            let confirm = sequence % CONFIRM_EVERY == 0;
+            observers.retain_mut(|observer| {
+                if confirm && observer.unacked.is_some() {
+                    log::info!("coap: {} stopped acknowledging", observer.addr);
+                    return false;
+                }
+                next_id = next_id.wrapping_add(1);
+                let kind = if confirm {
+                    observer.unacked = Some(next_id);
+                    Kind::Confirmable
+                } else {
+                    Kind::NonConfirmable
+                };
+                observer.last_id = next_id;
+                let message = Message::new(kind, coap::CONTENT, next_id)
+                    .token(&observer.token)
+                    .uint_option(coap::OBSERVE, sequence);
+                send(
+                    &socket,
+                    observer.addr,
+                    reading(message, data, observer.format),
+                );
+                true
+            });
+        }
+
+        let (len, addr) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                continue;
+            }
+            Err(err) => {
+                log::error!("coap: receiving error={:?}", err);
+                watchdog.sleep(POLL_INTERVAL);
+                continue;
+            }
+        };
+        let request = match Message::parse(&buf[..len]) {
+            Ok(request) => request,
+            Err(err) => {
+                log::debug!("coap: ignoring message from {} error={:#}", addr, err);
+                continue;
+            }
+        };
+
+        match (request.kind, request.code) {
+            (Kind::Acknowledgement, _) => {
+                for observer in observers.iter_mut().filter(|o| o.addr == addr) {
+                    if observer.unacked == Some(request.id) {
+                        observer.unacked = None;
+                    }
+                }
+                continue;
+            }
+            (Kind::Reset, _) => {
+                observers.retain(|o| !(o.addr == addr && o.last_id == request.id));
+                continue;
+            }
+            // An empty confirmable message is a ping.
+            (Kind::Confirmable, coap::EMPTY) => {
+                send(
+                    &socket,
+                    addr,
+                    Message::new(Kind::Reset, coap::EMPTY, request.id),
+                );
+                continue;
+            }
+            _ => {}
+        }
+
+        // Piggybacked on the acknowledgement of a confirmable request.
+        let (kind, id) = if request.kind == Kind::Confirmable {
+            (Kind::Acknowledgement, request.id)
+        } else {
+            next_id = next_id.wrapping_add(1);
+            (Kind::NonConfirmable, next_id)
+        };
+        let response = |code| Message::new(kind, code, id).token(&request.token);
+        let path = request.path();
+        let message = match (path.as_str(), request.code) {
+            (".well-known/core", coap::GET) => response(coap::CONTENT)
+                .uint_option(coap::CONTENT_FORMAT, coap::LINK_FORMAT)
+                .payload(format!(
+                    "</{}>;rt=\"sensor\";obs;ct=\"{} {}\"",
+                    RESOURCE,
+                    coap::JSON,
+                    coap::CBOR
+                )),
+            (RESOURCE, coap::GET) => match (latest, request.uint(coap::ACCEPT)) {
+                (_, Some(format)) if format != coap::JSON && format != coap::CBOR => {
+                    response(coap::NOT_ACCEPTABLE)
+                }
+                (Some(data), format) => {
+                    let format = format.unwrap_or(coap::JSON);
+                    // Observe 0 registers, 1 deregisters.
+                    observers.retain(|o| !(o.addr == addr && o.token == request.token));
+                    let mut message = response(coap::CONTENT);
+                    if request.uint(coap::OBSERVE) == Some(0) {
+                        if observers.len() < MAX_OBSERVERS {
+                            log::info!("coap: {} observes /{}", addr, RESOURCE);
+                            observers.push(Observer {
+                                addr,
+                                token: request.token.clone(),
+                                unacked: None,
+                                last_id: id,
+                                format,
+                            });
+                            message = message.uint_option(coap::OBSERVE, sequence);
+                        } else {
+                            log::warn!("coap: too many observers, not adding {}", addr);
+                        }
+                    }
+                    reading(message, data, format)
+                }
+                // An error response does not start an observation.
+                (None, _) => response(coap::SERVICE_UNAVAILABLE),
+            },
+            (".well-known/core" | RESOURCE, _) => response(coap::METHOD_NOT_ALLOWED),
+            _ => response(coap::NOT_FOUND),
+        };
+        send(&socket, addr, message);
+    }
+}
+
+/// `message` with the reading as its JSON or CBOR payload.
+fn reading(message: Message, data: SensorData, format: u32) -> Message {
+    let payload = if format == coap::CBOR {
+        cbor::reading(data)
+    } else {
+        let json = serde_json::json!({
+            "temperature": data.temperature,
+            "humidity": data.humidity,
+        });
+        json.to_string().into_bytes()
+    };
+    message
+        .uint_option(coap::CONTENT_FORMAT, format)
+        .payload(payload)
+}
+
+fn send(socket: &UdpSocket, addr: SocketAddr, message: Message) {
+    if let Err(err) = socket.send_to(&message.encode(), addr) {
+        log::warn!("coap: sending to {} error={:?}", addr, err);
+    }
+}
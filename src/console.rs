@@ -0,0 +1,218 @@
+use std::{
+    io::{self, Read},
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    device,
+    error::{self, bail, Context},
+    fault, health, logging,
+    relay::Relay,
+    settings::{Key, Store},
+    state::State,
+    trace::Trace,
+    SensorData,
+};
+
+const MAX_LINE_LEN: usize = 256;
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// An hour of readings replays in a minute.
+const DEFAULT_REPLAY_SPEED: u32 = 60;
+
+const HELP: &str = "commands:
+  help                     show this message
+  status                   show settings and latest reading
+  get <key>                show a setting
+  set <key> <value>        change and persist a setting
+  reset <key>              restore the compiled default of a setting
+  log <target> <level>     change and persist the log level of a target
+  relay [on|off|auto]      show or override the relay
+  wifi join <ssid> <pass>  change wi-fi credentials
+  profile                  list profiles
+  profile use <name>       activate (or create) a wi-fi and sink profile
+  send now                 read the sensor and send data immediately
+  trace                    show the trace recorder
+  trace record             record raw readings to flash, replacing the last trace
+  trace replay [speed]     replay the trace in place of the sensor, default 60x
+  trace stop               stop recording or replaying
+  reboot                   restart the device";
+
+const FAULT_HELP: &str = "debug build commands:
+  fault                    show injected faults
+  fault http <n>           fail every nth http request, 0 turns it off
+  fault sensor <n>         corrupt every nth sensor read
+  fault dns <ms>           delay opening connections by ms
+  fault clear              turn all faults off";
+
+/// Interactive console on the serial port (stdin/stdout).
+pub fn run(state: &State, store: &Store, relay: &Relay, trace: &Trace, wake: mpsc::Sender<()>) {
+    let mut line = String::new();
+    let mut stdin = io::stdin();
+    let mut byte = [0u8; 1];
+    let health = health::register("console");
+
+    println!("console ready, type `help` for commands");
+    loop {
+        health.tick();
+
+        // ESP-IDF stdin is non-blocking, so poll it.
+        match stdin.read(&mut byte) {
+            Ok(1) => {}
+            Ok(_) => {
+                thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+            Err(err) => {
+                log::error!("console: reading stdin error={:?}", err);
+                thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+        }
+
+        match byte[0] {
+            b'\r' | b'\n' => {
+                let command = line.trim();
+                if !command.is_empty() {
+                    let latest = state.latest().map(|s| s.data);
+                    if let Err(err) = execute(command, store, relay, trace, &wake, latest) {
+                        println!("error: {:#}", err);
+                    }
+                }
+                line.clear();
+            }
+            b if line.len() < MAX_LINE_LEN => line.push(char::from(b)),
+            _ => {
+                println!("error: line is too long");
+                line.clear();
+            }
+        }
+    }
+}
+
+fn execute(
+    command: &str,
+    store: &Store,
+    relay: &Relay,
+    trace: &Trace,
+    wake: &mpsc::Sender<()>,
+    latest: Option<SensorData>,
+) -> error::Result<()> {
+    let args: Vec<&str> = command.split_whitespace().collect();
+    match args.as_slice() {
+        ["help"] => {
+            println!("{}", HELP);
+            if cfg!(debug_assertions) {
+                println!("{}", FAULT_HELP);
+            }
+        }
+        ["status"] => status(store, latest),
+        ["get", key] => {
+            let key: Key = key.parse()?;
+            println!("{}={}", key, display_value(store, key));
+        }
+        ["set", key, value @ ..] if !value.is_empty() => {
+            store.set(key.parse()?, &value.join(" "))?;
+            println!("ok");
+        }
+        ["reset", key] => {
+            store.reset(key.parse()?)?;
+            println!("ok");
+        }
+        ["log", target, level] => {
+            let level = level.parse().context("parse log level")?;
+            let spec = logging::with_level(&store.get().log_levels, target, level)?;
+            store.set(Key::LogLevels, &spec)?;
+            println!("ok, log_levels={}", spec);
+        }
+        ["relay"] => println!("relay on={} mode={}", relay.is_on(), relay.mode().name()),
+        ["relay", mode] => {
+            relay.set_mode(mode.parse()?);
+            println!("ok");
+        }
+        ["wifi", "join", ssid, pass] => {
+            store.set(Key::Ssid, ssid)?;
+            store.set(Key::Password, pass)?;
+            println!("ok, reconnecting after the next reading");
+        }
+        ["profile"] => {
+            let active = store.profile();
+            for profile in store.profiles()? {
+                let marker = if profile == active { "*" } else { " " };
+                println!("{} {}", marker, profile);
+            }
+        }
+        ["profile", "use", name] => {
+            store.use_profile(name)?;
+            println!("ok, reconnecting after the next reading");
+        }
+        ["send", "now"] => {
+            wake.send(()).context("sensor task is not running")?;
+            println!("ok");
+        }
+        ["trace"] => println!(
+            "trace mode={:?} readings={}",
+            trace.mode(),
+            trace.recorded()?
+        ),
+        ["trace", "record"] => {
+            trace.start_recording()?;
+            println!("ok");
+        }
+        ["trace", "replay", speed @ ..] if speed.len() <= 1 => {
+            let speed = match speed.first() {
+                Some(speed) => speed.parse().context("parse speed")?,
+                None => DEFAULT_REPLAY_SPEED,
+            };
+            let len = trace.start_replay(speed)?;
+            // Start right away rather than after the current interval.
+            wake.send(()).context("sensor task is not running")?;
+            println!("ok, replaying {} readings", len);
+        }
+        ["trace", "stop"] => {
+            let replaying = trace.replaying();
+            trace.stop();
+            if replaying {
+                wake.send(()).context("sensor task is not running")?;
+            }
+            println!("ok");
+        }
+        ["fault", args @ ..] => println!("{}", fault::command(args)?),
+        ["reboot"] => {
+            println!("rebooting...");
+            thread::sleep(Duration::from_millis(100));
+            unsafe { esp_idf_sys::esp_restart() };
+        }
+        _ => bail!("unknown command {:?}, type `help` for commands", command),
+    }
+
+    Ok(())
+}
+
+fn status(store: &Store, latest: Option<SensorData>) {
+    let uptime_secs = device::uptime().as_secs();
+    let free_heap = unsafe { esp_idf_sys::esp_get_free_heap_size() };
+
+    println!("uptime={}s free_heap={}B", uptime_secs, free_heap);
+    println!("profile={}", store.profile());
+    match latest {
+        Some(data) => println!("latest {}", data),
+        None => println!("latest reading: none"),
+    }
+    for key in Key::ALL {
+        println!("{}={}", key, display_value(store, key));
+    }
+}
+
+fn display_value(store: &Store, key: Key) -> String {
+    if key.is_secret() {
+        "<hidden>".into()
+    } else {
+        store.value(key)
+    }
+}
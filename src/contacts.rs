@@ -0,0 +1,211 @@
+use std::sync::Mutex;
+#[cfg(feature = "contacts")]
+use std::{
+    ffi::c_void,
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use crate::error::{self, bail, Context};
+use crate::point::Point;
+#[cfg(feature = "contacts")]
+use crate::{broadcast, health, schedule, watchdog::Watchdog, SensorData};
+
+/// More contacts than this are rejected.
+const MAX_CONTACTS: usize = 8;
+/// Changes not yet picked up by the sender are dropped beyond this.
+#[cfg(feature = "contacts")]
+const MAX_PENDING_CHANGES: usize = 32;
+/// Contact bounce settles within this after an edge.
+#[cfg(feature = "contacts")]
+const DEBOUNCE: Duration = Duration::from_millis(30);
+/// The contacts are also sampled this often, in case an edge was missed.
+#[cfg(feature = "contacts")]
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Task notified by the edge interrupt, as an address.
+#[cfg(feature = "contacts")]
+static TASK: AtomicUsize = AtomicUsize::new(0);
+
+/// Door or window reed switch, written as `name:gpio`, e.g. `front_door:6`.
+/// The switch connects the pin to ground while the door is closed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Contact {
+    pub name: String,
+    pub pin: i32,
+}
+
+/// Parses comma separated contacts, empty means none.
+pub fn parse_contacts(spec: &str) -> error::Result<Vec<Contact>> {
+    let mut contacts: Vec<Contact> = Vec::new();
+    for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let Some((name, pin)) = entry.split_once(':') else {
+            bail!("contact {:?} is not name:gpio", entry);
+        };
+        let name = name.trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            bail!("contact name {:?} must be letters, digits and _", name);
+        }
+        let pin: i32 = pin
+            .trim()
+            .parse()
+            .with_context(|| format!("parse gpio of contact {:?}", name))?;
+        if !(0..esp_idf_sys::gpio_num_t_GPIO_NUM_MAX as i32).contains(&pin) {
+            bail!("gpio{} does not exist on this chip", pin);
+        }
+        if contacts.iter().any(|c| c.name == name) {
+            bail!("contact {:?} is defined more than once", name);
+        }
+        contacts.push(Contact {
+            name: name.into(),
+            pin,
+        });
+    }
+    if contacts.len() > MAX_CONTACTS {
+        bail!("at most {} contacts are supported", MAX_CONTACTS);
+    }
+    Ok(contacts)
+}
+
+/// A contact opened or closed.
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub name: String,
+    pub open: bool,
+    /// Unix time in nanoseconds, when the clock was set.
+    timestamp: Option<i64>,
+}
+
+impl Change {
+    pub fn point(&self, tags: &[(String, String)]) -> Point {
+        let point = Point::new("contact")
+            .tag("contact", &self.name)
+            .tags(tags)
+            .field("open", self.open);
+        match self.timestamp {
+            Some(ns) => point.timestamp(ns),
+            None => point,
+        }
+    }
+}
+
+/// Contact states shared with the sender.
+#[derive(Default)]
+pub struct Contacts {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    open: Vec<(String, bool)>,
+    changes: Vec<Change>,
+}
+
+impl Contacts {
+    /// Current state of every contact, `true` while open.
+    pub fn states(&self) -> Vec<(String, bool)> {
+        self.state.lock().unwrap().open.clone()
+    }
+
+    pub fn take_changes(&self) -> Vec<Change> {
+        std::mem::take(&mut self.state.lock().unwrap().changes)
+    }
+
+    /// Records the state of a contact, returns `true` if it changed. The
+    /// first state of a contact is not a change.
+    #[cfg(feature = "contacts")]
+    fn update(&self, name: &str, open: bool) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match state.open.iter_mut().find(|(n, _)| n == name) {
+            Some((_, current)) if *current == open => return false,
+            Some((_, current)) => *current = open,
+            None => {
+                state.open.push((name.into(), open));
+                return false;
+            }
+        }
+
+        if state.changes.len() == MAX_PENDING_CHANGES {
+            state.changes.remove(0);
+        }
+        state.changes.push(Change {
+            name: name.into(),
+            open,
+            timestamp: schedule::unix_time().map(|now| now.as_nanos() as i64),
+        });
+        true
+    }
+}
+
+#[cfg(feature = "contacts")]
+unsafe extern "C" fn on_edge(_: *mut c_void) {
+    let task = TASK.load(Ordering::Relaxed);
+    if task != 0 {
+        esp_idf_sys::vTaskGenericNotifyGiveFromISR(task as _, 0, ptr::null_mut());
+    }
+}
+
+/// Configures the pin as an input with pull-up and interrupts on both edges.
+#[cfg(feature = "contacts")]
+fn watch(pin: i32) -> error::Result<()> {
+    use esp_idf_sys::*;
+
+    unsafe {
+        esp!(gpio_reset_pin(pin))?;
+        esp!(gpio_set_direction(pin, gpio_mode_t_GPIO_MODE_INPUT))?;
+        esp!(gpio_set_pull_mode(pin, gpio_pull_mode_t_GPIO_PULLUP_ONLY))?;
+        esp!(gpio_set_intr_type(pin, gpio_int_type_t_GPIO_INTR_ANYEDGE))?;
+        // Already installed is fine.
+        let err = gpio_install_isr_service(0);
+        if err != ESP_ERR_INVALID_STATE {
+            esp!(err)?;
+        }
+        esp!(gpio_isr_handler_add(pin, Some(on_edge), ptr::null_mut()))?;
+        esp!(gpio_intr_enable(pin))?;
+    }
+    Ok(())
+}
+
+/// Waits for edges on the contacts and, once the state settled, records
+/// changes and wakes the sender so they are sent right away.
+#[cfg(feature = "contacts")]
+pub fn run(contacts: &Contacts, list: &[Contact], readings: &broadcast::Sender<SensorData>) {
+    let watchdog = Watchdog::subscribe("contacts");
+    let health = health::register("contacts");
+    TASK.store(
+        unsafe { esp_idf_sys::xTaskGetCurrentTaskHandle() } as usize,
+        Ordering::Relaxed,
+    );
+    for contact in list {
+        if let Err(err) = watch(contact.pin) {
+            log::error!("contacts: watching {} error={:?}", contact.name, err);
+        }
+    }
+
+    let wait_ticks = SAMPLE_INTERVAL.as_millis() as u32 * esp_idf_sys::configTICK_RATE_HZ / 1000;
+    loop {
+        health.tick();
+        watchdog.feed();
+        let edges = unsafe { esp_idf_sys::ulTaskGenericNotifyTake(0, 1, wait_ticks) };
+        if edges > 0 {
+            watchdog.sleep(DEBOUNCE);
+        }
+
+        let mut changed = false;
+        for contact in list {
+            let open = unsafe { esp_idf_sys::gpio_get_level(contact.pin) } != 0;
+            if contacts.update(&contact.name, open) {
+                log::info!(
+                    "contacts: {} {}",
+                    contact.name,
+                    if open { "opened" } else { "closed" }
+                );
+                changed = true;
+            }
+        }
+        if changed {
+            readings.wake();
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use std::time::Duration;
+
+use embedded_svc::{
+    http::{client::Client, Method},
+    io::Write,
+};
+use esp_idf_svc::http::client::{Configuration as HttpConfiguration, EspHttpConnection};
+use esp_idf_sys::esp;
+
+use crate::error::{self, bail, Context};
+
+const CHUNK_LEN: usize = 1024;
+
+/// Location of a core dump stored in the coredump flash partition.
+struct Image {
+    addr: usize,
+    size: usize,
+}
+
+fn stored_image() -> Option<Image> {
+    let mut addr = 0;
+    let mut size = 0;
+    let err = unsafe { esp_idf_sys::esp_core_dump_image_get(&mut addr, &mut size) };
+    if err != esp_idf_sys::ESP_OK {
+        return None;
+    }
+
+    if unsafe { esp_idf_sys::esp_core_dump_image_check() } != esp_idf_sys::ESP_OK {
+        log::error!("coredump: stored image is corrupted, erasing it");
+        erase();
+        return None;
+    }
+
+    Some(Image { addr, size })
+}
+
+fn erase() {
+    let err = unsafe { esp_idf_sys::esp_core_dump_image_erase() };
+    if err != esp_idf_sys::ESP_OK {
+        log::error!("coredump: erasing image error={}", err);
+    }
+}
+
+/// Uploads a stored core dump to `url` and erases it afterwards. Does nothing
+/// if there is no core dump.
+pub fn upload_if_present(url: &str, device_id: &str) -> error::Result<()> {
+    let Some(image) = stored_image() else {
+        return Ok(());
+    };
+    log::warn!(
+        "coredump: found image size={} at addr={:#x}, uploading",
+        image.size,
+        image.addr
+    );
+
+    let connection = EspHttpConnection::new(&HttpConfiguration {
+        timeout: Some(Duration::from_secs(60)),
+        ..Default::default()
+    })
+    .context("create esp http connection")?;
+    let mut client = Client::wrap(connection);
+
+    let content_length = image.size.to_string();
+    let headers = [
+        ("content-type", "application/octet-stream"),
+        ("content-length", content_length.as_str()),
+        ("x-device-id", device_id),
+        ("x-firmware-version", env!("CARGO_PKG_VERSION")),
+    ];
+    let mut request = client
+        .request(Method::Post, url, &headers)
+        .context("create post request")?;
+
+    let mut buf = [0u8; CHUNK_LEN];
+    let mut offset = 0;
+    while offset < image.size {
+        let len = CHUNK_LEN.min(image.size - offset);
+        esp!(unsafe {
+            esp_idf_sys::esp_flash_read(
+                std::ptr::null_mut(),
+                buf.as_mut_ptr().cast(),
+                (image.addr + offset) as u32,
+                len as u32,
+            )
+        })
+        .context("read core dump from flash")?;
+        request.write_all(&buf[..len])?;
+        offset += len;
+    }
+    request.flush()?;
+
+    let response = request.submit().context("do post request")?;
+    let status = response.status();
+    if !(200..300).contains(&status) {
+        bail!("upload rejected with http status code={}", status);
+    }
+
+    log::info!("coredump: uploaded, erasing image");
+    erase();
+    Ok(())
+}
@@ -0,0 +1,175 @@
+use std::{
+    any::Any,
+    fmt::Write as _,
+    mem::MaybeUninit,
+    panic::{self, Location},
+    ptr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+
+use crate::error::{self, Context};
+
+const NAMESPACE: &str = "crash";
+const MESSAGE_KEY: &str = "message";
+const REPORTED_KEY: &str = "reported";
+
+const MAGIC: u32 = 0x5041_4e43; // "PANC"
+const RECORD_LEN: usize = 512;
+
+/// Panic report kept in RTC memory, which survives the software reset that
+/// follows a panic but not a power cycle.
+#[repr(C)]
+struct Record {
+    magic: u32,
+    len: u32,
+    data: [u8; RECORD_LEN],
+}
+
+#[link_section = ".rtc_noinit"]
+static mut RECORD: MaybeUninit<Record> = MaybeUninit::uninit();
+
+/// Records panics into RTC memory before the default hook aborts.
+pub fn install_panic_hook() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        write_record(&describe(info.location(), info.payload()));
+        default_hook(info);
+    }));
+}
+
+fn describe(location: Option<&Location<'_>>, payload: &(dyn Any + Send)) -> String {
+    let mut report = String::new();
+    let thread = thread::current();
+    let _ = write!(
+        report,
+        "thread '{}' panicked",
+        thread.name().unwrap_or("<unnamed>")
+    );
+    if let Some(location) = location {
+        let _ = write!(
+            report,
+            " at {}:{}:{}",
+            location.file(),
+            location.line(),
+            location.column()
+        );
+    }
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        let _ = write!(report, ": {}", message);
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        let _ = write!(report, ": {}", message);
+    }
+
+    let backtrace = std::backtrace::Backtrace::force_capture();
+    if backtrace.status() == std::backtrace::BacktraceStatus::Captured {
+        let _ = write!(report, "\n{}", backtrace);
+    }
+
+    report
+}
+
+/// The longest prefix of `report` that fits a record, cut on a char
+/// boundary so it reads back as the same string.
+fn fit(report: &str) -> &str {
+    let mut len = report.len().min(RECORD_LEN);
+    while !report.is_char_boundary(len) {
+        len -= 1;
+    }
+    &report[..len]
+}
+
+fn write_record(report: &str) {
+    let bytes = fit(report).as_bytes();
+    let len = bytes.len();
+
+    // SAFETY: only the panicking thread writes the record, right before the
+    // device resets, and it is read once at boot before any thread starts.
+    unsafe {
+        let record = ptr::addr_of_mut!(RECORD).cast::<Record>();
+        ptr::addr_of_mut!((*record).data)
+            .cast::<u8>()
+            .copy_from_nonoverlapping(bytes.as_ptr(), len);
+        ptr::addr_of_mut!((*record).len).write(len as u32);
+        ptr::addr_of_mut!((*record).magic).write(MAGIC);
+    }
+}
+
+fn take_record() -> Option<String> {
+    // SAFETY: called once at boot before any other thread is started.
+    unsafe {
+        let record = ptr::addr_of_mut!(RECORD).cast::<Record>();
+        if ptr::addr_of!((*record).magic).read() != MAGIC {
+            return None;
+        }
+        ptr::addr_of_mut!((*record).magic).write(0);
+
+        let len = (ptr::addr_of!((*record).len).read() as usize).min(RECORD_LEN);
+        let data = &*ptr::addr_of!((*record).data);
+        // Garbage after a power glitch decodes longer than it was.
+        let report = String::from_utf8_lossy(&data[..len]);
+        Some(fit(&report).to_owned())
+    }
+}
+
+/// The last panic report. It is moved from RTC memory into NVS at boot so it
+/// survives power loss until it has been published.
+pub struct CrashLog {
+    nvs: Mutex<EspNvs<NvsDefault>>,
+    last: Option<String>,
+    reported: AtomicBool,
+}
+
+impl CrashLog {
+    /// Must be called at boot before any other thread is started.
+    pub fn load(partition: EspDefaultNvsPartition) -> error::Result<Self> {
+        let mut nvs = EspNvs::new(partition, NAMESPACE, true).context("open crash namespace")?;
+
+        if let Some(report) = take_record() {
+            log::error!("crash: previous boot panicked: {}", report);
+            nvs.set_str(MESSAGE_KEY, &report)?;
+            nvs.set_u8(REPORTED_KEY, 0)?;
+        }
+
+        let mut buf = [0u8; RECORD_LEN + 1];
+        // A report that cannot be read is dropped rather than failing boot.
+        let last = match nvs.get_str(MESSAGE_KEY, &mut buf) {
+            Ok(last) => last.map(String::from),
+            Err(err) => {
+                log::error!("crash: reading the last report error={:?}", err);
+                None
+            }
+        };
+        let reported = nvs.get_u8(REPORTED_KEY)?.unwrap_or(1) != 0;
+
+        Ok(Self {
+            nvs: Mutex::new(nvs),
+            last,
+            reported: AtomicBool::new(reported),
+        })
+    }
+
+    pub fn last(&self) -> Option<&str> {
+        self.last.as_deref()
+    }
+
+    /// The last panic report if it has not been published yet.
+    pub fn pending(&self) -> Option<&str> {
+        if self.reported.load(Ordering::Acquire) {
+            None
+        } else {
+            self.last()
+        }
+    }
+
+    pub fn mark_reported(&self) -> error::Result<()> {
+        self.nvs.lock().unwrap().set_u8(REPORTED_KEY, 1)?;
+        self.reported.store(true, Ordering::Release);
+        Ok(())
+    }
+}
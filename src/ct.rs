@@ -0,0 +1,94 @@
+/// RMS of calibrated ADC samples in mV of an AC signal on a DC bias, such as a CT clamp
+/// behind a mid-supply divider. The bias is the mean of the samples, so it
+/// needs no calibration as long as the window covers whole mains cycles.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Rms {
+    samples: u32,
+    sum: i64,
+    sum_squares: i64,
+}
+
+impl Rms {
+    pub fn push(&mut self, mv: i32) {
+        self.samples += 1;
+        self.sum += i64::from(mv);
+        self.sum_squares += i64::from(mv) * i64::from(mv);
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    /// RMS of the samples around their mean in volts at the ADC input,
+    /// `None` without samples.
+    pub fn volts(&self) -> Option<f32> {
+        if self.samples == 0 {
+            return None;
+        }
+        let n = f64::from(self.samples);
+        let mean = self.sum as f64 / n;
+        // Rounding can take a flat signal a little below zero.
+        let variance = (self.sum_squares as f64 / n - mean * mean).max(0.);
+        Some((variance.sqrt() / 1000.) as f32)
+    }
+}
+
+/// RMS current in amps through a clamp with `amps_per_volt`, e.g. 30 for
+/// the SCT-013-030 that puts out 1 V at 30 A. Readings below
+/// `noise_floor` amps are ADC noise and read as 0.
+pub fn amps(volts: f32, amps_per_volt: f32, noise_floor: f32) -> f32 {
+    let amps = volts * amps_per_volt;
+    if amps < noise_floor {
+        0.
+    } else {
+        amps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::{FRAC_1_SQRT_2, PI};
+
+    use super::*;
+
+    /// Samples of a sine around `bias` with `amplitude` in mV, over whole
+    /// cycles.
+    fn sine(bias: f64, amplitude: f64) -> Rms {
+        let mut rms = Rms::default();
+        for i in 0..1000 {
+            let angle = 2. * PI * f64::from(i) / 100.;
+            rms.push((bias + amplitude * angle.sin()).round() as i32);
+        }
+        rms
+    }
+
+    #[test]
+    fn measures_sine_around_bias() {
+        // 1 V peak.
+        for bias in [1100., 1250., 1400.] {
+            let volts = sine(bias, 1000.).volts().unwrap();
+            assert!(
+                (f64::from(volts) - FRAC_1_SQRT_2).abs() < 0.001,
+                "{}",
+                volts
+            );
+        }
+    }
+
+    #[test]
+    fn flat_signal_is_zero() {
+        let mut rms = Rms::default();
+        assert_eq!(rms.volts(), None);
+        for _ in 0..100 {
+            rms.push(1250);
+        }
+        assert_eq!(rms.samples(), 100);
+        assert_eq!(rms.volts(), Some(0.));
+    }
+
+    #[test]
+    fn converts_to_amps() {
+        assert_eq!(amps(0.5, 30., 0.1), 15.);
+        assert_eq!(amps(0.002, 30., 0.1), 0.);
+    }
+}
@@ -0,0 +1,104 @@
+//! Delta-based reporting: a reading is sent once it differs enough from
+//! the last one sent, otherwise only as a keepalive. A stable room then
+//! costs a write every few minutes while a change still goes out at once.
+
+use std::time::{Duration, Instant};
+
+use crate::reading::SensorData;
+
+/// How much each value has to change to be sent, 0 to not watch it. With
+/// both 0 every reading is sent.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Delta {
+    pub temperature: f32,
+    pub humidity: f32,
+}
+
+impl Delta {
+    fn is_off(&self) -> bool {
+        self.temperature <= 0. && self.humidity <= 0.
+    }
+
+    fn exceeded(&self, sent: SensorData, data: SensorData) -> bool {
+        let changed = |delta: f32, sent: f32, now: f32| delta > 0. && (now - sent).abs() >= delta;
+        changed(self.temperature, sent.temperature, data.temperature)
+            || changed(self.humidity, sent.humidity, data.humidity)
+    }
+}
+
+#[derive(Default)]
+pub struct Deadband {
+    /// The last reading sent and when.
+    sent: Option<(SensorData, Instant)>,
+}
+
+impl Deadband {
+    /// Whether `data` is to be sent: the first reading, one past `delta`
+    /// from the last sent or the first after `keepalive`. Readings are
+    /// compared with the last one sent, so a slow drift is sent once it
+    /// adds up to `delta`.
+    pub fn due(&mut self, data: SensorData, delta: Delta, keepalive: Duration) -> bool {
+        self.due_at(data, delta, keepalive, Instant::now())
+    }
+
+    fn due_at(
+        &mut self,
+        data: SensorData,
+        delta: Delta,
+        keepalive: Duration,
+        now: Instant,
+    ) -> bool {
+        let due = match self.sent {
+            None => true,
+            Some(_) if delta.is_off() => true,
+            Some((sent, at)) => delta.exceeded(sent, data) || now - at >= keepalive,
+        };
+        if due {
+            self.sent = Some((data, now));
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEEPALIVE: Duration = Duration::from_secs(600);
+
+    fn reading(temperature: f32, humidity: f32) -> SensorData {
+        SensorData {
+            temperature,
+            humidity,
+        }
+    }
+
+    #[test]
+    fn sends_every_reading_without_delta() {
+        let mut deadband = Deadband::default();
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert!(deadband.due_at(reading(20., 50.), Delta::default(), KEEPALIVE, now));
+        }
+    }
+
+    #[test]
+    fn sends_changes_and_keepalives() {
+        let delta = Delta {
+            temperature: 0.5,
+            humidity: 0.,
+        };
+        let mut deadband = Deadband::default();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        assert!(deadband.due_at(reading(20., 50.), delta, KEEPALIVE, at(0)));
+        // Humidity is not watched.
+        assert!(!deadband.due_at(reading(20.2, 70.), delta, KEEPALIVE, at(10)));
+        // The drift adds up against the last reading sent.
+        assert!(!deadband.due_at(reading(20.4, 50.), delta, KEEPALIVE, at(20)));
+        assert!(deadband.due_at(reading(20.5, 50.), delta, KEEPALIVE, at(30)));
+        assert!(deadband.due_at(reading(19.9, 50.), delta, KEEPALIVE, at(40)));
+        assert!(!deadband.due_at(reading(19.9, 50.), delta, KEEPALIVE, at(639)));
+        assert!(deadband.due_at(reading(19.9, 50.), delta, KEEPALIVE, at(640)));
+    }
+}
@@ -0,0 +1,113 @@
+use std::time::{Duration, Instant};
+
+use crate::point::Point;
+
+/// Device id derived from the factory programmed MAC address, e.g. `esp-a0b1c2d3e4f5`.
+pub fn mac_id() -> String {
+    let mut mac = [0u8; 6];
+    let err = unsafe { esp_idf_sys::esp_efuse_mac_get_default(mac.as_mut_ptr()) };
+    if err != esp_idf_sys::ESP_OK {
+        log::error!("device: reading mac address error={}", err);
+    }
+
+    let hex: String = mac.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("esp-{}", hex)
+}
+
+/// Erases the whole default NVS partition (settings, profiles and the Wi-Fi
+/// credentials cached by ESP-IDF) and restarts with the compiled defaults.
+pub fn factory_reset() -> ! {
+    let err = unsafe { esp_idf_sys::nvs_flash_erase() };
+    if err != esp_idf_sys::ESP_OK {
+        log::error!("device: erasing nvs error={}", err);
+    }
+
+    unsafe { esp_idf_sys::esp_restart() }
+}
+
+/// Produces the `device` measurement at most once per interval.
+#[derive(Default)]
+pub struct Telemetry {
+    last: Option<Instant>,
+}
+
+impl Telemetry {
+    pub fn poll(&mut self, interval: Duration, tags: &[(String, String)]) -> Option<Point> {
+        if matches!(self.last, Some(last) if last.elapsed() < interval) {
+            return None;
+        }
+        self.last = Some(Instant::now());
+
+        let point = Point::new("device")
+            .tags(tags)
+            .field("free_heap", unsafe {
+                esp_idf_sys::esp_get_free_heap_size()
+            })
+            .field("min_free_heap", unsafe {
+                esp_idf_sys::esp_get_minimum_free_heap_size()
+            })
+            .field("uptime_secs", uptime().as_secs())
+            .field("tasks", unsafe { esp_idf_sys::uxTaskGetNumberOfTasks() })
+            .field("reset_reason", reset_reason())
+            .field("version", env!("CARGO_PKG_VERSION"));
+        #[cfg(feature = "build-info")]
+        let point = crate::build_info::fields(point);
+        log::trace!("device: telemetry={:?}", point.fields);
+        Some(point)
+    }
+}
+
+/// Produces the `heartbeat` measurement once per interval, independent of
+/// sensor readings, so a missing node can be told apart from a broken sensor.
+#[derive(Default)]
+pub struct Heartbeat {
+    seq: u64,
+    last: Option<Instant>,
+}
+
+impl Heartbeat {
+    /// Time left until the next heartbeat is due.
+    pub fn remaining(&self, interval: Duration) -> Duration {
+        match self.last {
+            Some(last) => interval.saturating_sub(last.elapsed()),
+            None => Duration::ZERO,
+        }
+    }
+
+    pub fn poll(&mut self, interval: Duration, tags: &[(String, String)]) -> Option<Point> {
+        if !self.remaining(interval).is_zero() {
+            return None;
+        }
+        self.last = Some(Instant::now());
+        self.seq += 1;
+
+        Some(
+            Point::new("heartbeat")
+                .tags(tags)
+                .field("seq", self.seq)
+                .field("uptime_secs", uptime().as_secs()),
+        )
+    }
+}
+
+pub fn uptime() -> Duration {
+    let micros = unsafe { esp_idf_sys::esp_timer_get_time() };
+    Duration::from_micros(micros as u64)
+}
+
+pub fn reset_reason() -> &'static str {
+    #[allow(non_upper_case_globals)]
+    match unsafe { esp_idf_sys::esp_reset_reason() } {
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_POWERON => "power_on",
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_EXT => "external",
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_SW => "software",
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_PANIC => "panic",
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_INT_WDT => "interrupt_watchdog",
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_TASK_WDT => "task_watchdog",
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_WDT => "watchdog",
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_DEEPSLEEP => "deep_sleep",
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_BROWNOUT => "brownout",
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_SDIO => "sdio",
+        _ => "unknown",
+    }
+}
@@ -0,0 +1,160 @@
+use embedded_hal::{
+    blocking::delay::DelayUs,
+    digital::v2::{InputPin, OutputPin},
+};
+
+use crate::reading::SensorData;
+
+/// A level held for more polls than this means the sensor stopped
+/// answering, the longest the protocol has is 80 µs.
+const TIMEOUT_POLLS: u32 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error<E> {
+    Pin(E),
+    /// The sensor did not answer or stopped mid-frame.
+    Timeout,
+    Checksum,
+}
+
+/// Reads a DHT22 on an open-drain pin with a pull-up.
+///
+/// Bits are told apart by comparing how long the sensor keeps the line
+/// high (26 µs for 0, 70 µs for 1) with the 50 µs low before it, both
+/// counted in polls, so the poll rate does not matter.
+pub fn read<P, D, E>(pin: &mut P, delay: &mut D) -> Result<SensorData, Error<E>>
+where
+    P: InputPin<Error = E> + OutputPin<Error = E>,
+    D: DelayUs<u16>,
+{
+    // Start signal, at least 1 ms low, then the line is released.
+    pin.set_low().map_err(Error::Pin)?;
+    delay.delay_us(1_100);
+    pin.set_high().map_err(Error::Pin)?;
+
+    // Released line, then the 80 µs low and high response.
+    wait(pin, delay, true)?;
+    wait(pin, delay, false)?;
+    wait(pin, delay, true)?;
+
+    let mut frame = [0u8; 5];
+    for bit in 0..40 {
+        let low = wait(pin, delay, false)?;
+        let high = wait(pin, delay, true)?;
+        if high > low {
+            frame[bit / 8] |= 0x80 >> (bit % 8);
+        }
+    }
+    decode(frame).ok_or(Error::Checksum)
+}
+
+/// Polls while the pin is at `high`, returns the number of polls.
+fn wait<P, D, E>(pin: &mut P, delay: &mut D, high: bool) -> Result<u32, Error<E>>
+where
+    P: InputPin<Error = E>,
+    D: DelayUs<u16>,
+{
+    let mut polls = 0;
+    while pin.is_high().map_err(Error::Pin)? == high {
+        polls += 1;
+        if polls > TIMEOUT_POLLS {
+            return Err(Error::Timeout);
+        }
+        delay.delay_us(1);
+    }
+    Ok(polls)
+}
+
+/// Decodes the humidity and temperature of a frame, both in tenths and the
+/// temperature with a sign bit, followed by the sum of the four bytes.
+/// `None` if the sum does not match.
+pub fn decode(frame: [u8; 5]) -> Option<SensorData> {
+    let sum = frame[..4].iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    if sum != frame[4] {
+        return None;
+    }
+    let humidity = f32::from(u16::from_be_bytes([frame[0], frame[1]])) / 10.;
+    let temperature = f32::from(u16::from_be_bytes([frame[2] & 0x7F, frame[3]])) / 10.;
+    Some(SensorData {
+        temperature: if frame[2] & 0x80 != 0 {
+            -temperature
+        } else {
+            temperature
+        },
+        humidity,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_hal_mock::eh0::{
+        delay::NoopDelay,
+        digital::{Mock as PinMock, State, Transaction},
+    };
+
+    use super::*;
+
+    /// 65.2 % and 35.1 °C, the example of the datasheet.
+    const FRAME: [u8; 5] = [0x02, 0x8C, 0x01, 0x5F, 0xEE];
+
+    /// The pin transactions of a read of `frame`, with `polls` reads per
+    /// 10 µs.
+    fn transactions(frame: [u8; 5], polls: usize) -> Vec<Transaction> {
+        let mut levels = vec![(true, 2), (false, 8), (true, 8)];
+        for bit in 0..40 {
+            let one = frame[bit / 8] & (0x80 >> (bit % 8)) != 0;
+            levels.push((false, 5));
+            levels.push((true, if one { 7 } else { 3 }));
+        }
+
+        let mut transactions = vec![Transaction::set(State::Low), Transaction::set(State::High)];
+        for (high, tens_of_us) in levels {
+            let state = if high { State::High } else { State::Low };
+            transactions.extend((0..tens_of_us * polls).map(|_| Transaction::get(state)));
+        }
+        // The sensor pulls the line low once more after the last bit.
+        transactions.push(Transaction::get(State::Low));
+        transactions
+    }
+
+    #[test]
+    fn decodes_datasheet_frame() {
+        let data = decode(FRAME).unwrap();
+        assert_eq!(data.humidity, 65.2);
+        assert_eq!(data.temperature, 35.1);
+    }
+
+    #[test]
+    fn decodes_negative_temperature() {
+        let data = decode([0x02, 0x8C, 0x80, 0x65, 0x73]).unwrap();
+        assert_eq!(data.temperature, -10.1);
+    }
+
+    #[test]
+    fn rejects_bad_checksum() {
+        assert!(decode([0x02, 0x8C, 0x01, 0x5F, 0xEF]).is_none());
+    }
+
+    #[test]
+    fn reads_frame_at_any_poll_rate() {
+        for polls in [1, 3] {
+            let mut pin = PinMock::new(&transactions(FRAME, polls));
+            let data = read(&mut pin, &mut NoopDelay::new()).unwrap();
+            assert_eq!((data.humidity, data.temperature), (65.2, 35.1));
+            pin.done();
+        }
+    }
+
+    #[test]
+    fn times_out_without_sensor() {
+        let mut transactions = vec![Transaction::set(State::Low), Transaction::set(State::High)];
+        let polls = TIMEOUT_POLLS as usize + 1;
+        transactions.extend((0..polls).map(|_| Transaction::get(State::High)));
+        let mut pin = PinMock::new(&transactions);
+        assert!(matches!(
+            read(&mut pin, &mut NoopDelay::new()),
+            Err(Error::Timeout)
+        ));
+        pin.done();
+    }
+}
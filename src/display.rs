@@ -0,0 +1,247 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use esp_idf_hal::{
+    delay,
+    gpio::{self, PinDriver},
+};
+
+use crate::{
+    alert::Alerts,
+    encoder::Adjust,
+    error::{self, format_err},
+    health,
+    pir::Occupancy,
+    ready::{Ready, Step},
+    schedule,
+    segments::{self, Frame},
+    settings::Store,
+    state::State,
+    watchdog::Watchdog,
+    SensorData,
+};
+
+/// Short enough for the setpoint to follow the encoder.
+const TICK: Duration = Duration::from_millis(100);
+/// The colon blinks at this rate.
+const BLINK: Duration = Duration::from_millis(500);
+/// Longest time the self-test result stays up waiting for the first reading.
+const SELFTEST_HOLD: Duration = Duration::from_secs(5);
+/// How long the reading is shown before the clock page.
+const READING_PAGE: Duration = Duration::from_secs(10);
+
+pub type Tm1637<'d, PCLK, PDIO> = tm1637::TM1637<
+    PinDriver<'d, PCLK, gpio::InputOutput>,
+    PinDriver<'d, PDIO, gpio::InputOutput>,
+    delay::Ets,
+>;
+
+pub fn new<'d, PCLK, PDIO>(
+    clk: PinDriver<'d, PCLK, gpio::InputOutput>,
+    dio: PinDriver<'d, PDIO, gpio::InputOutput>,
+) -> Tm1637<'d, PCLK, PDIO>
+where
+    PCLK: gpio::InputPin + gpio::OutputPin,
+    PDIO: gpio::InputPin + gpio::OutputPin,
+{
+    tm1637::TM1637::new(clk, dio, delay::Ets)
+}
+
+pub fn init<PCLK, PDIO>(tm: &mut Tm1637<'_, PCLK, PDIO>)
+where
+    PCLK: gpio::InputPin + gpio::OutputPin,
+    PDIO: gpio::InputPin + gpio::OutputPin,
+{
+    log::trace!("init tm1637...");
+    if let Err(err) = tm.init() {
+        log::error!("could not init tm1637 error={:?}", err);
+    }
+    log::trace!("clear tm1637...");
+    if let Err(err) = tm.clear() {
+        log::error!("could not clear tm1637 error={:?}", err);
+    }
+    log::trace!("set brightness tm1637...");
+    if let Err(err) = tm.set_brightness(128) {
+        log::error!("could not set brightness tm1637 error={:?}", err);
+    }
+}
+
+pub fn print<PCLK, PDIO>(tm: &mut Tm1637<'_, PCLK, PDIO>, frame: Frame)
+where
+    PCLK: gpio::InputPin + gpio::OutputPin,
+    PDIO: gpio::InputPin + gpio::OutputPin,
+{
+    if let Err(err) = tm.print_raw(0, &frame) {
+        log::error!("failed to print raw on tm1637 error={:?}", err);
+    }
+}
+
+/// Lights every segment for a second. The display can not be read back, so
+/// only bus errors are detected and the rest is left to the eye.
+pub fn self_test<PCLK, PDIO>(tm: &mut Tm1637<'_, PCLK, PDIO>) -> error::Result<()>
+where
+    PCLK: gpio::InputPin + gpio::OutputPin,
+    PDIO: gpio::InputPin + gpio::OutputPin,
+{
+    tm.init()
+        .map_err(|err| format_err!("init error={:?}", err))?;
+    tm.set_brightness(128)
+        .map_err(|err| format_err!("set brightness error={:?}", err))?;
+    tm.print_raw(0, &segments::ALL)
+        .map_err(|err| format_err!("print error={:?}", err))?;
+    thread::sleep(Duration::from_secs(1));
+    Ok(())
+}
+
+/// Shows `PASS`.
+pub fn show_pass<PCLK, PDIO>(tm: &mut Tm1637<'_, PCLK, PDIO>)
+where
+    PCLK: gpio::InputPin + gpio::OutputPin,
+    PDIO: gpio::InputPin + gpio::OutputPin,
+{
+    print(tm, segments::PASS);
+}
+
+/// Shows an error code as `E0xx`.
+pub fn show_error<PCLK, PDIO>(tm: &mut Tm1637<'_, PCLK, PDIO>, code: u8)
+where
+    PCLK: gpio::InputPin + gpio::OutputPin,
+    PDIO: gpio::InputPin + gpio::OutputPin,
+{
+    print(tm, segments::error(code));
+}
+
+/// Shows the remaining seconds until a factory reset as `F0NN`.
+pub fn show_countdown<PCLK, PDIO>(tm: &mut Tm1637<'_, PCLK, PDIO>, remaining_secs: u8)
+where
+    PCLK: gpio::InputPin + gpio::OutputPin,
+    PDIO: gpio::InputPin + gpio::OutputPin,
+{
+    print(tm, segments::countdown(remaining_secs));
+}
+
+/// Shows the local time as `HH:MM`.
+fn show_clock<PCLK, PDIO>(tm: &mut Tm1637<'_, PCLK, PDIO>, minutes: u32, colon: bool)
+where
+    PCLK: gpio::InputPin + gpio::OutputPin,
+    PDIO: gpio::InputPin + gpio::OutputPin,
+{
+    print(tm, segments::clock(minutes, colon));
+}
+
+/// Shows a setpoint with one decimal, e.g. ` 20.5` or `-18.0`.
+fn show_setpoint<PCLK, PDIO>(tm: &mut Tm1637<'_, PCLK, PDIO>, value: f32)
+where
+    PCLK: gpio::InputPin + gpio::OutputPin,
+    PDIO: gpio::InputPin + gpio::OutputPin,
+{
+    print(tm, segments::setpoint(value));
+}
+
+/// Shows each reading and, when `display_clock` is set and the time is
+/// known, alternates it with a clock page. A setpoint being adjusted with
+/// the encoder takes precedence, and the display is blank while the room
+/// is vacant.
+#[allow(clippy::too_many_arguments)]
+pub fn display_sensor_data<PCLK, PDIO>(
+    state: &State,
+    ready: &Ready,
+    mut tm: Tm1637<'_, PCLK, PDIO>,
+    error_code: Option<u8>,
+    alerts: &Alerts,
+    adjust: &Adjust,
+    occupancy: &Occupancy,
+    store: &Store,
+) where
+    PCLK: gpio::InputPin + gpio::OutputPin,
+    PDIO: gpio::InputPin + gpio::OutputPin,
+{
+    let watchdog = Watchdog::subscribe("display");
+    let health = health::register("display");
+    let mut seen = None;
+    let mut latest = None;
+    let mut shown_at = Instant::now();
+    let mut adjusting = false;
+    let mut blank = false;
+
+    // The self-test result stays up until a reading can replace it.
+    watchdog.feed();
+    ready.wait(Step::Sensor, SELFTEST_HOLD);
+    init(&mut tm);
+    ready.mark(Step::Display);
+
+    loop {
+        health.tick();
+        let hold = Duration::from_secs(u64::from(store.get().pir_hold_secs));
+        if let Some(snapshot) = watchdog.wait_newer(state, seen, TICK) {
+            seen = Some(snapshot.sequence);
+            latest = Some(snapshot.data);
+            shown_at = Instant::now();
+            if adjust.pending().is_none() && occupancy.is_occupied(hold) {
+                show_reading(&mut tm, snapshot.data, error_code, alerts);
+                continue;
+            }
+        }
+
+        if let Some(setpoint) = adjust.pending() {
+            show_setpoint(&mut tm, setpoint);
+            adjusting = true;
+            continue;
+        }
+        if !occupancy.is_occupied(hold) {
+            if !std::mem::replace(&mut blank, true) {
+                log::trace!("blanking tm1637...");
+                if let Err(err) = tm.clear() {
+                    log::error!("could not clear tm1637 error={:?}", err);
+                }
+            }
+            continue;
+        }
+        if std::mem::take(&mut adjusting) | std::mem::take(&mut blank) {
+            if let Some(data) = latest {
+                print_reading(&mut tm, data);
+            }
+        }
+
+        let clock_page = Duration::from_secs(u64::from(store.get().display_clock_secs));
+        if clock_page.is_zero() {
+            continue;
+        }
+        let (Some(data), Some(minutes)) = (latest, schedule::local_minutes()) else {
+            continue;
+        };
+        let elapsed = shown_at.elapsed().as_millis() % (READING_PAGE + clock_page).as_millis();
+        if elapsed < READING_PAGE.as_millis() {
+            print_reading(&mut tm, data);
+        } else {
+            show_clock(&mut tm, minutes, elapsed / BLINK.as_millis() % 2 == 0);
+        }
+    }
+}
+
+fn show_reading<PCLK, PDIO>(
+    tm: &mut Tm1637<'_, PCLK, PDIO>,
+    data: SensorData,
+    error_code: Option<u8>,
+    alerts: &Alerts,
+) where
+    PCLK: gpio::InputPin + gpio::OutputPin,
+    PDIO: gpio::InputPin + gpio::OutputPin,
+{
+    if let Some(code) = error_code {
+        log::trace!("displaying error code on tm1637...");
+        show_error(tm, code);
+        thread::sleep(Duration::from_secs(3));
+    }
+    let active = alerts.active().len();
+    if active > 0 {
+        log::trace!("displaying alert indicator on tm1637...");
+        print(tm, segments::alerts(active));
+        thread::sleep(Duration::from_secs(3));
+    }
+
+    log::trace!("displaying data on tm1637...");
+    print(tm, segments::reading(data));
+}
@@ -0,0 +1,113 @@
+use embedded_hal::{
+    blocking::delay::DelayUs,
+    digital::v2::{InputPin, OutputPin},
+};
+
+use crate::onewire::{crc8, Error, OneWire};
+
+/// Family code of the DS18B20 in its ROM code.
+pub const FAMILY: u8 = 0x28;
+/// A 12 bit conversion takes this long.
+pub const CONVERSION_MS: u64 = 750;
+
+const SKIP_ROM: u8 = 0xCC;
+const MATCH_ROM: u8 = 0x55;
+const CONVERT_T: u8 = 0x44;
+const READ_SCRATCHPAD: u8 = 0xBE;
+/// The temperature register holds this from power-on until the first
+/// conversion, 85 °C.
+const POWER_ON_RESET: [u8; 2] = [0x50, 0x05];
+
+/// Starts a conversion on every DS18B20 on the bus, the readings are ready
+/// after [`CONVERSION_MS`].
+pub fn start_conversion<P, D, E>(bus: &mut OneWire<P, D>) -> Result<(), Error<E>>
+where
+    P: InputPin<Error = E> + OutputPin<Error = E>,
+    D: DelayUs<u16>,
+{
+    bus.reset()?;
+    bus.write_byte(SKIP_ROM)?;
+    bus.write_byte(CONVERT_T)
+}
+
+/// Temperature in °C of the DS18B20 with `rom`, or of the only one on the
+/// bus. `None` if it has not converted since power-on.
+pub fn read<P, D, E>(
+    bus: &mut OneWire<P, D>,
+    rom: Option<&[u8; 8]>,
+) -> Result<Option<f32>, Error<E>>
+where
+    P: InputPin<Error = E> + OutputPin<Error = E>,
+    D: DelayUs<u16>,
+{
+    bus.reset()?;
+    match rom {
+        Some(rom) => {
+            bus.write_byte(MATCH_ROM)?;
+            rom.iter().try_for_each(|&byte| bus.write_byte(byte))?;
+        }
+        None => bus.write_byte(SKIP_ROM)?,
+    }
+    bus.write_byte(READ_SCRATCHPAD)?;
+    let mut scratchpad = [0u8; 9];
+    for byte in &mut scratchpad {
+        *byte = bus.read_byte()?;
+    }
+    decode(scratchpad).ok_or(Error::Crc)
+}
+
+/// Decodes the temperature of a scratchpad, a signed count of 1/16 °C.
+/// `None` if the CRC does not match, `Some(None)` for the power-on value.
+pub fn decode(scratchpad: [u8; 9]) -> Option<Option<f32>> {
+    // A bus stuck low reads as all zeros, which passes the CRC.
+    if crc8(&scratchpad[..8]) != scratchpad[8] || scratchpad == [0; 9] {
+        return None;
+    }
+    if scratchpad[..2] == POWER_ON_RESET {
+        return Some(None);
+    }
+    let raw = i16::from_le_bytes([scratchpad[0], scratchpad[1]]);
+    Some(Some(f32::from(raw) / 16.))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A scratchpad with the temperature register `raw` and its CRC.
+    fn scratchpad(raw: u16) -> [u8; 9] {
+        let [lsb, msb] = raw.to_le_bytes();
+        let mut scratchpad = [lsb, msb, 0x4B, 0x46, 0x7F, 0xFF, 0x0C, 0x10, 0];
+        scratchpad[8] = crc8(&scratchpad[..8]);
+        scratchpad
+    }
+
+    #[test]
+    fn decodes_datasheet_temperatures() {
+        for (raw, celsius) in [
+            (0x07D0, 125.),
+            (0x0191, 25.0625),
+            (0x0008, 0.5),
+            (0x0000, 0.),
+            (0xFFF8, -0.5),
+            (0xFF5E, -10.125),
+            (0xFC90, -55.),
+        ] {
+            assert_eq!(decode(scratchpad(raw)), Some(Some(celsius)));
+        }
+    }
+
+    #[test]
+    fn reports_power_on_value() {
+        assert_eq!(decode(scratchpad(0x0550)), Some(None));
+    }
+
+    #[test]
+    fn rejects_bad_crc() {
+        let mut bad = scratchpad(0x0191);
+        bad[0] ^= 1;
+        assert_eq!(decode(bad), None);
+        assert_eq!(decode([0xFF; 9]), None);
+        assert_eq!(decode([0; 9]), None);
+    }
+}
@@ -0,0 +1,121 @@
+use std::sync::Mutex;
+#[cfg(feature = "dust")]
+use std::time::Duration;
+
+#[cfg(feature = "dust")]
+use embedded_hal::digital::v2::OutputPin;
+
+#[cfg(feature = "dust")]
+use crate::{adc, gp2y1010::Gp2y1010, health, settings::Store, watchdog::Watchdog};
+use crate::{gp2y1010, point::Point, settings::Settings};
+
+#[cfg(feature = "dust")]
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+/// LED pulses averaged per sample, 10 ms apart as the datasheet asks.
+#[cfg(feature = "dust")]
+const PULSES: i32 = 10;
+#[cfg(feature = "dust")]
+const PULSE_PERIOD: Duration = Duration::from_millis(10);
+
+/// Output voltages of the dust sensor since the previous point, shared with
+/// the sender.
+#[derive(Default)]
+pub struct Dust {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Sum and count of the sensor outputs in mV.
+    mv_sum: f32,
+    samples: u32,
+}
+
+impl Dust {
+    /// A `dust` point with the `sensor` tag `gp2y1010`, the average sensor
+    /// output `voltage` in mV and the dust `density` in µg/m³. `None`
+    /// without the dust sensor.
+    pub fn point(&self, tags: &[(String, String)], settings: &Settings) -> Option<Point> {
+        let mut state = self.state.lock().unwrap();
+        if state.samples == 0 {
+            return None;
+        }
+        let mv = state.mv_sum / state.samples as f32;
+        state.mv_sum = 0.;
+        state.samples = 0;
+
+        Some(
+            Point::new("dust")
+                .tag("sensor", "gp2y1010")
+                .tags(tags)
+                .field("voltage", mv)
+                .field(
+                    "density",
+                    gp2y1010::density(mv, settings.dust_zero_mv as f32),
+                ),
+        )
+    }
+}
+
+/// Averages 10 LED pulses every second. Each pulse and its ADC sample run
+/// with interrupts disabled, so the sample lands on the output's peak.
+#[cfg(feature = "dust")]
+pub fn run<L, E>(store: &Store, dust: &Dust, mut sensor: Gp2y1010<L>, pin: i32)
+where
+    L: OutputPin<Error = E>,
+    E: std::fmt::Debug,
+{
+    let watchdog = Watchdog::subscribe("dust");
+    let health = health::register("dust");
+    let channel = match adc::Channel::new(pin) {
+        Ok(channel) => channel,
+        Err(err) => {
+            log::error!("dust: init error={:?}", err);
+            return;
+        }
+    };
+
+    loop {
+        health.tick();
+        watchdog.sleep(SAMPLE_INTERVAL);
+
+        let mut sum = 0;
+        let mut failed = false;
+        for _ in 0..PULSES {
+            let raw = esp_idf_hal::interrupt::free(|| {
+                sensor.sample(&mut esp_idf_hal::delay::Ets, || channel.read())
+            });
+            match raw {
+                Ok(Ok(raw)) => sum += raw,
+                Ok(Err(err)) => {
+                    log::error!("dust: reading sensor error={:?}", err);
+                    failed = true;
+                    break;
+                }
+                Err(err) => {
+                    log::error!("dust: led pin error={:?}", err);
+                    failed = true;
+                    break;
+                }
+            }
+            watchdog.sleep(PULSE_PERIOD);
+        }
+        if failed {
+            continue;
+        }
+
+        // Converted after the pulses, which leave no time for it.
+        let mv = match channel.mv(sum / PULSES) {
+            Ok(mv) => mv as f32,
+            Err(err) => {
+                log::error!("dust: converting reading error={:?}", err);
+                continue;
+            }
+        };
+        let divider = store.get().dust_divider as f32 / 1000.;
+        let mv = mv * divider;
+        let mut state = dust.state.lock().unwrap();
+        state.mv_sum += mv;
+        state.samples += 1;
+    }
+}
@@ -0,0 +1,251 @@
+// The display shows the setpoint, only the encoder changes it.
+#![cfg_attr(not(feature = "encoder"), allow(dead_code))]
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "encoder")]
+use esp_idf_hal::gpio::{self, Input, PinDriver};
+
+#[cfg(feature = "encoder")]
+use crate::{
+    error::{self, Context},
+    health, relay,
+    settings::{Key, Store},
+    watchdog::Watchdog,
+};
+
+/// Setpoint change per detent.
+const STEP: f32 = 0.5;
+/// A setpoint not confirmed with the switch within this time is dropped.
+const EDIT_TIMEOUT: Duration = Duration::from_secs(10);
+#[cfg(feature = "encoder")]
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Setpoint being adjusted with the encoder, shown on the display while
+/// set.
+#[derive(Default)]
+pub struct Adjust {
+    pending: Mutex<Option<(f32, Instant)>>,
+}
+
+impl Adjust {
+    pub fn pending(&self) -> Option<f32> {
+        let mut pending = self.pending.lock().unwrap();
+        if matches!(*pending, Some((_, at)) if at.elapsed() >= EDIT_TIMEOUT) {
+            log::info!("encoder: setpoint not confirmed, dropping it");
+            *pending = None;
+        }
+        pending.map(|(value, _)| value)
+    }
+
+    fn set(&self, value: f32) {
+        *self.pending.lock().unwrap() = Some((value, Instant::now()));
+    }
+
+    fn take(&self) -> Option<f32> {
+        self.pending.lock().unwrap().take()
+    }
+}
+
+/// Quadrature decoder on the pulse counter, four counts per detent.
+#[cfg(all(feature = "encoder", esp_idf_soc_pcnt_supported))]
+pub struct Encoder {
+    unit: esp_idf_sys::pcnt_unit_handle_t,
+}
+
+// SAFETY: the unit handle is only used by the thread owning the encoder.
+#[cfg(all(feature = "encoder", esp_idf_soc_pcnt_supported))]
+unsafe impl Send for Encoder {}
+
+#[cfg(all(feature = "encoder", esp_idf_soc_pcnt_supported))]
+impl Encoder {
+    const COUNTS_PER_DETENT: i32 = 4;
+
+    pub fn new(a: i32, b: i32) -> error::Result<Encoder> {
+        use esp_idf_sys::*;
+
+        let mut unit = std::ptr::null_mut();
+        unsafe {
+            esp!(pcnt_new_unit(
+                &pcnt_unit_config_t {
+                    low_limit: -100,
+                    high_limit: 100,
+                    ..Default::default()
+                },
+                &mut unit
+            ))
+            .context("create pcnt unit")?;
+            esp!(pcnt_unit_set_glitch_filter(
+                unit,
+                &pcnt_glitch_filter_config_t {
+                    max_glitch_ns: 1000
+                }
+            ))?;
+
+            for (edge, level) in [(a, b), (b, a)] {
+                let mut channel = std::ptr::null_mut();
+                esp!(pcnt_new_channel(
+                    unit,
+                    &pcnt_chan_config_t {
+                        edge_gpio_num: edge,
+                        level_gpio_num: level,
+                        ..Default::default()
+                    },
+                    &mut channel
+                ))
+                .context("create pcnt channel")?;
+                let (rising, falling) = if edge == a {
+                    (
+                        pcnt_channel_edge_action_t_PCNT_CHANNEL_EDGE_ACTION_DECREASE,
+                        pcnt_channel_edge_action_t_PCNT_CHANNEL_EDGE_ACTION_INCREASE,
+                    )
+                } else {
+                    (
+                        pcnt_channel_edge_action_t_PCNT_CHANNEL_EDGE_ACTION_INCREASE,
+                        pcnt_channel_edge_action_t_PCNT_CHANNEL_EDGE_ACTION_DECREASE,
+                    )
+                };
+                esp!(pcnt_channel_set_edge_action(channel, rising, falling))?;
+                esp!(pcnt_channel_set_level_action(
+                    channel,
+                    pcnt_channel_level_action_t_PCNT_CHANNEL_LEVEL_ACTION_KEEP,
+                    pcnt_channel_level_action_t_PCNT_CHANNEL_LEVEL_ACTION_INVERSE
+                ))?;
+            }
+
+            esp!(pcnt_unit_enable(unit))?;
+            esp!(pcnt_unit_clear_count(unit))?;
+            esp!(pcnt_unit_start(unit))?;
+        }
+        Ok(Encoder { unit })
+    }
+
+    /// Detents turned since the last call, clockwise is positive.
+    fn take_steps(&mut self) -> error::Result<i32> {
+        let mut count = 0;
+        unsafe { esp_idf_sys::esp!(esp_idf_sys::pcnt_unit_get_count(self.unit, &mut count))? };
+        let steps = count / Self::COUNTS_PER_DETENT;
+        if steps != 0 {
+            unsafe { esp_idf_sys::esp!(esp_idf_sys::pcnt_unit_clear_count(self.unit))? };
+        }
+        Ok(steps)
+    }
+}
+
+#[cfg(all(feature = "encoder", not(esp_idf_soc_pcnt_supported)))]
+static DETENTS: std::sync::atomic::AtomicI32 = std::sync::atomic::AtomicI32::new(0);
+#[cfg(all(feature = "encoder", not(esp_idf_soc_pcnt_supported)))]
+static LAST_EDGE_US: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
+
+/// Contact bounce shorter than this is ignored.
+#[cfg(all(feature = "encoder", not(esp_idf_soc_pcnt_supported)))]
+const DEBOUNCE_US: u32 = 2000;
+
+/// Chips without a pulse counter, such as the ESP32-C3, decode in an
+/// interrupt handler on the falling edge of A instead.
+#[cfg(all(feature = "encoder", not(esp_idf_soc_pcnt_supported)))]
+pub struct Encoder;
+
+#[cfg(all(feature = "encoder", not(esp_idf_soc_pcnt_supported)))]
+unsafe extern "C" fn on_edge(b: *mut std::ffi::c_void) {
+    use std::sync::atomic::Ordering;
+
+    let now = esp_idf_sys::esp_timer_get_time() as u32;
+    if now.wrapping_sub(LAST_EDGE_US.swap(now, Ordering::Relaxed)) < DEBOUNCE_US {
+        return;
+    }
+    let clockwise = esp_idf_sys::gpio_get_level(b as usize as i32) != 0;
+    DETENTS.fetch_add(if clockwise { 1 } else { -1 }, Ordering::Relaxed);
+}
+
+#[cfg(all(feature = "encoder", not(esp_idf_soc_pcnt_supported)))]
+impl Encoder {
+    pub fn new(a: i32, b: i32) -> error::Result<Encoder> {
+        use esp_idf_sys::*;
+
+        unsafe {
+            for pin in [a, b] {
+                esp!(gpio_reset_pin(pin))?;
+                esp!(gpio_set_direction(pin, gpio_mode_t_GPIO_MODE_INPUT))?;
+                esp!(gpio_set_pull_mode(pin, gpio_pull_mode_t_GPIO_PULLUP_ONLY))?;
+            }
+            esp!(gpio_set_intr_type(a, gpio_int_type_t_GPIO_INTR_NEGEDGE))?;
+            // Already installed is fine.
+            let err = gpio_install_isr_service(0);
+            if err != ESP_ERR_INVALID_STATE {
+                esp!(err)?;
+            }
+            esp!(gpio_isr_handler_add(a, Some(on_edge), b as usize as *mut _))
+                .context("add encoder interrupt handler")?;
+            esp!(gpio_intr_enable(a))?;
+        }
+        Ok(Encoder)
+    }
+
+    /// Detents turned since the last call, clockwise is positive.
+    fn take_steps(&mut self) -> error::Result<i32> {
+        Ok(DETENTS.swap(0, std::sync::atomic::Ordering::Relaxed))
+    }
+}
+
+/// Adjusts the `relay_control` setpoint: turning changes it by [`STEP`]
+/// per detent and pressing the switch saves it.
+#[cfg(feature = "encoder")]
+pub fn run<P: gpio::InputPin>(
+    store: &Store,
+    adjust: &Adjust,
+    mut encoder: Encoder,
+    switch: PinDriver<'_, P, Input>,
+) {
+    let watchdog = Watchdog::subscribe("encoder");
+    let health = health::register("encoder");
+    let mut pressed = false;
+
+    loop {
+        health.tick();
+        watchdog.sleep(POLL_INTERVAL);
+
+        match encoder.take_steps() {
+            Ok(0) => {}
+            Ok(steps) => {
+                // The store only accepts valid rules.
+                let rule = relay::parse_control(&store.get().relay_control)
+                    .ok()
+                    .flatten();
+                match rule {
+                    Some(rule) => {
+                        let current = adjust.pending().unwrap_or(rule.threshold());
+                        let value = current + steps as f32 * STEP;
+                        // Keep one decimal, the steps add up rounding errors.
+                        adjust.set((value * 10.).round() / 10.);
+                    }
+                    None => log::warn!("encoder: relay_control is not set, nothing to adjust"),
+                }
+            }
+            Err(err) => log::error!("encoder: reading count error={:?}", err),
+        }
+
+        let was_pressed = pressed;
+        pressed = switch.is_low();
+        if pressed && !was_pressed {
+            if let Some(setpoint) = adjust.take() {
+                if let Err(err) = save(store, setpoint) {
+                    log::error!("encoder: saving setpoint error={:?}", err);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "encoder")]
+fn save(store: &Store, setpoint: f32) -> error::Result<()> {
+    let rule =
+        relay::parse_control(&store.get().relay_control)?.context("relay_control is not set")?;
+    let spec = relay::format_control(&rule.with_threshold(setpoint));
+    store.set(Key::RelayControl, &spec)?;
+    log::info!("encoder: relay_control {:?}", spec);
+    Ok(())
+}
@@ -0,0 +1,100 @@
+use std::sync::Mutex;
+#[cfg(feature = "energy")]
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "energy")]
+use crate::{adc, ct, health, settings::Store, watchdog::Watchdog};
+use crate::{point::Point, settings::Settings};
+
+#[cfg(feature = "energy")]
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+/// Whole mains cycles at both 50 Hz and 60 Hz.
+#[cfg(feature = "energy")]
+const WINDOW: Duration = Duration::from_millis(200);
+
+/// RMS currents of the CT clamp since the previous point, shared with the
+/// sender.
+#[derive(Default)]
+pub struct Energy {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Sum and count of the RMS currents in amps.
+    amps_sum: f32,
+    samples: u32,
+}
+
+impl Energy {
+    /// A `power` point with the `sensor` tag `sct013`, the average RMS
+    /// `current` in amps and the apparent `power` in watts at
+    /// `mains_voltage`. `None` without the CT clamp.
+    pub fn point(&self, tags: &[(String, String)], settings: &Settings) -> Option<Point> {
+        let mut state = self.state.lock().unwrap();
+        if state.samples == 0 {
+            return None;
+        }
+        let amps = state.amps_sum / state.samples as f32;
+        state.amps_sum = 0.;
+        state.samples = 0;
+
+        Some(
+            Point::new("power")
+                .tag("sensor", "sct013")
+                .tags(tags)
+                .field("current", amps)
+                .field("power", amps * settings.mains_voltage as f32),
+        )
+    }
+}
+
+/// Samples the CT clamp as fast as the ADC allows for 200 ms every second
+/// and keeps the RMS current.
+#[cfg(feature = "energy")]
+pub fn run(store: &Store, energy: &Energy, pin: i32) {
+    let watchdog = Watchdog::subscribe("energy");
+    let health = health::register("energy");
+    let channel = match adc::Channel::new(pin) {
+        Ok(channel) => channel,
+        Err(err) => {
+            log::error!("energy: init error={:?}", err);
+            return;
+        }
+    };
+
+    loop {
+        health.tick();
+        watchdog.sleep(SAMPLE_INTERVAL);
+
+        let mut rms = ct::Rms::default();
+        let started = Instant::now();
+        let result = loop {
+            if started.elapsed() >= WINDOW {
+                break Ok(());
+            }
+            match channel.read_mv() {
+                Ok(mv) => rms.push(mv),
+                Err(err) => break Err(err),
+            }
+        };
+        if let Err(err) = result {
+            log::error!("energy: reading clamp error={:?}", err);
+            continue;
+        }
+        let Some(volts) = rms.volts() else {
+            continue;
+        };
+
+        let settings = store.get();
+        let amps = ct::amps(
+            volts,
+            settings.ct_amps as f32,
+            settings.ct_noise_ma as f32 / 1000.,
+        );
+        log::debug!("energy: samples={} current={}", rms.samples(), amps);
+        let mut state = energy.state.lock().unwrap();
+        state.amps_sum += amps;
+        state.samples += 1;
+    }
+}
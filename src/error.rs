@@ -1,43 +1,271 @@
-use core::convert::Infallible;
+//! The firmware's error type: a category code for the diagnostics and a
+//! message of at most [`MESSAGE_LEN`] bytes, kept inline so that neither
+//! failing nor adding context allocates. Longer messages are truncated.
+//!
+//! Any standard error converts with `?`, its category guessed from its
+//! type, and [`Context::code`] sets the category where the cause is known
+//! better, e.g. at the Wi-Fi connect.
 
-use embassy_net::{dns, tcp};
-use esp_sensor::line_proto;
+use std::{
+    fmt::{self, Display, Write},
+    io,
+    num::{ParseFloatError, ParseIntError},
+    str::Utf8Error,
+};
 
-#[derive(Debug)]
-pub(crate) enum Error {
-    Dns(dns::Error),
-    ConnectTcp(tcp::ConnectError),
-    Tcp(tcp::Error),
-    LineProto(line_proto::Error<Infallible>),
-    Reqwless(reqwless::Error),
+/// Room for a message and a context or two.
+pub const MESSAGE_LEN: usize = 96;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// What failed, published by number so it can be grouped on. Numbers are
+/// never reused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Code {
+    Other = 0,
+    Io = 1,
+    /// An ESP-IDF call.
+    Esp = 2,
+    Timeout = 3,
+    /// Malformed text, numbers or JSON.
+    Parse = 4,
+    /// A setting that cannot be used.
+    Config = 5,
+    Storage = 6,
+    Wifi = 7,
+    Http = 8,
+    Sensor = 9,
+    /// A peer answered with something unexpected.
+    Protocol = 10,
+}
+
+impl Code {
+    pub const ALL: [Code; 11] = [
+        Code::Other,
+        Code::Io,
+        Code::Esp,
+        Code::Timeout,
+        Code::Parse,
+        Code::Config,
+        Code::Storage,
+        Code::Wifi,
+        Code::Http,
+        Code::Sensor,
+        Code::Protocol,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Code::Other => "other",
+            Code::Io => "io",
+            Code::Esp => "esp",
+            Code::Timeout => "timeout",
+            Code::Parse => "parse",
+            Code::Config => "config",
+            Code::Storage => "storage",
+            Code::Wifi => "wifi",
+            Code::Http => "http",
+            Code::Sensor => "sensor",
+            Code::Protocol => "protocol",
+        }
+    }
+
+    /// The category of a standard error, by its type.
+    fn of(err: &(dyn std::error::Error + 'static)) -> Code {
+        if let Some(err) = err.downcast_ref::<io::Error>() {
+            return match err.kind() {
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => Code::Timeout,
+                _ => Code::Io,
+            };
+        }
+        #[cfg(target_os = "espidf")]
+        if let Some(err) = err.downcast_ref::<esp_idf_sys::EspError>() {
+            return if err.code() == esp_idf_sys::ESP_ERR_TIMEOUT as esp_idf_sys::esp_err_t {
+                Code::Timeout
+            } else {
+                Code::Esp
+            };
+        }
+        if err.is::<ParseIntError>()
+            || err.is::<ParseFloatError>()
+            || err.is::<Utf8Error>()
+            || err.is::<serde_json::Error>()
+        {
+            return Code::Parse;
+        }
+        Code::Other
+    }
+}
+
+/// Does not implement [`std::error::Error`], which would conflict with the
+/// conversion from every type that does.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Error {
+    code: Code,
+    message: heapless::String<MESSAGE_LEN>,
+}
+
+impl Error {
+    pub fn new(code: Code, message: impl Display) -> Self {
+        let mut error = Error {
+            code,
+            message: heapless::String::new(),
+        };
+        let _ = write!(Truncating(&mut error.message), "{}", message);
+        error
+    }
+
+    pub fn code(&self) -> Code {
+        self.code
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    fn context(self, context: impl Display) -> Self {
+        let mut message = heapless::String::new();
+        let _ = write!(Truncating(&mut message), "{}: {}", context, self.message);
+        Error {
+            code: self.code,
+            message,
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> From<E> for Error {
+    fn from(err: E) -> Self {
+        Error::new(Code::of(&err), err)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
 }
 
-impl From<dns::Error> for Error {
-    fn from(value: dns::Error) -> Self {
-        Self::Dns(value)
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} code={}", self.message, self.code.name())
     }
 }
 
-impl From<tcp::ConnectError> for Error {
-    fn from(value: tcp::ConnectError) -> Self {
-        Self::ConnectTcp(value)
+/// Writes what fits and drops the rest.
+struct Truncating<'a>(&'a mut heapless::String<MESSAGE_LEN>);
+
+impl Write for Truncating<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.0.push_str(s).is_err() {
+            for c in s.chars() {
+                if self.0.push(c).is_err() {
+                    break;
+                }
+            }
+        }
+        Ok(())
     }
 }
 
-impl From<tcp::Error> for Error {
-    fn from(value: tcp::Error) -> Self {
-        Self::Tcp(value)
+/// Adds what was being done to errors on their way up.
+pub trait Context<T> {
+    fn context(self, context: impl Display) -> Result<T>;
+
+    /// Like [`Context::context`], builds the context only on an error.
+    fn with_context<C: Display>(self, context: impl FnOnce() -> C) -> Result<T>;
+
+    /// Sets the category of the error, replacing the one it had.
+    fn code(self, code: Code) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> Context<T> for Result<T, E> {
+    fn context(self, context: impl Display) -> Result<T> {
+        self.map_err(|err| err.into().context(context))
+    }
+
+    fn with_context<C: Display>(self, context: impl FnOnce() -> C) -> Result<T> {
+        self.map_err(|err| err.into().context(context()))
+    }
+
+    fn code(self, code: Code) -> Result<T> {
+        self.map_err(|err| Error { code, ..err.into() })
     }
 }
 
-impl From<line_proto::Error<Infallible>> for Error {
-    fn from(value: line_proto::Error<Infallible>) -> Self {
-        Self::LineProto(value)
+impl<T> Context<T> for Option<T> {
+    fn context(self, context: impl Display) -> Result<T> {
+        self.ok_or_else(|| Error::new(Code::Other, context))
+    }
+
+    fn with_context<C: Display>(self, context: impl FnOnce() -> C) -> Result<T> {
+        self.ok_or_else(|| Error::new(Code::Other, context()))
+    }
+
+    fn code(self, code: Code) -> Result<T> {
+        self.ok_or_else(|| Error::new(code, "none"))
     }
 }
 
-impl From<reqwless::Error> for Error {
-    fn from(value: reqwless::Error) -> Self {
-        Self::Reqwless(value)
+/// An [`Error`] from a format string, of [`Code::Other`] unless a code
+/// comes first: `format_err!(Code::Sensor, "no ack from {}", name)`.
+macro_rules! format_err {
+    ($fmt:literal $($arg:tt)*) => {
+        $crate::error::Error::new($crate::error::Code::Other, format_args!($fmt $($arg)*))
+    };
+    ($code:expr, $($arg:tt)+) => {
+        $crate::error::Error::new($code, format_args!($($arg)+))
+    };
+}
+pub(crate) use format_err;
+
+/// Returns early with [`format_err!`].
+macro_rules! bail {
+    ($($arg:tt)+) => {
+        return Err($crate::error::format_err!($($arg)+))
+    };
+}
+pub(crate) use bail;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Result<u8> {
+        let n: u8 = s.parse().context("parse count")?;
+        if n == 0 {
+            bail!("count must not be zero, got {}", s);
+        }
+        Ok(n)
+    }
+
+    #[test]
+    fn keeps_the_category_of_the_source() {
+        let err = parse("x").unwrap_err();
+        assert_eq!(err.code(), Code::Parse);
+        assert_eq!(
+            err.to_string(),
+            "parse count: invalid digit found in string"
+        );
+
+        let err = parse("0").unwrap_err();
+        assert_eq!(err.code(), Code::Other);
+        assert_eq!(err.message(), "count must not be zero, got 0");
+
+        let err = parse("x").context("read config").code(Code::Config);
+        assert_eq!(err.unwrap_err().code(), Code::Config);
+    }
+
+    #[test]
+    fn truncates_long_messages() {
+        // 101 bytes, the 48th "é" would take bytes 96 and 97.
+        let err = format_err!(Code::Sensor, "a{}", "é".repeat(50));
+        assert_eq!(err.message(), format!("a{}", "é".repeat(47)));
+
+        // "read: a" leaves 89 bytes, one short of the 45th "é".
+        let err = err.context("read");
+        assert_eq!(err.code(), Code::Sensor);
+        assert_eq!(err.message(), format!("read: a{}", "é".repeat(44)));
+        assert_eq!(err.message().len(), MESSAGE_LEN - 1);
     }
 }
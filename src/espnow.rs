@@ -0,0 +1,143 @@
+use std::{
+    sync::{mpsc, Arc},
+    time::Duration,
+};
+
+use esp_idf_hal::{modem::Modem, peripheral::Peripheral};
+use esp_idf_svc::{
+    espnow::{EspNow, PeerInfo, BROADCAST},
+    eventloop::EspSystemEventLoop,
+    wifi::{BlockingWifi, ClientConfiguration, Configuration, EspWifi},
+};
+
+use crate::{
+    broadcast,
+    error::{self, Context},
+    health,
+    lora::{self, Inbox, Packet, Role, Seen, Uplink},
+    settings::Store,
+    watchdog::Watchdog,
+    SensorData,
+};
+
+/// Packets heard by a relay, waiting for the task to repeat them.
+const MAX_QUEUED: usize = 16;
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Receives readings from nodes while the gateway's Wi-Fi is connected, on
+/// the access point's channel. Receiving stops when the handle is dropped.
+pub fn listen(inbox: Arc<Inbox>) -> error::Result<EspNow> {
+    let espnow = EspNow::take().context("start esp-now")?;
+    let mut seen = Seen::default();
+    espnow
+        .register_recv_cb(move |_mac, bytes| match Packet::decode(bytes) {
+            Ok(packet) if !seen.insert(&packet) => {}
+            Ok(packet) => inbox.push(Uplink {
+                device: packet.device,
+                data: packet.data,
+                rssi: None,
+                snr: None,
+            }),
+            Err(err) => log::warn!("espnow: dropping packet error={:?}", err),
+        })
+        .context("register esp-now receiver")?;
+    Ok(espnow)
+}
+
+/// Broadcasts every reading as a node, additionally repeats packets of
+/// other nodes as a relay. Relaying floods like on LoRa, see [`lora::run`].
+///
+/// Wi-Fi is started without joining a network, only to fix the channel.
+pub fn run(
+    mut sub: broadcast::Receiver<SensorData>,
+    store: &Store,
+    modem: impl Peripheral<P = Modem>,
+    sysloop: EspSystemEventLoop,
+) -> error::Result<()> {
+    let watchdog = Watchdog::subscribe("espnow");
+    let health = health::register("espnow");
+    let settings = store.get();
+    let role: Role = settings.espnow_role.parse().unwrap_or_default();
+    let device = settings.device_id();
+    log::info!("espnow: running as {:?}", role);
+
+    let mut esp_wifi = EspWifi::new(modem, sysloop.clone(), None)?;
+    let mut wifi = BlockingWifi::wrap(&mut esp_wifi, sysloop)?;
+    wifi.set_configuration(&Configuration::Client(ClientConfiguration::default()))?;
+    wifi.start()?;
+    unsafe {
+        esp_idf_sys::esp!(esp_idf_sys::esp_wifi_set_channel(
+            settings.espnow_channel as u8,
+            esp_idf_sys::wifi_second_chan_t_WIFI_SECOND_CHAN_NONE
+        ))
+        .context("set esp-now channel")?;
+    }
+
+    let espnow = EspNow::take().context("start esp-now")?;
+    espnow
+        .add_peer(PeerInfo {
+            peer_addr: BROADCAST,
+            ..Default::default()
+        })
+        .context("add esp-now broadcast peer")?;
+    let send = |packet: &Packet| {
+        if let Err(err) = espnow.send(BROADCAST, &packet.encode()) {
+            log::error!("espnow: sending error={:?}", err);
+        }
+    };
+    let mut seq: u16 = 0;
+
+    if role == Role::Node {
+        while let Some(data) = watchdog.recv(&mut sub) {
+            health.tick();
+            seq = seq.wrapping_add(1);
+            send(&Packet {
+                ttl: lora::MAX_HOPS,
+                seq,
+                device: device.clone(),
+                data,
+            });
+        }
+        return Ok(());
+    }
+
+    // The callback runs in the Wi-Fi task, which must not wait on the jitter.
+    let (heard, incoming) = mpsc::sync_channel(MAX_QUEUED);
+    espnow
+        .register_recv_cb(move |_mac, bytes| {
+            let _ = heard.try_send(bytes.to_vec());
+        })
+        .context("register esp-now receiver")?;
+    let mut seen = Seen::default();
+    loop {
+        health.tick();
+        watchdog.feed();
+
+        let mut outgoing = Vec::new();
+        while let Ok(data) = sub.try_recv() {
+            seq = seq.wrapping_add(1);
+            outgoing.push(Packet {
+                ttl: lora::MAX_HOPS,
+                seq,
+                device: device.clone(),
+                data,
+            });
+        }
+        if let Ok(bytes) = incoming.recv_timeout(POLL_INTERVAL) {
+            match Packet::decode(&bytes) {
+                Ok(packet) if packet.device == device || !seen.insert(&packet) => {}
+                Ok(packet) if packet.ttl > 0 => outgoing.push(Packet {
+                    ttl: packet.ttl - 1,
+                    ..packet
+                }),
+                Ok(_) => {}
+                Err(err) => log::warn!("espnow: dropping packet error={:?}", err),
+            }
+        }
+
+        for packet in outgoing {
+            std::thread::sleep(lora::jitter());
+            send(&packet);
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use std::time::Duration;
+
+use crate::error::{self, bail, Context};
+/// The alarm clears once the concentration stayed below every level this
+/// long.
+pub const CLEAR_AFTER: Duration = Duration::from_secs(60);
+
+/// Alarm level of a gas concentration, written as `ppm@seconds`: the alarm
+/// sounds once the concentration stayed at or above `ppm` that long.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Level {
+    pub ppm: f32,
+    pub duration: Duration,
+}
+
+/// Parses comma separated levels, e.g. `50@3600,100@600,300@60`.
+pub fn parse_levels(spec: &str) -> error::Result<Vec<Level>> {
+    let mut levels = Vec::new();
+    for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let Some((ppm, secs)) = entry.split_once('@') else {
+            bail!("alarm level {:?} must be written as ppm@seconds", entry);
+        };
+        let ppm: f32 = ppm.trim().parse().context("parse ppm")?;
+        let secs: u64 = secs.trim().parse().context("parse seconds")?;
+        if ppm <= 0. {
+            bail!("alarm level {:?} must be above 0 ppm", entry);
+        }
+        levels.push(Level {
+            ppm,
+            duration: Duration::from_secs(secs),
+        });
+    }
+    if levels.is_empty() {
+        bail!("at least one alarm level is needed");
+    }
+    Ok(levels)
+}
+
+/// Exposure alarm over a series of readings.
+#[derive(Debug, Clone)]
+pub struct Alarm {
+    /// Levels with how long the concentration has been at or above them.
+    levels: Vec<(Level, Duration)>,
+    active: bool,
+    /// How long the concentration has been below every level.
+    below: Duration,
+}
+
+impl Alarm {
+    pub fn new(levels: Vec<Level>) -> Alarm {
+        Alarm {
+            levels: levels.into_iter().map(|l| (l, Duration::ZERO)).collect(),
+            active: false,
+            below: Duration::ZERO,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Adds a reading of `ppm` that held for `elapsed`, returns whether the
+    /// alarm is active.
+    pub fn update(&mut self, ppm: f32, elapsed: Duration) -> bool {
+        let mut reached = false;
+        let mut above_any = false;
+        for (level, time) in &mut self.levels {
+            if ppm >= level.ppm {
+                *time += elapsed;
+                above_any = true;
+                reached |= *time >= level.duration;
+            } else {
+                *time = Duration::ZERO;
+            }
+        }
+
+        self.below = if above_any {
+            Duration::ZERO
+        } else {
+            self.below + elapsed
+        };
+        if reached {
+            self.active = true;
+        } else if self.below >= CLEAR_AFTER {
+            self.active = false;
+        }
+        self.active
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    fn alarm() -> Alarm {
+        Alarm::new(parse_levels("50@3600, 100@600,300@60").unwrap())
+    }
+
+    /// Seconds of `ppm` until the alarm sounds, up to two hours.
+    fn seconds_to_alarm(ppm: f32) -> Option<u32> {
+        let mut alarm = alarm();
+        (1..=7200).find(|_| alarm.update(ppm, SECOND))
+    }
+
+    #[test]
+    fn parses_levels() {
+        let levels = parse_levels("50@3600").unwrap();
+        assert_eq!(
+            levels,
+            [Level {
+                ppm: 50.,
+                duration: Duration::from_secs(3600)
+            }]
+        );
+        assert!(parse_levels("").is_err());
+        assert!(parse_levels("50").is_err());
+        assert!(parse_levels("0@60").is_err());
+        assert!(parse_levels("fifty@60").is_err());
+    }
+
+    #[test]
+    fn higher_levels_sound_sooner() {
+        assert_eq!(seconds_to_alarm(30.), None);
+        assert_eq!(seconds_to_alarm(60.), Some(3600));
+        assert_eq!(seconds_to_alarm(150.), Some(600));
+        assert_eq!(seconds_to_alarm(400.), Some(60));
+    }
+
+    #[test]
+    fn dips_restart_the_level() {
+        let mut alarm = alarm();
+        for _ in 0..59 {
+            assert!(!alarm.update(400., SECOND));
+        }
+        // Still above 100 ppm, which keeps counting.
+        assert!(!alarm.update(200., SECOND));
+        for _ in 0..59 {
+            assert!(!alarm.update(400., SECOND));
+        }
+        assert!(alarm.update(400., SECOND));
+    }
+
+    #[test]
+    fn clears_after_fresh_air() {
+        let mut alarm = alarm();
+        assert!(alarm.update(400., Duration::from_secs(60)));
+        // Still above a level, the alarm holds.
+        assert!(alarm.update(60., Duration::from_secs(120)));
+        assert!(alarm.update(10., Duration::from_secs(59)));
+        assert!(!alarm.update(10., SECOND));
+        assert!(!alarm.is_active());
+    }
+}
@@ -0,0 +1,172 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+#[cfg(feature = "fan")]
+use std::{
+    ffi::c_void,
+    ptr,
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "fan")]
+use esp_idf_hal::{
+    gpio::{self, Input, PinDriver},
+    ledc::LedcDriver,
+};
+
+use crate::error::{self, bail, Context};
+#[cfg(feature = "fan")]
+use crate::{broadcast, health, settings::Store, watchdog::Watchdog, SensorData};
+
+/// 4-pin fans expect a 25 kHz PWM signal.
+#[cfg(feature = "fan")]
+pub const FREQUENCY_HZ: u32 = 25_000;
+/// Tach pulses per fan revolution.
+#[cfg(feature = "fan")]
+const PULSES_PER_REV: u32 = 2;
+#[cfg(feature = "fan")]
+const TICK: Duration = Duration::from_secs(2);
+/// Without a reading for this long the fan runs at full speed.
+#[cfg(feature = "fan")]
+const STALE_AFTER: Duration = Duration::from_secs(5 * 60);
+
+#[cfg(feature = "fan")]
+static TACH_PULSES: AtomicU32 = AtomicU32::new(0);
+
+/// Parses a fan curve of comma separated `temperature:duty` points with
+/// the duty in percent, e.g. `25:20,35:100`. Temperatures must increase.
+pub fn parse_curve(spec: &str) -> error::Result<Vec<(f32, f32)>> {
+    let mut curve: Vec<(f32, f32)> = Vec::new();
+    for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let Some((temperature, duty)) = entry.split_once(':') else {
+            bail!("fan curve point {:?} is not temperature:duty", entry);
+        };
+        let temperature: f32 = temperature.trim().parse().context("parse temperature")?;
+        let duty: f32 = duty.trim().parse().context("parse duty")?;
+        if !(0. ..=100.).contains(&duty) {
+            bail!("fan duty {} is outside of 0..=100", duty);
+        }
+        if matches!(curve.last(), Some((last, _)) if *last >= temperature) {
+            bail!("fan curve temperatures must increase");
+        }
+        curve.push((temperature, duty));
+    }
+    Ok(curve)
+}
+
+/// Duty in percent for `temperature`, linearly interpolated between points
+/// and clamped to the first and last one. An empty curve means full speed.
+#[cfg(feature = "fan")]
+pub fn duty_for(curve: &[(f32, f32)], temperature: f32) -> f32 {
+    let Some(&(first_t, first_d)) = curve.first() else {
+        return 100.;
+    };
+    if temperature <= first_t {
+        return first_d;
+    }
+
+    for pair in curve.windows(2) {
+        let ((t0, d0), (t1, d1)) = (pair[0], pair[1]);
+        if temperature <= t1 {
+            return d0 + (d1 - d0) * (temperature - t0) / (t1 - t0);
+        }
+    }
+    curve[curve.len() - 1].1
+}
+
+/// Fan state shared with the sender.
+#[derive(Default)]
+pub struct Fan {
+    duty_percent: AtomicU32,
+    rpm: AtomicU32,
+}
+
+impl Fan {
+    pub fn duty_percent(&self) -> u32 {
+        self.duty_percent.load(Ordering::Relaxed)
+    }
+
+    pub fn rpm(&self) -> u32 {
+        self.rpm.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(feature = "fan")]
+unsafe extern "C" fn on_tach_pulse(_: *mut c_void) {
+    TACH_PULSES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Counts falling edges of the tach output in an interrupt handler.
+#[cfg(feature = "fan")]
+fn count_tach_pulses<P: gpio::InputPin>(tach: &PinDriver<'_, P, Input>) -> error::Result<()> {
+    use esp_idf_sys::esp;
+
+    let pin = tach.pin();
+    unsafe {
+        esp!(esp_idf_sys::gpio_set_intr_type(
+            pin,
+            esp_idf_sys::gpio_int_type_t_GPIO_INTR_NEGEDGE
+        ))?;
+        // Already installed is fine.
+        let err = esp_idf_sys::gpio_install_isr_service(0);
+        if err != esp_idf_sys::ESP_ERR_INVALID_STATE {
+            esp!(err)?;
+        }
+        esp!(esp_idf_sys::gpio_isr_handler_add(
+            pin,
+            Some(on_tach_pulse),
+            ptr::null_mut()
+        ))?;
+    }
+    Ok(())
+}
+
+/// Sets the PWM duty from `fan_curve` and the latest temperature, and
+/// measures the speed from the tach pulses.
+#[cfg(feature = "fan")]
+pub fn run<P: gpio::InputPin>(
+    mut sub: broadcast::Receiver<SensorData>,
+    store: &Store,
+    fan: &Fan,
+    tach: PinDriver<'_, P, Input>,
+    mut pwm: LedcDriver<'_>,
+) {
+    let watchdog = Watchdog::subscribe("fan");
+    let health = health::register("fan");
+    if let Err(err) = count_tach_pulses(&tach) {
+        log::error!("fan: setting up tach interrupt error={:?}", err);
+    }
+
+    let mut temperature: Option<(f32, Instant)> = None;
+    let mut window_started = Instant::now();
+    loop {
+        health.tick();
+        match watchdog.recv_timeout(&mut sub, TICK) {
+            Ok(data) => temperature = Some((data.temperature, Instant::now())),
+            Err(broadcast::RecvError::Closed) => return,
+            Err(_) => {}
+        }
+
+        // The store only accepts valid curves.
+        let curve = parse_curve(&store.get().fan_curve).unwrap_or_default();
+        let duty = match temperature {
+            Some((value, at)) if at.elapsed() < STALE_AFTER => duty_for(&curve, value),
+            _ => 100.,
+        };
+        let max = pwm.get_max_duty();
+        let raw = (duty / 100. * max as f32) as u32;
+        if raw != pwm.get_duty() {
+            log::debug!("fan: duty {:.0}%", duty);
+            if let Err(err) = pwm.set_duty(raw) {
+                log::error!("fan: setting duty error={:?}", err);
+            }
+        }
+        fan.duty_percent.store(duty as u32, Ordering::Relaxed);
+
+        let elapsed = window_started.elapsed();
+        if elapsed >= TICK {
+            let pulses = TACH_PULSES.swap(0, Ordering::Relaxed);
+            window_started = Instant::now();
+            let rpm = pulses as f32 / PULSES_PER_REV as f32 * 60. / elapsed.as_secs_f32();
+            fan.rpm.store(rpm as u32, Ordering::Relaxed);
+        }
+    }
+}
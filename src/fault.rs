@@ -0,0 +1,96 @@
+use std::{
+    sync::atomic::{AtomicU32, Ordering},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    dht,
+    error::{self, bail, Context},
+    SensorData,
+};
+
+/// Fires on every nth call once set, 0 turns it off.
+struct Every {
+    n: AtomicU32,
+    calls: AtomicU32,
+}
+
+impl Every {
+    const fn new() -> Self {
+        Every {
+            n: AtomicU32::new(0),
+            calls: AtomicU32::new(0),
+        }
+    }
+
+    fn set(&self, n: u32) {
+        self.n.store(n, Ordering::Relaxed);
+        self.calls.store(0, Ordering::Relaxed);
+    }
+
+    fn fires(&self) -> bool {
+        let n = self.n.load(Ordering::Relaxed);
+        cfg!(debug_assertions) && n > 0 && (self.calls.fetch_add(1, Ordering::Relaxed) + 1) % n == 0
+    }
+}
+
+static HTTP: Every = Every::new();
+static SENSOR: Every = Every::new();
+static CONNECT_DELAY_MS: AtomicU32 = AtomicU32::new(0);
+
+/// Fails the HTTP request about to be sent when due, as a dropped
+/// connection would.
+pub fn http_request() -> error::Result<()> {
+    if HTTP.fires() {
+        log::warn!("fault: dropping http request");
+        bail!("injected fault: http request dropped");
+    }
+    Ok(())
+}
+
+/// Turns a sensor read into a checksum error when due.
+pub fn sensor_read<E>(
+    result: Result<SensorData, dht::Error<E>>,
+) -> Result<SensorData, dht::Error<E>> {
+    if SENSOR.fires() {
+        log::warn!("fault: corrupting sensor read");
+        return Err(dht::Error::Checksum);
+    }
+    result
+}
+
+/// Stalls before a connection is opened, as a slow DNS lookup would.
+pub fn connect() {
+    let delay = CONNECT_DELAY_MS.load(Ordering::Relaxed);
+    if cfg!(debug_assertions) && delay > 0 {
+        log::warn!("fault: delaying connection by {}ms", delay);
+        thread::sleep(Duration::from_millis(u64::from(delay)));
+    }
+}
+
+/// Runs a `fault` console command, only available in debug builds.
+pub fn command(args: &[&str]) -> error::Result<String> {
+    if !cfg!(debug_assertions) {
+        bail!("fault injection is only available in debug builds");
+    }
+    let parse = |value: &str| value.parse::<u32>().context("parse number");
+    match args {
+        [] => {}
+        ["http", n] => HTTP.set(parse(n)?),
+        ["sensor", n] => SENSOR.set(parse(n)?),
+        ["dns", ms] => CONNECT_DELAY_MS.store(parse(ms)?, Ordering::Relaxed),
+        ["clear"] => {
+            HTTP.set(0);
+            SENSOR.set(0);
+            CONNECT_DELAY_MS.store(0, Ordering::Relaxed);
+        }
+        _ => bail!("usage: fault [http <n>|sensor <n>|dns <ms>|clear]"),
+    }
+    Ok(format!(
+        "fault http={} sensor={} dns={}ms",
+        HTTP.n.load(Ordering::Relaxed),
+        SENSOR.n.load(Ordering::Relaxed),
+        CONNECT_DELAY_MS.load(Ordering::Relaxed)
+    ))
+}
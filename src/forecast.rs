@@ -0,0 +1,117 @@
+use std::{collections::VecDeque, time::Duration};
+
+/// Dew point in °C, from the Magnus formula with the constants of Sonntag,
+/// within 0.35 °C between -45 and 60 °C.
+pub fn dew_point(temperature: f32, humidity: f32) -> f32 {
+    const B: f32 = 17.62;
+    const C: f32 = 243.12;
+    let gamma = (humidity / 100.).ln() + B * temperature / (C + temperature);
+    C * gamma / (B - gamma)
+}
+
+/// Least squares trend of the readings in a sliding window.
+#[derive(Debug, Clone)]
+pub struct Trend {
+    window: Duration,
+    /// Time of each reading since any fixed start, and its value.
+    samples: VecDeque<(Duration, f32)>,
+}
+
+impl Trend {
+    pub fn new(window: Duration) -> Trend {
+        Trend {
+            window,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Adds a reading taken at `at`, dropping those that left the window.
+    pub fn push(&mut self, at: Duration, value: f32) {
+        while let Some(&(first, _)) = self.samples.front() {
+            if at.saturating_sub(first) <= self.window {
+                break;
+            }
+            self.samples.pop_front();
+        }
+        self.samples.push_back((at, value));
+    }
+
+    /// Change per hour, `None` until the readings span half the window.
+    pub fn per_hour(&self) -> Option<f32> {
+        let (first, _) = *self.samples.front()?;
+        let (last, _) = *self.samples.back()?;
+        if (last - first) * 2 < self.window {
+            return None;
+        }
+
+        // Hours since the first reading keep the sums small.
+        let hours = |at: Duration| (at - first).as_secs_f32() / 3600.;
+        let n = self.samples.len() as f32;
+        let mean_x = self.samples.iter().map(|&(at, _)| hours(at)).sum::<f32>() / n;
+        let mean_y = self.samples.iter().map(|&(_, v)| v).sum::<f32>() / n;
+        let (mut covariance, mut variance) = (0., 0.);
+        for &(at, value) in &self.samples {
+            let dx = hours(at) - mean_x;
+            covariance += dx * (value - mean_y);
+            variance += dx * dx;
+        }
+        (variance > 0.).then(|| covariance / variance)
+    }
+}
+
+/// Lowest temperature expected within `lead`: the falling trend carried
+/// on, but not below the dew point, where condensation releases heat and
+/// the cooling stalls. A rising trend predicts the current temperature.
+pub fn predicted_low(temperature: f32, per_hour: f32, dew_point: f32, lead: Duration) -> f32 {
+    let extrapolated = temperature + per_hour.min(0.) * lead.as_secs_f32() / 3600.;
+    extrapolated.max(dew_point.min(temperature))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn dew_point_matches_tables() {
+        assert!((dew_point(20., 50.) - 9.3).abs() < 0.1);
+        assert!((dew_point(5., 80.) - 1.9).abs() < 0.1);
+        assert!((dew_point(25., 100.) - 25.).abs() < 0.01);
+    }
+
+    #[test]
+    fn trend_needs_half_the_window() {
+        let mut trend = Trend::new(60 * MINUTE);
+        for minute in 0..30 {
+            trend.push(minute * MINUTE, 10.);
+            assert_eq!(trend.per_hour(), None);
+        }
+        trend.push(30 * MINUTE, 10.);
+        assert_eq!(trend.per_hour(), Some(0.));
+    }
+
+    #[test]
+    fn trend_follows_the_window() {
+        let mut trend = Trend::new(60 * MINUTE);
+        // Rising 6 °C per hour, then falling 2 °C per hour.
+        for minute in 0..60 {
+            trend.push(minute * MINUTE, minute as f32 / 10.);
+        }
+        assert!((trend.per_hour().unwrap() - 6.).abs() < 0.01);
+        for minute in 60..=150 {
+            trend.push(minute * MINUTE, 6. - (minute - 60) as f32 / 30.);
+        }
+        assert!((trend.per_hour().unwrap() + 2.).abs() < 0.01);
+    }
+
+    #[test]
+    fn prediction_stops_at_the_dew_point() {
+        let hours = |h: u64| Duration::from_secs(h * 3600);
+        assert_eq!(predicted_low(6., -2., -10., hours(2)), 2.);
+        assert_eq!(predicted_low(6., -2., 4., hours(2)), 4.);
+        assert_eq!(predicted_low(6., 1., -10., hours(2)), 6.);
+        // Readings a little below the dew point are sensor error.
+        assert_eq!(predicted_low(3., -2., 4., hours(2)), 3.);
+    }
+}
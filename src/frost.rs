@@ -0,0 +1,120 @@
+use std::sync::Mutex;
+#[cfg(feature = "frost")]
+use std::time::Duration;
+
+use crate::point::Point;
+#[cfg(feature = "frost")]
+use crate::{
+    alert::Alerts, broadcast, forecast, health, settings::Store, trace, watchdog::Watchdog,
+    SensorData,
+};
+
+/// The trend is fitted over the readings of this long.
+#[cfg(feature = "frost")]
+const TREND_WINDOW: Duration = Duration::from_secs(3600);
+/// The warning clears once the prediction is this far above `frost_temp`.
+#[cfg(feature = "frost")]
+const HYSTERESIS: f32 = 1.;
+/// Name of the alert raised while frost is expected.
+#[cfg(feature = "frost")]
+const ALERT: &str = "frost";
+
+/// Latest frost forecast, shared with the sender and the relay.
+#[derive(Default)]
+pub struct Frost {
+    state: Mutex<Option<Forecast>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Forecast {
+    dew_point: f32,
+    /// Temperature change in °C per hour, `None` for the first half hour.
+    per_hour: Option<f32>,
+    /// Lowest temperature expected within `frost_lead`.
+    predicted: f32,
+    warning: bool,
+}
+
+impl Frost {
+    /// Whether frost is expected, the relay then runs the heater.
+    pub fn is_warning(&self) -> bool {
+        self.state.lock().unwrap().is_some_and(|f| f.warning)
+    }
+
+    /// A `frost` point with the `dew_point`, the `trend` in °C per hour, the
+    /// `predicted` low and the `warning` state. `None` without the `frost`
+    /// feature.
+    pub fn point(&self, tags: &[(String, String)]) -> Option<Point> {
+        let forecast = (*self.state.lock().unwrap())?;
+        let point = Point::new("frost")
+            .tags(tags)
+            .field("dew_point", forecast.dew_point)
+            .field("predicted", forecast.predicted)
+            .field("warning", forecast.warning);
+        Some(match forecast.per_hour {
+            Some(per_hour) => point.field("trend", per_hour),
+            None => point,
+        })
+    }
+}
+
+/// Predicts the lowest temperature of the next `frost_lead` seconds from
+/// the trend of the last hour and the dew point on every reading. Once it
+/// reaches `frost_temp` the urgent `frost` alert is raised and the sender
+/// woken, so the push message goes out right away.
+#[cfg(feature = "frost")]
+pub fn run(
+    mut sub: broadcast::Receiver<SensorData>,
+    store: &Store,
+    frost: &Frost,
+    alerts: &Alerts,
+    readings: &broadcast::Sender<SensorData>,
+) {
+    let watchdog = Watchdog::subscribe("frost");
+    let health = health::register("frost");
+    let start = trace::now();
+    let mut trend = forecast::Trend::new(TREND_WINDOW);
+    let mut warning = false;
+
+    while let Some(data) = watchdog.recv(&mut sub) {
+        health.tick();
+        let settings = store.get();
+        // The store only accepts numbers.
+        let threshold: f32 = settings.frost_temp.parse().unwrap_or(0.);
+        let lead = Duration::from_secs(u64::from(settings.frost_lead_secs));
+
+        trend.push(trace::now() - start, data.temperature);
+        let per_hour = trend.per_hour();
+        let dew_point = forecast::dew_point(data.temperature, data.humidity);
+        let predicted =
+            forecast::predicted_low(data.temperature, per_hour.unwrap_or(0.), dew_point, lead);
+        log::debug!(
+            "frost: dew_point={:.1} trend={:?} predicted={:.1}",
+            dew_point,
+            per_hour,
+            predicted
+        );
+
+        if !warning && predicted <= threshold {
+            log::warn!(
+                "frost: {:.1}°C expected within {:?} at {:.1}°C",
+                predicted,
+                lead,
+                data.temperature
+            );
+            warning = true;
+        } else if warning && predicted > threshold + HYSTERESIS {
+            log::info!("frost: no longer expected, {:.1}°C predicted", predicted);
+            warning = false;
+        }
+        *frost.state.lock().unwrap() = Some(Forecast {
+            dew_point,
+            per_hour,
+            predicted,
+            warning,
+        });
+        if alerts.set_external(ALERT, warning, predicted) {
+            readings.wake();
+        }
+    }
+}
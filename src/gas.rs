@@ -0,0 +1,198 @@
+#[cfg(feature = "gas")]
+use std::time::{Duration, Instant};
+use std::{str::FromStr, sync::Mutex};
+
+#[cfg(feature = "gas")]
+use crate::{
+    adc,
+    error::Context,
+    health,
+    settings::{Key, Store},
+    watchdog::Watchdog,
+};
+use crate::{
+    error::{self, bail},
+    point::Point,
+    settings::Settings,
+};
+
+#[cfg(feature = "gas")]
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// MQ series gas sensor, written as `mq2` or `mq135`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    /// Flammable gases and smoke.
+    Mq2,
+    /// Air quality.
+    Mq135,
+}
+
+impl FromStr for Model {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> error::Result<Model> {
+        Ok(match s {
+            "mq2" => Model::Mq2,
+            "mq135" => Model::Mq135,
+            _ => bail!("unknown gas sensor {:?}, expected mq2 or mq135", s),
+        })
+    }
+}
+
+impl Model {
+    pub fn name(self) -> &'static str {
+        match self {
+            Model::Mq2 => "mq2",
+            Model::Mq135 => "mq135",
+        }
+    }
+
+    /// Rs/R0 in clean air, from the datasheet.
+    #[cfg_attr(not(feature = "gas"), allow(dead_code))]
+    fn clean_air_ratio(self) -> f32 {
+        match self {
+            Model::Mq2 => 9.83,
+            Model::Mq135 => 3.6,
+        }
+    }
+
+    /// Gases with `a` and `b` of their datasheet curve, ppm = a * (Rs/R0)^b.
+    fn curves(self) -> &'static [(&'static str, f32, f32)] {
+        match self {
+            Model::Mq2 => &[
+                ("lpg", 574.25, -2.222),
+                ("co", 36974., -3.109),
+                ("smoke", 3616.1, -2.675),
+                ("h2", 987.99, -2.162),
+            ],
+            Model::Mq135 => &[
+                ("co2", 110.47, -2.862),
+                ("nh3", 102.2, -2.473),
+                ("co", 605.18, -3.937),
+                ("alcohol", 77.255, -3.18),
+            ],
+        }
+    }
+}
+
+/// CO2 of the outdoor air the MQ-135 is calibrated in, its curve gives the
+/// CO2 above that.
+const OUTDOOR_CO2_PPM: f32 = 400.;
+
+/// Readings of the gas sensor since the previous point, shared with the
+/// sender and the HTTP server.
+#[derive(Default)]
+pub struct Gas {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Sum and count of the raw readings.
+    raw_sum: i64,
+    samples: u32,
+    /// Latest sensor resistance in ohms, `None` while the heater warms up.
+    rs: Option<f32>,
+}
+
+impl Gas {
+    /// A `gas` point with the `sensor` tag and the average `raw` reading.
+    /// Once the heater warmed up it has the sensor resistance `rs` in ohms
+    /// and, once calibrated, the `ratio` Rs/R0 and the ppm of each gas of
+    /// the sensor's curves. `None` without the gas sensor.
+    pub fn point(&self, tags: &[(String, String)], settings: &Settings) -> Option<Point> {
+        let mut state = self.state.lock().unwrap();
+        if state.samples == 0 {
+            return None;
+        }
+        // The store only accepts valid sensors.
+        let model: Model = settings.gas_sensor.parse().ok()?;
+        let mut point = Point::new("gas")
+            .tag("sensor", model.name())
+            .tags(tags)
+            .field("raw", state.raw_sum / i64::from(state.samples));
+        state.raw_sum = 0;
+        state.samples = 0;
+
+        let Some(rs) = state.rs else {
+            return Some(point);
+        };
+        point = point.field("rs", rs);
+        if settings.gas_r0 > 0 {
+            let ratio = rs / settings.gas_r0 as f32;
+            point = point.field("ratio", ratio);
+            for &(gas, a, b) in model.curves() {
+                let mut ppm = a * ratio.powf(b);
+                if model == Model::Mq135 && gas == "co2" {
+                    ppm += OUTDOOR_CO2_PPM;
+                }
+                point = point.field(gas, ppm);
+            }
+        }
+        Some(point)
+    }
+
+    /// Sets `gas_r0` from the latest reading, taken in clean air.
+    #[cfg(feature = "gas")]
+    pub fn calibrate(&self, store: &Store) -> error::Result<u32> {
+        let rs = self.state.lock().unwrap().rs;
+        let rs = rs.context("the heater is still warming up")?;
+        let model: Model = store.get().gas_sensor.parse()?;
+        let r0 = (rs / model.clean_air_ratio()).round() as u32;
+        store.set(Key::GasR0, &r0.to_string())?;
+        log::info!("gas: calibrated r0={}", r0);
+        Ok(r0)
+    }
+}
+
+/// Sensor resistance in ohms from a raw reading. The module's output and
+/// supply are read through the same divider, so `supply` is the reading
+/// the supply voltage would give.
+#[cfg(feature = "gas")]
+fn resistance(raw: i32, supply: u32, load_ohms: u32) -> f32 {
+    let raw = raw.max(1) as f32;
+    load_ohms as f32 * (supply as f32 - raw).max(0.) / raw
+}
+
+/// Samples the gas sensor every second. The resistance is only reported
+/// once the heater had `gas_warmup` seconds since boot.
+#[cfg(feature = "gas")]
+pub fn run(store: &Store, gas: &Gas, pin: i32) {
+    let watchdog = Watchdog::subscribe("gas");
+    let health = health::register("gas");
+    let channel = match adc::Channel::new(pin) {
+        Ok(channel) => channel,
+        Err(err) => {
+            log::error!("gas: init error={:?}", err);
+            return;
+        }
+    };
+
+    let started = Instant::now();
+    let mut warm = false;
+    loop {
+        health.tick();
+        watchdog.sleep(SAMPLE_INTERVAL);
+
+        let raw = match channel.read() {
+            Ok(raw) => raw,
+            Err(err) => {
+                log::error!("gas: reading sensor error={:?}", err);
+                continue;
+            }
+        };
+        let settings = store.get();
+        if !warm && started.elapsed() >= Duration::from_secs(u64::from(settings.gas_warmup_secs)) {
+            log::info!("gas: heater warmed up");
+            warm = true;
+        }
+
+        let mut state = gas.state.lock().unwrap();
+        state.raw_sum += i64::from(raw);
+        state.samples += 1;
+        if warm {
+            state.rs = Some(resistance(raw, settings.gas_supply, settings.gas_load));
+        }
+    }
+}
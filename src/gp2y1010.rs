@@ -0,0 +1,74 @@
+use embedded_hal::{blocking::delay::DelayUs, digital::v2::OutputPin};
+
+/// The output peaks this long after the LED is switched on.
+const SAMPLE_DELAY_US: u16 = 280;
+/// Rest of the 320 µs pulse after the sample.
+const PULSE_REST_US: u16 = 40;
+/// Output change per µg/m³ of dust, 0.5 V per 100 µg/m³.
+const MV_PER_UG: f32 = 5.;
+
+/// Sharp GP2Y1010AU0F optical dust sensor. Its LED input is active low and
+/// must be pulsed for 320 µs at most once every 10 ms, the output is only
+/// valid 280 µs into the pulse. The pulse must not be interrupted.
+pub struct Gp2y1010<L> {
+    led: L,
+}
+
+impl<L, E> Gp2y1010<L>
+where
+    L: OutputPin<Error = E>,
+{
+    pub fn new(mut led: L) -> Result<Self, E> {
+        led.set_high()?;
+        Ok(Gp2y1010 { led })
+    }
+
+    /// Pulses the LED and returns what `read` samples of the output at the
+    /// peak.
+    pub fn sample<D: DelayUs<u16>, T>(
+        &mut self,
+        delay: &mut D,
+        read: impl FnOnce() -> T,
+    ) -> Result<T, E> {
+        self.led.set_low()?;
+        delay.delay_us(SAMPLE_DELAY_US);
+        let value = read();
+        delay.delay_us(PULSE_REST_US);
+        self.led.set_high()?;
+        Ok(value)
+    }
+}
+
+/// Dust density in µg/m³ of an output in mV, given the output in clean air.
+pub fn density(mv: f32, zero_mv: f32) -> f32 {
+    ((mv - zero_mv) / MV_PER_UG).max(0.)
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_hal_mock::eh0::{
+        delay::NoopDelay,
+        digital::{Mock as PinMock, State, Transaction},
+    };
+
+    use super::*;
+
+    #[test]
+    fn samples_during_pulse() {
+        let mut led = PinMock::new(&[
+            Transaction::set(State::High),
+            Transaction::set(State::Low),
+            Transaction::set(State::High),
+        ]);
+        let mut sensor = Gp2y1010::new(led.clone()).unwrap();
+        assert_eq!(sensor.sample(&mut NoopDelay::new(), || 1234), Ok(1234));
+        led.done();
+    }
+
+    #[test]
+    fn converts_to_density() {
+        assert_eq!(density(900., 900.), 0.);
+        assert_eq!(density(1400., 900.), 100.);
+        assert_eq!(density(600., 900.), 0.);
+    }
+}
@@ -0,0 +1,101 @@
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc, Mutex,
+};
+
+use crate::point::Point;
+
+/// Tasks with less free stack than this are reported with a warning.
+const LOW_STACK_BYTES: u32 = 512;
+
+static TASKS: Mutex<Vec<Entry>> = Mutex::new(Vec::new());
+
+struct Entry {
+    name: &'static str,
+    /// FreeRTOS task handle, stored as an address so the registry is `Send`.
+    handle: usize,
+    iterations: Arc<AtomicU32>,
+}
+
+/// Health registration of the current task, removed on drop.
+pub struct Task {
+    handle: usize,
+    iterations: Arc<AtomicU32>,
+}
+
+impl Task {
+    /// Counts one iteration of the task main loop.
+    pub fn tick(&self) {
+        self.iterations.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Task {
+    fn drop(&mut self) {
+        TASKS.lock().unwrap().retain(|e| e.handle != self.handle);
+    }
+}
+
+/// Registers the current task for stack and loop counter reporting.
+pub fn register(name: &'static str) -> Task {
+    let handle = unsafe { esp_idf_sys::xTaskGetCurrentTaskHandle() } as usize;
+    let iterations = Arc::new(AtomicU32::new(0));
+    TASKS.lock().unwrap().push(Entry {
+        name,
+        handle,
+        iterations: iterations.clone(),
+    });
+
+    Task { handle, iterations }
+}
+
+/// Snapshot of a registered task.
+pub struct Status {
+    pub name: &'static str,
+    /// Lowest amount of free stack since the task started, in bytes.
+    pub stack_free: u32,
+    pub iterations: u32,
+}
+
+pub fn tasks() -> Vec<Status> {
+    let tasks = TASKS.lock().unwrap();
+    tasks
+        .iter()
+        .map(|e| Status {
+            name: e.name,
+            // ESP-IDF measures stacks in bytes, not words.
+            stack_free: unsafe { esp_idf_sys::uxTaskGetStackHighWaterMark(e.handle as _) },
+            iterations: e.iterations.load(Ordering::Relaxed),
+        })
+        .collect()
+}
+
+/// Logs the lowest free stack of every registered task, to size the
+/// stacks in `cfg.toml` by.
+pub fn log_stacks() {
+    for task in tasks() {
+        log::info!("health: task {} stack_free={}B", task.name, task.stack_free);
+    }
+}
+
+/// Builds one `task` point per registered task.
+pub fn points(tags: &[(String, String)]) -> Vec<Point> {
+    tasks()
+        .into_iter()
+        .map(|task| {
+            if task.stack_free < LOW_STACK_BYTES {
+                log::warn!(
+                    "health: task {} has only {}B of stack left",
+                    task.name,
+                    task.stack_free
+                );
+            }
+
+            Point::new("task")
+                .tag("task", task.name)
+                .tags(tags)
+                .field("stack_free", task.stack_free)
+                .field("iterations", task.iterations)
+        })
+        .collect()
+}
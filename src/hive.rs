@@ -0,0 +1,184 @@
+use std::sync::Mutex;
+#[cfg(feature = "hive")]
+use std::{collections::VecDeque, time::Duration};
+
+#[cfg(feature = "hive")]
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+#[cfg(feature = "hive")]
+use crate::{
+    broadcast,
+    error::{self, bail, Context},
+    health,
+    hx711::{self, Hx711},
+    settings::{Key, Store},
+    watchdog::Watchdog,
+    SensorData,
+};
+use crate::{point::Point, settings::Settings};
+
+#[cfg(feature = "hive")]
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+/// A conversion is ready every 100 ms, give up on the HX711 after this.
+#[cfg(feature = "hive")]
+const READY_TIMEOUT: Duration = Duration::from_millis(500);
+/// Readings averaged for the tare and the calibration.
+#[cfg(feature = "hive")]
+const RECENT_SAMPLES: usize = 10;
+
+/// Readings of the hive scale since the previous point, shared with the
+/// sender and the HTTP server.
+#[derive(Default)]
+pub struct Hive {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Sum and count of the raw readings.
+    raw_sum: i64,
+    samples: u32,
+    /// Latest readings, newest last.
+    #[cfg(feature = "hive")]
+    recent: VecDeque<i32>,
+    /// Latest DHT22 temperature, the load cell drifts with it.
+    temperature: Option<f32>,
+}
+
+impl Hive {
+    /// A `hive` point with the average `raw` reading and, once calibrated,
+    /// the temperature compensated `weight` in kg. `None` without the hive
+    /// scale.
+    pub fn point(&self, tags: &[(String, String)], settings: &Settings) -> Option<Point> {
+        let mut state = self.state.lock().unwrap();
+        if state.samples == 0 {
+            return None;
+        }
+        let raw = (state.raw_sum / i64::from(state.samples)) as i32;
+        state.raw_sum = 0;
+        state.samples = 0;
+
+        let point = Point::new("hive")
+            .tag("sensor", "hx711")
+            .tags(tags)
+            .field("raw", i64::from(raw));
+        Some(match scale(settings).kg(raw, state.temperature) {
+            Some(kg) => point.field("weight", kg),
+            None => point,
+        })
+    }
+
+    /// Stores the current reading of the empty scale and the temperature
+    /// as `hive_tare` and `hive_tare_temp`.
+    #[cfg(feature = "hive")]
+    pub fn tare(&self, store: &Store) -> error::Result<i32> {
+        let (raw, temperature) = {
+            let state = self.state.lock().unwrap();
+            (average(&state.recent)?, state.temperature)
+        };
+        let temperature = temperature.map(|t| t.to_string()).unwrap_or_default();
+        store.update(&[
+            (Key::HiveTare, &raw.to_string()),
+            (Key::HiveTareTemp, &temperature),
+        ])?;
+        log::info!("hive: tared raw={} temperature={:?}", raw, temperature);
+        Ok(raw)
+    }
+
+    /// Sets `hive_scale` from the current reading with `kg` on the tared
+    /// scale.
+    #[cfg(feature = "hive")]
+    pub fn calibrate(&self, store: &Store, kg: f32) -> error::Result<f32> {
+        let raw = average(&self.state.lock().unwrap().recent)?;
+        let counts = scale(&store.get())
+            .calibrate(raw, kg)
+            .context("the weight must be positive and change the reading")?;
+        store.set(Key::HiveScale, &counts.to_string())?;
+        log::info!("hive: calibrated counts_per_kg={}", counts);
+        Ok(counts)
+    }
+}
+
+/// The store only accepts numbers, an unset tare temperature is empty.
+fn scale(settings: &Settings) -> hx711::Scale {
+    hx711::Scale {
+        tare: settings.hive_tare.parse().unwrap_or(0),
+        counts_per_kg: settings.hive_scale.parse().unwrap_or(0.),
+        tare_temperature: settings.hive_tare_temp.parse().ok(),
+        grams_per_degree: settings.hive_tempco.parse().unwrap_or(0.),
+    }
+}
+
+#[cfg(feature = "hive")]
+fn average(recent: &VecDeque<i32>) -> error::Result<i32> {
+    if recent.len() < RECENT_SAMPLES {
+        bail!("the scale has not settled yet");
+    }
+    let sum: i64 = recent.iter().map(|&raw| i64::from(raw)).sum();
+    Ok((sum / recent.len() as i64) as i32)
+}
+
+/// Reads the load cell every second and keeps the temperature of the
+/// latest reading for the compensation.
+#[cfg(feature = "hive")]
+pub fn run<D, S, E>(hive: &Hive, mut sub: broadcast::Receiver<SensorData>, mut hx711: Hx711<D, S>)
+where
+    D: InputPin<Error = E>,
+    S: OutputPin<Error = E>,
+    E: std::fmt::Debug,
+{
+    let watchdog = Watchdog::subscribe("hive");
+    let health = health::register("hive");
+    loop {
+        health.tick();
+        watchdog.sleep(SAMPLE_INTERVAL);
+
+        let mut temperature = None;
+        loop {
+            match sub.try_recv() {
+                Ok(data) => temperature = Some(data.temperature),
+                Err(broadcast::RecvError::Lagged(_)) => {}
+                Err(_) => break,
+            }
+        }
+
+        let raw = match read(&mut hx711, &watchdog) {
+            Ok(raw) => raw,
+            Err(err) => {
+                log::error!("hive: reading scale error={:?}", err);
+                continue;
+            }
+        };
+        let mut state = hive.state.lock().unwrap();
+        state.raw_sum += i64::from(raw);
+        state.samples += 1;
+        if state.recent.len() == RECENT_SAMPLES {
+            state.recent.pop_front();
+        }
+        state.recent.push_back(raw);
+        if temperature.is_some() {
+            state.temperature = temperature;
+        }
+    }
+}
+
+/// Waits for a conversion and shifts it out with interrupts disabled.
+#[cfg(feature = "hive")]
+fn read<D, S, E>(hx711: &mut Hx711<D, S>, watchdog: &Watchdog) -> error::Result<i32>
+where
+    D: InputPin<Error = E>,
+    S: OutputPin<Error = E>,
+    E: std::fmt::Debug,
+{
+    let started = std::time::Instant::now();
+    loop {
+        match esp_idf_hal::interrupt::free(|| hx711.read(&mut esp_idf_hal::delay::Ets)) {
+            Ok(raw) => return Ok(raw),
+            Err(hx711::Error::NotReady) if started.elapsed() < READY_TIMEOUT => {
+                watchdog.sleep(Duration::from_millis(10));
+            }
+            Err(hx711::Error::NotReady) => bail!("no conversion, check the wiring"),
+            Err(hx711::Error::Pin(err)) => bail!("pin error {:?}", err),
+        }
+    }
+}
@@ -0,0 +1,320 @@
+#[cfg(feature = "hvac")]
+use std::time::{Duration, Instant};
+use std::{str::FromStr, sync::Mutex};
+
+#[cfg(feature = "hvac")]
+use esp_idf_hal::i2c::I2cDriver;
+#[cfg(feature = "hvac")]
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+
+use crate::error::{self, bail, format_err};
+use crate::point::Point;
+#[cfg(feature = "hvac")]
+use crate::{
+    alert::{Alerts, Event},
+    bmp280::{self, Bmp280},
+    health, sdp810,
+    settings::Store,
+    watchdog::Watchdog,
+};
+
+#[cfg(feature = "hvac")]
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+/// The runtimes are written to NVS at most this often.
+#[cfg(feature = "hvac")]
+const SAVE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+#[cfg(feature = "hvac")]
+const NAMESPACE: &str = "hvac";
+/// The pressure drop has to stay on the same side of `hvac_clogged` this
+/// long with the fan running to raise or clear the alert, so a damper or
+/// a fan starting up does not.
+#[cfg(feature = "hvac")]
+const CLOGGED_AFTER: Duration = Duration::from_secs(5 * 60);
+/// Name of the alert raised for a clogged filter.
+#[cfg(feature = "hvac")]
+const ALERT: &str = "filter";
+
+/// Pressure sensors across the filter.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Sensor {
+    /// Two BMP280, before the filter at 0x76 and after it at 0x77.
+    #[default]
+    Bmp280,
+    /// One SDP810 differential pressure sensor, high port before the filter.
+    Sdp810,
+}
+
+impl Sensor {
+    pub fn name(self) -> &'static str {
+        match self {
+            Sensor::Bmp280 => "bmp280",
+            Sensor::Sdp810 => "sdp810",
+        }
+    }
+}
+
+impl FromStr for Sensor {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bmp280" => Ok(Sensor::Bmp280),
+            "sdp810" => Ok(Sensor::Sdp810),
+            _ => bail!("hvac sensor {:?} is not one of bmp280, sdp810", s),
+        }
+    }
+}
+
+/// Filter readings since the previous point and the runtimes, shared with
+/// the sender and the HTTP server.
+#[derive(Default)]
+pub struct Hvac {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    sensor: Sensor,
+    /// Sum and count of the pressure drops in Pa.
+    pa_sum: f32,
+    samples: u32,
+    running: bool,
+    clogged: bool,
+    /// Fan runtime since first use and since the last filter change.
+    runtime_secs: f64,
+    filter_secs: f64,
+    /// The runtimes changed outside the task and are saved right away.
+    reset: bool,
+}
+
+impl Hvac {
+    /// An `hvac` point with the `sensor` tag, the average `pressure_drop` in
+    /// Pa, whether the fan is `running`, the fan `runtime` and
+    /// `filter_runtime` in hours and whether the filter is `clogged`.
+    /// `None` without the `hvac` feature.
+    pub fn point(&self, tags: &[(String, String)]) -> Option<Point> {
+        let mut state = self.state.lock().unwrap();
+        if state.samples == 0 {
+            return None;
+        }
+        let pa = state.pa_sum / state.samples as f32;
+        state.pa_sum = 0.;
+        state.samples = 0;
+
+        Some(
+            Point::new("hvac")
+                .tag("sensor", state.sensor.name())
+                .tags(tags)
+                .field("pressure_drop", pa)
+                .field("running", state.running)
+                .field("runtime", state.runtime_secs / 3600.)
+                .field("filter_runtime", state.filter_secs / 3600.)
+                .field("clogged", state.clogged),
+        )
+    }
+
+    /// Starts the filter runtime over after a filter change.
+    #[cfg(feature = "hvac")]
+    pub fn reset_filter(&self) {
+        let mut state = self.state.lock().unwrap();
+        log::info!(
+            "hvac: filter changed after {:.1} hours",
+            state.filter_secs / 3600.
+        );
+        state.filter_secs = 0.;
+        state.reset = true;
+    }
+}
+
+/// The sensors of `hvac_sensor`, sharing the I2C bus.
+#[cfg(feature = "hvac")]
+enum Sensors {
+    Bmp280 {
+        upstream: Bmp280,
+        downstream: Bmp280,
+    },
+    Sdp810,
+}
+
+#[cfg(feature = "hvac")]
+impl Sensors {
+    fn new(sensor: Sensor, i2c: &mut I2cDriver<'_>) -> error::Result<Sensors> {
+        Ok(match sensor {
+            Sensor::Bmp280 => Sensors::Bmp280 {
+                upstream: Bmp280::new(i2c, bmp280::ADDRESS_LOW)
+                    .map_err(|err| format_err!("upstream bmp280 {:?}", err))?,
+                downstream: Bmp280::new(i2c, bmp280::ADDRESS_HIGH)
+                    .map_err(|err| format_err!("downstream bmp280 {:?}", err))?,
+            },
+            Sensor::Sdp810 => {
+                sdp810::start(i2c).map_err(|err| format_err!("sdp810 {:?}", err))?;
+                Sensors::Sdp810
+            }
+        })
+    }
+
+    /// Pressure drop across the filter in Pa.
+    fn read(&self, i2c: &mut I2cDriver<'_>) -> error::Result<f32> {
+        match self {
+            Sensors::Bmp280 {
+                upstream,
+                downstream,
+            } => {
+                let (_, before) = upstream
+                    .read(i2c)
+                    .map_err(|err| format_err!("upstream bmp280 {:?}", err))?;
+                let (_, after) = downstream
+                    .read(i2c)
+                    .map_err(|err| format_err!("downstream bmp280 {:?}", err))?;
+                Ok(before - after)
+            }
+            Sensors::Sdp810 => {
+                let (pa, _) = sdp810::read(i2c).map_err(|err| format_err!("{:?}", err))?;
+                Ok(pa)
+            }
+        }
+    }
+}
+
+/// Reads the pressure drop across the filter every second. While it is at
+/// least `hvac_fan` the fan counts as running and the runtimes grow, they
+/// are kept in NVS every 5 minutes. The `filter` alert is raised once the
+/// drop stayed at `hvac_clogged` for 5 minutes, or the filter ran
+/// `hvac_filter` hours.
+#[cfg(feature = "hvac")]
+pub fn run(
+    store: &Store,
+    hvac: &Hvac,
+    alerts: &Alerts,
+    bus: &Mutex<I2cDriver<'_>>,
+    partition: EspDefaultNvsPartition,
+) {
+    let watchdog = Watchdog::subscribe("hvac");
+    let health = health::register("hvac");
+    // The store only accepts known sensors.
+    let sensor = store.get().hvac_sensor.parse().unwrap_or_default();
+    let sensors = match Sensors::new(sensor, &mut bus.lock().unwrap()) {
+        Ok(sensors) => sensors,
+        Err(err) => {
+            log::error!("hvac: init error={:?}", err);
+            return;
+        }
+    };
+
+    let mut nvs = match EspNvs::new(partition, NAMESPACE, true) {
+        Ok(nvs) => Some(nvs),
+        Err(err) => {
+            log::error!("hvac: opening nvs error={:?}", err);
+            None
+        }
+    };
+    {
+        let get = |key| {
+            nvs.as_ref()
+                .and_then(|nvs| nvs.get_u32(key).ok().flatten())
+                .unwrap_or(0)
+        };
+        let mut state = hvac.state.lock().unwrap();
+        state.sensor = sensor;
+        state.runtime_secs = f64::from(get("runtime"));
+        state.filter_secs = f64::from(get("filter"));
+        log::info!(
+            "hvac: loaded runtime={}s filter={}s",
+            state.runtime_secs,
+            state.filter_secs
+        );
+    }
+
+    let mut last = Instant::now();
+    let mut saved_at = Instant::now();
+    let mut clogged = false;
+    // Since when the drop is on the other side of `hvac_clogged`.
+    let mut crossed: Option<Instant> = None;
+    loop {
+        health.tick();
+        watchdog.sleep(SAMPLE_INTERVAL);
+
+        let result = sensors.read(&mut bus.lock().unwrap());
+        let elapsed = last.elapsed();
+        last = Instant::now();
+        let pa = match result {
+            Ok(pa) => pa,
+            Err(err) => {
+                log::error!("hvac: reading sensors error={:?}", err);
+                continue;
+            }
+        };
+
+        let settings = store.get();
+        // The store only accepts numbers.
+        let pa = pa - settings.hvac_offset.parse::<f32>().unwrap_or(0.);
+        let running = pa >= settings.hvac_fan_pa as f32;
+        let mut state = hvac.state.lock().unwrap();
+        state.pa_sum += pa;
+        state.samples += 1;
+        state.running = running;
+        if running {
+            state.runtime_secs += elapsed.as_secs_f64();
+            state.filter_secs += elapsed.as_secs_f64();
+        }
+
+        if state.reset {
+            clogged = false;
+        }
+        let above = pa >= settings.hvac_clogged_pa as f32;
+        crossed = if running && above != clogged {
+            crossed.or(Some(Instant::now()))
+        } else {
+            None
+        };
+        if crossed.is_some_and(|since| since.elapsed() >= CLOGGED_AFTER) {
+            clogged = above;
+            crossed = None;
+        }
+        let worn = settings.hvac_filter_hours > 0
+            && state.filter_secs >= f64::from(settings.hvac_filter_hours) * 3600.;
+        let raised = clogged || worn;
+        let events = if raised != state.clogged {
+            if raised {
+                log::warn!(
+                    "hvac: filter needs a change, drop={:.0}Pa after {:.1} hours",
+                    pa,
+                    state.filter_secs / 3600.
+                );
+            } else {
+                log::info!("hvac: filter is fine again");
+            }
+            vec![Event {
+                name: ALERT.into(),
+                urgent: false,
+                raised,
+                value: pa,
+                notified: false,
+            }]
+        } else {
+            Vec::new()
+        };
+        state.clogged = raised;
+
+        let save = state.reset || saved_at.elapsed() >= SAVE_INTERVAL;
+        state.reset = false;
+        let (runtime, filter) = (state.runtime_secs as u32, state.filter_secs as u32);
+        drop(state);
+
+        let active = if raised {
+            vec![(ALERT.into(), false)]
+        } else {
+            Vec::new()
+        };
+        alerts.publish("hvac", active, events);
+        if let (true, Some(nvs)) = (save, &mut nvs) {
+            saved_at = Instant::now();
+            let result = nvs
+                .set_u32("runtime", runtime)
+                .and_then(|_| nvs.set_u32("filter", filter));
+            if let Err(err) = result {
+                log::error!("hvac: saving runtimes error={:?}", err);
+            }
+        }
+    }
+}
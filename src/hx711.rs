@@ -0,0 +1,192 @@
+use embedded_hal::{
+    blocking::delay::DelayUs,
+    digital::v2::{InputPin, OutputPin},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error<E> {
+    Pin(E),
+    /// No conversion is ready, the HX711 holds DOUT high until there is.
+    NotReady,
+}
+
+/// HX711 load cell amplifier on channel A at a gain of 128.
+///
+/// SCK held high for more than 60 µs powers the chip down, so a read must
+/// not be interrupted.
+pub struct Hx711<D, S> {
+    dout: D,
+    sck: S,
+}
+
+impl<D, S, E> Hx711<D, S>
+where
+    D: InputPin<Error = E>,
+    S: OutputPin<Error = E>,
+{
+    pub fn new(dout: D, sck: S) -> Self {
+        Hx711 { dout, sck }
+    }
+
+    /// A conversion is ready once DOUT goes low, 10 times a second.
+    pub fn is_ready(&self) -> Result<bool, Error<E>> {
+        self.dout.is_low().map_err(Error::Pin)
+    }
+
+    /// Shifts out the ready conversion, a signed 24 bit value. The 25th
+    /// pulse selects channel A at a gain of 128 for the next one.
+    pub fn read<T: DelayUs<u16>>(&mut self, delay: &mut T) -> Result<i32, Error<E>> {
+        if !self.is_ready()? {
+            return Err(Error::NotReady);
+        }
+        let mut value = 0u32;
+        for _ in 0..24 {
+            value = value << 1 | u32::from(self.pulse(delay)?);
+        }
+        self.pulse(delay)?;
+        // Sign extends the 24 bits.
+        Ok((value << 8) as i32 >> 8)
+    }
+
+    /// One SCK pulse, returns DOUT as it is during the pulse.
+    fn pulse<T: DelayUs<u16>>(&mut self, delay: &mut T) -> Result<bool, Error<E>> {
+        self.sck.set_high().map_err(Error::Pin)?;
+        delay.delay_us(1);
+        let bit = self.dout.is_high().map_err(Error::Pin)?;
+        self.sck.set_low().map_err(Error::Pin)?;
+        delay.delay_us(1);
+        Ok(bit)
+    }
+}
+
+/// Turns raw readings into kilograms.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Scale {
+    /// Reading of the empty scale.
+    pub tare: i32,
+    /// Counts per kilogram, 0 until calibrated. Negative for a load cell
+    /// wired the other way around.
+    pub counts_per_kg: f32,
+    /// Temperature at the tare, no compensation without it.
+    pub tare_temperature: Option<f32>,
+    /// Drift of the reading in grams per °C away from the tare temperature.
+    pub grams_per_degree: f32,
+}
+
+impl Scale {
+    /// Weight in kilograms at `temperature`, `None` until calibrated.
+    pub fn kg(&self, raw: i32, temperature: Option<f32>) -> Option<f32> {
+        if self.counts_per_kg == 0. {
+            return None;
+        }
+        let kg = (raw - self.tare) as f32 / self.counts_per_kg;
+        let drift = match (temperature, self.tare_temperature) {
+            (Some(now), Some(tare)) => (now - tare) * self.grams_per_degree / 1000.,
+            _ => 0.,
+        };
+        Some(kg - drift)
+    }
+
+    /// Counts per kilogram given the reading with `kg` on the scale.
+    pub fn calibrate(&self, raw: i32, kg: f32) -> Option<f32> {
+        let counts = (raw - self.tare) as f32 / kg;
+        (kg > 0. && counts != 0.).then_some(counts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_hal_mock::eh0::{
+        delay::NoopDelay,
+        digital::{Mock as PinMock, State, Transaction},
+    };
+
+    use super::*;
+
+    /// DOUT reads of a ready conversion of `value`, MSB first.
+    fn dout(value: u32) -> Vec<Transaction> {
+        let mut transactions = vec![Transaction::get(State::Low)];
+        for bit in (0..24).rev() {
+            let high = value >> bit & 1 != 0;
+            transactions.push(Transaction::get(if high {
+                State::High
+            } else {
+                State::Low
+            }));
+        }
+        // The 25th pulse, DOUT goes high until the next conversion.
+        transactions.push(Transaction::get(State::High));
+        transactions
+    }
+
+    fn sck() -> Vec<Transaction> {
+        (0..25)
+            .flat_map(|_| [Transaction::set(State::High), Transaction::set(State::Low)])
+            .collect()
+    }
+
+    fn read(value: u32) -> i32 {
+        let (mut dout, mut sck) = (PinMock::new(&dout(value)), PinMock::new(&sck()));
+        let raw = Hx711::new(dout.clone(), sck.clone())
+            .read(&mut NoopDelay::new())
+            .unwrap();
+        dout.done();
+        sck.done();
+        raw
+    }
+
+    #[test]
+    fn reads_signed_24_bits() {
+        assert_eq!(read(0x000100), 256);
+        assert_eq!(read(0x7FFFFF), 8_388_607);
+        assert_eq!(read(0xFFFFFF), -1);
+        assert_eq!(read(0x800000), -8_388_608);
+    }
+
+    #[test]
+    fn waits_for_conversion() {
+        let mut dout = PinMock::new(&[Transaction::get(State::High)]);
+        let mut sck = PinMock::new(&[]);
+        let result = Hx711::new(dout.clone(), sck.clone()).read(&mut NoopDelay::new());
+        assert_eq!(result, Err(Error::NotReady));
+        dout.done();
+        sck.done();
+    }
+
+    const SCALE: Scale = Scale {
+        tare: 8_000,
+        counts_per_kg: 20_000.,
+        tare_temperature: Some(20.),
+        grams_per_degree: 5.,
+    };
+
+    #[test]
+    fn converts_to_kg() {
+        assert_eq!(SCALE.kg(8_000, Some(20.)), Some(0.));
+        assert_eq!(SCALE.kg(408_000, Some(20.)), Some(20.));
+        assert_eq!(SCALE.kg(-12_000, None), Some(-1.));
+    }
+
+    #[test]
+    fn compensates_temperature() {
+        // 10 °C warmer reads 50 g heavier.
+        assert_eq!(SCALE.kg(409_000, Some(30.)), Some(20.));
+        let untared = Scale {
+            tare_temperature: None,
+            ..SCALE
+        };
+        assert_eq!(untared.kg(409_000, Some(30.)), Some(20.05));
+    }
+
+    #[test]
+    fn needs_calibration() {
+        let scale = Scale {
+            counts_per_kg: 0.,
+            ..SCALE
+        };
+        assert_eq!(scale.kg(408_000, Some(20.)), None);
+        assert_eq!(scale.calibrate(208_000, 10.), Some(20_000.));
+        assert_eq!(scale.calibrate(8_000, 10.), None);
+        assert_eq!(scale.calibrate(208_000, 0.), None);
+    }
+}
@@ -0,0 +1,178 @@
+use std::{
+    fmt::{self, Write},
+    time::Instant,
+};
+
+use crate::error::{self, bail, Code, Context};
+use crate::metrics::SenderMetrics;
+use crate::point::{self, Point};
+
+/// Longest write URL or authorization header.
+pub const MAX_HEADER_LEN: usize = 512;
+
+/// A write URL or authorization header, built once per connection outside
+/// the heap so long uptimes do not fragment it.
+pub type Header = heapless::String<MAX_HEADER_LEN>;
+
+/// Formats a [`Header`], e.g. `header(format_args!("Token {}", token))`.
+pub fn header(args: fmt::Arguments) -> error::Result<Header> {
+    let mut header = Header::new();
+    if header.write_fmt(args).is_err() {
+        bail!(
+            Code::Config,
+            "header is longer than {} bytes",
+            MAX_HEADER_LEN
+        );
+    }
+    Ok(header)
+}
+
+/// A request body, handed to the connection in pieces so that a large
+/// batch never has to be in RAM whole. Its length is known up front and
+/// sent as `content-length`, so no chunked encoding is needed.
+pub trait Body {
+    fn content_length(&self) -> usize;
+
+    /// Passes the body to `write` piece by piece. Called once per attempt.
+    fn write_to(&mut self, write: &mut dyn FnMut(&[u8]) -> error::Result<()>) -> error::Result<()>;
+}
+
+/// A body already in RAM, written in one piece.
+impl<T: AsRef<[u8]>> Body for T {
+    fn content_length(&self) -> usize {
+        self.as_ref().len()
+    }
+
+    fn write_to(&mut self, write: &mut dyn FnMut(&[u8]) -> error::Result<()>) -> error::Result<()> {
+        write(self.as_ref())
+    }
+}
+
+/// Points encoded as line protocol one line at a time into `line`, which
+/// is kept across batches and so only ever grows to the longest line.
+pub struct LineProtocol<'a> {
+    points: &'a [Point],
+    line: &'a mut Vec<u8>,
+}
+
+impl<'a> LineProtocol<'a> {
+    pub fn new(points: &'a [Point], line: &'a mut Vec<u8>) -> Self {
+        LineProtocol { points, line }
+    }
+}
+
+impl Body for LineProtocol<'_> {
+    fn content_length(&self) -> usize {
+        point::encoded_len(self.points)
+    }
+
+    fn write_to(&mut self, write: &mut dyn FnMut(&[u8]) -> error::Result<()>) -> error::Result<()> {
+        for point in self.points {
+            self.line.clear();
+            point::encode_line(point, self.line);
+            if !self.line.is_empty() {
+                write(self.line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// HTTP connection the writes go over: the ESP-IDF client on the device,
+/// a host client in the simulator.
+pub trait Connection {
+    /// Posts `body` with `headers`, reads the response and returns its
+    /// status. Errors are for requests that got no response.
+    fn post(
+        &mut self,
+        addr: &str,
+        headers: &[(&str, &str)],
+        body: &mut dyn Body,
+    ) -> error::Result<u16>;
+}
+
+/// Writes line protocol to the InfluxDB v2 write API over a kept-alive
+/// connection, opening a fresh one when a write fails. Other write APIs
+/// get their format with [`Client::with_content_type`].
+pub struct Client<C, F> {
+    connection: C,
+    connect: F,
+    content_type: &'static str,
+}
+
+impl<C, F> Client<C, F>
+where
+    C: Connection,
+    F: FnMut() -> error::Result<C>,
+{
+    pub fn new(mut connect: F) -> error::Result<Self> {
+        Ok(Client {
+            connection: connect()?,
+            connect,
+            content_type: "text/plain",
+        })
+    }
+
+    pub fn with_content_type(mut self, content_type: &'static str) -> Self {
+        self.content_type = content_type;
+        self
+    }
+
+    /// Writes a body and returns the status, recording every attempt in
+    /// the metrics. An empty `token` sends no authorization header. A
+    /// request without response is retried once on a fresh connection, as
+    /// the server may have closed the kept-alive one. Errors are of
+    /// [`Code::Http`].
+    pub fn write(
+        &mut self,
+        metrics: &mut SenderMetrics,
+        addr: &str,
+        token: &str,
+        mut body: impl Body,
+    ) -> error::Result<u16> {
+        match self.timed_post(metrics, addr, token, &mut body) {
+            Ok(status) => Ok(status),
+            Err(err) => {
+                log::warn!("http post failed, reopening connection error={:?}", err);
+                self.connection = (self.connect)().code(Code::Http)?;
+                self.timed_post(metrics, addr, token, &mut body)
+                    .code(Code::Http)
+            }
+        }
+    }
+
+    fn timed_post(
+        &mut self,
+        metrics: &mut SenderMetrics,
+        addr: &str,
+        token: &str,
+        body: &mut dyn Body,
+    ) -> error::Result<u16> {
+        let len = body.content_length();
+        let mut content_length = heapless::String::<20>::new();
+        // A usize has at most 20 digits.
+        let _ = write!(content_length, "{}", len);
+        let headers = [
+            ("authorization", token),
+            ("accept", "application/json"),
+            ("content-type", self.content_type),
+            ("connection", "keep-alive"),
+            ("content-length", &*content_length),
+        ];
+        let headers = if token.is_empty() {
+            &headers[1..]
+        } else {
+            &headers[..]
+        };
+
+        let started = Instant::now();
+        let result = self.connection.post(addr, headers, body);
+        metrics.record(len, started.elapsed(), result.as_ref().ok().copied());
+        match result {
+            Ok(status) if (200..300).contains(&status) => log::trace!("http post success!"),
+            Ok(status) => log::error!("http status code={}", status),
+            Err(_) => {}
+        }
+        result
+    }
+}
@@ -0,0 +1,97 @@
+use std::time::Duration;
+
+/// Drying takes back growth hours at this fraction of the elapsed time, so
+/// short dry spells do not undo days of damp.
+const DRY_RATE: f32 = 0.5;
+
+/// Lowest relative humidity in % at which mould grows at `temperature` in
+/// °C: the LIM B I isopleth of DIN 4108 and Sedlbauer, as fitted by Hukka
+/// and Viitanen. It is 80 % from 20 °C up and rises towards the cold end,
+/// nothing grows at 0 °C and below.
+pub fn critical_humidity(temperature: f32) -> Option<f32> {
+    if temperature <= 0. {
+        return None;
+    }
+    let t = temperature.min(20.);
+    Some(-0.00267 * t * t * t + 0.160 * t * t - 3.13 * t + 100.)
+}
+
+/// Time weighted mould risk: hours with humidity at or above the isopleth
+/// add up, drier hours take back half as much.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Index {
+    hours: f32,
+}
+
+impl Index {
+    pub fn new(hours: f32) -> Index {
+        Index {
+            hours: hours.max(0.),
+        }
+    }
+
+    /// Accumulated growth hours.
+    pub fn hours(&self) -> f32 {
+        self.hours
+    }
+
+    /// Adds a reading that held for `elapsed`. The hours stop at `limit`,
+    /// a risk of 100.
+    pub fn update(&mut self, temperature: f32, humidity: f32, elapsed: Duration, limit: f32) {
+        let hours = elapsed.as_secs_f32() / 3600.;
+        let favourable = critical_humidity(temperature).is_some_and(|rh| humidity >= rh);
+        self.hours = if favourable {
+            (self.hours + hours).min(limit)
+        } else {
+            (self.hours - hours * DRY_RATE).max(0.)
+        };
+    }
+
+    /// Risk from 0 to 100, where 100 means `limit` hours of growth.
+    pub fn risk(&self, limit: f32) -> f32 {
+        if limit <= 0. {
+            return 0.;
+        }
+        (self.hours / limit * 100.).min(100.)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    #[test]
+    fn isopleth_rises_towards_the_cold() {
+        assert_eq!(critical_humidity(0.), None);
+        assert_eq!(critical_humidity(-5.), None);
+        assert!((critical_humidity(5.).unwrap() - 88.).abs() < 0.1);
+        assert!((critical_humidity(20.).unwrap() - 80.).abs() < 0.1);
+        assert_eq!(critical_humidity(30.), critical_humidity(20.));
+    }
+
+    #[test]
+    fn damp_hours_add_up() {
+        let mut index = Index::default();
+        for _ in 0..60 {
+            index.update(21., 85., HOUR, 120.);
+        }
+        assert_eq!(index.hours(), 60.);
+        assert_eq!(index.risk(120.), 50.);
+        for _ in 0..100 {
+            index.update(21., 85., HOUR, 120.);
+        }
+        assert_eq!(index.risk(120.), 100.);
+    }
+
+    #[test]
+    fn drying_is_slower() {
+        let mut index = Index::new(10.);
+        // 85 % is below the isopleth at 5 °C.
+        index.update(5., 85., 4 * HOUR, 120.);
+        assert_eq!(index.hours(), 8.);
+        index.update(0., 100., 20 * HOUR, 120.);
+        assert_eq!(index.hours(), 0.);
+    }
+}
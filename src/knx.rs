@@ -0,0 +1,246 @@
+use std::{
+    io::ErrorKind,
+    net::UdpSocket,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::{self, bail, Code, Context},
+    health,
+    knxnet::{self, Frame},
+    settings::Store,
+    state::State,
+    watchdog::Watchdog,
+};
+
+/// How long a receive waits before new readings are checked.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// How long the gateway has to answer a request.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(1);
+/// Connection state requests keep the tunnel open, the gateway drops it
+/// after 120 seconds without.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60);
+const RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// An open tunnel to the gateway.
+struct Tunnel {
+    socket: UdpSocket,
+    channel: u8,
+    sequence: u8,
+    last_heartbeat: Instant,
+}
+
+impl Tunnel {
+    fn connect(gateway: &str) -> error::Result<Tunnel> {
+        let socket = UdpSocket::bind("0.0.0.0:0").context("bind knx socket")?;
+        if gateway.contains(':') {
+            socket.connect(gateway)
+        } else {
+            socket.connect((gateway, knxnet::PORT))
+        }
+        .context("resolve knx_gateway")?;
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+        socket.send(&knxnet::connect_request())?;
+
+        let deadline = Instant::now() + RESPONSE_TIMEOUT;
+        while Instant::now() < deadline {
+            match receive(&socket)? {
+                Some(Frame::Connect { status: 0, channel }) => {
+                    return Ok(Tunnel {
+                        socket,
+                        channel,
+                        sequence: 0,
+                        last_heartbeat: Instant::now(),
+                    })
+                }
+                // E.g. 0x24 when all its tunnels are taken.
+                Some(Frame::Connect { status, .. }) => {
+                    bail!(
+                        Code::Protocol,
+                        "knx gateway refused the connection status={:#04x}",
+                        status
+                    )
+                }
+                _ => {}
+            }
+        }
+        bail!(
+            Code::Timeout,
+            "knx gateway did not answer the connection request"
+        )
+    }
+
+    /// Acknowledges telegrams and confirmations the gateway sends, returns
+    /// what else came.
+    fn poll(&mut self) -> error::Result<Option<Frame>> {
+        let frame = receive(&self.socket)?;
+        match frame {
+            Some(Frame::Tunneling { channel, sequence }) if channel == self.channel => {
+                self.socket
+                    .send(&knxnet::tunneling_ack(channel, sequence))?;
+                Ok(None)
+            }
+            Some(Frame::Disconnect { channel }) if channel == self.channel => {
+                self.socket.send(&knxnet::disconnect_response(channel))?;
+                bail!(Code::Protocol, "knx gateway closed the tunnel")
+            }
+            frame => Ok(frame),
+        }
+    }
+
+    /// Writes `value` to `group` and waits for the gateway to acknowledge
+    /// it, sending it once more if it does not.
+    fn write(&mut self, group: u16, value: &[u8]) -> error::Result<()> {
+        let request = knxnet::group_write(self.channel, self.sequence, group, value);
+        for _ in 0..2 {
+            self.socket.send(&request)?;
+            let deadline = Instant::now() + RESPONSE_TIMEOUT;
+            while Instant::now() < deadline {
+                if let Some(Frame::TunnelingAck {
+                    channel,
+                    sequence,
+                    status,
+                }) = self.poll()?
+                {
+                    if channel != self.channel || sequence != self.sequence {
+                        continue;
+                    }
+                    if status != 0 {
+                        bail!(
+                            Code::Protocol,
+                            "knx gateway rejected a write status={:#04x}",
+                            status
+                        );
+                    }
+                    self.sequence = self.sequence.wrapping_add(1);
+                    return Ok(());
+                }
+            }
+        }
+        bail!(Code::Timeout, "knx gateway did not acknowledge a write")
+    }
+
+    fn heartbeat(&mut self) -> error::Result<()> {
+        self.socket
+            .send(&knxnet::connectionstate_request(self.channel))?;
+        let deadline = Instant::now() + RESPONSE_TIMEOUT;
+        while Instant::now() < deadline {
+            match self.poll()? {
+                Some(Frame::ConnectionState { status: 0, .. }) => {
+                    self.last_heartbeat = Instant::now();
+                    return Ok(());
+                }
+                Some(Frame::ConnectionState { status, .. }) => {
+                    bail!(
+                        Code::Protocol,
+                        "knx connection state status={:#04x}",
+                        status
+                    )
+                }
+                _ => {}
+            }
+        }
+        bail!(
+            Code::Timeout,
+            "knx gateway did not answer the connection state request"
+        )
+    }
+}
+
+impl Drop for Tunnel {
+    fn drop(&mut self) {
+        // Frees the tunnel on the gateway, which has only a few.
+        let _ = self.socket.send(&knxnet::disconnect_request(self.channel));
+    }
+}
+
+/// A frame from the socket, `None` on a timeout or a frame that does not
+/// parse.
+fn receive(socket: &UdpSocket) -> error::Result<Option<Frame>> {
+    let mut buf = [0u8; 64];
+    let len = match socket.recv(&mut buf) {
+        Ok(len) => len,
+        Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+            return Ok(None)
+        }
+        Err(err) => return Err(err.into()),
+    };
+    match knxnet::parse(&buf[..len]) {
+        Ok(frame) => Ok(Some(frame)),
+        Err(err) => {
+            log::debug!("knx: ignoring frame error={:#}", err);
+            Ok(None)
+        }
+    }
+}
+
+/// Writes each reading through a KNXnet/IP tunnel to `knx_gateway`, the
+/// temperature to the `knx_temperature` and the humidity to the
+/// `knx_humidity` group address as 2-byte floats. The tunnel is reopened
+/// when the gateway stops answering.
+pub fn run(state: &State, store: &Store) {
+    let watchdog = Watchdog::subscribe("knx");
+    let health = health::register("knx");
+    let mut tunnel: Option<Tunnel> = None;
+    let mut next_connect = Instant::now();
+    let mut seen = None;
+    loop {
+        health.tick();
+        watchdog.feed();
+
+        let settings = store.get();
+        if settings.knx_gateway.is_empty() {
+            tunnel = None;
+            watchdog.sleep(RECONNECT_DELAY);
+            continue;
+        }
+        if tunnel.is_none() && Instant::now() >= next_connect {
+            match Tunnel::connect(&settings.knx_gateway) {
+                Ok(connected) => {
+                    log::info!(
+                        "knx: connected to {} channel={}",
+                        settings.knx_gateway,
+                        connected.channel
+                    );
+                    tunnel = Some(connected);
+                }
+                Err(err) => {
+                    log::warn!("knx: connecting error={:#}", err);
+                    next_connect = Instant::now() + RECONNECT_DELAY;
+                }
+            }
+        }
+        let Some(connected) = tunnel.as_mut() else {
+            watchdog.sleep(POLL_INTERVAL);
+            continue;
+        };
+
+        // Each reading is written once, the one read before a connect too.
+        let snapshot = state.latest().filter(|s| Some(s.sequence) != seen);
+        let latest = snapshot.map(|s| s.data);
+        // The store only accepts valid group addresses.
+        let groups = [
+            (&settings.knx_temperature, latest.map(|d| d.temperature)),
+            (&settings.knx_humidity, latest.map(|d| d.humidity)),
+        ];
+        let result = groups
+            .into_iter()
+            .filter(|(group, _)| !group.is_empty())
+            .filter_map(|(group, value)| Some((knxnet::parse_group(group).ok()?, value?)))
+            .try_for_each(|(group, value)| connected.write(group, &knxnet::dpt9(value)))
+            .and_then(|()| {
+                if connected.last_heartbeat.elapsed() >= HEARTBEAT_INTERVAL {
+                    connected.heartbeat()
+                } else {
+                    connected.poll().map(drop)
+                }
+            });
+        if let Err(err) = result {
+            log::warn!("knx: reconnecting error={:#}", err);
+            tunnel = None;
+            next_connect = Instant::now() + RECONNECT_DELAY;
+        } else if let Some(snapshot) = snapshot {
+            seen = Some(snapshot.sequence);
+        }
+    }
+}
@@ -0,0 +1,241 @@
+//! KNXnet/IP tunneling frames and the KNX group addresses and 2-byte float
+//! values (DPT 9) they carry, as far as writing to group addresses goes.
+
+use crate::error::{self, bail, Context};
+pub const PORT: u16 = 3671;
+
+const CONNECT_REQUEST: u16 = 0x0205;
+const CONNECT_RESPONSE: u16 = 0x0206;
+const CONNECTIONSTATE_REQUEST: u16 = 0x0207;
+const CONNECTIONSTATE_RESPONSE: u16 = 0x0208;
+const DISCONNECT_REQUEST: u16 = 0x0209;
+const DISCONNECT_RESPONSE: u16 = 0x020A;
+const TUNNELING_REQUEST: u16 = 0x0420;
+const TUNNELING_ACK: u16 = 0x0421;
+
+const HEADER_LEN: u8 = 6;
+const VERSION: u8 = 0x10;
+/// cEMI message code of a data request to the bus.
+const L_DATA_REQ: u8 = 0x11;
+
+/// Parses a group address as `main/middle/sub` or `main/sub`.
+pub fn parse_group(s: &str) -> error::Result<u16> {
+    let parts = s
+        .split('/')
+        .map(|part| part.trim().parse::<u16>())
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("group address {:?} is not numbers separated by /", s))?;
+    match parts[..] {
+        [main, middle, sub] if main < 32 && middle < 8 && sub < 256 => {
+            Ok(main << 11 | middle << 8 | sub)
+        }
+        [main, sub] if main < 32 && sub < 2048 => Ok(main << 11 | sub),
+        _ => bail!(
+            "group address {:?} must be main/middle/sub up to 31/7/255 or main/sub up to 31/2047",
+            s
+        ),
+    }
+}
+
+/// Encodes a value as a KNX 2-byte float (DPT 9), `0.01 * mantissa * 2^exponent`.
+/// Values out of its range saturate.
+pub fn dpt9(value: f32) -> [u8; 2] {
+    let mut exponent = 0;
+    let mut mantissa = (value * 100.).round();
+    while !(-2048. ..=2047.).contains(&mantissa) && exponent < 15 {
+        exponent += 1;
+        mantissa = (value * 100. / (1 << exponent) as f32).round();
+    }
+    let mantissa = mantissa.clamp(-2048., 2047.) as i16;
+    let sign = if mantissa < 0 { 0x8000 } else { 0 };
+    let raw = sign | exponent << 11 | (mantissa as u16 & 0x07FF);
+    raw.to_be_bytes()
+}
+
+/// A frame received from the gateway.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Frame {
+    Connect {
+        channel: u8,
+        status: u8,
+    },
+    ConnectionState {
+        channel: u8,
+        status: u8,
+    },
+    /// A telegram from the bus or a confirmation, it has to be acknowledged.
+    Tunneling {
+        channel: u8,
+        sequence: u8,
+    },
+    TunnelingAck {
+        channel: u8,
+        sequence: u8,
+        status: u8,
+    },
+    /// The gateway closes the connection.
+    Disconnect {
+        channel: u8,
+    },
+    DisconnectResponse,
+    Other(u16),
+}
+
+pub fn parse(bytes: &[u8]) -> error::Result<Frame> {
+    let [HEADER_LEN, VERSION, service_high, service_low, len_high, len_low, body @ ..] = bytes
+    else {
+        bail!("knx frame without a KNXnet/IP 1.0 header");
+    };
+    let len = usize::from(u16::from_be_bytes([*len_high, *len_low]));
+    if len != bytes.len() {
+        bail!("knx frame of {} bytes says it has {}", bytes.len(), len);
+    }
+    let service = u16::from_be_bytes([*service_high, *service_low]);
+    let frame = match (service, body) {
+        (CONNECT_RESPONSE, [channel, status, ..]) => Frame::Connect {
+            channel: *channel,
+            status: *status,
+        },
+        (CONNECTIONSTATE_RESPONSE, [channel, status, ..]) => Frame::ConnectionState {
+            channel: *channel,
+            status: *status,
+        },
+        (TUNNELING_REQUEST, [4, channel, sequence, _, ..]) => Frame::Tunneling {
+            channel: *channel,
+            sequence: *sequence,
+        },
+        (TUNNELING_ACK, [4, channel, sequence, status]) => Frame::TunnelingAck {
+            channel: *channel,
+            sequence: *sequence,
+            status: *status,
+        },
+        (DISCONNECT_REQUEST, [channel, ..]) => Frame::Disconnect { channel: *channel },
+        (DISCONNECT_RESPONSE, _) => Frame::DisconnectResponse,
+        (CONNECT_RESPONSE | CONNECTIONSTATE_RESPONSE | TUNNELING_REQUEST | TUNNELING_ACK, _) => {
+            bail!("knx service {:#06x} with a malformed body", service)
+        }
+        (service, _) => Frame::Other(service),
+    };
+    Ok(frame)
+}
+
+fn frame(service: u16, body: &[u8]) -> Vec<u8> {
+    let len = (usize::from(HEADER_LEN) + body.len()) as u16;
+    let mut frame = vec![HEADER_LEN, VERSION];
+    frame.extend_from_slice(&service.to_be_bytes());
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.extend_from_slice(body);
+    frame
+}
+
+/// UDP endpoint of all zeros, the gateway answers to where the frame came
+/// from, which also works behind NAT.
+const ROUTE_BACK: [u8; 8] = [0x08, 0x01, 0, 0, 0, 0, 0, 0];
+
+/// Opens a tunnel on the data link layer.
+pub fn connect_request() -> Vec<u8> {
+    let mut body = Vec::with_capacity(20);
+    // Control and data endpoint.
+    body.extend_from_slice(&ROUTE_BACK);
+    body.extend_from_slice(&ROUTE_BACK);
+    body.extend_from_slice(&[0x04, 0x04, 0x02, 0x00]);
+    frame(CONNECT_REQUEST, &body)
+}
+
+/// Keeps the tunnel open, the gateway drops it after 120 seconds without.
+pub fn connectionstate_request(channel: u8) -> Vec<u8> {
+    let mut body = vec![channel, 0];
+    body.extend_from_slice(&ROUTE_BACK);
+    frame(CONNECTIONSTATE_REQUEST, &body)
+}
+
+pub fn disconnect_request(channel: u8) -> Vec<u8> {
+    let mut body = vec![channel, 0];
+    body.extend_from_slice(&ROUTE_BACK);
+    frame(DISCONNECT_REQUEST, &body)
+}
+
+pub fn disconnect_response(channel: u8) -> Vec<u8> {
+    frame(DISCONNECT_RESPONSE, &[channel, 0])
+}
+
+pub fn tunneling_ack(channel: u8, sequence: u8) -> Vec<u8> {
+    frame(TUNNELING_ACK, &[0x04, channel, sequence, 0])
+}
+
+/// A GroupValueWrite of `value` to `group`.
+pub fn group_write(channel: u8, sequence: u8, group: u16, value: &[u8]) -> Vec<u8> {
+    let mut body = vec![0x04, channel, sequence, 0];
+    // Standard frame, low priority, group destination, hop count 6, the
+    // gateway fills in its own source address.
+    body.extend_from_slice(&[L_DATA_REQ, 0, 0xBC, 0xE0, 0, 0]);
+    body.extend_from_slice(&group.to_be_bytes());
+    body.push(value.len() as u8 + 1);
+    // Unnumbered data, GroupValueWrite.
+    body.extend_from_slice(&[0x00, 0x80]);
+    body.extend_from_slice(value);
+    frame(TUNNELING_REQUEST, &body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_group_addresses() {
+        assert_eq!(parse_group("1/2/3").unwrap(), 0x0A03);
+        assert_eq!(parse_group("31/7/255").unwrap(), 0xFFFF);
+        assert_eq!(parse_group("1/515").unwrap(), 0x0A03);
+        assert!(parse_group("32/0/0").is_err());
+        assert!(parse_group("1/8/0").is_err());
+        assert!(parse_group("1/2048").is_err());
+        assert!(parse_group("1").is_err());
+        assert!(parse_group("1/a/3").is_err());
+    }
+
+    #[test]
+    fn encodes_2_byte_floats() {
+        assert_eq!(dpt9(0.), [0x00, 0x00]);
+        assert_eq!(dpt9(21.5), [0x0C, 0x33]);
+        assert_eq!(dpt9(-4.25), [0x86, 0x57]);
+        assert_eq!(dpt9(60.), [0x15, 0xDC]);
+        // The largest value, 2047 * 2^15 / 100.
+        assert_eq!(dpt9(1e9), [0x7F, 0xFF]);
+    }
+
+    #[test]
+    fn builds_a_group_write() {
+        assert_eq!(
+            group_write(7, 2, 0x0A03, &dpt9(21.5)),
+            [
+                0x06, 0x10, 0x04, 0x20, 0x00, 0x17, 0x04, 0x07, 0x02, 0x00, 0x11, 0x00, 0xBC, 0xE0,
+                0x00, 0x00, 0x0A, 0x03, 0x03, 0x00, 0x80, 0x0C, 0x33
+            ]
+        );
+        assert_eq!(connect_request().len(), 26);
+    }
+
+    #[test]
+    fn parses_gateway_frames() {
+        let connect = [0x06, 0x10, 0x02, 0x06, 0x00, 0x08, 0x15, 0x00];
+        assert_eq!(
+            parse(&connect).unwrap(),
+            Frame::Connect {
+                channel: 0x15,
+                status: 0
+            }
+        );
+        let ack = [0x06, 0x10, 0x04, 0x21, 0x00, 0x0A, 0x04, 0x15, 0x02, 0x00];
+        assert_eq!(
+            parse(&ack).unwrap(),
+            Frame::TunnelingAck {
+                channel: 0x15,
+                sequence: 2,
+                status: 0
+            }
+        );
+        // Length mismatch.
+        assert!(parse(&[0x06, 0x10, 0x02, 0x06, 0x00, 0x09, 0x15, 0x00]).is_err());
+        assert!(parse(&[0x06, 0x10, 0x04, 0x21, 0x00, 0x07, 0x04]).is_err());
+    }
+}
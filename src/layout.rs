@@ -0,0 +1,171 @@
+use crate::error::{self, bail, Context};
+use crate::onewire::parse_rom;
+
+/// Highest rack unit, racks are at most 52U tall.
+pub const MAX_U: u32 = 52;
+
+/// Parses the sensors of a rack, comma separated `rom:u` pairs of a ROM
+/// code and the rack unit it is mounted at, e.g.
+/// `28ff4a1b2c3d4e62:1,28ff4a1b2c3d4f3c:21`.
+pub fn parse_sensors(spec: &str) -> error::Result<Vec<([u8; 8], u32)>> {
+    let mut sensors: Vec<([u8; 8], u32)> = Vec::new();
+    for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let Some((rom, u)) = entry.split_once(':') else {
+            bail!("rack sensor {:?} must be written as rom:u", entry);
+        };
+        let rom = parse_rom(rom)?;
+        let u = parse_u(u)?;
+        if sensors.iter().any(|&(r, v)| r == rom || v == u) {
+            bail!("rack sensor {:?} repeats a rom or unit", entry);
+        }
+        sensors.push((rom, u));
+    }
+    Ok(sensors)
+}
+
+/// Part of a rack with its own temperature limit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Zone {
+    pub name: String,
+    /// Lowest and highest rack unit of the zone.
+    pub first: u32,
+    pub last: u32,
+    /// Temperature in °C that raises the zone's alert.
+    pub max: f32,
+}
+
+impl Zone {
+    pub fn contains(&self, u: u32) -> bool {
+        (self.first..=self.last).contains(&u)
+    }
+
+    /// Highest of the `readings`, rack unit and °C, in the zone.
+    pub fn hottest(&self, readings: &[(u32, f32)]) -> Option<f32> {
+        readings
+            .iter()
+            .filter(|&&(u, _)| self.contains(u))
+            .map(|&(_, t)| t)
+            .reduce(f32::max)
+    }
+}
+
+/// Parses comma separated `name:first-last:max` zones, e.g.
+/// `bottom:1-14:27,top:29-42:35`. Zones must not overlap.
+pub fn parse_zones(spec: &str) -> error::Result<Vec<Zone>> {
+    let mut zones: Vec<Zone> = Vec::new();
+    for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let mut parts = entry.split(':').map(str::trim);
+        let (Some(name), Some(units), Some(max), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            bail!(
+                "rack zone {:?} must be written as name:first-last:max",
+                entry
+            );
+        };
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            bail!("rack zone name {:?} must be letters, digits and _", name);
+        }
+        if name == "delta" {
+            bail!("rack zone name delta is taken by the rack_delta alert");
+        }
+        let Some((first, last)) = units.split_once('-') else {
+            bail!(
+                "rack zone {:?} must be written as name:first-last:max",
+                entry
+            );
+        };
+        let zone = Zone {
+            name: name.into(),
+            first: parse_u(first)?,
+            last: parse_u(last)?,
+            max: max.parse().context("parse zone temperature")?,
+        };
+        if zone.first > zone.last {
+            bail!("rack zone {:?} ends below its start", entry);
+        }
+        if zones
+            .iter()
+            .any(|z| z.name == zone.name || (z.first <= zone.last && zone.first <= z.last))
+        {
+            bail!(
+                "rack zone {:?} repeats a name or overlaps another zone",
+                entry
+            );
+        }
+        zones.push(zone);
+    }
+    Ok(zones)
+}
+
+fn parse_u(s: &str) -> error::Result<u32> {
+    let u: u32 = s.trim().parse().context("parse rack unit")?;
+    if !(1..=MAX_U).contains(&u) {
+        bail!("rack unit {} is not between 1 and {}", u, MAX_U);
+    }
+    Ok(u)
+}
+
+/// Temperature at the highest rack unit minus that at the lowest, `None`
+/// with fewer than two readings.
+pub fn delta(readings: &[(u32, f32)]) -> Option<f32> {
+    let (_, bottom) = readings.iter().min_by_key(|&&(u, _)| u)?;
+    let (_, top) = readings.iter().max_by_key(|&&(u, _)| u)?;
+    (readings.len() > 1).then(|| top - bottom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The example of Maxim application note 27.
+    const ROM: &str = "021cb801000000a2";
+
+    #[test]
+    fn parses_sensors() {
+        let spec = format!("{}:1, 28ff4a1b2c3d4e62 : 21", ROM);
+        let sensors = parse_sensors(&spec).unwrap();
+        assert_eq!(sensors.len(), 2);
+        assert_eq!(sensors[0], (parse_rom(ROM).unwrap(), 1));
+        assert_eq!(sensors[1].1, 21);
+        assert!(parse_sensors("").unwrap().is_empty());
+        assert!(parse_sensors(&format!("{}:0", ROM)).is_err());
+        assert!(parse_sensors(&format!("{}:53", ROM)).is_err());
+        assert!(parse_sensors(&format!("{}:1,{}:2", ROM, ROM)).is_err());
+        assert!(parse_sensors("021cb801000000a3:1").is_err());
+    }
+
+    #[test]
+    fn parses_zones() {
+        let zones = parse_zones("bottom:1-14:27, top:29-42:35.5").unwrap();
+        assert_eq!(
+            zones[1],
+            Zone {
+                name: "top".into(),
+                first: 29,
+                last: 42,
+                max: 35.5,
+            }
+        );
+        assert!(parse_zones("a:1-14").is_err());
+        assert!(parse_zones("a:14-1:30").is_err());
+        assert!(parse_zones("a b:1-14:30").is_err());
+        assert!(parse_zones("delta:1-14:30").is_err());
+        assert!(parse_zones("a:1-14:30,b:14-20:30").is_err());
+        assert!(parse_zones("a:1-14:30,a:15-20:30").is_err());
+    }
+
+    #[test]
+    fn finds_the_hottest_and_the_delta() {
+        let readings = [(21, 26.), (1, 22.5), (42, 31.), (14, 24.)];
+        let zones = parse_zones("bottom:1-14:27,top:29-42:35").unwrap();
+        assert_eq!(zones[0].hottest(&readings), Some(24.));
+        assert_eq!(zones[1].hottest(&readings), Some(31.));
+        assert_eq!(
+            parse_zones("mid:15-20:30").unwrap()[0].hottest(&readings),
+            None
+        );
+        assert_eq!(delta(&readings), Some(8.5));
+        assert_eq!(delta(&readings[..1]), None);
+    }
+}
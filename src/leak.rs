@@ -0,0 +1,100 @@
+use std::time::Duration;
+
+use crate::{
+    adc, alert::Alerts, broadcast, error, health, settings::Store, watchdog::Watchdog, SensorData,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// Consecutive samples needed to change between wet and dry.
+const CONFIRM_SAMPLES: u32 = 3;
+/// Name of the alert raised while wet.
+const ALERT: &str = "leak";
+
+/// Leak probe, a digital input that is low while wet or an ADC input that
+/// reads at least the threshold while wet.
+enum Probe {
+    Digital(i32),
+    Adc {
+        channel: adc::Channel,
+        threshold: i32,
+    },
+}
+
+impl Probe {
+    fn new(pin: i32, threshold: u32) -> error::Result<Probe> {
+        use esp_idf_sys::*;
+
+        if threshold == 0 {
+            unsafe {
+                esp!(gpio_reset_pin(pin))?;
+                esp!(gpio_set_direction(pin, gpio_mode_t_GPIO_MODE_INPUT))?;
+                esp!(gpio_set_pull_mode(pin, gpio_pull_mode_t_GPIO_PULLUP_ONLY))?;
+            }
+            return Ok(Probe::Digital(pin));
+        }
+
+        Ok(Probe::Adc {
+            channel: adc::Channel::new(pin)?,
+            threshold: threshold as i32,
+        })
+    }
+
+    /// Whether the probe is wet, and the raw level or ADC reading.
+    fn read(&self) -> error::Result<(bool, f32)> {
+        match self {
+            Probe::Digital(pin) => {
+                let level = unsafe { esp_idf_sys::gpio_get_level(*pin) };
+                Ok((level == 0, level as f32))
+            }
+            Probe::Adc { channel, threshold } => {
+                let raw = channel.read()?;
+                Ok((raw >= *threshold, raw as f32))
+            }
+        }
+    }
+}
+
+/// Watches the leak probe. Getting wet raises the urgent `leak` alert and
+/// wakes the sender, so the alert point and push notification go out
+/// right away and the buzzer sounds, regardless of the send interval.
+pub fn run(store: &Store, alerts: &Alerts, readings: &broadcast::Sender<SensorData>, pin: i32) {
+    let watchdog = Watchdog::subscribe("leak");
+    let health = health::register("leak");
+    let probe = match Probe::new(pin, store.get().leak_threshold) {
+        Ok(probe) => probe,
+        Err(err) => {
+            log::error!("leak: init error={:?}", err);
+            return;
+        }
+    };
+
+    let mut wet = false;
+    let mut streak = 0;
+    loop {
+        health.tick();
+        watchdog.sleep(POLL_INTERVAL);
+
+        let (sample, value) = match probe.read() {
+            Ok(reading) => reading,
+            Err(err) => {
+                log::error!("leak: reading probe error={:?}", err);
+                continue;
+            }
+        };
+        streak = if sample == wet { 0 } else { streak + 1 };
+        if streak < CONFIRM_SAMPLES {
+            continue;
+        }
+
+        wet = sample;
+        streak = 0;
+        if wet {
+            log::warn!("leak: probe is wet value={}", value);
+        } else {
+            log::info!("leak: probe is dry again value={}", value);
+        }
+        if alerts.set_external(ALERT, wet, value) {
+            readings.wake();
+        }
+    }
+}
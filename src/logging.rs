@@ -0,0 +1,116 @@
+use std::{collections::VecDeque, str::FromStr, sync::Mutex};
+
+use esp_idf_svc::log::EspLogger;
+use log::{LevelFilter, Log, Metadata, Record};
+
+use crate::device;
+use crate::error::{self, bail, Context};
+
+/// How many bytes of the most recent log output are kept in memory.
+const RING_LEN: usize = 4 * 1024;
+
+static LOGGER: RingLogger = RingLogger {
+    ring: Mutex::new(VecDeque::new()),
+};
+
+/// Forwards records to [`EspLogger`] and keeps a copy of the latest ones.
+///
+/// Only output of the `log` crate is captured, not native ESP-IDF components.
+struct RingLogger {
+    ring: Mutex<VecDeque<u8>>,
+}
+
+impl Log for RingLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        EspLogger.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        EspLogger.log(record);
+
+        let line = format!(
+            "{} ({}) {}: {}\n",
+            record.level().as_str().chars().next().unwrap_or('?'),
+            device::uptime().as_millis(),
+            record.target(),
+            record.args()
+        );
+        let line = &line.as_bytes()[line.len().saturating_sub(RING_LEN)..];
+
+        let mut ring = self.ring.lock().unwrap();
+        let overflow = (ring.len() + line.len()).saturating_sub(RING_LEN);
+        ring.drain(..overflow);
+        ring.extend(line);
+    }
+
+    fn flush(&self) {
+        EspLogger.flush();
+    }
+}
+
+/// Installs the logger. Levels are then controlled with [`apply`].
+pub fn init() {
+    if let Err(err) = log::set_logger(&LOGGER) {
+        println!("logging: logger is already set error={:?}", err);
+        return;
+    }
+    log::set_max_level(LevelFilter::Trace);
+}
+
+/// Returns the buffered log output, oldest first.
+pub fn recent() -> Vec<u8> {
+    let ring = LOGGER.ring.lock().unwrap();
+    ring.iter().copied().collect()
+}
+
+/// Parses a comma separated list of `target=level` pairs, e.g.
+/// `esp_sensor=trace,wifi=warn`.
+pub fn parse(spec: &str) -> error::Result<Vec<(String, LevelFilter)>> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let Some((target, level)) = entry.split_once('=') else {
+                bail!("log level {:?} is not a target=level pair", entry);
+            };
+            let level = LevelFilter::from_str(level.trim())
+                .with_context(|| format!("unknown log level {:?}", level))?;
+            Ok((target.trim().to_string(), level))
+        })
+        .collect()
+}
+
+/// Applies the log levels to the ESP-IDF logger.
+pub fn apply(spec: &str) {
+    let levels = match parse(spec) {
+        Ok(levels) => levels,
+        Err(err) => {
+            log::error!("logging: invalid log levels error={:?}", err);
+            return;
+        }
+    };
+
+    for (target, level) in levels {
+        if let Err(err) = EspLogger.set_target_level(&target, level) {
+            log::error!("logging: setting level of {} error={:?}", target, err);
+        }
+    }
+}
+
+/// Returns `spec` with the level of `target` replaced or added.
+pub fn with_level(spec: &str, target: &str, level: LevelFilter) -> error::Result<String> {
+    let mut levels = parse(spec)?;
+    match levels.iter_mut().find(|(t, _)| t == target) {
+        Some((_, l)) => *l = level,
+        None => levels.push((target.to_string(), level)),
+    }
+
+    Ok(levels
+        .iter()
+        .map(|(target, level)| format!("{}={}", target, level.as_str().to_lowercase()))
+        .collect::<Vec<_>>()
+        .join(","))
+}
@@ -0,0 +1,421 @@
+use std::str::FromStr;
+#[cfg(feature = "lora")]
+use std::time::Instant;
+#[cfg(any(feature = "lora", feature = "espnow"))]
+use std::{collections::VecDeque, sync::Mutex, time::Duration};
+
+#[cfg(feature = "lora")]
+use esp_idf_hal::spi::SpiDeviceDriver;
+
+use crate::error::{self, bail};
+#[cfg(feature = "lora")]
+use crate::{broadcast, health, settings::Store, watchdog::Watchdog};
+#[cfg(any(feature = "lora", feature = "espnow"))]
+use crate::{error::Context, SensorData};
+
+#[cfg(any(feature = "lora", feature = "espnow"))]
+const MAGIC: u8 = 0xE5;
+#[cfg(any(feature = "lora", feature = "espnow"))]
+const HEADER_LEN: usize = 8;
+#[cfg(any(feature = "lora", feature = "espnow"))]
+const MAX_DEVICE_LEN: usize = 32;
+/// Hops a reading may take through relays to reach the gateway.
+#[cfg(any(feature = "lora", feature = "espnow"))]
+pub const MAX_HOPS: u8 = 3;
+/// Packets remembered to drop copies arriving over several paths.
+#[cfg(any(feature = "lora", feature = "espnow"))]
+const SEEN_LEN: usize = 32;
+/// Uplinks not yet picked up by the sender are dropped beyond this.
+#[cfg(any(feature = "lora", feature = "espnow"))]
+const MAX_PENDING_UPLINKS: usize = 32;
+#[cfg(feature = "lora")]
+const TX_TIMEOUT: Duration = Duration::from_secs(5);
+#[cfg(feature = "lora")]
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Role of this node on the LoRa or ESP-NOW link.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Role {
+    #[default]
+    Off,
+    /// Sends readings over the link instead of Wi-Fi.
+    Node,
+    /// A node that also repeats packets of other nodes.
+    Relay,
+    /// Receives readings from nodes and forwards them to InfluxDB.
+    Gateway,
+}
+
+impl Role {
+    pub fn uses_wifi(self) -> bool {
+        !matches!(self, Role::Node | Role::Relay)
+    }
+}
+
+impl FromStr for Role {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Role::Off),
+            "node" => Ok(Role::Node),
+            "relay" => Ok(Role::Relay),
+            "gateway" => Ok(Role::Gateway),
+            _ => bail!("role {:?} is not one of off, node, relay, gateway", s),
+        }
+    }
+}
+
+/// Reading received from a remote node.
+#[cfg(any(feature = "lora", feature = "espnow"))]
+#[derive(Debug, Clone)]
+pub struct Uplink {
+    pub device: String,
+    pub data: SensorData,
+    /// Signal strength in dBm, ESP-NOW does not report it.
+    pub rssi: Option<i16>,
+    /// LoRa only.
+    pub snr: Option<f32>,
+}
+
+/// Reading as sent over the air.
+#[cfg(any(feature = "lora", feature = "espnow"))]
+#[derive(Debug, Clone)]
+pub struct Packet {
+    /// Remaining relay hops.
+    pub ttl: u8,
+    pub seq: u16,
+    pub device: String,
+    pub data: SensorData,
+}
+
+#[cfg(any(feature = "lora", feature = "espnow"))]
+impl Packet {
+    /// Compact encoding: magic byte, ttl, sequence number, temperature and
+    /// humidity in hundredths, all little endian, followed by the device id.
+    pub fn encode(&self) -> Vec<u8> {
+        let device = &self.device.as_bytes()[..self.device.len().min(MAX_DEVICE_LEN)];
+        let mut packet = Vec::with_capacity(HEADER_LEN + device.len());
+        packet.extend_from_slice(&[MAGIC, self.ttl]);
+        packet.extend_from_slice(&self.seq.to_le_bytes());
+        packet.extend_from_slice(&((self.data.temperature * 100.).round() as i16).to_le_bytes());
+        packet.extend_from_slice(&((self.data.humidity * 100.).round() as u16).to_le_bytes());
+        packet.extend_from_slice(device);
+        packet
+    }
+
+    pub fn decode(packet: &[u8]) -> error::Result<Self> {
+        if packet.len() <= HEADER_LEN || packet[0] != MAGIC {
+            bail!("not a sensor packet");
+        }
+        let temperature = i16::from_le_bytes([packet[4], packet[5]]);
+        let humidity = u16::from_le_bytes([packet[6], packet[7]]);
+        let device = std::str::from_utf8(&packet[HEADER_LEN..]).context("device id")?;
+
+        Ok(Packet {
+            ttl: packet[1],
+            seq: u16::from_le_bytes([packet[2], packet[3]]),
+            device: device.into(),
+            data: SensorData {
+                temperature: f32::from(temperature) / 100.,
+                humidity: f32::from(humidity) / 100.,
+            },
+        })
+    }
+}
+
+/// Recently handled packets, by device and sequence number.
+#[cfg(any(feature = "lora", feature = "espnow"))]
+#[derive(Default)]
+pub struct Seen {
+    packets: VecDeque<(String, u16)>,
+}
+
+#[cfg(any(feature = "lora", feature = "espnow"))]
+impl Seen {
+    /// Remembers the packet, returns `false` if it was already seen.
+    pub fn insert(&mut self, packet: &Packet) -> bool {
+        let id = (packet.device.clone(), packet.seq);
+        if self.packets.contains(&id) {
+            return false;
+        }
+        if self.packets.len() == SEEN_LEN {
+            self.packets.pop_front();
+        }
+        self.packets.push_back(id);
+        true
+    }
+}
+
+/// Uplinks received by the gateway, drained by the sender.
+#[cfg(any(feature = "lora", feature = "espnow"))]
+#[derive(Default)]
+pub struct Inbox {
+    uplinks: Mutex<VecDeque<Uplink>>,
+}
+
+#[cfg(any(feature = "lora", feature = "espnow"))]
+impl Inbox {
+    pub fn push(&self, uplink: Uplink) {
+        let mut uplinks = self.uplinks.lock().unwrap();
+        if uplinks.len() == MAX_PENDING_UPLINKS {
+            uplinks.pop_front();
+        }
+        uplinks.push_back(uplink);
+    }
+
+    pub fn take(&self) -> Vec<Uplink> {
+        self.uplinks.lock().unwrap().drain(..).collect()
+    }
+}
+
+#[cfg(feature = "lora")]
+mod reg {
+    pub const FIFO: u8 = 0x00;
+    pub const OP_MODE: u8 = 0x01;
+    pub const FRF_MSB: u8 = 0x06;
+    pub const PA_CONFIG: u8 = 0x09;
+    pub const FIFO_ADDR_PTR: u8 = 0x0D;
+    pub const FIFO_TX_BASE_ADDR: u8 = 0x0E;
+    pub const FIFO_RX_BASE_ADDR: u8 = 0x0F;
+    pub const FIFO_RX_CURRENT_ADDR: u8 = 0x10;
+    pub const IRQ_FLAGS: u8 = 0x12;
+    pub const RX_NB_BYTES: u8 = 0x13;
+    pub const PKT_SNR_VALUE: u8 = 0x19;
+    pub const PKT_RSSI_VALUE: u8 = 0x1A;
+    pub const MODEM_CONFIG_1: u8 = 0x1D;
+    pub const MODEM_CONFIG_2: u8 = 0x1E;
+    pub const PAYLOAD_LENGTH: u8 = 0x22;
+    pub const MODEM_CONFIG_3: u8 = 0x26;
+    pub const SYNC_WORD: u8 = 0x39;
+    pub const VERSION: u8 = 0x42;
+}
+
+#[cfg(feature = "lora")]
+mod mode {
+    pub const LONG_RANGE: u8 = 0x80;
+    pub const SLEEP: u8 = 0x00;
+    pub const STANDBY: u8 = 0x01;
+    pub const TX: u8 = 0x03;
+    pub const RX_CONTINUOUS: u8 = 0x05;
+}
+
+#[cfg(feature = "lora")]
+const IRQ_TX_DONE: u8 = 0x08;
+#[cfg(feature = "lora")]
+const IRQ_CRC_ERROR: u8 = 0x20;
+#[cfg(feature = "lora")]
+const IRQ_RX_DONE: u8 = 0x40;
+
+/// Minimal SX1276 driver: LoRa mode, 125 kHz, SF9, CR 4/5, explicit
+/// header with CRC and a private sync word.
+#[cfg(feature = "lora")]
+pub struct Sx1276<'d> {
+    spi: SpiDeviceDriver<'d, esp_idf_hal::spi::SpiDriver<'d>>,
+}
+
+#[cfg(feature = "lora")]
+impl<'d> Sx1276<'d> {
+    pub fn new(
+        spi: SpiDeviceDriver<'d, esp_idf_hal::spi::SpiDriver<'d>>,
+        frequency_hz: u32,
+    ) -> error::Result<Self> {
+        let mut radio = Self { spi };
+        let version = radio.read(reg::VERSION)?;
+        if version != 0x12 {
+            bail!("unexpected sx1276 version 0x{:02x}", version);
+        }
+
+        // LoRa mode can only be selected while sleeping.
+        radio.write(reg::OP_MODE, mode::LONG_RANGE | mode::SLEEP)?;
+        let frf = (u64::from(frequency_hz) << 19) / 32_000_000;
+        radio.write(reg::FRF_MSB, (frf >> 16) as u8)?;
+        radio.write(reg::FRF_MSB + 1, (frf >> 8) as u8)?;
+        radio.write(reg::FRF_MSB + 2, frf as u8)?;
+        radio.write(reg::FIFO_TX_BASE_ADDR, 0)?;
+        radio.write(reg::FIFO_RX_BASE_ADDR, 0)?;
+        // 125 kHz, 4/5, explicit header.
+        radio.write(reg::MODEM_CONFIG_1, 0x72)?;
+        // SF9, CRC on.
+        radio.write(reg::MODEM_CONFIG_2, 0x94)?;
+        // AGC on.
+        radio.write(reg::MODEM_CONFIG_3, 0x04)?;
+        radio.write(reg::SYNC_WORD, 0x12)?;
+        // PA_BOOST, 17 dBm.
+        radio.write(reg::PA_CONFIG, 0x8F)?;
+        radio.write(reg::OP_MODE, mode::LONG_RANGE | mode::STANDBY)?;
+        Ok(radio)
+    }
+
+    pub fn transmit(&mut self, packet: &[u8]) -> error::Result<()> {
+        self.write(reg::OP_MODE, mode::LONG_RANGE | mode::STANDBY)?;
+        self.write(reg::FIFO_ADDR_PTR, 0)?;
+        for byte in packet {
+            self.write(reg::FIFO, *byte)?;
+        }
+        self.write(reg::PAYLOAD_LENGTH, packet.len() as u8)?;
+        self.write(reg::OP_MODE, mode::LONG_RANGE | mode::TX)?;
+
+        let started = Instant::now();
+        while self.read(reg::IRQ_FLAGS)? & IRQ_TX_DONE == 0 {
+            if started.elapsed() > TX_TIMEOUT {
+                bail!("transmit timed out");
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        self.write(reg::IRQ_FLAGS, 0xFF)?;
+        Ok(())
+    }
+
+    pub fn start_receive(&mut self) -> error::Result<()> {
+        self.write(reg::OP_MODE, mode::LONG_RANGE | mode::RX_CONTINUOUS)
+    }
+
+    /// Returns a received packet with its RSSI and SNR, if any.
+    pub fn poll_receive(&mut self) -> error::Result<Option<(Vec<u8>, i16, f32)>> {
+        let flags = self.read(reg::IRQ_FLAGS)?;
+        if flags & IRQ_RX_DONE == 0 {
+            return Ok(None);
+        }
+        self.write(reg::IRQ_FLAGS, 0xFF)?;
+        if flags & IRQ_CRC_ERROR != 0 {
+            bail!("packet with a crc error");
+        }
+
+        let len = self.read(reg::RX_NB_BYTES)?;
+        let current = self.read(reg::FIFO_RX_CURRENT_ADDR)?;
+        self.write(reg::FIFO_ADDR_PTR, current)?;
+        let mut packet = Vec::with_capacity(usize::from(len));
+        for _ in 0..len {
+            packet.push(self.read(reg::FIFO)?);
+        }
+        let snr = f32::from(self.read(reg::PKT_SNR_VALUE)? as i8) / 4.;
+        // High frequency port.
+        let rssi = i16::from(self.read(reg::PKT_RSSI_VALUE)?) - 157;
+        Ok(Some((packet, rssi, snr)))
+    }
+
+    fn read(&mut self, register: u8) -> error::Result<u8> {
+        let mut buf = [register & 0x7F, 0];
+        self.spi.transfer_in_place(&mut buf)?;
+        Ok(buf[1])
+    }
+
+    fn write(&mut self, register: u8, value: u8) -> error::Result<()> {
+        self.spi.write(&[register | 0x80, value])?;
+        Ok(())
+    }
+}
+
+/// Wait before repeating a packet, so relays hearing the same packet do
+/// not repeat it in sync.
+#[cfg(any(feature = "lora", feature = "espnow"))]
+pub fn jitter() -> Duration {
+    Duration::from_millis(u64::from(unsafe { esp_idf_sys::esp_random() } % 200))
+}
+
+/// Sends every reading as a node, additionally repeats packets of other
+/// nodes as a relay, or receives readings as a gateway.
+///
+/// Relaying is flooding: a relay repeats every packet it has not seen yet
+/// with the ttl decreased, until the ttl runs out.
+#[cfg(feature = "lora")]
+pub fn run(
+    mut sub: broadcast::Receiver<SensorData>,
+    store: &Store,
+    inbox: &Inbox,
+    mut radio: Sx1276<'_>,
+) {
+    let watchdog = Watchdog::subscribe("lora");
+    let health = health::register("lora");
+    let role: Role = store.get().lora_role.parse().unwrap_or_default();
+    let device = store.get().device_id();
+    let mut seq: u16 = 0;
+    let mut seen = Seen::default();
+    log::info!("lora: running as {:?}", role);
+
+    if role == Role::Node {
+        while let Some(data) = watchdog.recv(&mut sub) {
+            health.tick();
+            seq = seq.wrapping_add(1);
+            let packet = Packet {
+                ttl: MAX_HOPS,
+                seq,
+                device: device.clone(),
+                data,
+            };
+            if let Err(err) = radio.transmit(&packet.encode()) {
+                log::error!("lora: transmit error={:?}", err);
+            }
+        }
+        return;
+    }
+
+    if let Err(err) = radio.start_receive() {
+        log::error!("lora: starting receiver error={:?}", err);
+        return;
+    }
+    loop {
+        health.tick();
+        watchdog.feed();
+
+        let mut outgoing = Vec::new();
+        if role == Role::Relay {
+            while let Ok(data) = sub.try_recv() {
+                seq = seq.wrapping_add(1);
+                outgoing.push(Packet {
+                    ttl: MAX_HOPS,
+                    seq,
+                    device: device.clone(),
+                    data,
+                });
+            }
+        }
+
+        match radio.poll_receive() {
+            Ok(Some((bytes, rssi, snr))) => match Packet::decode(&bytes) {
+                Ok(packet) if packet.device == device || !seen.insert(&packet) => {}
+                Ok(packet) => {
+                    log::debug!(
+                        "lora: {} #{} ttl={} {} rssi={}",
+                        packet.device,
+                        packet.seq,
+                        packet.ttl,
+                        packet.data,
+                        rssi
+                    );
+                    match role {
+                        Role::Gateway => inbox.push(Uplink {
+                            device: packet.device,
+                            data: packet.data,
+                            rssi: Some(rssi),
+                            snr: Some(snr),
+                        }),
+                        _ if packet.ttl > 0 => outgoing.push(Packet {
+                            ttl: packet.ttl - 1,
+                            ..packet
+                        }),
+                        _ => {}
+                    }
+                }
+                Err(err) => log::warn!("lora: dropping packet error={:?}", err),
+            },
+            Ok(None) if outgoing.is_empty() => std::thread::sleep(POLL_INTERVAL * 5),
+            Ok(None) => {}
+            Err(err) => log::warn!("lora: receive error={:?}", err),
+        }
+
+        if outgoing.is_empty() {
+            continue;
+        }
+        for packet in outgoing {
+            std::thread::sleep(jitter());
+            if let Err(err) = radio.transmit(&packet.encode()) {
+                log::error!("lora: transmit error={:?}", err);
+            }
+        }
+        if let Err(err) = radio.start_receive() {
+            log::error!("lora: restarting receiver error={:?}", err);
+        }
+    }
+}
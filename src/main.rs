@@ -1,11 +1,10 @@
-use anyhow::{bail, Context};
-use bus::Bus;
 use embedded_svc::{
     http::client::{Client, Response},
     io::Write,
     utils::io,
     wifi::{ClientConfiguration, Configuration},
 };
+use error::{bail, format_err, Code, Context};
 use esp_idf_hal::{
     delay::{self},
     gpio::{self, PinDriver},
@@ -14,163 +13,1626 @@ use esp_idf_hal::{
 };
 use esp_idf_svc::{
     eventloop::EspSystemEventLoop, http::client::Configuration as HttpConfiguration,
-    http::client::EspHttpConnection, nvs::EspDefaultNvsPartition, wifi::BlockingWifi,
-    wifi::EspWifi,
+    http::client::EspHttpConnection, nvs::EspDefaultNvsPartition, sntp::EspSntp,
+    wifi::BlockingWifi, wifi::EspWifi,
 };
 use esp_idf_sys as _; // If using the `binstart` feature of `esp-idf-sys`, always keep this module imported
-use std::{convert::Infallible, fmt::Display, thread, time::Duration};
+use point::Point;
+use reading::SensorData;
+use settings::{Settings, Store};
+use std::{
+    convert::Infallible,
+    sync::{mpsc, Arc},
+    thread,
+    time::Duration,
+};
+use watchdog::Watchdog;
+
+#[cfg(any(
+    feature = "leak",
+    feature = "weather",
+    feature = "gas",
+    feature = "aquarium",
+    feature = "energy",
+    feature = "dust",
+    feature = "co"
+))]
+mod adc;
+mod alert;
+mod aquarium;
+#[cfg(feature = "influx")]
+mod bench;
+#[cfg(feature = "ble")]
+mod ble;
+#[cfg_attr(not(feature = "hvac"), allow(dead_code))]
+mod bmp280;
+mod board;
+mod broadcast;
+mod bthome;
+#[cfg(feature = "build-info")]
+mod build_info;
+mod button;
+mod buzzer;
+#[cfg_attr(not(feature = "coap"), allow(dead_code))]
+mod cbor;
+mod co;
+#[cfg(feature = "coap")]
+mod coap;
+#[cfg(feature = "coap")]
+mod coap_server;
+mod console;
+mod contacts;
+mod coredump;
+mod crash;
+#[cfg_attr(not(feature = "energy"), allow(dead_code))]
+mod ct;
+mod deadband;
+mod device;
+mod dht;
+#[cfg(feature = "display")]
+mod display;
+#[cfg_attr(not(any(feature = "aquarium", feature = "rack")), allow(dead_code))]
+mod ds18b20;
+mod dust;
+mod encoder;
+mod energy;
+mod error;
+#[cfg(feature = "espnow")]
+mod espnow;
+#[cfg_attr(not(feature = "co"), allow(dead_code))]
+mod exposure;
+mod fan;
+mod fault;
+#[cfg_attr(not(feature = "frost"), allow(dead_code))]
+mod forecast;
+mod frost;
+mod gas;
+#[cfg_attr(not(feature = "dust"), allow(dead_code))]
+mod gp2y1010;
+mod health;
+mod hive;
+mod hvac;
+#[cfg_attr(not(feature = "hive"), allow(dead_code))]
+mod hx711;
+#[cfg_attr(
+    not(any(feature = "influx", feature = "otlp", feature = "grafana")),
+    allow(dead_code)
+)]
+mod influx;
+#[cfg_attr(not(feature = "mold"), allow(dead_code))]
+mod isopleth;
+#[cfg(feature = "knx")]
+mod knx;
+#[cfg_attr(not(feature = "knx"), allow(dead_code))]
+mod knxnet;
+#[cfg_attr(not(feature = "rack"), allow(dead_code))]
+mod layout;
+#[cfg(feature = "leak")]
+mod leak;
+mod logging;
+mod lora;
+mod metrics;
+#[cfg(feature = "modbus")]
+mod modbus;
+#[cfg(feature = "modbus")]
+mod modbus_server;
+mod mold;
+#[cfg_attr(not(feature = "ota"), allow(dead_code))]
+mod mqtt;
+#[cfg_attr(not(feature = "nats"), allow(dead_code))]
+mod nats;
+mod noise;
+mod notify;
+#[cfg_attr(not(any(feature = "aquarium", feature = "rack")), allow(dead_code))]
+mod onewire;
+#[cfg(feature = "ota")]
+mod ota;
+#[cfg(feature = "otlp")]
+mod otlp;
+#[cfg_attr(not(feature = "influx"), allow(dead_code))]
+mod outbox;
+mod pir;
+mod point;
+mod presence;
+mod pulse;
+mod rack;
+mod reading;
+mod ready;
+mod relay;
+mod remote_config;
+#[cfg_attr(not(feature = "ota"), allow(dead_code))]
+mod rollout;
+#[cfg(feature = "rtc")]
+mod rtc;
+mod schedule;
+mod script;
+#[cfg_attr(not(feature = "hvac"), allow(dead_code))]
+mod sdp810;
+#[cfg(feature = "display")]
+mod segments;
+mod selftest;
+mod sequence;
+mod server;
+mod settings;
+#[cfg_attr(not(feature = "ota"), allow(dead_code))]
+mod sha256;
+#[cfg_attr(not(feature = "influx"), allow(dead_code))]
+mod soak;
+#[cfg_attr(not(feature = "noise"), allow(dead_code))]
+mod spl;
+mod state;
+#[cfg(feature = "statsd")]
+mod statsd;
+mod sun;
+#[cfg_attr(not(feature = "telegram"), allow(dead_code))]
+mod telegram;
+mod thing;
+mod trace;
+mod validation;
+mod watchdog;
+mod weather;
 
 #[derive(Debug)]
 #[toml_cfg::toml_config]
 pub struct Config {
     #[default("<CHANGEME>")]
     ssid: &'static str,
-    #[default("<CHANGEME>")]
+    /// Prefer provisioning it into the encrypted NVS, see README.
+    #[default("")]
     password: &'static str,
     #[default("<CHANGEME>")]
     addr: &'static str,
-    #[default("<CHANGEME>")]
+    /// Prefer provisioning it into the encrypted NVS, see README.
+    #[default("")]
     influx_token: &'static str,
     #[default("<CHANGEME>")]
     influx_org: &'static str,
     #[default("<CHANGEME>")]
     influx_bucket: &'static str,
+    #[default("")]
+    otlp_url: &'static str,
+    /// Prefer provisioning it into the encrypted NVS, see README.
+    #[default("")]
+    otlp_auth: &'static str,
+    #[default("")]
+    grafana_url: &'static str,
+    /// Prefer provisioning it into the encrypted NVS, see README.
+    #[default("")]
+    grafana_token: &'static str,
+    #[default("esp-sensor")]
+    grafana_stream: &'static str,
+    #[default("")]
+    statsd_addr: &'static str,
+    #[default("")]
+    statsd_prefix: &'static str,
+    #[default("")]
+    knx_gateway: &'static str,
+    #[default("")]
+    knx_temperature: &'static str,
+    #[default("")]
+    knx_humidity: &'static str,
+    #[default("")]
+    nats_url: &'static str,
+    /// Prefer provisioning it into the encrypted NVS, see README.
+    #[default("")]
+    nats_token: &'static str,
+    #[default("sensors.{device}.{measurement}")]
+    nats_subject: &'static str,
+    #[default(0)]
+    nats_ack_secs: u32,
+    #[default("")]
+    mqtt_url: &'static str,
+    #[default("")]
+    mqtt_user: &'static str,
+    /// Prefer provisioning it into the encrypted NVS, see README.
+    #[default("")]
+    mqtt_password: &'static str,
+    #[default("esp-sensor/ota")]
+    ota_topic: &'static str,
+    #[default("")]
+    ota_source: &'static str,
+    #[default("wifi,write")]
+    ota_checks: &'static str,
+    #[default(300)]
+    ota_window_secs: u32,
     #[default(30)]
     read_sensor_interval_secs: u32,
+    #[default("")]
+    config_url: &'static str,
+    #[default(3600)]
+    config_pull_interval_secs: u32,
+    #[default("living room #1")]
+    measurement: &'static str,
+    #[default("")]
+    tags: &'static str,
+    #[default("")]
+    device_id: &'static str,
+    #[default("temperature")]
+    temperature_field: &'static str,
+    #[default("humidity")]
+    humidity_field: &'static str,
+    #[default(300)]
+    telemetry_interval_secs: u32,
+    #[default("")]
+    coredump_url: &'static str,
+    #[default("esp_sensor=trace")]
+    log_levels: &'static str,
+    #[default(60)]
+    heartbeat_interval_secs: u32,
+    #[default("0")]
+    delta_temp: &'static str,
+    #[default("0")]
+    delta_humidity: &'static str,
+    #[default(600)]
+    keepalive_secs: u32,
+    #[default("on")]
+    selftest: &'static str,
+    #[default(0)]
+    http_rx_buffer: u32,
+    #[default(0)]
+    http_tx_buffer: u32,
+    #[default(120)]
+    http_timeout_secs: u32,
+    #[default("")]
+    alert_rules: &'static str,
+    #[default("200,200,200,1000")]
+    buzzer_pattern: &'static str,
+    #[default("")]
+    relay_control: &'static str,
+    #[default(0)]
+    relay_max_on_secs: u32,
+    #[default("25:20,35:100")]
+    fan_curve: &'static str,
+    #[default("")]
+    ntfy_url: &'static str,
+    /// Prefer provisioning it into the encrypted NVS, see README.
+    #[default("")]
+    ntfy_token: &'static str,
+    /// Prefer provisioning it into the encrypted NVS, see README.
+    #[default("")]
+    pushover_token: &'static str,
+    /// Prefer provisioning it into the encrypted NVS, see README.
+    #[default("")]
+    pushover_user: &'static str,
+    #[default(300)]
+    notify_interval_secs: u32,
+    /// Prefer provisioning it into the encrypted NVS, see README.
+    #[default("")]
+    telegram_token: &'static str,
+    #[default("")]
+    telegram_chat: &'static str,
+    #[default("")]
+    quiet_hours: &'static str,
+    #[default("")]
+    automation: &'static str,
+    #[default("off")]
+    bthome: &'static str,
+    #[default("")]
+    ble_beacons: &'static str,
+    #[default("off")]
+    lora_role: &'static str,
+    #[default(868100000)]
+    lora_freq_hz: u32,
+    #[default("off")]
+    espnow_role: &'static str,
+    #[default(1)]
+    espnow_channel: u32,
+    #[default("UTC0")]
+    timezone: &'static str,
+    #[default(0)]
+    display_clock_secs: u32,
+    #[default("")]
+    location: &'static str,
+    #[default("")]
+    pins: &'static str,
+    #[default(300)]
+    pir_hold_secs: u32,
+    #[default("")]
+    contacts: &'static str,
+    #[default(0)]
+    leak_threshold: u32,
+    #[default("")]
+    pulse_inputs: &'static str,
+    #[default(667)]
+    wind_factor: u32,
+    #[default(279)]
+    rain_tip: u32,
+    #[default("mq135")]
+    gas_sensor: &'static str,
+    #[default(10000)]
+    gas_load: u32,
+    #[default(3300)]
+    gas_supply: u32,
+    #[default(0)]
+    gas_r0: u32,
+    #[default(180)]
+    gas_warmup_secs: u32,
+    #[default(0)]
+    bench_rate: u32,
+    #[default(10)]
+    bench_batch: u32,
+    #[default(0)]
+    bench_report: u32,
+    #[default(0)]
+    soak_interval_secs: u32,
+    #[default(120)]
+    soak_outage_secs: u32,
+    #[default("0")]
+    hive_tare: &'static str,
+    #[default("")]
+    hive_tare_temp: &'static str,
+    #[default("0")]
+    hive_scale: &'static str,
+    #[default("0")]
+    hive_tempco: &'static str,
+    #[default("water_cold=water<24~0.5@600,water_hot=water>28~0.5@600,ph_low=ph<6.5~0.1@1800,ph_high=ph>8~0.1@1800,tds_high=tds>400~20@1800")]
+    aquarium_alerts: &'static str,
+    #[default("")]
+    ph_calibration: &'static str,
+    #[default("")]
+    aquarium_probe: &'static str,
+    #[default(1000)]
+    tds_factor: u32,
+    #[default(30)]
+    ct_amps: u32,
+    #[default(100)]
+    ct_noise_ma: u32,
+    #[default(230)]
+    mains_voltage: u32,
+    #[default(900)]
+    dust_zero_mv: u32,
+    #[default(1000)]
+    dust_divider: u32,
+    #[default(1000)]
+    noise_window_ms: u32,
+    #[default("-26")]
+    mic_sensitivity: &'static str,
+    #[default("50@3600,100@600,300@60")]
+    co_levels: &'static str,
+    #[default(400)]
+    co_zero_mv: u32,
+    #[default(3200)]
+    co_uv_per_ppm: u32,
+    #[default(300)]
+    co_repeat_secs: u32,
+    #[default(7200)]
+    frost_lead_secs: u32,
+    #[default("1")]
+    frost_temp: &'static str,
+    #[default(120)]
+    mold_hours: u32,
+    #[default(100)]
+    mold_alert: u32,
+    #[default("bmp280")]
+    hvac_sensor: &'static str,
+    #[default("0")]
+    hvac_offset: &'static str,
+    #[default(10)]
+    hvac_fan_pa: u32,
+    #[default(150)]
+    hvac_clogged_pa: u32,
+    #[default(0)]
+    hvac_filter_hours: u32,
+    #[default("")]
+    rack_sensors: &'static str,
+    #[default("")]
+    rack_zones: &'static str,
+    #[default(10)]
+    rack_delta: u32,
+    /// Stack sizes in bytes of the threads named, compiled in since stacks
+    /// are allocated at boot. The other threads get the ESP-IDF default of
+    /// `CONFIG_PTHREAD_TASK_STACK_SIZE_DEFAULT`. The sender needs room for
+    /// the TLS handshake.
+    #[default(4096)]
+    sensor_stack_size: usize,
+    #[default(3072)]
+    display_stack_size: usize,
+    #[default(7000)]
+    sender_stack_size: usize,
 }
 
-fn main() -> anyhow::Result<()> {
+/// Holding the button this long at boot activates the next settings profile.
+const PROFILE_SWITCH_HOLD: Duration = Duration::from_secs(3);
+/// Holding the button this long at boot erases all settings and credentials.
+const FACTORY_RESET_HOLD: Duration = Duration::from_secs(10);
+/// The DHT22 ignores requests this long after power-on.
+const DHT_POWER_ON: Duration = Duration::from_secs(1);
+/// The DHT22 measures at most every two seconds.
+const DHT_MIN_INTERVAL: Duration = Duration::from_secs(2);
+
+fn main() -> error::Result<()> {
     // It is necessary to call this function once. Otherwise some patches to the runtime
     // implemented by esp-idf-sys might not link properly. See https://github.com/esp-rs/esp-idf-template/issues/71
     esp_idf_sys::link_patches();
+    crash::install_panic_hook();
     // Bind the log crate to the ESP Logging facilities
-    esp_idf_svc::log::EspLogger::initialize_default();
-    let logger = esp_idf_svc::log::EspLogger;
-    logger.set_target_level("esp_sensor", log::LevelFilter::Trace)?;
+    logging::init();
+    logging::apply(CONFIG.log_levels);
 
-    log::info!("using {:?}", CONFIG);
-    let mut bus = bus::Bus::<SensorData>::new(4);
-    let sub2 = bus.add_rx();
+    let readings = broadcast::Sender::<SensorData>::new(4);
+    let sub2 = readings.subscribe();
+    #[cfg(feature = "telegram")]
+    let telegram_sub = readings.subscribe();
+    #[cfg(feature = "coap")]
+    let coap_sub = readings.subscribe();
+    #[cfg(feature = "relay")]
+    let relay_sub = readings.subscribe();
+    #[cfg(feature = "fan")]
+    let fan_sub = readings.subscribe();
+    #[cfg(feature = "hive")]
+    let hive_sub = readings.subscribe();
+    #[cfg(feature = "frost")]
+    let frost_sub = readings.subscribe();
+    #[cfg(feature = "mold")]
+    let mold_sub = readings.subscribe();
+    let (wake_tx, wake_rx) = mpsc::channel();
 
     let sysloop = EspSystemEventLoop::take()?;
     let nvs = EspDefaultNvsPartition::take()?;
+    let shared = Shared {
+        crash_log: Arc::new(crash::CrashLog::load(nvs.clone()).context("load crash log")?),
+        sequence: Arc::new(sequence::Sequence::load(nvs.clone()).context("load sequence")?),
+        alerts: Default::default(),
+        relay: Default::default(),
+        fan: Default::default(),
+        adjust: Default::default(),
+        occupancy: Default::default(),
+        contacts: Default::default(),
+        pulses: Default::default(),
+        weather: Default::default(),
+        gas: Default::default(),
+        hive: Default::default(),
+        aquarium: Default::default(),
+        rack: Default::default(),
+        energy: Default::default(),
+        dust: Default::default(),
+        noise: Default::default(),
+        co: Default::default(),
+        frost: Default::default(),
+        mold: Default::default(),
+        hvac: Default::default(),
+        trace: Default::default(),
+        state: Default::default(),
+        ready: Default::default(),
+        #[cfg(any(feature = "lora", feature = "espnow"))]
+        lora: Default::default(),
+    };
+    let store = Arc::new(Store::load(nvs.clone()).context("load settings")?);
+    logging::apply(&store.get().log_levels);
+    schedule::apply_timezone(&store.get().timezone);
+    log::info!("using {:?}", store.get());
+    settings::check_secrets_storage();
     let mut peripherals = Peripherals::take().context("no peripherals")?;
 
-    let dht22_pin = PinDriver::input_output(peripherals.pins.gpio3)?;
+    let board = board::BOARD
+        .with_pins(&store.get().pins)
+        .unwrap_or_else(|err| {
+            log::error!("board: ignoring pins error={:?}", err);
+            board::BOARD
+        });
+    log::info!("board: {:?}", board);
+    let mut button = PinDriver::input(board.button_pin())?;
+    if board.button_pull_up {
+        button.set_pull(gpio::Pull::Up)?;
+    }
+    // Open drain, the sensor pulls the line low against its pull-up.
+    let mut dht22_pin = PinDriver::input_output_od(board.dht22_pin())?;
+    let status_led = PinDriver::output(board.status_led_pin())?;
+    #[allow(unused_mut)]
+    let mut pins = vec![
+        ("button", button.pin()),
+        ("dht22", dht22_pin.pin()),
+        ("status led", status_led.pin()),
+    ];
+
+    #[cfg(feature = "display")]
+    let mut tm = {
+        let (clk, dio) = board.display_pins();
+        let display_clk = PinDriver::input_output(clk)?;
+        let display_dio = PinDriver::input_output(dio)?;
+        pins.push(("tm1637 clk", display_clk.pin()));
+        pins.push(("tm1637 dio", display_dio.pin()));
+        display::new(display_clk, display_dio)
+    };
+
+    #[cfg(feature = "relay")]
+    let relay_pin = {
+        let pin = PinDriver::output(board.relay_pin())?;
+        pins.push(("relay", pin.pin()));
+        pin
+    };
+
+    #[cfg(feature = "fan")]
+    let (fan_tach, fan_pwm) = {
+        let timer = esp_idf_hal::ledc::LedcTimerDriver::new(
+            peripherals.ledc.timer1,
+            &esp_idf_hal::ledc::config::TimerConfig::new()
+                .frequency(esp_idf_hal::units::Hertz(fan::FREQUENCY_HZ)),
+        )?;
+        let (pin, tach) = board.fan_pins();
+        pins.push(("fan pwm", gpio::Pin::pin(&pin)));
+        let pwm = esp_idf_hal::ledc::LedcDriver::new(peripherals.ledc.channel1, timer, pin)?;
+
+        let mut tach = PinDriver::input(tach)?;
+        tach.set_pull(gpio::Pull::Up)?;
+        pins.push(("fan tach", tach.pin()));
+        (tach, pwm)
+    };
+
+    #[cfg(feature = "lora")]
+    let lora_spi = {
+        use esp_idf_hal::spi;
+
+        let (sclk, mosi, miso, nss) = board.lora_pins();
+        pins.push(("lora sck", gpio::Pin::pin(&sclk)));
+        pins.push(("lora mosi", gpio::Pin::pin(&mosi)));
+        pins.push(("lora miso", gpio::Pin::pin(&miso)));
+        pins.push(("lora nss", gpio::Pin::pin(&nss)));
+        let driver = spi::SpiDriver::new(
+            peripherals.spi2,
+            sclk,
+            mosi,
+            Some(miso),
+            &spi::SpiDriverConfig::new(),
+        )?;
+        spi::SpiDeviceDriver::new(
+            driver,
+            Some(nss),
+            &spi::config::Config::new().baudrate(esp_idf_hal::units::Hertz(1_000_000)),
+        )?
+    };
+
+    // The RTC and the HVAC pressure sensors share the bus.
+    #[cfg(any(feature = "rtc", feature = "hvac"))]
+    let i2c_bus = {
+        use esp_idf_hal::i2c;
+
+        let (sda, scl) = board.i2c_pins();
+        pins.push(("i2c sda", gpio::Pin::pin(&sda)));
+        pins.push(("i2c scl", gpio::Pin::pin(&scl)));
+        let config = i2c::I2cConfig::new().baudrate(esp_idf_hal::units::Hertz(100_000));
+        std::sync::Mutex::new(i2c::I2cDriver::new(peripherals.i2c0, sda, scl, &config)?)
+    };
+
+    #[cfg(feature = "rtc")]
+    let rtc = {
+        let mut rtc = rtc::Ds3231::new(&i2c_bus);
+        rtc::restore(&mut rtc);
+        rtc
+    };
+
+    #[cfg(feature = "encoder")]
+    let encoder = {
+        let mut switch = PinDriver::input(board.encoder_sw_pin())?;
+        if let Err(err) = switch.set_pull(gpio::Pull::Up) {
+            log::warn!("encoder: no pull-up on the switch error={:?}", err);
+        }
+        pins.push(("encoder a", board.encoder_a));
+        pins.push(("encoder b", board.encoder_b));
+        pins.push(("encoder switch", switch.pin()));
+        match encoder::Encoder::new(board.encoder_a, board.encoder_b) {
+            Ok(encoder) => Some((encoder, switch)),
+            Err(err) => {
+                log::error!("encoder: init error={:?}", err);
+                None
+            }
+        }
+    };
+
+    #[cfg(feature = "pir")]
+    let pir = {
+        let mut pin = PinDriver::input(board.pir_pin())?;
+        if let Err(err) = pin.set_pull(gpio::Pull::Down) {
+            log::warn!("pir: no pull-down error={:?}", err);
+        }
+        pins.push(("pir", pin.pin()));
+        pin
+    };
+
+    #[cfg(feature = "contacts")]
+    let contact_list = {
+        // The store only accepts valid contacts.
+        let list = contacts::parse_contacts(&store.get().contacts).unwrap_or_default();
+        for contact in &list {
+            pins.push(("contact", contact.pin));
+        }
+        list
+    };
+
+    #[cfg(feature = "leak")]
+    pins.push(("leak probe", board.leak));
+
+    #[cfg(feature = "weather")]
+    let weather_nvs = {
+        pins.push(("anemometer", board.anemometer));
+        pins.push(("rain gauge", board.rain_gauge));
+        pins.push(("wind vane", board.wind_vane));
+        nvs.clone()
+    };
+
+    #[cfg(feature = "gas")]
+    pins.push(("gas sensor", board.gas));
+
+    #[cfg(feature = "hive")]
+    let hx711 = {
+        let (dout, sck) = board.hx711_pins();
+        let dout = PinDriver::input(dout)?;
+        let sck = PinDriver::output(sck)?;
+        pins.push(("hx711 dout", dout.pin()));
+        pins.push(("hx711 sck", sck.pin()));
+        hx711::Hx711::new(dout, sck)
+    };
+
+    #[cfg(any(feature = "aquarium", feature = "rack"))]
+    let onewire = {
+        let pin = PinDriver::input_output_od(board.onewire_pin())?;
+        pins.push(("onewire", pin.pin()));
+        #[cfg(feature = "aquarium")]
+        pins.push(("ph probe", board.ph_probe));
+        #[cfg(feature = "aquarium")]
+        pins.push(("tds probe", board.tds_probe));
+        // Interrupts must not stretch the time slots.
+        let bus = onewire::OneWire::new(pin, delay::Ets)
+            .with_critical(|slot| esp_idf_hal::interrupt::free(|| slot()));
+        // The aquarium probe and the rack sensors share the bus.
+        std::sync::Mutex::new(bus)
+    };
+
+    #[cfg(feature = "energy")]
+    pins.push(("ct clamp", board.ct_clamp));
+
+    #[cfg(feature = "dust")]
+    let dust_sensor = {
+        let led = PinDriver::output(board.dust_led_pin())?;
+        pins.push(("dust led", led.pin()));
+        pins.push(("dust sensor", board.dust));
+        gp2y1010::Gp2y1010::new(led)?
+    };
+
+    #[cfg(feature = "co")]
+    pins.push(("co sensor", board.co));
+
+    #[cfg(feature = "mold")]
+    let mold_nvs = nvs.clone();
+
+    #[cfg(feature = "hvac")]
+    let hvac_nvs = nvs.clone();
+
+    #[cfg(feature = "ota")]
+    let ota_nvs = nvs.clone();
+
+    #[cfg(feature = "noise")]
+    let microphone = {
+        pins.push(("i2s sck", board.i2s_sck));
+        pins.push(("i2s ws", board.i2s_ws));
+        pins.push(("i2s sd", board.i2s_sd));
+        match noise::Microphone::new(board.i2s_sck, board.i2s_ws, board.i2s_sd) {
+            Ok(microphone) => Some(microphone),
+            Err(err) => {
+                log::error!("noise: init error={:?}", err);
+                None
+            }
+        }
+    };
+
+    #[cfg(feature = "pulse")]
+    let (pulse_inputs, pulse_counter, pulse_nvs) = {
+        // The store only accepts valid inputs.
+        let inputs = pulse::parse_inputs(&store.get().pulse_inputs).unwrap_or_default();
+        for input in &inputs {
+            pins.push(("pulse", input.pin));
+        }
+        let spec: Vec<_> = inputs.iter().map(|i| (i.pin, i.filter_ns)).collect();
+        let counter = match pulse::Counter::new(&spec) {
+            Ok(counter) => Some(counter),
+            Err(err) => {
+                log::error!("pulse: init error={:?}", err);
+                None
+            }
+        };
+        (inputs, counter, nvs.clone())
+    };
+
+    #[cfg(feature = "buzzer")]
+    let buzzer = {
+        let timer = esp_idf_hal::ledc::LedcTimerDriver::new(
+            peripherals.ledc.timer0,
+            &esp_idf_hal::ledc::config::TimerConfig::new()
+                .frequency(esp_idf_hal::units::Hertz(buzzer::FREQUENCY_HZ)),
+        )?;
+        let pin = board.buzzer_pin();
+        pins.push(("buzzer", gpio::Pin::pin(&pin)));
+        esp_idf_hal::ledc::LedcDriver::new(peripherals.ledc.channel0, timer, pin)?
+    };
+
+    if button.is_low() {
+        #[cfg(feature = "display")]
+        display::init(&mut tm);
+
+        let mut shown = None;
+        let held = button::held_for(&button, FACTORY_RESET_HOLD, |elapsed| {
+            let remaining = FACTORY_RESET_HOLD.saturating_sub(elapsed).as_secs() as u8;
+            if elapsed >= PROFILE_SWITCH_HOLD && shown != Some(remaining) {
+                shown = Some(remaining);
+                log::warn!(
+                    "factory reset in {}s, release the button to cancel",
+                    remaining
+                );
+                #[cfg(feature = "display")]
+                display::show_countdown(&mut tm, remaining);
+            }
+        });
+
+        if held >= FACTORY_RESET_HOLD {
+            log::warn!("button held at boot, performing factory reset");
+            device::factory_reset();
+        } else if held >= PROFILE_SWITCH_HOLD {
+            log::info!("button held at boot, switching profile");
+            if let Err(err) = store.next_profile() {
+                log::error!("could not switch profile error={:?}", err);
+            }
+        }
+    }
+
+    let problems = validation::validate(&store.get(), &pins);
+    for problem in &problems {
+        log::error!("invalid configuration: {}", problem);
+    }
+
+    let mode = store.get().selftest.parse().unwrap_or(selftest::Mode::On);
+    let mut halted = false;
+    #[cfg_attr(not(feature = "display"), allow(unused_variables))]
+    let selftest_failure = if mode == selftest::Mode::Off {
+        None
+    } else {
+        let mut report = selftest::Report::default();
+        report.record(selftest::Check::Sensor, selftest::sensor(&mut dht22_pin));
+        report.record(selftest::Check::Flash, selftest::flash(nvs.clone()));
+        #[cfg(feature = "display")]
+        report.record(selftest::Check::Display, display::self_test(&mut tm));
+        report.log_summary();
+
+        if let (selftest::Mode::Strict, Some(check)) = (mode, report.critical_failure()) {
+            log::error!(
+                "selftest: {} failed, not starting. Use `set selftest on` and `reboot` to skip",
+                check
+            );
+            halted = true;
+        }
+        report.first_failure()
+    };
+
+    #[cfg(feature = "display")]
+    match selftest_failure {
+        Some(check) => display::show_error(&mut tm, check as u8),
+        None if mode != selftest::Mode::Off => display::show_pass(&mut tm),
+        None => {}
+    }
+
+    if halted {
+        console::run(&shared.state, &store, &shared.relay, &shared.trace, wake_tx);
+        return Ok(());
+    }
 
     #[cfg(feature = "display")]
     let display_task = {
-        let sub1 = bus.add_rx();
-        let display_clk = PinDriver::input_output(peripherals.pins.gpio1)?;
-        let display_dio = PinDriver::input_output(peripherals.pins.gpio10)?;
-        || display_sensor_data(sub1, display_clk, display_dio)
+        let state = shared.state.clone();
+        let ready = shared.ready.clone();
+        let error_code = problems
+            .first()
+            .map(|p| p.code as u8)
+            .or(selftest_failure.map(|check| check as u8));
+        let alerts = shared.alerts.clone();
+        let adjust = shared.adjust.clone();
+        let occupancy = shared.occupancy.clone();
+        let store = store.clone();
+        move || {
+            display::display_sensor_data(
+                &state, &ready, tm, error_code, &alerts, &adjust, &occupancy, &store,
+            )
+        }
     };
 
+    let bthome_mode = if cfg!(feature = "ble") {
+        store.get().bthome.parse().unwrap_or_default()
+    } else {
+        bthome::Mode::Off
+    };
+    let lora_role = if cfg!(feature = "lora") {
+        store.get().lora_role.parse().unwrap_or_default()
+    } else {
+        lora::Role::Off
+    };
+    let espnow_role = if cfg!(feature = "espnow") {
+        store.get().espnow_role.parse().unwrap_or_default()
+    } else {
+        lora::Role::Off
+    };
+    #[cfg(feature = "lora")]
+    let lora_task = match lora_role {
+        lora::Role::Off => None,
+        _ => match lora::Sx1276::new(lora_spi, store.get().lora_freq_hz) {
+            Ok(radio) => {
+                let sub = readings.subscribe();
+                let inbox = shared.lora.clone();
+                let store = &store;
+                Some(move || lora::run(sub, store, &inbox, radio))
+            }
+            Err(err) => {
+                log::error!("lora: init error={:?}", err);
+                None
+            }
+        },
+    };
+
+    #[cfg(feature = "ble")]
+    let ble_ready = (bthome_mode != bthome::Mode::Off || !store.get().ble_beacons.is_empty())
+        && match ble::init() {
+            Ok(()) => true,
+            Err(err) => {
+                log::error!("ble: init error={:?}", err);
+                false
+            }
+        };
+    #[cfg(feature = "ble")]
+    let bthome_task = (ble_ready && bthome_mode != bthome::Mode::Off).then(|| {
+        let state = shared.state.clone();
+        move || bthome::run(&state)
+    });
+
     thread::scope(|s| {
-        s.spawn(|| read_sensor(&mut bus, dht22_pin));
-        s.spawn(|| data_sender(sub2, &mut peripherals.modem, &sysloop, Some(nvs)));
+        spawn_with_stack(s, "read_sensor", CONFIG.sensor_stack_size, || {
+            read_sensor(
+                &readings,
+                &shared.state,
+                &shared.ready,
+                dht22_pin,
+                &store,
+                &shared.trace,
+                wake_rx,
+            )
+        });
+        if bthome_mode.uses_wifi() && lora_role.uses_wifi() && espnow_role.uses_wifi() {
+            spawn_with_stack(s, "data_sender", CONFIG.sender_stack_size, || {
+                data_sender(
+                    sub2,
+                    &mut peripherals.modem,
+                    &sysloop,
+                    Some(nvs),
+                    &store,
+                    &pins,
+                    &shared,
+                )
+            });
+        } else {
+            log::info!("bthome only or lora or esp-now node, not joining wi-fi");
+            #[cfg(feature = "espnow")]
+            if !espnow_role.uses_wifi() {
+                let sub = readings.subscribe();
+                let modem = &mut peripherals.modem;
+                let (store, sysloop) = (&store, sysloop.clone());
+                spawn_with_stack(s, "espnow", CONFIG.sender_stack_size, move || {
+                    if let Err(err) = espnow::run(sub, store, modem, sysloop) {
+                        log::error!("espnow: error={:?}", err);
+                    }
+                });
+            }
+        }
+        s.spawn(|| alert::run(&shared.state, &store, &shared.alerts, status_led));
+        #[cfg(feature = "telegram")]
+        s.spawn(|| telegram::run(telegram_sub, &store, &shared.alerts));
+        #[cfg(feature = "coap")]
+        s.spawn(|| coap_server::run(coap_sub));
+        #[cfg(feature = "modbus")]
+        s.spawn(|| modbus_server::run(&shared.state, &shared.alerts));
+        #[cfg(feature = "knx")]
+        s.spawn(|| knx::run(&shared.state, &store));
+        #[cfg(feature = "ota")]
+        s.spawn(|| ota::run(&store, &shared.ready, ota_nvs));
+        #[cfg(feature = "relay")]
+        s.spawn(|| relay::run(relay_sub, &store, &shared.relay, &shared.frost, relay_pin));
+        #[cfg(feature = "fan")]
+        s.spawn(|| fan::run(fan_sub, &store, &shared.fan, fan_tach, fan_pwm));
+        #[cfg(feature = "buzzer")]
+        s.spawn(|| buzzer::run(&store, &shared.alerts, button, buzzer));
+        #[cfg(feature = "frost")]
+        s.spawn(|| frost::run(frost_sub, &store, &shared.frost, &shared.alerts, &readings));
+        #[cfg(feature = "mold")]
+        s.spawn(|| mold::run(mold_sub, &store, &shared.mold, &shared.alerts, mold_nvs));
+        #[cfg(feature = "hvac")]
+        s.spawn(|| hvac::run(&store, &shared.hvac, &shared.alerts, &i2c_bus, hvac_nvs));
+        #[cfg(feature = "pir")]
+        s.spawn(|| pir::run(&store, &shared.occupancy, pir));
+        #[cfg(feature = "leak")]
+        s.spawn(|| leak::run(&store, &shared.alerts, &readings, board.leak));
+        #[cfg(feature = "contacts")]
+        if !contact_list.is_empty() {
+            s.spawn(|| contacts::run(&shared.contacts, &contact_list, &readings));
+        }
+        #[cfg(feature = "weather")]
+        s.spawn(|| weather::run(&store, &shared.weather, &board, weather_nvs));
+        #[cfg(feature = "gas")]
+        s.spawn(|| gas::run(&store, &shared.gas, board.gas));
+        #[cfg(feature = "hive")]
+        s.spawn(|| hive::run(&shared.hive, hive_sub, hx711));
+        #[cfg(feature = "aquarium")]
+        s.spawn(|| {
+            aquarium::run(
+                &store,
+                &shared.alerts,
+                &readings,
+                &shared.aquarium,
+                &onewire,
+                board.ph_probe,
+                board.tds_probe,
+            )
+        });
+        #[cfg(feature = "rack")]
+        s.spawn(|| rack::run(&store, &shared.rack, &shared.alerts, &onewire));
+        #[cfg(feature = "energy")]
+        s.spawn(|| energy::run(&store, &shared.energy, board.ct_clamp));
+        #[cfg(feature = "dust")]
+        s.spawn(|| dust::run(&store, &shared.dust, dust_sensor, board.dust));
+        #[cfg(feature = "noise")]
+        if let Some(microphone) = microphone {
+            s.spawn(|| noise::run(&store, &shared.noise, microphone));
+        }
+        #[cfg(feature = "co")]
+        {
+            use esp_idf_hal::task::thread::ThreadSpawnConfiguration;
+
+            // Above the other tasks, so nothing delays the alarm.
+            let config = ThreadSpawnConfiguration {
+                name: Some(b"co\0"),
+                priority: 10,
+                ..Default::default()
+            };
+            if let Err(err) = config.set() {
+                log::error!("co: raising task priority error={:?}", err);
+            }
+            s.spawn(|| co::run(&store, &shared.co, &shared.alerts, &readings, board.co));
+            if let Err(err) = ThreadSpawnConfiguration::default().set() {
+                log::error!("co: restoring task priority error={:?}", err);
+            }
+        }
+        #[cfg(feature = "pulse")]
+        if let Some(counter) = pulse_counter.filter(|_| !pulse_inputs.is_empty()) {
+            s.spawn(|| pulse::run(&shared.pulses, &pulse_inputs, counter, pulse_nvs));
+        }
+        #[cfg(feature = "encoder")]
+        if let Some((encoder, switch)) = encoder {
+            s.spawn(|| encoder::run(&store, &shared.adjust, encoder, switch));
+        }
+        s.spawn(|| console::run(&shared.state, &store, &shared.relay, &shared.trace, wake_tx));
         #[cfg(feature = "display")]
-        s.spawn(display_task);
+        spawn_with_stack(s, "display", CONFIG.display_stack_size, display_task);
+        #[cfg(feature = "ble")]
+        if let Some(task) = bthome_task {
+            s.spawn(task);
+        }
+        #[cfg(feature = "rtc")]
+        s.spawn(|| rtc::run(rtc));
+        #[cfg(feature = "lora")]
+        if let Some(task) = lora_task {
+            s.spawn(task);
+        }
+        #[cfg(feature = "ble")]
+        if ble_ready {
+            s.spawn(|| presence::run(&store));
+        }
     });
 
     Ok(())
 }
 
+/// Spawns `f` on a thread with a stack of `stack_size` bytes.
+fn spawn_with_stack<'scope>(
+    s: &'scope thread::Scope<'scope, '_>,
+    name: &str,
+    stack_size: usize,
+    f: impl FnOnce() + Send + 'scope,
+) {
+    let spawned = thread::Builder::new()
+        .stack_size(stack_size)
+        .spawn_scoped(s, f);
+    if let Err(err) = spawned {
+        log::error!(
+            "{}: spawning with {}B of stack error={:?}",
+            name,
+            stack_size,
+            err
+        );
+    }
+}
+
 fn data_sender(
-    mut sub: bus::BusReader<SensorData>,
+    mut sub: broadcast::Receiver<SensorData>,
     modem: &mut impl peripheral::Peripheral<P = esp_idf_hal::modem::Modem>,
     sysloop: &EspSystemEventLoop,
     nvs: Option<EspDefaultNvsPartition>,
+    store: &Arc<Store>,
+    pins: &[(&'static str, i32)],
+    shared: &Shared,
 ) {
+    let mut state = SenderState {
+        puller: Default::default(),
+        telemetry: Default::default(),
+        heartbeat: Default::default(),
+        health: health::register("data_sender"),
+        metrics: Default::default(),
+        notifier: Default::default(),
+        soak: Default::default(),
+        network_checked: false,
+        stacks_logged: false,
+        deadband: Default::default(),
+        #[cfg(feature = "influx")]
+        outbox: Default::default(),
+        #[cfg(any(feature = "influx", feature = "grafana"))]
+        line: Vec::new(),
+        watchdog: Watchdog::subscribe("data_sender"),
+        shared: shared.clone(),
+    };
     loop {
-        if let Err(err) = data_sender_inner(&mut sub, modem, sysloop, nvs.clone()) {
+        if let Err(err) = data_sender_inner(
+            &mut sub,
+            modem,
+            sysloop,
+            nvs.clone(),
+            store,
+            pins,
+            &mut state,
+        ) {
             log::error!("could not send sensor data error={:?}", err);
+            state.metrics.record_error(err.code());
         }
+        state.metrics.record_reconnect();
 
-        thread::sleep(Duration::from_secs(30));
+        state.watchdog.sleep(Duration::from_secs(30));
     }
 }
 
+/// State of the sender task that outlives Wi-Fi reconnects.
+struct SenderState {
+    puller: remote_config::Puller,
+    telemetry: device::Telemetry,
+    heartbeat: device::Heartbeat,
+    health: health::Task,
+    metrics: metrics::SenderMetrics,
+    notifier: notify::Notifier,
+    soak: soak::Soak,
+    /// The network self test runs once, after the first connect.
+    network_checked: bool,
+    /// The stack use of the tasks is logged once, after the first send.
+    stacks_logged: bool,
+    deadband: deadband::Deadband,
+    /// Points waiting for InfluxDB, kept across reconnects.
+    #[cfg(feature = "influx")]
+    outbox: outbox::Outbox,
+    /// One line of the line protocol body, which is streamed line by line.
+    /// Kept so each batch reuses its allocation.
+    #[cfg(any(feature = "influx", feature = "grafana"))]
+    line: Vec<u8>,
+    watchdog: Watchdog,
+    shared: Shared,
+}
+
+/// Handles shared between the tasks and the HTTP server.
+#[derive(Clone)]
+struct Shared {
+    crash_log: Arc<crash::CrashLog>,
+    sequence: Arc<sequence::Sequence>,
+    alerts: Arc<alert::Alerts>,
+    relay: Arc<relay::Relay>,
+    fan: Arc<fan::Fan>,
+    #[cfg_attr(not(feature = "display"), allow(dead_code))]
+    adjust: Arc<encoder::Adjust>,
+    occupancy: Arc<pir::Occupancy>,
+    contacts: Arc<contacts::Contacts>,
+    pulses: Arc<pulse::Pulses>,
+    weather: Arc<weather::Weather>,
+    gas: Arc<gas::Gas>,
+    hive: Arc<hive::Hive>,
+    aquarium: Arc<aquarium::Aquarium>,
+    rack: Arc<rack::Rack>,
+    energy: Arc<energy::Energy>,
+    dust: Arc<dust::Dust>,
+    noise: Arc<noise::Noise>,
+    co: Arc<co::Co>,
+    frost: Arc<frost::Frost>,
+    mold: Arc<mold::Mold>,
+    hvac: Arc<hvac::Hvac>,
+    trace: Arc<trace::Trace>,
+    /// The latest reading, for the tasks that need no other.
+    state: Arc<state::State>,
+    /// Which init steps are done, in place of fixed start-up delays.
+    ready: Arc<ready::Ready>,
+    /// Readings of remote nodes, received over LoRa or ESP-NOW.
+    #[cfg(any(feature = "lora", feature = "espnow"))]
+    lora: Arc<lora::Inbox>,
+}
+
 fn data_sender_inner(
-    sub: &mut bus::BusReader<SensorData>,
+    sub: &mut broadcast::Receiver<SensorData>,
     modem: &mut impl peripheral::Peripheral<P = esp_idf_hal::modem::Modem>,
     sysloop: &EspSystemEventLoop,
     nvs: Option<EspDefaultNvsPartition>,
-) -> anyhow::Result<Infallible> {
-    let _wifi = wifi(modem, sysloop.clone(), nvs).context("connect to wi-fi")?;
+    store: &Arc<Store>,
+    pins: &[(&'static str, i32)],
+    state: &mut SenderState,
+) -> error::Result<Infallible> {
+    let revision = store.revision();
+    let settings = store.get();
+    let problems = validation::validate(&settings, pins);
+    if let Some(problem) = problems.iter().find(|p| p.code.blocks_wifi()) {
+        bail!(Code::Config, "invalid configuration: {}", problem);
+    }
+
+    let esp_wifi = wifi(modem, sysloop.clone(), nvs, &settings)
+        .context("connect to wi-fi")
+        .code(Code::Wifi)?;
     log::info!("Connected to Wi-Fi network!");
+    state.shared.ready.mark(ready::Step::Wifi);
+    let _sntp = EspSntp::new_default().context("start sntp")?;
 
-    let http_connection = EspHttpConnection::new(&HttpConfiguration {
-        timeout: Some(Duration::from_secs(120)),
-        ..Default::default()
-    })
-    .context("create esp http connection")?;
-    let mut client = Client::wrap(http_connection);
-    let addr = format!(
-        "{}/api/v2/write?org={}&bucket={}&precision=ns",
-        CONFIG.addr, CONFIG.influx_org, CONFIG.influx_bucket
-    );
+    let selftest_off = matches!(settings.selftest.parse(), Ok(selftest::Mode::Off));
+    if !state.network_checked && !selftest_off {
+        state.network_checked = true;
+        let mut report = selftest::Report::default();
+        report.record(selftest::Check::Network, selftest::network(&esp_wifi));
+    }
 
-    log::info!("http API addr={}", addr);
+    if !settings.coredump_url.is_empty() {
+        if let Err(err) = coredump::upload_if_present(&settings.coredump_url, &settings.device_id())
+        {
+            log::error!("could not upload core dump error={:?}", err);
+        }
+    }
+
+    let _server = server::start(store.clone(), pins.to_vec(), state.shared.clone())
+        .context("start http server")?;
+
+    // Nodes send on the channel of the access point, the one joined now.
+    #[cfg(feature = "espnow")]
+    let _espnow = if matches!(settings.espnow_role.parse(), Ok(lora::Role::Gateway)) {
+        match espnow::listen(state.shared.lora.clone()) {
+            Ok(espnow) => Some(espnow),
+            Err(err) => {
+                log::error!("espnow: listening error={:?}", err);
+                None
+            }
+        }
+    } else {
+        None
+    };
 
-    let token = format!("Token {}", CONFIG.influx_token);
-    for data in sub.iter() {
-        do_request(&mut client, &addr, &token, data)?;
+    if let Some(problem) = problems.iter().find(|p| p.code.blocks_sending()) {
+        log::error!("not sending data, invalid configuration: {}", problem);
+        while state.watchdog.recv(sub).is_some() {
+            state.puller.poll(store);
+            if store.revision() != revision {
+                bail!("settings changed, reconnecting");
+            }
+        }
+        bail!("subscription drained");
     }
 
-    bail!("subscription drained")
-}
+    #[cfg(feature = "influx")]
+    let (mut client, addr, token) = {
+        let client = influx::Client::new(|| http_client(&settings))?;
+        let addr = influx::header(format_args!(
+            "{}/api/v2/write?org={}&bucket={}&precision=ns",
+            settings.addr, settings.influx_org, settings.influx_bucket
+        ))
+        .context("influx write url")?;
+        log::info!("http API addr={}", addr);
+        let token = influx::header(format_args!("Token {}", settings.influx_token.expose()))
+            .context("influx authorization")?;
+        (client, addr, token)
+    };
+
+    #[cfg(feature = "otlp")]
+    let mut otlp_client = if settings.otlp_url.is_empty() {
+        None
+    } else if let Some(problem) = validation::find(&problems, validation::Code::OtlpUrl) {
+        log::error!("not exporting to otlp, invalid configuration: {}", problem);
+        None
+    } else {
+        log::info!("otlp url={}", settings.otlp_url);
+        let client = influx::Client::new(|| http_client(&settings))?;
+        Some(client.with_content_type("application/json"))
+    };
 
-fn handle_response(response: Response<&mut EspHttpConnection>) -> Result<(), anyhow::Error> {
-    let status = response.status();
-    if (200..300).contains(&status) {
-        log::trace!("http post success!");
+    // Grafana Live takes line protocol like InfluxDB, with a bearer token.
+    #[cfg(feature = "grafana")]
+    let mut grafana = if settings.grafana_url.is_empty() {
+        None
+    } else if let Some(problem) = validation::find(&problems, validation::Code::Grafana) {
+        log::error!("not pushing to grafana, invalid configuration: {}", problem);
+        None
     } else {
-        log::error!(
-            "http status code={} status={:?}",
-            status,
-            response.status_message()
+        let url = influx::header(format_args!(
+            "{}/api/live/push/{}",
+            settings.grafana_url.trim_end_matches('/'),
+            settings.grafana_stream
+        ))
+        .context("grafana push url")?;
+        log::info!("grafana live url={}", url);
+        let auth = influx::header(format_args!("Bearer {}", settings.grafana_token.expose()))
+            .context("grafana authorization")?;
+        let client = influx::Client::new(|| http_client(&settings))?;
+        Some((client, url, auth))
+    };
+
+    #[cfg(feature = "statsd")]
+    let statsd = if settings.statsd_addr.is_empty() {
+        None
+    } else if let Some(problem) = validation::find(&problems, validation::Code::StatsdAddr) {
+        log::error!("not sending to statsd, invalid configuration: {}", problem);
+        None
+    } else {
+        log::info!("statsd addr={}", settings.statsd_addr);
+        let socket = std::net::UdpSocket::bind("0.0.0.0:0").context("bind statsd socket")?;
+        socket
+            .connect(&settings.statsd_addr)
+            .context("resolve statsd_addr")?;
+        Some(socket)
+    };
+
+    #[cfg(feature = "nats")]
+    let nats_enabled = if settings.nats_url.is_empty() {
+        false
+    } else if let Some(problem) = validation::find(&problems, validation::Code::NatsUrl) {
+        log::error!("not publishing to nats, invalid configuration: {}", problem);
+        false
+    } else {
+        true
+    };
+    #[cfg(feature = "nats")]
+    let mut nats_client = None;
+
+    let tags = settings.tags();
+    log::info!("measurement={} tags={:?}", settings.measurement, tags);
+    #[cfg(feature = "influx")]
+    if settings.bench_rate > 0 {
+        return bench::run(
+            &mut client,
+            &mut state.metrics,
+            &addr,
+            &token,
+            &tags,
+            &settings,
+            store,
+            &state.health,
+            &state.watchdog,
         );
     }
-    read_body(response)?;
-    Ok(())
+    state.puller.poll(store);
+    let heartbeat_interval = Duration::from_secs(u64::from(settings.heartbeat_interval_secs));
+    let delta = settings.delta();
+    let keepalive = Duration::from_secs(u64::from(settings.keepalive_secs));
+    loop {
+        state.health.tick();
+        if state.soak.due(&settings, sub.sent()) == Some(soak::Disruption::WifiDrop) {
+            bail!("soak: dropping wi-fi");
+        }
+        // Wake up for the heartbeat even when the sensor stays silent.
+        let timeout = state.heartbeat.remaining(heartbeat_interval);
+        // A backlog goes out batch by batch without waiting for readings.
+        #[cfg(feature = "influx")]
+        let timeout = if state.outbox.has_backlog() {
+            Duration::ZERO
+        } else {
+            timeout
+        };
+        let received = state.watchdog.recv_timeout(sub, timeout);
+        // Replayed readings would be written with the current time.
+        let replayed = received.is_ok() && state.shared.trace.replaying();
+        // Readings within the deltas of the last one sent wait for the keepalive.
+        let skipped = match received {
+            Ok(data) if !replayed => !state.deadband.due(data, delta, keepalive),
+            _ => false,
+        };
+        if skipped {
+            log::trace!("reading within the deltas, not sending");
+        }
+        #[cfg_attr(not(feature = "influx"), allow(unused_variables))]
+        let reading = received.is_ok() && !replayed && !skipped;
+        #[cfg_attr(not(any(feature = "lora", feature = "espnow")), allow(unused_mut))]
+        let mut points = match received {
+            Ok(_) if replayed => Vec::new(),
+            Ok(data) => {
+                let mut points = Vec::new();
+                // Only the DHT22 reading waits for its deltas, the other
+                // points go out on their own schedule.
+                if !skipped {
+                    let mut point = sensor_point(&settings, &tags, data);
+                    if cfg!(feature = "relay")
+                        && !(settings.relay_control.is_empty() && settings.automation.is_empty())
+                    {
+                        point = point.field("relay", state.shared.relay.is_on());
+                    }
+                    if cfg!(feature = "fan") {
+                        point = point
+                            .field("fan_duty", state.shared.fan.duty_percent())
+                            .field("fan_rpm", state.shared.fan.rpm());
+                    }
+                    if cfg!(feature = "pir") {
+                        let hold = Duration::from_secs(u64::from(settings.pir_hold_secs));
+                        point = point.field("occupied", state.shared.occupancy.is_occupied(hold));
+                    }
+                    for (name, open) in state.shared.contacts.states() {
+                        point = point.field(name, open);
+                    }
+                    points.push(point);
+                }
+                points.extend(presence::points(&tags));
+                points.extend(state.shared.pulses.points(&tags));
+                points.extend(state.shared.weather.point(&tags));
+                points.extend(state.shared.gas.point(&tags, &settings));
+                points.extend(state.shared.hive.point(&tags, &settings));
+                points.extend(state.shared.aquarium.point(&tags));
+                points.extend(state.shared.rack.points(&tags));
+                points.extend(state.shared.energy.point(&tags, &settings));
+                points.extend(state.shared.dust.point(&tags, &settings));
+                points.extend(state.shared.noise.point(&tags));
+                points.extend(state.shared.co.point(&tags));
+                points.extend(state.shared.frost.point(&tags));
+                points.extend(state.shared.mold.point(&tags));
+                points.extend(state.shared.hvac.point(&tags));
+                points
+            }
+            Err(broadcast::RecvError::Closed) => break,
+            Err(_) => Vec::new(),
+        };
+        let mut diagnostics = Vec::new();
+        if let Some(point) = state.heartbeat.poll(heartbeat_interval, &tags) {
+            diagnostics.push(point);
+        }
+        diagnostics.extend(state.soak.take_point(&tags));
+        let telemetry_interval = Duration::from_secs(u64::from(settings.telemetry_interval_secs));
+        if let Some(point) = state.telemetry.poll(telemetry_interval, &tags) {
+            diagnostics.push(point);
+            diagnostics.extend(health::points(&tags));
+            diagnostics.push(state.metrics.point(&tags));
+            #[cfg(feature = "influx")]
+            diagnostics.push(state.outbox.point(&tags));
+        }
+        let mut alerts = Vec::new();
+        // Contact changes wake the sender, so they go out right away.
+        for change in state.shared.contacts.take_changes() {
+            alerts.push(change.point(&tags));
+        }
+        let events = state.shared.alerts.take_events();
+        for event in &events {
+            alerts.push(event.point(&tags));
+        }
+        state.notifier.notify(&settings, &events);
+        #[cfg(any(feature = "lora", feature = "espnow"))]
+        for uplink in state.shared.lora.take() {
+            // Forwarded readings are tagged with the node's device id.
+            let remote_tags: Vec<(String, String)> = tags
+                .iter()
+                .map(|(key, value)| match key.as_str() {
+                    "device" => (key.clone(), uplink.device.clone()),
+                    _ => (key.clone(), value.clone()),
+                })
+                .collect();
+            let mut point = sensor_point(&settings, &remote_tags, uplink.data);
+            if let Some(rssi) = uplink.rssi {
+                point = point.field("rssi", i64::from(rssi));
+            }
+            if let Some(snr) = uplink.snr {
+                point = point.field("snr", snr);
+            }
+            points.push(point);
+        }
+        let crash_report = state.shared.crash_log.pending();
+        if let Some(report) = crash_report {
+            // Line protocol does not allow newlines in field values.
+            let report = report.replace('\n', " | ");
+            alerts.push(Point::new("panic").tags(&tags).field("message", &*report));
+        }
+
+        let fresh = [
+            (outbox::Class::Alert, alerts),
+            (outbox::Class::Reading, points),
+            (outbox::Class::Diagnostics, diagnostics),
+        ];
+        let nothing_new = fresh.iter().all(|(_, points)| points.is_empty());
+        #[cfg(feature = "influx")]
+        let nothing_new = nothing_new && state.outbox.is_empty();
+        if nothing_new {
+            continue;
+        }
+
+        // The other sinks drop what fails, they only get the new points.
+        #[cfg(any(
+            feature = "grafana",
+            feature = "otlp",
+            feature = "statsd",
+            feature = "nats"
+        ))]
+        let points: Vec<Point> = fresh.iter().flat_map(|(_, p)| p).cloned().collect();
+        // Encoded before numbering, `point_seq` is for InfluxDB queries.
+        #[cfg(feature = "otlp")]
+        let otlp_body = (otlp_client.is_some() && !points.is_empty()).then(|| {
+            let now = schedule::unix_time().map_or(0, |now| now.as_nanos() as i64);
+            otlp::encode(&points, now)
+        });
+        #[cfg(feature = "statsd")]
+        let statsd_packets = (statsd.is_some() && !points.is_empty())
+            .then(|| statsd::encode(&points, &settings.statsd_prefix, statsd::MAX_PACKET));
+        #[cfg(feature = "nats")]
+        let nats_messages = (nats_enabled && !points.is_empty())
+            .then(|| nats::messages(&points, &settings.nats_subject));
+        // Live dashboards have no use for replayed data, Grafana is handled
+        // like the other sinks and tried before InfluxDB can give up on Wi-Fi.
+        #[cfg(feature = "grafana")]
+        if let Some((client, url, auth)) = grafana.as_mut().filter(|_| !points.is_empty()) {
+            let body = influx::LineProtocol::new(&points, &mut state.line);
+            if let Err(err) = client.write(&mut state.metrics, url, auth, body) {
+                log::warn!("grafana: sending error={:#}", err);
+            }
+        }
+        // Without a clock the server's time is the best there is.
+        #[cfg(feature = "influx")]
+        let now = schedule::unix_time().map(|now| now.as_nanos() as i64);
+        #[cfg(feature = "influx")]
+        for (class, mut points) in fresh {
+            // Numbered once on the way in, so a resent point repeats its number.
+            state.shared.sequence.stamp(&mut points);
+            state.outbox.extend(class, points, now);
+        }
+        #[cfg(feature = "influx")]
+        {
+            let batch = state.outbox.next_batch(outbox::MAX_BATCH);
+            let body = influx::LineProtocol::new(batch, &mut state.line);
+            // A failure after the retry on a fresh connection gives up on Wi-Fi,
+            // the batch stays in the outbox for the next connection.
+            let status = client.write(&mut state.metrics, &addr, &token, body)?;
+            state.soak.written(status, reading, sub.sent());
+            if (200..300).contains(&status) {
+                state.shared.ready.mark(ready::Step::Written);
+            }
+            // Throttled or failing servers get the batch again later.
+            if status != 429 && status < 500 {
+                state.outbox.sent();
+            }
+        }
+        #[cfg(feature = "otlp")]
+        if let (Some(client), Some(body)) = (&mut otlp_client, &otlp_body) {
+            let auth = settings.otlp_auth.expose();
+            let body = body.as_slice();
+            // Dropped like the other sinks, InfluxDB may have the batch already.
+            if let Err(err) = client.write(&mut state.metrics, &settings.otlp_url, auth, body) {
+                log::warn!("otlp: sending error={:#}", err);
+            }
+        }
+        #[cfg(feature = "statsd")]
+        if let (Some(socket), Some(packets)) = (&statsd, &statsd_packets) {
+            // StatsD is lossy by design, the next batch goes out regardless.
+            if let Err(err) = packets.iter().try_for_each(|p| socket.send(p).map(drop)) {
+                log::warn!("statsd: sending error={:?}", err);
+            }
+        }
+        #[cfg(feature = "nats")]
+        if let Some(messages) = &nats_messages {
+            publish_nats(&mut nats_client, &settings, messages);
+        }
+        if crash_report.is_some() {
+            state.shared.crash_log.mark_reported()?;
+        }
+        if !state.stacks_logged {
+            // By the first send every task went through its loop once.
+            state.stacks_logged = true;
+            state.shared.ready.mark(ready::Step::Sent);
+            health::log_stacks();
+        }
+        state.puller.poll(store);
+
+        if store.revision() != revision {
+            bail!("settings changed, reconnecting");
+        }
+    }
+
+    bail!("subscription drained")
 }
 
-fn do_request(
-    client: &mut Client<EspHttpConnection>,
-    addr: &str,
-    token: &str,
-    data: SensorData,
-) -> Result<(), anyhow::Error> {
-    let mut body = influxdb_line_protocol::builder::LineProtocolBuilder::new()
-        .measurement("living room #1")
-        .tag("sensor", "dht22")
-        .field("humidity", data.humidity as f64)
-        .field("temperature", data.temperature as f64)
-        .close_line()
-        .build();
-    body.shrink_to_fit();
-    let content_length_header = format!("{}", body.len());
-    let headers = [
-        ("authorization", token),
-        ("accept", "application/json"),
-        ("content-type", "text/plain"),
-        ("connection", "keep-alive"),
-        ("content-length", &*content_length_header),
-    ];
+/// Publishes to NATS, connecting first when there is no connection. A
+/// failed batch is retried once on a fresh connection and then dropped, so
+/// NATS being down does not hold back the other sinks.
+#[cfg(feature = "nats")]
+fn publish_nats(
+    client: &mut Option<nats::Client<std::net::TcpStream>>,
+    settings: &Settings,
+    messages: &[nats::Message],
+) {
+    for attempt in 0..2 {
+        let connected = match client {
+            Some(connected) => connected,
+            None => {
+                // JetStream acknowledgements go to a per-device inbox.
+                let inbox = (settings.nats_ack_secs > 0)
+                    .then(|| format!("_INBOX.{}", settings.device_id()));
+                let timeout = match settings.nats_ack_secs {
+                    0 => Duration::from_secs(u64::from(settings.http_timeout_secs)),
+                    secs => Duration::from_secs(u64::from(secs)),
+                };
+                let token = settings.nats_token.expose();
+                match nats::Client::connect(&settings.nats_url, token, inbox, timeout) {
+                    Ok(connected) => client.insert(connected),
+                    Err(err) => {
+                        log::warn!("nats: connecting error={:#}", err);
+                        return;
+                    }
+                }
+            }
+        };
+        match connected.publish(messages) {
+            Ok(()) => return,
+            Err(err) => {
+                log::warn!("nats: publishing attempt={} error={:#}", attempt, err);
+                *client = None;
+            }
+        }
+    }
+}
 
-    let mut request = client.post(addr, &headers).context("create post request")?;
-    request.write_all(&body)?;
-    request.flush()?;
+/// Builds the sensor point using the configured measurement, tags and field names.
+fn sensor_point(settings: &Settings, tags: &[(String, String)], data: SensorData) -> Point {
+    reading::sensor_point(
+        &settings.measurement,
+        &settings.temperature_field,
+        &settings.humidity_field,
+        tags,
+        data,
+        // Stamped here rather than by the server once SNTP or the RTC set
+        // the clock, so delayed requests keep the time of the reading.
+        schedule::unix_time().map(|now| now.as_nanos() as i64),
+    )
+}
 
-    log::trace!("doing http post request...");
-    let response = request.submit().context("do post request")?;
-    handle_response(response)?;
+/// Opens the connection used for writes. It is kept alive between writes.
+#[cfg_attr(
+    not(any(feature = "influx", feature = "otlp", feature = "grafana")),
+    allow(dead_code)
+)]
+fn http_client(settings: &Settings) -> error::Result<Client<EspHttpConnection>> {
+    fault::connect();
+    let buffer_size = |size: u32| (size > 0).then_some(size as usize);
+    let http_connection = EspHttpConnection::new(&HttpConfiguration {
+        timeout: Some(Duration::from_secs(u64::from(settings.http_timeout_secs))),
+        buffer_size: buffer_size(settings.http_rx_buffer),
+        buffer_size_tx: buffer_size(settings.http_tx_buffer),
+        ..Default::default()
+    })
+    .context("create esp http connection")?;
+    Ok(Client::wrap(http_connection))
+}
 
-    Ok(())
+impl influx::Connection for Client<EspHttpConnection> {
+    fn post(
+        &mut self,
+        addr: &str,
+        headers: &[(&str, &str)],
+        body: &mut dyn influx::Body,
+    ) -> error::Result<u16> {
+        fault::http_request()?;
+        soak::check_outage()?;
+        let mut request = Client::post(self, addr, headers).context("create post request")?;
+        // Each piece goes out through the client's send buffer.
+        body.write_to(&mut |piece| Ok(request.write_all(piece)?))?;
+        request.flush()?;
+
+        log::trace!("doing http post request...");
+        let response = request.submit().context("do post request")?;
+        let status = response.status();
+        read_body(response)?;
+        Ok(status)
+    }
 }
 
-fn read_body(mut response: Response<&mut EspHttpConnection>) -> anyhow::Result<()> {
+fn read_body(mut response: Response<&mut EspHttpConnection>) -> error::Result<()> {
     let mut buf = [0u8; 128];
     let (_, mut body) = response.split();
     let bytes_read = io::try_read_full(&mut body, &mut buf[..]).map_err(|e| e.0)?;
@@ -195,111 +1657,70 @@ fn read_body(mut response: Response<&mut EspHttpConnection>) -> anyhow::Result<(
 }
 
 fn read_sensor<P: gpio::InputPin + gpio::OutputPin>(
-    bus: &mut Bus<SensorData>,
+    readings: &broadcast::Sender<SensorData>,
+    state: &state::State,
+    ready: &ready::Ready,
     mut pin: PinDriver<'_, P, gpio::InputOutput>,
+    store: &Store,
+    trace: &trace::Trace,
+    wake: mpsc::Receiver<()>,
 ) {
-    thread::sleep(Duration::from_secs(10));
-    dht_hal_drv::dht_read(dht_hal_drv::DhtType::DHT22, &mut pin, delay::Ets).ok();
+    let watchdog = Watchdog::subscribe("read_sensor");
+    let health = health::register("read_sensor");
+
+    // Only as long as the DHT22 needs after power-on, Wi-Fi comes up
+    // meanwhile. The first reading after it may be stale.
+    watchdog.sleep(DHT_POWER_ON.saturating_sub(device::uptime()));
+    dht::read(&mut pin, &mut delay::Ets).ok();
     thread::sleep(Duration::from_millis(500));
 
     loop {
-        let value = match dht_hal_drv::dht_read(dht_hal_drv::DhtType::DHT22, &mut pin, delay::Ets) {
+        watchdog.feed();
+        health.tick();
+        // A replay stands in for the sensor, paced by the recording.
+        if let Some((value, wait)) = trace.next_replayed() {
+            watchdog.wait(&wake, wait);
+            if value.is_correct() {
+                log::info!("read_sensor: replayed data={}", value);
+                readings.send(value);
+                state.update(value);
+                ready.mark(ready::Step::Sensor);
+            } else {
+                log::error!("read_sensor: replayed invalid data={}", value);
+            }
+            continue;
+        }
+
+        let value = match fault::sensor_read(dht::read(&mut pin, &mut delay::Ets)) {
             Result::Ok(x) => x,
             Result::Err(err) => {
                 log::error!("read_sensor: reading dht sensor error={:?}", err);
-                log::trace!("read_sensor: going to sleep for 10s...");
-                thread::sleep(Duration::from_secs(10));
+                // Until the first reading, retry as soon as the sensor allows.
+                let retry = if ready.is_done(ready::Step::Sensor) {
+                    Duration::from_secs(10)
+                } else {
+                    DHT_MIN_INTERVAL
+                };
+                log::trace!("read_sensor: going to sleep for {:?}...", retry);
+                watchdog.sleep(retry);
                 continue;
             }
         };
+        trace.record(value);
 
-        let value: SensorData = value.into();
         if value.is_correct() {
             log::info!("read_sensor: data={}", value);
-            bus.broadcast(value);
+            readings.send(value);
+            state.update(value);
+            ready.mark(ready::Step::Sensor);
         } else {
             log::error!("read_sensor: got invalid data={}", value);
         }
 
-        let interval = CONFIG.read_sensor_interval_secs;
+        let interval = store.get().read_sensor_interval_secs;
         log::trace!("read_sensor: sleeping for {}s...", interval);
-        thread::sleep(Duration::from_secs(u64::from(interval)));
-    }
-}
-
-#[cfg(feature = "display")]
-fn display_sensor_data<'d, PCLK, PDIO>(
-    mut sub: bus::BusReader<SensorData>,
-    clk: PinDriver<'d, PCLK, gpio::InputOutput>,
-    dio: PinDriver<'d, PDIO, gpio::InputOutput>,
-) where
-    PCLK: gpio::InputPin + gpio::OutputPin,
-    PDIO: gpio::InputPin + gpio::OutputPin,
-{
-    thread::sleep(Duration::from_secs(5));
-
-    let mut tm = tm1637::TM1637::new(clk, dio, delay::Ets);
-    log::trace!("init tm1637...");
-    if let Err(err) = tm.init() {
-        log::error!("could not init tm1637 error={:?}", err);
-    }
-    log::trace!("clear tm1637...");
-    if let Err(err) = tm.clear() {
-        log::error!("could not clear tm1637 error={:?}", err);
-    }
-    log::trace!("set brightness tm1637...");
-    if let Err(err) = tm.set_brightness(128) {
-        log::error!("could not set brightness tm1637 error={:?}", err);
-    }
-
-    for SensorData {
-        temperature,
-        humidity,
-    } in sub.iter()
-    {
-        let digits = [
-            ((temperature / 10.) as u32 % 10) as u8,
-            (temperature as u32 % 10) as u8,
-            ((humidity / 10.) as u32 % 10) as u8,
-            (humidity as u32 % 10) as u8,
-        ];
-
-        log::trace!("displaying data on tm1637...");
-        if let Err(err) = tm.print_hex(0, &digits) {
-            log::error!("failed to print hex on tm1637 error={:?}", err);
-        }
-    }
-}
-
-#[derive(Debug, Clone, Copy)]
-struct SensorData {
-    temperature: f32,
-    humidity: f32,
-}
-
-impl SensorData {
-    fn is_correct(&self) -> bool {
-        let humidity_correct = self.humidity > 0.0 && self.humidity < 100.0;
-        let temperature_correct = self.temperature >= -40.0 && self.temperature <= 80.0;
-
-        humidity_correct && temperature_correct
-    }
-}
-
-impl Display for SensorData {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_fmt(format_args!(
-            "temperature={:2.1}°C humidity={:2.1}%",
-            self.temperature, self.humidity
-        ))
-    }
-}
-
-impl From<dht_hal_drv::DhtValue> for SensorData {
-    fn from(value: dht_hal_drv::DhtValue) -> Self {
-        Self {
-            temperature: value.temperature(),
-            humidity: value.humidity(),
+        if watchdog.wait(&wake, Duration::from_secs(u64::from(interval))) {
+            log::info!("read_sensor: woken up early");
         }
     }
 }
@@ -308,14 +1729,15 @@ fn wifi(
     modem: &'_ mut impl peripheral::Peripheral<P = esp_idf_hal::modem::Modem>,
     sysloop: EspSystemEventLoop,
     nvs: Option<EspDefaultNvsPartition>,
-) -> anyhow::Result<Box<EspWifi<'_>>> {
-    let ssid = CONFIG.ssid;
-    let pass = CONFIG.password;
+    settings: &Settings,
+) -> error::Result<Box<EspWifi<'_>>> {
+    let ssid = settings.ssid.as_str();
+    let pass = settings.password.expose();
     if ssid.is_empty() {
-        bail!("Missing WiFi name")
+        bail!(Code::Config, "Missing WiFi name")
     }
     if pass.is_empty() {
-        bail!("Missing WiFi password")
+        bail!(Code::Config, "Missing WiFi password")
     }
 
     let mut esp_wifi = EspWifi::new(modem, sysloop.clone(), nvs)?;
@@ -353,8 +1775,12 @@ fn wifi(
     };
 
     wifi.set_configuration(&Configuration::Client(ClientConfiguration {
-        ssid: ssid.into(),
-        password: pass.into(),
+        ssid: ssid
+            .parse()
+            .map_err(|_| format_err!(Code::Config, "WiFi name is too long"))?,
+        password: pass
+            .parse()
+            .map_err(|_| format_err!(Code::Config, "WiFi password is too long"))?,
         channel,
         ..Default::default()
     }))?;
@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+use crate::{error::Code, point::Point};
+
+/// Upper bounds of the request duration histogram buckets, in milliseconds.
+const DURATION_BUCKETS_MS: [u64; 6] = [100, 250, 500, 1000, 2500, 5000];
+
+/// Counters of the send pipeline since boot, published as the `sender`
+/// measurement. Values only grow, so rates are computed on the server side.
+#[derive(Default)]
+pub struct SenderMetrics {
+    requests: u32,
+    /// Requests that failed before an HTTP status was received.
+    failures: u32,
+    status_2xx: u32,
+    status_4xx: u32,
+    status_5xx: u32,
+    status_other: u32,
+    bytes: u64,
+    /// Times the sender dropped the connection and started over.
+    reconnects: u32,
+    /// What made it start over, counted per [`Code`].
+    errors: [u32; Code::ALL.len()],
+    duration_ms_sum: u64,
+    /// Cumulative counts per bucket of `DURATION_BUCKETS_MS`, plus one for
+    /// everything slower.
+    duration_buckets: [u32; DURATION_BUCKETS_MS.len() + 1],
+}
+
+impl SenderMetrics {
+    /// Records a request of `bytes` that got `status`, or no response at all.
+    pub fn record(&mut self, bytes: usize, duration: Duration, status: Option<u16>) {
+        self.requests += 1;
+        self.bytes += bytes as u64;
+
+        match status {
+            None => self.failures += 1,
+            Some(200..=299) => self.status_2xx += 1,
+            Some(400..=499) => self.status_4xx += 1,
+            Some(500..=599) => self.status_5xx += 1,
+            Some(_) => self.status_other += 1,
+        }
+
+        let millis = duration.as_millis() as u64;
+        self.duration_ms_sum += millis;
+        for (i, bound) in DURATION_BUCKETS_MS.iter().enumerate() {
+            if millis <= *bound {
+                self.duration_buckets[i] += 1;
+            }
+        }
+        self.duration_buckets[DURATION_BUCKETS_MS.len()] += 1;
+    }
+
+    pub fn record_reconnect(&mut self) {
+        self.reconnects += 1;
+    }
+
+    pub fn record_error(&mut self, code: Code) {
+        self.errors[code as usize] += 1;
+    }
+
+    pub fn point(&self, tags: &[(String, String)]) -> Point {
+        let mut point = Point::new("sender")
+            .tags(tags)
+            .field("requests", self.requests)
+            .field("failures", self.failures)
+            .field("status_2xx", self.status_2xx)
+            .field("status_4xx", self.status_4xx)
+            .field("status_5xx", self.status_5xx)
+            .field("status_other", self.status_other)
+            .field("bytes", self.bytes)
+            .field("reconnects", self.reconnects)
+            .field("duration_ms_sum", self.duration_ms_sum);
+        for (i, bound) in DURATION_BUCKETS_MS.iter().enumerate() {
+            point = point.field(format!("duration_le_{}ms", bound), self.duration_buckets[i]);
+        }
+        // Only the codes seen, most never are.
+        for code in Code::ALL {
+            if self.errors[code as usize] > 0 {
+                point = point.field(
+                    format!("errors_{}", code.name()),
+                    self.errors[code as usize],
+                );
+            }
+        }
+        point.field(
+            "duration_le_inf",
+            self.duration_buckets[DURATION_BUCKETS_MS.len()],
+        )
+    }
+}
@@ -0,0 +1,195 @@
+//! Modbus TCP framing and the register map of the device, read with the
+//! Read Holding Registers and Read Input Registers functions alike.
+
+use crate::error::{self, bail};
+use crate::reading::SensorData;
+
+pub const PORT: u16 = 502;
+/// Length of the MBAP header: transaction, protocol, length and unit id.
+pub const HEADER: usize = 7;
+
+const READ_HOLDING_REGISTERS: u8 = 0x03;
+const READ_INPUT_REGISTERS: u8 = 0x04;
+/// Most registers a single read may ask for.
+const MAX_COUNT: u16 = 125;
+
+const ILLEGAL_FUNCTION: u8 = 0x01;
+const ILLEGAL_DATA_ADDRESS: u8 = 0x02;
+const ILLEGAL_DATA_VALUE: u8 = 0x03;
+
+/// Register of a reading that is not there yet.
+pub const MISSING: u16 = 0x8000;
+
+/// Device status mapped after the reading.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Status {
+    pub uptime_secs: u32,
+    pub free_heap: u32,
+    pub alerts: u16,
+}
+
+/// The register map:
+///
+/// | Register | Value                                          |
+/// |----------|------------------------------------------------|
+/// | 0        | temperature, 0.1 °C, signed                    |
+/// | 1        | humidity, 0.1 %                                |
+/// | 2–3      | temperature, °C, 32-bit float, high word first |
+/// | 4–5      | humidity, %, 32-bit float, high word first     |
+/// | 6        | 1 once there is a reading                      |
+/// | 7        | active alerts                                  |
+/// | 8–9      | uptime, s, high word first                     |
+/// | 10–11    | free heap, bytes, high word first              |
+///
+/// Registers 0 to 5 are [`MISSING`] before the first reading.
+pub fn registers(reading: Option<SensorData>, status: &Status) -> Vec<u16> {
+    let mut registers = Vec::with_capacity(12);
+    match reading {
+        Some(data) => {
+            registers.push(tenths(data.temperature));
+            registers.push(tenths(data.humidity));
+            registers.extend(words(data.temperature.to_bits()));
+            registers.extend(words(data.humidity.to_bits()));
+            registers.push(1);
+        }
+        None => {
+            registers.extend([MISSING; 6]);
+            registers.push(0);
+        }
+    }
+    registers.push(status.alerts);
+    registers.extend(words(status.uptime_secs));
+    registers.extend(words(status.free_heap));
+    registers
+}
+
+/// `value` in tenths as a signed register, saturated to its range.
+fn tenths(value: f32) -> u16 {
+    (value * 10.).round().clamp(-32767., 32767.) as i16 as u16
+}
+
+fn words(value: u32) -> [u16; 2] {
+    [(value >> 16) as u16, value as u16]
+}
+
+/// Length of the PDU that follows an MBAP header.
+pub fn pdu_len(header: &[u8; HEADER]) -> error::Result<usize> {
+    let protocol = u16::from_be_bytes([header[2], header[3]]);
+    if protocol != 0 {
+        bail!("modbus protocol id {} is not 0", protocol);
+    }
+    // The length counts the unit id and a PDU of at most 253 bytes.
+    let len = u16::from_be_bytes([header[4], header[5]]);
+    if !(2..=254).contains(&len) {
+        bail!("modbus length {} is invalid", len);
+    }
+    Ok(usize::from(len) - 1)
+}
+
+/// The response frame to a request of `header` and `pdu`, reading from
+/// `registers` or with an exception.
+pub fn respond(header: &[u8; HEADER], pdu: &[u8], registers: &[u16]) -> Vec<u8> {
+    let function = pdu.first().copied().unwrap_or_default();
+    let body = read(function, pdu, registers).unwrap_or_else(|exception| {
+        log::debug!("modbus: exception {} to function {}", exception, function);
+        vec![function | 0x80, exception]
+    });
+
+    let mut frame = Vec::with_capacity(HEADER + body.len());
+    // Transaction and protocol id as they came.
+    frame.extend_from_slice(&header[..4]);
+    frame.extend_from_slice(&(body.len() as u16 + 1).to_be_bytes());
+    frame.push(header[6]);
+    frame.extend_from_slice(&body);
+    frame
+}
+
+/// Response PDU of a read, or the exception code.
+fn read(function: u8, pdu: &[u8], registers: &[u16]) -> Result<Vec<u8>, u8> {
+    if !matches!(function, READ_HOLDING_REGISTERS | READ_INPUT_REGISTERS) {
+        return Err(ILLEGAL_FUNCTION);
+    }
+    let [_, address_high, address_low, count_high, count_low] = pdu else {
+        return Err(ILLEGAL_DATA_VALUE);
+    };
+    let address = usize::from(u16::from_be_bytes([*address_high, *address_low]));
+    let count = u16::from_be_bytes([*count_high, *count_low]);
+    if !(1..=MAX_COUNT).contains(&count) {
+        return Err(ILLEGAL_DATA_VALUE);
+    }
+    let values = registers
+        .get(address..address + usize::from(count))
+        .ok_or(ILLEGAL_DATA_ADDRESS)?;
+
+    let mut body = vec![function, count as u8 * 2];
+    for value in values {
+        body.extend_from_slice(&value.to_be_bytes());
+    }
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER_BYTES: [u8; HEADER] = [0x00, 0x2A, 0x00, 0x00, 0x00, 0x06, 0x01];
+
+    fn reading() -> Vec<u16> {
+        let data = SensorData {
+            temperature: -4.25,
+            humidity: 55.5,
+        };
+        let status = Status {
+            uptime_secs: 0x0001_0002,
+            free_heap: 120_000,
+            alerts: 2,
+        };
+        registers(Some(data), &status)
+    }
+
+    #[test]
+    fn maps_reading_and_status() {
+        let map = reading();
+        assert_eq!(map.len(), 12);
+        assert_eq!(map[0] as i16, -43);
+        assert_eq!(map[1], 555);
+        assert_eq!(
+            f32::from_bits(u32::from(map[2]) << 16 | u32::from(map[3])),
+            -4.25
+        );
+        assert_eq!(&map[6..10], [1, 2, 1, 2]);
+
+        let empty = registers(None, &Status::default());
+        assert_eq!(empty.len(), 12);
+        assert_eq!(empty[0], MISSING);
+        assert_eq!(empty[6], 0);
+    }
+
+    #[test]
+    fn reads_registers() {
+        assert_eq!(pdu_len(&HEADER_BYTES).unwrap(), 5);
+        let response = respond(&HEADER_BYTES, &[0x04, 0x00, 0x00, 0x00, 0x02], &reading());
+        assert_eq!(
+            response,
+            [0x00, 0x2A, 0x00, 0x00, 0x00, 0x07, 0x01, 0x04, 0x04, 0xFF, 0xD5, 0x02, 0x2B]
+        );
+    }
+
+    #[test]
+    fn answers_exceptions() {
+        let registers = reading();
+        let exception = |pdu: &[u8]| respond(&HEADER_BYTES, pdu, &registers)[7..].to_vec();
+        // Write Single Register.
+        assert_eq!(exception(&[0x06, 0x00, 0x00, 0x00, 0x01]), [0x86, 0x01]);
+        assert_eq!(exception(&[0x03, 0x00, 0x0B, 0x00, 0x02]), [0x83, 0x02]);
+        assert_eq!(exception(&[0x03, 0x00, 0x00, 0x00, 0x00]), [0x83, 0x03]);
+        assert_eq!(exception(&[0x03, 0x00]), [0x83, 0x03]);
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        assert!(pdu_len(&[0, 1, 0, 1, 0, 6, 1]).is_err());
+        assert!(pdu_len(&[0, 1, 0, 0, 0, 1, 1]).is_err());
+        assert!(pdu_len(&[0, 1, 0, 0, 1, 0, 1]).is_err());
+    }
+}
@@ -0,0 +1,118 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    alert::Alerts,
+    device, error, health,
+    modbus::{self, Status},
+    state::State,
+    watchdog::Watchdog,
+};
+
+/// How long the task waits between polls of the connections.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+const MAX_CLIENTS: usize = 4;
+/// Connections without a request for this long are closed.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+struct Client {
+    stream: TcpStream,
+    /// Received bytes of an incomplete frame.
+    buf: Vec<u8>,
+    last_request: Instant,
+}
+
+/// Serves the latest reading and the device status as Modbus TCP registers
+/// on port 502, to up to 4 connections at a time.
+pub fn run(state: &State, alerts: &Alerts) {
+    let watchdog = Watchdog::subscribe("modbus");
+    let health = health::register("modbus");
+    let listener = match TcpListener::bind(("0.0.0.0", modbus::PORT)) {
+        Ok(listener) => listener,
+        Err(err) => {
+            log::error!("modbus: binding port {} error={:?}", modbus::PORT, err);
+            return;
+        }
+    };
+    if let Err(err) = listener.set_nonblocking(true) {
+        log::error!("modbus: setting non-blocking error={:?}", err);
+        return;
+    }
+    log::info!("modbus: listening on port {}", modbus::PORT);
+
+    let mut clients: Vec<Client> = Vec::new();
+    loop {
+        health.tick();
+        watchdog.sleep(POLL_INTERVAL);
+
+        match listener.accept() {
+            Ok((stream, addr)) if clients.len() < MAX_CLIENTS => {
+                match stream.set_nonblocking(true) {
+                    Ok(()) => {
+                        log::info!("modbus: {} connected", addr);
+                        clients.push(Client {
+                            stream,
+                            buf: Vec::new(),
+                            last_request: Instant::now(),
+                        });
+                    }
+                    Err(err) => log::error!("modbus: setting non-blocking error={:?}", err),
+                }
+            }
+            Ok((_, addr)) => log::warn!("modbus: too many connections, closing {}", addr),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+            Err(err) => log::error!("modbus: accepting error={:?}", err),
+        }
+
+        if clients.is_empty() {
+            continue;
+        }
+        let status = Status {
+            uptime_secs: device::uptime().as_secs() as u32,
+            free_heap: unsafe { esp_idf_sys::esp_get_free_heap_size() },
+            alerts: alerts.active().len() as u16,
+        };
+        let latest = state.latest().map(|s| s.data);
+        let registers = modbus::registers(latest, &status);
+        clients.retain_mut(|client| match serve(client, &registers) {
+            Ok(true) => true,
+            Ok(false) => false,
+            Err(err) => {
+                log::warn!("modbus: dropping connection error={:#}", err);
+                false
+            }
+        });
+    }
+}
+
+/// Answers the complete requests of a client, returns whether the
+/// connection stays open.
+fn serve(client: &mut Client, registers: &[u16]) -> error::Result<bool> {
+    let mut chunk = [0u8; 260];
+    loop {
+        match client.stream.read(&mut chunk) {
+            Ok(0) => return Ok(false),
+            Ok(len) => client.buf.extend_from_slice(&chunk[..len]),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    while client.buf.len() >= modbus::HEADER {
+        let mut header = [0u8; modbus::HEADER];
+        header.copy_from_slice(&client.buf[..modbus::HEADER]);
+        let len = modbus::HEADER + modbus::pdu_len(&header)?;
+        if client.buf.len() < len {
+            break;
+        }
+        let response = modbus::respond(&header, &client.buf[modbus::HEADER..len], registers);
+        // Responses are at most 260 bytes and fit the send buffer.
+        client.stream.write_all(&response)?;
+        client.buf.drain(..len);
+        client.last_request = Instant::now();
+    }
+    Ok(client.last_request.elapsed() < IDLE_TIMEOUT)
+}
@@ -0,0 +1,178 @@
+use std::sync::Mutex;
+#[cfg(feature = "mold")]
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "mold")]
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+
+use crate::point::Point;
+#[cfg(feature = "mold")]
+use crate::{
+    alert::{Alerts, Event},
+    broadcast, health,
+    isopleth::{self, Index},
+    settings::Store,
+    trace,
+    watchdog::Watchdog,
+    SensorData,
+};
+
+/// The growth hours are written to NVS at most this often.
+#[cfg(feature = "mold")]
+const SAVE_INTERVAL: Duration = Duration::from_secs(30 * 60);
+#[cfg(feature = "mold")]
+const NAMESPACE: &str = "mold";
+/// A reading counts for at most this long, so a sensor that was silent
+/// for hours does not stretch the last reading over the gap.
+#[cfg(feature = "mold")]
+const MAX_ELAPSED: Duration = Duration::from_secs(10 * 60);
+/// Name of the alert raised at `mold_alert`.
+#[cfg(feature = "mold")]
+const ALERT: &str = "mold";
+
+/// Latest mould risk, shared with the sender.
+#[derive(Default)]
+pub struct Mold {
+    state: Mutex<Option<State>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct State {
+    risk: f32,
+    hours: f32,
+    /// Relative humidity mould grows at, `None` at 0 °C and below.
+    critical_humidity: Option<f32>,
+}
+
+impl Mold {
+    /// A `mold` point with the `risk` from 0 to 100, the growth `hours`
+    /// and the `critical_humidity` at the current temperature. `None`
+    /// without the `mold` feature.
+    pub fn point(&self, tags: &[(String, String)]) -> Option<Point> {
+        let state = (*self.state.lock().unwrap())?;
+        let point = Point::new("mold")
+            .tags(tags)
+            .field("risk", state.risk)
+            .field("hours", state.hours);
+        Some(match state.critical_humidity {
+            Some(humidity) => point.field("critical_humidity", humidity),
+            None => point,
+        })
+    }
+}
+
+/// Growth hours kept across reboots, as minutes in NVS.
+#[cfg(feature = "mold")]
+struct Saved {
+    nvs: Option<EspNvs<NvsDefault>>,
+    saved_at: Instant,
+}
+
+#[cfg(feature = "mold")]
+impl Saved {
+    fn load(partition: EspDefaultNvsPartition) -> (Saved, Index) {
+        let nvs = match EspNvs::new(partition, NAMESPACE, true) {
+            Ok(nvs) => Some(nvs),
+            Err(err) => {
+                log::error!("mold: opening nvs error={:?}", err);
+                None
+            }
+        };
+        let minutes = nvs
+            .as_ref()
+            .and_then(|nvs| nvs.get_u32("minutes").ok().flatten())
+            .unwrap_or(0);
+        let saved = Saved {
+            nvs,
+            saved_at: Instant::now(),
+        };
+        (saved, Index::new(minutes as f32 / 60.))
+    }
+
+    fn poll(&mut self, index: &Index) {
+        if self.saved_at.elapsed() < SAVE_INTERVAL {
+            return;
+        }
+        self.saved_at = Instant::now();
+        if let Some(nvs) = &mut self.nvs {
+            if let Err(err) = nvs.set_u32("minutes", (index.hours() * 60.) as u32) {
+                log::error!("mold: saving hours error={:?}", err);
+            }
+        }
+    }
+}
+
+/// Adds every reading to the mould risk index. The `mold` alert is raised
+/// once the risk reaches `mold_alert` and cleared when it fell to half of
+/// that.
+#[cfg(feature = "mold")]
+pub fn run(
+    mut sub: broadcast::Receiver<SensorData>,
+    store: &Store,
+    mold: &Mold,
+    alerts: &Alerts,
+    partition: EspDefaultNvsPartition,
+) {
+    let watchdog = Watchdog::subscribe("mold");
+    let health = health::register("mold");
+    let (mut saved, mut index) = Saved::load(partition);
+    log::info!("mold: loaded {:.1} growth hours", index.hours());
+    let mut last: Option<Instant> = None;
+    let mut active = false;
+
+    while let Some(data) = watchdog.recv(&mut sub) {
+        health.tick();
+        let settings = store.get();
+        let limit = settings.mold_hours as f32;
+        let now = trace::now();
+        // Each reading holds until the next one.
+        let elapsed = last.map_or(Duration::ZERO, |last| now.duration_since(last));
+        last = Some(now);
+        index.update(
+            data.temperature,
+            data.humidity,
+            elapsed.min(MAX_ELAPSED),
+            limit,
+        );
+        saved.poll(&index);
+
+        let risk = index.risk(limit);
+        *mold.state.lock().unwrap() = Some(State {
+            risk,
+            hours: index.hours(),
+            critical_humidity: isopleth::critical_humidity(data.temperature),
+        });
+
+        let threshold = settings.mold_alert as f32;
+        let raised = if threshold == 0. {
+            false
+        } else if active {
+            risk > threshold / 2.
+        } else {
+            risk >= threshold
+        };
+        let events = if raised != active {
+            active = raised;
+            if raised {
+                log::warn!("mold: risk {:.0} after {:.1} hours", risk, index.hours());
+            } else {
+                log::info!("mold: risk down to {:.0}", risk);
+            }
+            vec![Event {
+                name: ALERT.into(),
+                urgent: false,
+                raised,
+                value: risk,
+                notified: false,
+            }]
+        } else {
+            Vec::new()
+        };
+        let active_alerts = if active {
+            vec![(ALERT.into(), false)]
+        } else {
+            Vec::new()
+        };
+        alerts.publish("mold", active_alerts, events);
+    }
+}
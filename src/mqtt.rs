@@ -0,0 +1,419 @@
+//! MQTT 3.1.1 client, enough for QoS 0 subscriptions and publishing:
+//! `CONNECT`, `SUBSCRIBE`, `PUBLISH` and the keep-alive pings.
+
+use std::{
+    collections::VecDeque,
+    io::{BufReader, ErrorKind, Read, Write},
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+use crate::error::{self, bail, Code, Context};
+
+pub const PORT: u16 = 1883;
+/// Largest packet accepted from the broker, larger ones end the connection.
+pub const MAX_PACKET: usize = 4096;
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PUBACK: u8 = 0x40;
+const SUBSCRIBE: u8 = 0x82;
+const SUBACK: u8 = 0x90;
+const PINGREQ: u8 = 0xc0;
+const PINGRESP: u8 = 0xd0;
+
+/// A message of a topic.
+#[derive(Debug, PartialEq)]
+pub struct Message {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    /// Whether the broker kept it from before the subscription.
+    pub retain: bool,
+}
+
+/// Parses `mqtt://host` or `mqtt://host:port` into the host and port.
+pub fn parse_url(url: &str) -> error::Result<(&str, u16)> {
+    let Some(authority) = url.strip_prefix("mqtt://") else {
+        bail!("{:?} must start with mqtt://", url);
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse()
+                .with_context(|| format!("{:?} has an invalid port", url))?,
+        ),
+        None => (authority, PORT),
+    };
+    if host.is_empty() || host.contains('/') || host.contains(char::is_whitespace) {
+        bail!("{:?} has an invalid host", url);
+    }
+    Ok((host, port))
+}
+
+/// Checks a topic to subscribe or publish to: `/` separated levels without
+/// wildcards.
+pub fn check_topic(topic: &str) -> error::Result<()> {
+    if topic.is_empty() || topic.len() > 128 || topic.contains(['+', '#', '\0']) {
+        bail!("topic {:?} must be 1 to 128 bytes without wildcards", topic);
+    }
+    Ok(())
+}
+
+/// A connection to an MQTT broker.
+pub struct Client<S> {
+    stream: BufReader<S>,
+    keep_alive: Duration,
+    last_sent: Instant,
+    next_id: u16,
+    /// Messages read while waiting for an acknowledgement.
+    pending: VecDeque<Message>,
+}
+
+impl Client<TcpStream> {
+    /// Connects over TCP to `url`, see [`Client::new`]. Reads time out
+    /// after `timeout`, which has to be below half the keep-alive for
+    /// [`Client::poll`] to ping in time.
+    pub fn connect(
+        url: &str,
+        client_id: &str,
+        user: &str,
+        password: &str,
+        keep_alive: Duration,
+        timeout: Duration,
+    ) -> error::Result<Self> {
+        let (host, port) = parse_url(url)?;
+        let stream = TcpStream::connect((host, port)).context("connect to mqtt")?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(keep_alive))?;
+        Client::new(stream, client_id, user, password, keep_alive)
+    }
+}
+
+impl<S: Read + Write> Client<S> {
+    /// Sends `CONNECT` with a clean session, with `user` and `password`
+    /// when they are not empty, and waits for the broker to accept it. A
+    /// password alone goes with an empty user name, as MQTT 3.1.1 allows
+    /// no password without one.
+    pub fn new(
+        stream: S,
+        client_id: &str,
+        user: &str,
+        password: &str,
+        keep_alive: Duration,
+    ) -> error::Result<Self> {
+        let mut client = Client {
+            stream: BufReader::new(stream),
+            keep_alive,
+            last_sent: Instant::now(),
+            next_id: 0,
+            pending: VecDeque::new(),
+        };
+
+        let mut flags = 0x02;
+        let mut body = Vec::new();
+        put_str(&mut body, "MQTT");
+        body.push(4);
+        let flags_at = body.len();
+        body.push(0);
+        let secs = keep_alive.as_secs().min(u64::from(u16::MAX)) as u16;
+        body.extend_from_slice(&secs.to_be_bytes());
+        put_str(&mut body, client_id);
+        if !user.is_empty() || !password.is_empty() {
+            flags |= 0x80;
+            put_str(&mut body, user);
+        }
+        if !password.is_empty() {
+            flags |= 0x40;
+            put_str(&mut body, password);
+        }
+        body[flags_at] = flags;
+        client.send(CONNECT, &body)?;
+
+        let (header, body) = client.read_packet()?;
+        if header != CONNACK || body.len() != 2 {
+            bail!(
+                Code::Protocol,
+                "mqtt broker sent {:#04x} instead of CONNACK",
+                header
+            );
+        }
+        // E.g. 4 for a wrong password, 5 for not authorized.
+        if body[1] != 0 {
+            bail!(
+                Code::Protocol,
+                "mqtt broker refused the connection code={}",
+                body[1]
+            );
+        }
+        Ok(client)
+    }
+
+    /// Subscribes to `topics` at QoS 0 and waits for the broker to accept
+    /// them. Retained messages follow, from [`Client::poll`].
+    pub fn subscribe(&mut self, topics: &[&str]) -> error::Result<()> {
+        let id = self.next_id();
+        let mut body = id.to_be_bytes().to_vec();
+        for topic in topics {
+            put_str(&mut body, topic);
+            body.push(0);
+        }
+        self.send(SUBSCRIBE, &body)?;
+
+        loop {
+            let (header, body) = self.read_packet()?;
+            if header != SUBACK {
+                self.handle(header, body)?;
+                continue;
+            }
+            if body.len() < 2 || body[..2] != id.to_be_bytes() {
+                continue;
+            }
+            if body[2..].contains(&0x80) {
+                bail!(Code::Protocol, "mqtt broker rejected a subscription");
+            }
+            return Ok(());
+        }
+    }
+
+    /// Publishes `payload` at QoS 0, kept by the broker for later
+    /// subscribers with `retain`.
+    pub fn publish(&mut self, topic: &str, payload: &[u8], retain: bool) -> error::Result<()> {
+        let mut body = Vec::with_capacity(topic.len() + 2 + payload.len());
+        put_str(&mut body, topic);
+        body.extend_from_slice(payload);
+        self.send(PUBLISH | u8::from(retain), &body)
+    }
+
+    /// The next message, `None` when the read timed out. Pings the broker
+    /// when nothing was sent for half the keep-alive.
+    pub fn poll(&mut self) -> error::Result<Option<Message>> {
+        if let Some(message) = self.pending.pop_front() {
+            return Ok(Some(message));
+        }
+        if self.last_sent.elapsed() >= self.keep_alive / 2 {
+            self.send(PINGREQ, &[])?;
+        }
+        // Only a timeout before the first byte leaves the stream in sync.
+        let mut header = [0];
+        match self.stream.read(&mut header) {
+            Ok(0) => bail!(Code::Protocol, "mqtt broker closed the connection"),
+            Ok(_) => {}
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Ok(None)
+            }
+            Err(err) => return Err(err.into()),
+        }
+        let body = self.read_body()?;
+        self.handle(header[0], body)?;
+        Ok(self.pending.pop_front())
+    }
+
+    /// Queues a `PUBLISH`, acknowledging it at QoS 1. Other packets are
+    /// ignored.
+    fn handle(&mut self, header: u8, body: Vec<u8>) -> error::Result<()> {
+        match header & 0xf0 {
+            PUBLISH => {
+                let qos = (header >> 1) & 0x03;
+                let (topic, rest) = take_str(&body)?;
+                let (id, payload) = match qos {
+                    0 => (None, rest),
+                    _ if rest.len() >= 2 => (Some([rest[0], rest[1]]), &rest[2..]),
+                    _ => bail!(Code::Protocol, "mqtt PUBLISH without a packet id"),
+                };
+                self.pending.push_back(Message {
+                    topic: topic.to_string(),
+                    payload: payload.to_vec(),
+                    retain: header & 0x01 != 0,
+                });
+                if let (1, Some(id)) = (qos, id) {
+                    self.send(PUBACK, &id)?;
+                }
+            }
+            PINGRESP => {}
+            _ => log::debug!("mqtt: ignoring packet header={:#04x}", header),
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, header: u8, body: &[u8]) -> error::Result<()> {
+        let mut packet = Vec::with_capacity(body.len() + 5);
+        packet.push(header);
+        let mut len = body.len();
+        loop {
+            let mut byte = (len % 128) as u8;
+            len /= 128;
+            if len > 0 {
+                byte |= 0x80;
+            }
+            packet.push(byte);
+            if len == 0 {
+                break;
+            }
+        }
+        packet.extend_from_slice(body);
+        self.stream.get_mut().write_all(&packet)?;
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+
+    fn read_packet(&mut self) -> error::Result<(u8, Vec<u8>)> {
+        let mut header = [0];
+        self.stream.read_exact(&mut header)?;
+        Ok((header[0], self.read_body()?))
+    }
+
+    /// The remaining length and the bytes after the fixed header.
+    fn read_body(&mut self) -> error::Result<Vec<u8>> {
+        let mut len = 0;
+        for shift in [0, 7, 14, 21] {
+            let mut byte = [0];
+            self.stream.read_exact(&mut byte)?;
+            len |= usize::from(byte[0] & 0x7f) << shift;
+            if byte[0] & 0x80 == 0 {
+                if len > MAX_PACKET {
+                    bail!(Code::Protocol, "mqtt packet of {} bytes is too long", len);
+                }
+                let mut body = vec![0; len];
+                self.stream.read_exact(&mut body)?;
+                return Ok(body);
+            }
+        }
+        bail!(Code::Protocol, "mqtt packet with an invalid length")
+    }
+
+    fn next_id(&mut self) -> u16 {
+        // Packet ids are never 0.
+        self.next_id = self.next_id.checked_add(1).unwrap_or(1);
+        self.next_id
+    }
+}
+
+fn put_str(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+fn take_str(buf: &[u8]) -> error::Result<(&str, &[u8])> {
+    if buf.len() < 2 {
+        bail!(Code::Protocol, "mqtt string without a length");
+    }
+    let (len, rest) = buf.split_at(2);
+    let len = usize::from(u16::from_be_bytes([len[0], len[1]]));
+    if rest.len() < len {
+        bail!(Code::Protocol, "mqtt string longer than its packet");
+    }
+    let s = std::str::from_utf8(&rest[..len]).context("mqtt topic")?;
+    Ok((s, &rest[len..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// Replays what the broker says and records what the client sends.
+    struct Mock {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Mock {
+        fn new(input: &[u8]) -> Self {
+            Mock {
+                input: Cursor::new(input.to_vec()),
+                output: Vec::new(),
+            }
+        }
+    }
+
+    impl Read for Mock {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Mock {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    const KEEP_ALIVE: Duration = Duration::from_secs(60);
+
+    fn sent(client: Client<Mock>) -> Vec<u8> {
+        client.stream.into_inner().output
+    }
+
+    #[test]
+    fn parses_urls_and_topics() {
+        assert_eq!(parse_url("mqtt://broker").unwrap(), ("broker", PORT));
+        assert_eq!(
+            parse_url("mqtt://10.0.0.2:1884").unwrap(),
+            ("10.0.0.2", 1884)
+        );
+        assert!(parse_url("mqtts://broker").is_err());
+        assert!(parse_url("mqtt://:1883").is_err());
+        assert!(check_topic("esp-sensor/ota").is_ok());
+        assert!(check_topic("esp-sensor/#").is_err());
+        assert!(check_topic("").is_err());
+    }
+
+    #[test]
+    fn connects_with_credentials() {
+        let mock = Mock::new(&[CONNACK, 2, 0, 0]);
+        let client = Client::new(mock, "dev", "user", "pw", KEEP_ALIVE).unwrap();
+        let mut expected = vec![CONNECT, 25, 0, 4, b'M', b'Q', b'T', b'T', 4, 0xc2, 0, 60];
+        expected.extend_from_slice(b"\0\x03dev\0\x04user\0\x02pw");
+        assert_eq!(sent(client), expected);
+
+        let mock = Mock::new(&[CONNACK, 2, 0, 0]);
+        let client = Client::new(mock, "dev", "", "pw", KEEP_ALIVE).unwrap();
+        let mut expected = vec![CONNECT, 21, 0, 4, b'M', b'Q', b'T', b'T', 4, 0xc2, 0, 60];
+        expected.extend_from_slice(b"\0\x03dev\0\0\0\x02pw");
+        assert_eq!(sent(client), expected);
+
+        let mock = Mock::new(&[CONNACK, 2, 0, 5]);
+        assert!(Client::new(mock, "dev", "user", "wrong", KEEP_ALIVE).is_err());
+    }
+
+    #[test]
+    fn subscribes_and_receives() {
+        let mut input = vec![CONNACK, 2, 0, 0];
+        // A retained message before the SUBACK, and one at QoS 1 after it.
+        input.extend_from_slice(&[PUBLISH | 1, 5, 0, 1, b'a', b'h', b'i']);
+        input.extend_from_slice(&[SUBACK, 4, 0, 1, 0, 0]);
+        input.extend_from_slice(&[PUBLISH | 2, 7, 0, 1, b'b', 0, 9, b'y', b'o']);
+        let mut client = Client::new(Mock::new(&input), "dev", "", "", KEEP_ALIVE).unwrap();
+        client.subscribe(&["a", "b"]).unwrap();
+        client.publish("s", b"up", true).unwrap();
+
+        let message = |topic: &str, payload: &[u8], retain| Message {
+            topic: topic.into(),
+            payload: payload.to_vec(),
+            retain,
+        };
+        assert_eq!(client.poll().unwrap(), Some(message("a", b"hi", true)));
+        assert_eq!(client.poll().unwrap(), Some(message("b", b"yo", false)));
+        assert!(client.poll().is_err());
+
+        let sent = sent(client);
+        let subscribe = [SUBSCRIBE, 10, 0, 1, 0, 1, b'a', 0, 0, 1, b'b', 0];
+        let publish = [PUBLISH | 1, 5, 0, 1, b's', b'u', b'p'];
+        let puback = [PUBACK, 2, 0, 9];
+        assert!(sent.ends_with(&[&subscribe[..], &publish, &puback].concat()));
+    }
+
+    #[test]
+    fn rejects_oversized_packets() {
+        let mut input = vec![CONNACK, 2, 0, 0];
+        input.extend_from_slice(&[PUBLISH, 0xff, 0xff, 0x03]);
+        let mut client = Client::new(Mock::new(&input), "dev", "", "", KEEP_ALIVE).unwrap();
+        assert!(client.poll().is_err());
+    }
+}
@@ -0,0 +1,442 @@
+//! NATS client protocol: core publishing, and JetStream publishing that
+//! waits for the stream to acknowledge each message.
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    time::Duration,
+};
+
+use serde_json::{json, Map, Value as Json};
+
+use crate::error::{self, bail, Code, Context};
+use crate::point::{Point, Value};
+
+pub const PORT: u16 = 4222;
+
+/// A message for a subject.
+#[derive(Debug, PartialEq)]
+pub struct Message {
+    pub subject: String,
+    pub payload: Vec<u8>,
+}
+
+/// Parses `nats://host` or `nats://host:port` into the host and port.
+pub fn parse_url(url: &str) -> error::Result<(&str, u16)> {
+    let Some(authority) = url.strip_prefix("nats://") else {
+        bail!("{:?} must start with nats://", url);
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse()
+                .with_context(|| format!("{:?} has an invalid port", url))?,
+        ),
+        None => (authority, PORT),
+    };
+    if host.is_empty() || host.contains('/') || host.contains(char::is_whitespace) {
+        bail!("{:?} has an invalid host", url);
+    }
+    Ok((host, port))
+}
+
+/// Checks a subject template: dot separated tokens without whitespace or
+/// wildcards.
+pub fn check_subject(template: &str) -> error::Result<()> {
+    if template
+        .split('.')
+        .any(|token| token.is_empty() || token.contains([' ', '\t', '*', '>']))
+    {
+        bail!(
+            "subject {:?} must be dot separated tokens without spaces or wildcards",
+            template
+        );
+    }
+    Ok(())
+}
+
+/// The messages of points for a subject template. `{measurement}`, `{field}`
+/// and `{<tag>}` are replaced by the point's measurement, field name and
+/// tags. With `{field}` each numeric or boolean field is its own message with
+/// the value as text, otherwise each point is a JSON object of its
+/// `measurement`, `tags`, `fields` and `time` in nanoseconds since the Unix
+/// epoch when it has one.
+pub fn messages(points: &[Point], template: &str) -> Vec<Message> {
+    let per_field = template.contains("{field}");
+    let mut messages = Vec::new();
+    for point in points {
+        let mut subject = template.replace("{measurement}", &token(&point.measurement));
+        for (key, value) in &point.tags {
+            subject = subject.replace(&format!("{{{}}}", key), &token(value));
+        }
+
+        if !per_field {
+            let mut tags = Map::new();
+            for (key, value) in &point.tags {
+                tags.insert(key.clone(), json!(value));
+            }
+            let mut fields = Map::new();
+            for (key, value) in &point.fields {
+                fields.insert(key.clone(), json_value(value));
+            }
+            let mut object = json!({
+                "measurement": point.measurement,
+                "tags": tags,
+                "fields": fields,
+            });
+            if let Some(time) = point.timestamp {
+                object["time"] = json!(time);
+            }
+            messages.push(Message {
+                subject,
+                payload: object.to_string().into_bytes(),
+            });
+            continue;
+        }
+
+        for (key, value) in &point.fields {
+            let payload = match value {
+                Value::Float(v) if v.is_finite() => v.to_string(),
+                Value::Integer(v) => v.to_string(),
+                Value::UInteger(v) => v.to_string(),
+                Value::Bool(v) => v.to_string(),
+                Value::Float(_) | Value::String(_) => continue,
+            };
+            messages.push(Message {
+                subject: subject.replace("{field}", &token(key)),
+                payload: payload.into_bytes(),
+            });
+        }
+    }
+    messages
+}
+
+fn json_value(value: &Value) -> Json {
+    match value {
+        // JSON has no NaN, `null` keeps the field.
+        Value::Float(v) => json!(v),
+        Value::Integer(v) => json!(v),
+        Value::UInteger(v) => json!(v),
+        Value::Bool(v) => json!(v),
+        Value::String(v) => json!(v),
+    }
+}
+
+/// A subject token from a value, the separators and wildcards replaced
+/// with `_`.
+fn token(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '.' | '*' | '>' => '_',
+            c if c.is_whitespace() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// A connection to a NATS server.
+pub struct Client<S> {
+    stream: BufReader<S>,
+    /// Subject prefix of the replies JetStream acknowledgements go to,
+    /// `None` publishes without acknowledgements.
+    inbox: Option<String>,
+    next_reply: u32,
+}
+
+impl Client<TcpStream> {
+    /// Connects over TCP to `url`, see [`Client::new`].
+    pub fn connect(
+        url: &str,
+        token: &str,
+        inbox: Option<String>,
+        timeout: Duration,
+    ) -> error::Result<Self> {
+        let (host, port) = parse_url(url)?;
+        let stream = TcpStream::connect((host, port)).context("connect to nats")?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        Client::new(stream, token, inbox)
+    }
+}
+
+impl<S: Read + Write> Client<S> {
+    /// Reads the server's `INFO`, sends `CONNECT` with `token` when it is
+    /// not empty, and subscribes to the replies under `inbox` for JetStream
+    /// acknowledgements.
+    pub fn new(stream: S, token: &str, inbox: Option<String>) -> error::Result<Self> {
+        let mut client = Client {
+            stream: BufReader::new(stream),
+            inbox,
+            next_reply: 0,
+        };
+        let line = client.read_line()?;
+        if !line.starts_with("INFO ") {
+            bail!(
+                Code::Protocol,
+                "nats server sent {:?} instead of INFO",
+                line
+            );
+        }
+
+        let mut options = json!({
+            "verbose": false,
+            "pedantic": false,
+            "name": "esp-sensor",
+            "lang": "rust",
+            "version": env!("CARGO_PKG_VERSION"),
+        });
+        if !token.is_empty() {
+            options["auth_token"] = json!(token);
+        }
+        let mut commands = format!("CONNECT {}\r\n", options);
+        if let Some(inbox) = &client.inbox {
+            commands.push_str(&format!("SUB {}.* 1\r\n", inbox));
+        }
+        client.stream.get_mut().write_all(commands.as_bytes())?;
+        // An -ERR for the CONNECT, e.g. a wrong token, comes before the PONG.
+        client.flush()?;
+        Ok(client)
+    }
+
+    /// Publishes messages. Without an inbox they are only checked to have
+    /// reached the server, with one each waits for its JetStream
+    /// acknowledgement.
+    pub fn publish(&mut self, messages: &[Message]) -> error::Result<()> {
+        let Some(inbox) = self.inbox.clone() else {
+            let mut commands = Vec::new();
+            for message in messages {
+                write!(
+                    commands,
+                    "PUB {} {}\r\n",
+                    message.subject,
+                    message.payload.len()
+                )?;
+                commands.extend_from_slice(&message.payload);
+                commands.extend_from_slice(b"\r\n");
+            }
+            self.stream.get_mut().write_all(&commands)?;
+            return self.flush();
+        };
+
+        for message in messages {
+            self.next_reply = self.next_reply.wrapping_add(1);
+            let reply = format!("{}.{}", inbox, self.next_reply);
+            let header = format!(
+                "PUB {} {} {}\r\n",
+                message.subject,
+                reply,
+                message.payload.len()
+            );
+            let stream = self.stream.get_mut();
+            stream.write_all(header.as_bytes())?;
+            stream.write_all(&message.payload)?;
+            stream.write_all(b"\r\n")?;
+
+            let ack = loop {
+                if let Some((subject, payload)) = self.read_message()? {
+                    // Late acknowledgements of earlier attempts are skipped.
+                    if subject == reply {
+                        break payload;
+                    }
+                }
+            };
+            let ack: Json = serde_json::from_slice(&ack).context("parse jetstream ack")?;
+            if let Some(error) = ack.get("error") {
+                bail!(
+                    Code::Protocol,
+                    "jetstream rejected {} error={}",
+                    message.subject,
+                    error
+                );
+            }
+            if ack.get("seq").is_none() {
+                bail!(Code::Protocol, "no stream stores {}", message.subject);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends a `PING` and waits for the `PONG`, the server has processed
+    /// everything before it then.
+    fn flush(&mut self) -> error::Result<()> {
+        self.stream.get_mut().write_all(b"PING\r\n")?;
+        loop {
+            let line = self.read_line()?;
+            match line.as_str() {
+                "PONG" => return Ok(()),
+                _ => self.handle(&line)?,
+            }
+        }
+    }
+
+    /// Reads until a `MSG`, returns its subject and payload, `None` for
+    /// other lines.
+    fn read_message(&mut self) -> error::Result<Option<(String, Vec<u8>)>> {
+        let line = self.read_line()?;
+        let Some(args) = line.strip_prefix("MSG ") else {
+            self.handle(&line)?;
+            return Ok(None);
+        };
+        // MSG <subject> <sid> [reply-to] <#bytes>
+        let args: Vec<_> = args.split(' ').collect();
+        let (Some(subject), Some(len)) = (args.first(), args.last()) else {
+            bail!(Code::Protocol, "nats MSG without arguments");
+        };
+        let len: usize = len.parse().context("parse nats MSG length")?;
+        let mut payload = vec![0; len + 2];
+        self.stream.read_exact(&mut payload)?;
+        payload.truncate(len);
+        Ok(Some((subject.to_string(), payload)))
+    }
+
+    /// Answers a `PING`, fails on `-ERR`.
+    fn handle(&mut self, line: &str) -> error::Result<()> {
+        match line {
+            "PING" => self.stream.get_mut().write_all(b"PONG\r\n")?,
+            _ if line.starts_with("-ERR") => bail!(Code::Protocol, "nats server error: {}", line),
+            // +OK, INFO updates and messages of other subscriptions.
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn read_line(&mut self) -> error::Result<String> {
+        let mut line = String::new();
+        if self.stream.read_line(&mut line)? == 0 {
+            bail!(Code::Protocol, "nats server closed the connection");
+        }
+        Ok(line.trim_end().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// Replays what the server says and records what the client sends.
+    struct Mock {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Mock {
+        fn new(input: &str) -> Self {
+            Mock {
+                input: Cursor::new(input.as_bytes().to_vec()),
+                output: Vec::new(),
+            }
+        }
+    }
+
+    impl Read for Mock {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Mock {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn sent(client: Client<Mock>) -> String {
+        String::from_utf8(client.stream.into_inner().output).unwrap()
+    }
+
+    fn point() -> Point {
+        Point::new("dht")
+            .tag("device", "kitchen.1")
+            .field("temperature", 21.5f32)
+            .field("note", "skipped")
+            .timestamp(5)
+    }
+
+    #[test]
+    fn builds_messages_per_point_and_per_field() {
+        let messages = messages(&[point()], "sensors.{device}.{measurement}");
+        assert_eq!(messages[0].subject, "sensors.kitchen_1.dht");
+        let payload: Json = serde_json::from_slice(&messages[0].payload).unwrap();
+        assert_eq!(
+            payload,
+            json!({
+                "measurement": "dht",
+                "tags": { "device": "kitchen.1" },
+                "fields": { "temperature": 21.5, "note": "skipped" },
+                "time": 5,
+            })
+        );
+
+        let messages = super::messages(&[point()], "{measurement}.{field}");
+        assert_eq!(
+            messages,
+            [Message {
+                subject: "dht.temperature".into(),
+                payload: b"21.5".to_vec(),
+            }]
+        );
+    }
+
+    #[test]
+    fn parses_urls_and_subjects() {
+        assert_eq!(parse_url("nats://broker").unwrap(), ("broker", PORT));
+        assert_eq!(
+            parse_url("nats://10.0.0.2:4333").unwrap(),
+            ("10.0.0.2", 4333)
+        );
+        assert!(parse_url("http://broker").is_err());
+        assert!(parse_url("nats://:4222").is_err());
+        assert!(check_subject("sensors.{device}").is_ok());
+        assert!(check_subject("sensors..x").is_err());
+        assert!(check_subject("sensors.>").is_err());
+    }
+
+    #[test]
+    fn publishes_and_flushes() {
+        let mock = Mock::new("INFO {}\r\nPONG\r\nPING\r\nPONG\r\n");
+        let mut client = Client::new(mock, "secret", None).unwrap();
+        let message = Message {
+            subject: "a.b".into(),
+            payload: b"1".to_vec(),
+        };
+        client.publish(&[message]).unwrap();
+
+        let sent = sent(client);
+        assert!(sent.starts_with("CONNECT {"));
+        assert!(sent.contains("\"auth_token\":\"secret\""));
+        assert!(sent.ends_with("\r\nPING\r\nPUB a.b 1\r\n1\r\nPING\r\nPONG\r\n"));
+    }
+
+    #[test]
+    fn waits_for_jetstream_acks() {
+        let mock = Mock::new(
+            "INFO {}\r\nPONG\r\n\
+             MSG _INBOX.dev.1 1 28\r\n{\"stream\":\"SENSORS\",\"seq\":7}\r\n\
+             MSG _INBOX.dev.2 1 40\r\n{\"error\":{\"code\":503,\"description\":\"x\"}}\r\n",
+        );
+        let mut client = Client::new(mock, "", Some("_INBOX.dev".into())).unwrap();
+        let message = |subject: &str| Message {
+            subject: subject.into(),
+            payload: b"1".to_vec(),
+        };
+        client.publish(&[message("a")]).unwrap();
+        assert!(client.publish(&[message("b")]).is_err());
+
+        let sent = sent(client);
+        assert!(sent.contains("SUB _INBOX.dev.* 1\r\n"));
+        assert!(sent.contains("PUB a _INBOX.dev.1 1\r\n1\r\n"));
+        assert!(sent.contains("PUB b _INBOX.dev.2 1\r\n1\r\n"));
+    }
+
+    #[test]
+    fn fails_on_server_errors() {
+        let mock = Mock::new("INFO {}\r\n-ERR 'Authorization Violation'\r\n");
+        assert!(Client::new(mock, "wrong", None).is_err());
+    }
+}
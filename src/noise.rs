@@ -0,0 +1,182 @@
+use std::sync::Mutex;
+
+#[cfg(feature = "noise")]
+use crate::{
+    error::{self, Context},
+    health,
+    settings::{Settings, Store},
+    watchdog::Watchdog,
+};
+use crate::{point::Point, spl};
+
+/// Sample rate of the microphone, the A-weighting is accurate to 4 kHz.
+#[cfg(feature = "noise")]
+const SAMPLE_RATE: u32 = 32_000;
+/// Frames read from the DMA buffers at a time.
+#[cfg(feature = "noise")]
+const CHUNK: usize = 256;
+/// Shortest window, the IEC "fast" time weighting.
+pub const MIN_WINDOW_MS: u32 = 125;
+/// Level of the sound pressure a microphone's sensitivity is given at.
+#[cfg(feature = "noise")]
+const REFERENCE_DB_SPL: f32 = 94.;
+
+/// Noise levels of the windows since the previous point, shared with the
+/// sender. Only the levels are kept, never the audio.
+#[derive(Default)]
+pub struct Noise {
+    levels: Mutex<spl::Levels>,
+}
+
+impl Noise {
+    /// A `noise` point with the `sensor` tag `inmp441` and the `min`, `avg`
+    /// (equivalent continuous) and `max` levels of the windows in dB(A).
+    /// `None` without the microphone.
+    pub fn point(&self, tags: &[(String, String)]) -> Option<Point> {
+        let levels = std::mem::take(&mut *self.levels.lock().unwrap());
+        let (min, avg, max) = levels.summary()?;
+        Some(
+            Point::new("noise")
+                .tag("sensor", "inmp441")
+                .tags(tags)
+                .field("min", min)
+                .field("avg", avg)
+                .field("max", max),
+        )
+    }
+}
+
+/// Receive channel of the I2S peripheral in standard Philips mode, reading
+/// the left slot. The INMP441 needs its L/R pin tied to ground for it.
+#[cfg(feature = "noise")]
+pub struct Microphone {
+    channel: esp_idf_sys::i2s_chan_handle_t,
+}
+
+// SAFETY: the channel is only used by the task that owns it.
+#[cfg(feature = "noise")]
+unsafe impl Send for Microphone {}
+
+#[cfg(feature = "noise")]
+impl Microphone {
+    pub fn new(sck: i32, ws: i32, sd: i32) -> error::Result<Microphone> {
+        use esp_idf_sys::*;
+
+        let mut channel = std::ptr::null_mut();
+        unsafe {
+            esp!(i2s_new_channel(
+                &i2s_chan_config_t {
+                    id: i2s_port_t_I2S_NUM_0,
+                    role: i2s_role_t_I2S_ROLE_MASTER,
+                    dma_desc_num: 4,
+                    dma_frame_num: CHUNK as u32,
+                    ..Default::default()
+                },
+                std::ptr::null_mut(),
+                &mut channel
+            ))
+            .context("create i2s channel")?;
+            esp!(i2s_channel_init_std_mode(
+                channel,
+                &i2s_std_config_t {
+                    clk_cfg: i2s_std_clk_config_t {
+                        sample_rate_hz: SAMPLE_RATE,
+                        clk_src: soc_periph_i2s_clk_src_t_I2S_CLK_SRC_DEFAULT,
+                        mclk_multiple: i2s_mclk_multiple_t_I2S_MCLK_MULTIPLE_256,
+                    },
+                    // 24 bit samples, MSB first, in 32 bit slots.
+                    slot_cfg: i2s_std_slot_config_t {
+                        data_bit_width: i2s_data_bit_width_t_I2S_DATA_BIT_WIDTH_32BIT,
+                        slot_bit_width: i2s_slot_bit_width_t_I2S_SLOT_BIT_WIDTH_AUTO,
+                        slot_mode: i2s_slot_mode_t_I2S_SLOT_MODE_MONO,
+                        slot_mask: i2s_std_slot_mask_t_I2S_STD_SLOT_LEFT,
+                        ws_width: 32,
+                        ws_pol: false,
+                        bit_shift: true,
+                        ..Default::default()
+                    },
+                    gpio_cfg: i2s_std_gpio_config_t {
+                        mclk: gpio_num_t_GPIO_NUM_NC,
+                        bclk: sck,
+                        ws,
+                        dout: gpio_num_t_GPIO_NUM_NC,
+                        din: sd,
+                        ..Default::default()
+                    },
+                }
+            ))
+            .context("configure i2s channel")?;
+            esp!(i2s_channel_enable(channel)).context("enable i2s channel")?;
+        }
+        Ok(Microphone { channel })
+    }
+
+    /// Fills `buf` with the 32 bit slots of the samples, returns how many
+    /// were read.
+    fn read(&mut self, buf: &mut [i32; CHUNK]) -> error::Result<usize> {
+        let mut read = 0;
+        unsafe {
+            esp_idf_sys::esp!(esp_idf_sys::i2s_channel_read(
+                self.channel,
+                buf.as_mut_ptr().cast(),
+                std::mem::size_of_val(buf),
+                &mut read,
+                1000
+            ))?;
+        }
+        Ok(read / std::mem::size_of::<i32>())
+    }
+}
+
+/// Reads the microphone continuously and adds the A-weighted level of every
+/// `noise_window_ms` to the noise levels. The first window is dropped while
+/// the microphone and the filter settle.
+#[cfg(feature = "noise")]
+pub fn run(store: &Store, noise: &Noise, mut microphone: Microphone) {
+    let watchdog = Watchdog::subscribe("noise");
+    let health = health::register("noise");
+    let mut meter = spl::Meter::new(SAMPLE_RATE);
+    let mut buf = [0i32; CHUNK];
+    let mut settled = false;
+    let mut window = window_samples(&store.get());
+
+    loop {
+        let frames = match microphone.read(&mut buf) {
+            Ok(frames) => frames,
+            Err(err) => {
+                log::error!("noise: reading microphone error={:?}", err);
+                watchdog.sleep(std::time::Duration::from_secs(1));
+                continue;
+            }
+        };
+        for &word in &buf[..frames] {
+            // Drops the 8 padding bits of the 24 bit sample.
+            meter.push((word >> 8) as f32 / 8_388_608.);
+        }
+
+        if meter.samples() < window {
+            continue;
+        }
+        health.tick();
+        watchdog.feed();
+        let dbfs = meter.take();
+        let settings = store.get();
+        window = window_samples(&settings);
+        if !settled {
+            settled = true;
+            continue;
+        }
+        // The store only accepts numbers.
+        let sensitivity: f32 = settings.mic_sensitivity.parse().unwrap_or(-26.);
+        if let Some(dbfs) = dbfs {
+            let db = dbfs - sensitivity + REFERENCE_DB_SPL;
+            log::debug!("noise: {} dB(A)", db);
+            noise.levels.lock().unwrap().push(db);
+        }
+    }
+}
+
+#[cfg(feature = "noise")]
+fn window_samples(settings: &Settings) -> u32 {
+    SAMPLE_RATE / 1000 * settings.noise_window_ms.max(MIN_WINDOW_MS)
+}
@@ -0,0 +1,175 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use embedded_svc::{
+    http::{client::Client, Method},
+    io::Write,
+    utils::io,
+};
+use esp_idf_svc::http::client::{Configuration as HttpConfiguration, EspHttpConnection};
+
+use crate::{
+    alert::Event,
+    error::{self, bail, Context},
+    schedule,
+    settings::Settings,
+    telegram,
+};
+
+const PUSHOVER_URL: &str = "https://api.pushover.net/1/messages.json";
+
+/// Sends alert events as push messages through ntfy, Pushover and Telegram.
+///
+/// Each alert notifies at most once per `notify_int` seconds. Events within
+/// that time are counted and mentioned in the next message of the alert.
+#[derive(Default)]
+pub struct Notifier {
+    alerts: HashMap<String, Limit>,
+}
+
+#[derive(Default)]
+struct Limit {
+    last_sent: Option<Instant>,
+    suppressed: u32,
+}
+
+impl Notifier {
+    pub fn notify(&mut self, settings: &Settings, events: &[Event]) {
+        if settings.ntfy_url.is_empty()
+            && settings.pushover_token.expose().is_empty()
+            && settings.telegram_token.expose().is_empty()
+        {
+            return;
+        }
+
+        let interval = Duration::from_secs(u64::from(settings.notify_interval_secs));
+        let quiet = schedule::is_quiet(settings);
+        for event in events.iter().filter(|e| !e.notified) {
+            if quiet && !event.urgent {
+                log::info!("notify: quiet hours, not sending {}", event);
+                continue;
+            }
+
+            let limit = self.alerts.entry(event.name.clone()).or_default();
+            if matches!(limit.last_sent, Some(last) if last.elapsed() < interval) {
+                limit.suppressed += 1;
+                log::info!("notify: rate limited {}", event);
+                continue;
+            }
+
+            let mut message = format!("{}: {}", settings.device_id(), event);
+            if limit.suppressed > 0 {
+                message += &format!(" ({} earlier changes not sent)", limit.suppressed);
+            }
+            limit.last_sent = Some(Instant::now());
+            limit.suppressed = 0;
+
+            if let Err(err) = send(settings, event.raised, &message) {
+                log::error!("notify: sending {:?} error={:?}", message, err);
+            }
+        }
+    }
+}
+
+/// Sends `message` through every configured service right away, without
+/// the rate limit and quiet hours of [`Notifier`].
+pub fn send(settings: &Settings, raised: bool, message: &str) -> error::Result<()> {
+    if cfg!(feature = "push") && !settings.ntfy_url.is_empty() {
+        let auth = format!("Bearer {}", settings.ntfy_token.expose());
+        let mut headers = vec![
+            ("title", "esp-sensor alert"),
+            ("priority", if raised { "high" } else { "default" }),
+            (
+                "tags",
+                if raised {
+                    "warning"
+                } else {
+                    "white_check_mark"
+                },
+            ),
+        ];
+        if !settings.ntfy_token.expose().is_empty() {
+            headers.push(("authorization", &auth));
+        }
+        post(&settings.ntfy_url, &headers, message.as_bytes()).context("ntfy")?;
+    }
+
+    if cfg!(feature = "push") && !settings.pushover_token.expose().is_empty() {
+        let body = form_encode(&[
+            ("token", settings.pushover_token.expose()),
+            ("user", settings.pushover_user.expose()),
+            ("title", "esp-sensor alert"),
+            ("priority", if raised { "1" } else { "0" }),
+            ("message", message),
+        ]);
+        let headers = [("content-type", "application/x-www-form-urlencoded")];
+        post(PUSHOVER_URL, &headers, body.as_bytes()).context("pushover")?;
+    }
+
+    let telegram_token = settings.telegram_token.expose();
+    if cfg!(feature = "telegram")
+        && !telegram_token.is_empty()
+        && !settings.telegram_chat.is_empty()
+    {
+        telegram::send_message(telegram_token, &settings.telegram_chat, message)
+            .context("telegram")?;
+    }
+
+    Ok(())
+}
+
+fn post(url: &str, headers: &[(&str, &str)], body: &[u8]) -> error::Result<()> {
+    let connection = EspHttpConnection::new(&HttpConfiguration {
+        timeout: Some(Duration::from_secs(15)),
+        crt_bundle_attach: Some(esp_idf_sys::esp_crt_bundle_attach),
+        ..Default::default()
+    })
+    .context("create esp http connection")?;
+    let mut client = Client::wrap(connection);
+
+    let content_length = body.len().to_string();
+    let mut all_headers = headers.to_vec();
+    all_headers.push(("content-length", &content_length));
+
+    let mut request = client
+        .request(Method::Post, url, &all_headers)
+        .context("create post request")?;
+    request.write_all(body)?;
+    request.flush()?;
+    let mut response = request.submit().context("do post request")?;
+
+    let status = response.status();
+    let mut buf = [0u8; 128];
+    let len = io::try_read_full(&mut response, &mut buf).map_err(|e| e.0)?;
+    if !(200..300).contains(&status) {
+        bail!(
+            "http status code={} body={:?}",
+            status,
+            String::from_utf8_lossy(&buf[..len])
+        );
+    }
+    Ok(())
+}
+
+/// Encodes `application/x-www-form-urlencoded` pairs.
+fn form_encode(pairs: &[(&str, &str)]) -> String {
+    let encode = |s: &str| {
+        s.bytes()
+            .map(|b| match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                    char::from(b).to_string()
+                }
+                b' ' => "+".to_string(),
+                _ => format!("%{:02X}", b),
+            })
+            .collect::<String>()
+    };
+
+    pairs
+        .iter()
+        .map(|(key, value)| format!("{}={}", encode(key), encode(value)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
@@ -0,0 +1,222 @@
+use embedded_hal::{
+    blocking::delay::DelayUs,
+    digital::v2::{InputPin, OutputPin},
+};
+
+use crate::error::{self, bail, format_err};
+
+const SEARCH_ROM: u8 = 0xF0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error<E> {
+    Pin(E),
+    /// No device answered the reset.
+    NoPresence,
+    Crc,
+}
+
+/// 1-Wire bus master on an open-drain pin with a 4.7 kΩ pull-up.
+///
+/// Every time slot runs through `critical`, which the firmware sets to
+/// disable interrupts so they cannot stretch the slot.
+pub struct OneWire<P, D> {
+    pin: P,
+    delay: D,
+    critical: fn(&mut dyn FnMut()),
+}
+
+impl<P, D, E> OneWire<P, D>
+where
+    P: InputPin<Error = E> + OutputPin<Error = E>,
+    D: DelayUs<u16>,
+{
+    pub fn new(pin: P, delay: D) -> Self {
+        OneWire {
+            pin,
+            delay,
+            critical: |slot| slot(),
+        }
+    }
+
+    pub fn with_critical(self, critical: fn(&mut dyn FnMut())) -> Self {
+        OneWire { critical, ..self }
+    }
+
+    /// Resets the bus, fails if no device pulls it low in response.
+    pub fn reset(&mut self) -> Result<(), Error<E>> {
+        let present = self.slot(|pin, delay| {
+            pin.set_low()?;
+            delay.delay_us(480);
+            pin.set_high()?;
+            delay.delay_us(70);
+            pin.is_low()
+        })?;
+        self.delay.delay_us(410);
+        if !present {
+            return Err(Error::NoPresence);
+        }
+        Ok(())
+    }
+
+    pub fn write_byte(&mut self, byte: u8) -> Result<(), Error<E>> {
+        (0..8).try_for_each(|bit| self.write_bit(byte >> bit & 1 != 0))
+    }
+
+    pub fn read_byte(&mut self) -> Result<u8, Error<E>> {
+        let mut byte = 0;
+        for bit in 0..8 {
+            byte |= u8::from(self.read_bit()?) << bit;
+        }
+        Ok(byte)
+    }
+
+    pub fn write_bit(&mut self, bit: bool) -> Result<(), Error<E>> {
+        let (low, high) = if bit { (6, 64) } else { (60, 10) };
+        self.slot(|pin, delay| {
+            pin.set_low()?;
+            delay.delay_us(low);
+            pin.set_high()?;
+            delay.delay_us(high);
+            Ok(())
+        })
+    }
+
+    pub fn read_bit(&mut self) -> Result<bool, Error<E>> {
+        self.slot(|pin, delay| {
+            pin.set_low()?;
+            delay.delay_us(6);
+            pin.set_high()?;
+            delay.delay_us(9);
+            let bit = pin.is_high()?;
+            delay.delay_us(55);
+            Ok(bit)
+        })
+    }
+
+    fn slot<T>(&mut self, f: impl FnOnce(&mut P, &mut D) -> Result<T, E>) -> Result<T, Error<E>> {
+        let mut f = Some(f);
+        let mut result = None;
+        let critical = self.critical;
+        critical(&mut || {
+            if let Some(f) = f.take() {
+                result = Some(f(&mut self.pin, &mut self.delay));
+            }
+        });
+        result
+            .expect("critical section ran the slot")
+            .map_err(Error::Pin)
+    }
+
+    /// ROM codes of every device on the bus, with the Search ROM command.
+    pub fn search(&mut self) -> Result<Vec<[u8; 8]>, Error<E>> {
+        let mut roms = Vec::new();
+        let mut rom = [0u8; 8];
+        // Bit index of the last branch where 0 was taken, 0 when done.
+        let mut last_branch = 0;
+        loop {
+            if self.reset().is_err() {
+                return Ok(roms);
+            }
+            self.write_byte(SEARCH_ROM)?;
+            let mut branch = 0;
+            for index in 1..=64 {
+                let (byte, mask) = ((index - 1) / 8, 1 << ((index - 1) % 8));
+                let bit = self.read_bit()?;
+                let complement = self.read_bit()?;
+                let take = match (bit, complement) {
+                    // No device left, e.g. one was removed mid-search.
+                    (true, true) => return Ok(roms),
+                    (bit, _) if bit != complement => bit,
+                    // Devices differ at this bit.
+                    _ => {
+                        let take = if index < last_branch {
+                            rom[byte] & mask != 0
+                        } else {
+                            index == last_branch
+                        };
+                        if !take {
+                            branch = index;
+                        }
+                        take
+                    }
+                };
+                if take {
+                    rom[byte] |= mask;
+                } else {
+                    rom[byte] &= !mask;
+                }
+                self.write_bit(take)?;
+            }
+            if crc8(&rom[..7]) != rom[7] {
+                return Err(Error::Crc);
+            }
+            roms.push(rom);
+            last_branch = branch;
+            if last_branch == 0 {
+                return Ok(roms);
+            }
+        }
+    }
+}
+
+/// Dallas/Maxim CRC-8 of ROM codes and scratchpads.
+pub fn crc8(bytes: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in bytes {
+        let mut byte = byte;
+        for _ in 0..8 {
+            let mix = (crc ^ byte) & 1;
+            crc >>= 1;
+            if mix != 0 {
+                crc ^= 0x8C;
+            }
+            byte >>= 1;
+        }
+    }
+    crc
+}
+
+/// ROM code as printed in settings, 16 hex digits with the family code
+/// first.
+pub fn format_rom(rom: &[u8; 8]) -> String {
+    rom.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn parse_rom(s: &str) -> error::Result<[u8; 8]> {
+    let s = s.trim();
+    if s.len() != 16 || !s.is_ascii() {
+        bail!("rom {:?} must be 16 hex digits", s);
+    }
+    let mut rom = [0u8; 8];
+    for (i, byte) in rom.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16)
+            .map_err(|_| format_err!("rom {:?} must be 16 hex digits", s))?;
+    }
+    if crc8(&rom[..7]) != rom[7] {
+        bail!("rom {:?} has a bad crc", s);
+    }
+    Ok(rom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The example of Maxim application note 27.
+    const ROM: [u8; 8] = [0x02, 0x1C, 0xB8, 0x01, 0x00, 0x00, 0x00, 0xA2];
+
+    #[test]
+    fn computes_crc() {
+        assert_eq!(crc8(&ROM[..7]), ROM[7]);
+        assert_eq!(crc8(&ROM), 0);
+    }
+
+    #[test]
+    fn formats_and_parses_rom() {
+        assert_eq!(format_rom(&ROM), "021cb801000000a2");
+        assert_eq!(parse_rom(" 021CB801000000A2 ").unwrap(), ROM);
+        assert!(parse_rom("021cb801000000a3").is_err());
+        assert!(parse_rom("021cb801").is_err());
+        assert!(parse_rom("021cb8010000zza2").is_err());
+    }
+}
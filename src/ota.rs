@@ -0,0 +1,415 @@
+//! Firmware updates announced over MQTT. The device listens on `ota_topic`
+//! for the fleet and on `<ota_topic>/<device>` for itself, streams an
+//! announced image into the other OTA slot while hashing it and boots it
+//! only when its SHA-256 matches the announcement. A new image then has to
+//! pass the `ota_checks` and keep running for `ota_window`, or the device
+//! rolls back to the previous one. Each step is published retained to
+//! `<ota_topic>/<device>/state`, see [`rollout`].
+
+use std::{thread, time::Duration};
+
+use embedded_svc::{
+    http::{
+        client::{Client, Response},
+        Method,
+    },
+    io::Read,
+};
+use esp_idf_svc::{
+    http::client::{Configuration as HttpConfiguration, EspHttpConnection},
+    nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault},
+};
+use esp_idf_sys::esp;
+
+use crate::{
+    device,
+    error::{self, bail, Code, Context},
+    health, mqtt,
+    ready::{Ready, Step},
+    rollout::{self, Check, Notice, Phase},
+    settings::{Settings, Store},
+    sha256::{self, Sha256},
+    watchdog::Watchdog,
+};
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+/// How long a read waits before the watchdog is fed again.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const RECONNECT_DELAY: Duration = Duration::from_secs(30);
+const CHUNK_LEN: usize = 4096;
+
+const NAMESPACE: &str = "ota";
+/// Version and SHA-256 of the last image installed, kept until the next
+/// one to tell a rollback and not to install that image again.
+const VERSION_KEY: &str = "version";
+const SHA256_KEY: &str = "sha256";
+/// Why the image failed the health check, not set when it restarted.
+const REASON_KEY: &str = "reason";
+
+/// The health check of an image running for the first time. Until it is
+/// marked valid, any restart boots the previous image, which covers panics.
+struct Trial {
+    checks: Vec<Check>,
+    window: Duration,
+}
+
+impl Trial {
+    /// A trial when the running image waits for its health check.
+    fn start(settings: &Settings) -> Option<Trial> {
+        let running = unsafe { esp_idf_sys::esp_ota_get_running_partition() };
+        let mut state = 0;
+        let err = unsafe { esp_idf_sys::esp_ota_get_state_partition(running, &mut state) };
+        // Images flashed over USB are valid from the start.
+        if err != esp_idf_sys::ESP_OK
+            || state != esp_idf_sys::esp_ota_img_states_t_ESP_OTA_IMG_PENDING_VERIFY
+        {
+            return None;
+        }
+        // The store only accepts valid checks.
+        let checks = rollout::parse_checks(&settings.ota_checks).unwrap_or_default();
+        let window = Duration::from_secs(u64::from(settings.ota_window_secs));
+        log::warn!(
+            "ota: new image, checking {:?} for {}s",
+            settings.ota_checks,
+            window.as_secs()
+        );
+        Some(Trial { checks, window })
+    }
+
+    /// Once the window since boot passed, keeps the image if every check
+    /// passed and rolls back otherwise. Returns whether the image was kept.
+    fn finish(&self, ready: &Ready, nvs: &mut EspNvs<NvsDefault>) -> bool {
+        if device::uptime() < self.window {
+            return false;
+        }
+        if let Some(check) = self.checks.iter().find(|c| !ready.is_done(c.step())) {
+            let reason = format!(
+                "{} not passed within {}s",
+                check.name(),
+                self.window.as_secs()
+            );
+            log::error!("ota: {}, rolling back", reason);
+            if let Err(err) = nvs.set_str(REASON_KEY, &reason) {
+                log::error!("ota: saving the reason error={:?}", err);
+            }
+            let err = unsafe { esp_idf_sys::esp_ota_mark_app_invalid_rollback_and_reboot() };
+            // Only returns when there is no image to go back to.
+            log::error!("ota: rolling back error={}", err);
+        }
+        let err = unsafe { esp_idf_sys::esp_ota_mark_app_valid_cancel_rollback() };
+        if err != esp_idf_sys::ESP_OK {
+            log::error!("ota: marking the image valid error={}", err);
+        }
+        log::info!("ota: image passed the health check, keeping it");
+        true
+    }
+}
+
+/// State of the update task that outlives reconnects.
+struct Updater {
+    nvs: EspNvs<NvsDefault>,
+    trial: Option<Trial>,
+    /// Published on each connect until an update starts.
+    phase: Phase,
+    target: Option<String>,
+    detail: String,
+    /// SHA-256 of an image that failed to install or was rolled back. It is
+    /// not tried again until a different image is announced.
+    rejected: Option<[u8; 32]>,
+}
+
+impl Updater {
+    fn load(partition: EspDefaultNvsPartition, settings: &Settings) -> error::Result<Self> {
+        let nvs = EspNvs::new(partition, NAMESPACE, true).context("open ota namespace")?;
+        let mut updater = Updater {
+            nvs,
+            trial: Trial::start(settings),
+            phase: Phase::Idle,
+            target: None,
+            detail: String::new(),
+            rejected: None,
+        };
+        if let Some(trial) = &updater.trial {
+            updater.phase = Phase::Verifying;
+            updater.detail = format!("{:?} for {}s", settings.ota_checks, trial.window.as_secs());
+            return Ok(updater);
+        }
+
+        let mut buf = [0u8; 96];
+        let Some(version) = updater
+            .nvs
+            .get_str(VERSION_KEY, &mut buf)?
+            .map(String::from)
+        else {
+            return Ok(updater);
+        };
+        let last_invalid = unsafe { esp_idf_sys::esp_ota_get_last_invalid_partition() };
+        if version != VERSION && !last_invalid.is_null() {
+            let sha256 = updater
+                .nvs
+                .get_str(SHA256_KEY, &mut buf)?
+                .and_then(sha256::parse_hex);
+            let reason = updater.nvs.get_str(REASON_KEY, &mut buf)?.map(String::from);
+            let reason = reason.unwrap_or_else(|| "restarted during the health check".into());
+            log::error!("ota: version={} was rolled back, {}", version, reason);
+            updater.phase = Phase::RolledBack;
+            updater.target = Some(version);
+            updater.detail = reason;
+            updater.rejected = sha256;
+        }
+        Ok(updater)
+    }
+
+    fn report(&self) -> String {
+        rollout::report(self.phase, VERSION, self.target.as_deref(), &self.detail)
+    }
+
+    /// Finishes the health check when it is due. Returns whether the image
+    /// was kept just now.
+    fn poll(&mut self, ready: &Ready) -> bool {
+        let kept = self
+            .trial
+            .as_ref()
+            .is_some_and(|trial| trial.finish(ready, &mut self.nvs));
+        if kept {
+            self.trial = None;
+            self.phase = Phase::Idle;
+            self.detail = "passed the health check".into();
+        }
+        kept
+    }
+
+    /// Remembers the image about to be booted, to tell a rollback.
+    fn installed(&mut self, notice: &Notice) -> error::Result<()> {
+        self.nvs.set_str(VERSION_KEY, &notice.version)?;
+        self.nvs.set_str(SHA256_KEY, &sha256::hex(&notice.sha256))?;
+        self.nvs.remove(REASON_KEY)?;
+        Ok(())
+    }
+}
+
+/// Runs the health check of a new image and, once Wi-Fi is up, connects to
+/// `mqtt_url` and installs the updates announced there, reconnecting when
+/// the broker goes away or the settings change.
+pub fn run(store: &Store, ready: &Ready, nvs: EspDefaultNvsPartition) {
+    let watchdog = Watchdog::subscribe("ota");
+    let health = health::register("ota");
+    let mut updater = match Updater::load(nvs, &store.get()) {
+        Ok(updater) => updater,
+        Err(err) => {
+            log::error!("ota: init error={:?}", err);
+            return;
+        }
+    };
+    loop {
+        health.tick();
+        watchdog.feed();
+        updater.poll(ready);
+
+        let settings = store.get();
+        if settings.mqtt_url.is_empty() || !ready.is_done(Step::Wifi) {
+            watchdog.sleep(RECONNECT_DELAY);
+            continue;
+        }
+        if let Err(err) = listen(&settings, store, ready, &mut updater, &watchdog, &health) {
+            log::warn!("ota: error={:#}", err);
+        }
+        watchdog.sleep(RECONNECT_DELAY);
+    }
+}
+
+fn listen(
+    settings: &Settings,
+    store: &Store,
+    ready: &Ready,
+    updater: &mut Updater,
+    watchdog: &Watchdog,
+    health: &health::Task,
+) -> error::Result<()> {
+    let revision = store.revision();
+    let device = settings.device_id();
+    let own_topic = format!("{}/{}", settings.ota_topic, device);
+    let state_topic = format!("{}/state", own_topic);
+    let mut client = mqtt::Client::connect(
+        &settings.mqtt_url,
+        &device,
+        &settings.mqtt_user,
+        settings.mqtt_password.expose(),
+        KEEP_ALIVE,
+        POLL_INTERVAL,
+    )?;
+    let report = |phase, notice: &Notice, detail: &str| {
+        rollout::report(phase, VERSION, Some(&notice.version), detail)
+    };
+    client.publish(&state_topic, updater.report().as_bytes(), true)?;
+    client.subscribe(&[settings.ota_topic.as_str(), own_topic.as_str()])?;
+    log::info!(
+        "ota: listening topic={} version={}",
+        settings.ota_topic,
+        VERSION
+    );
+
+    while store.revision() == revision {
+        health.tick();
+        watchdog.feed();
+        if updater.poll(ready) {
+            client.publish(&state_topic, updater.report().as_bytes(), true)?;
+        }
+        let Some(message) = client.poll()? else {
+            continue;
+        };
+        let mut notice = match Notice::parse(&message.payload) {
+            Ok(notice) => notice,
+            Err(err) => {
+                log::warn!(
+                    "ota: ignoring notice topic={} error={:#}",
+                    message.topic,
+                    err
+                );
+                continue;
+            }
+        };
+        // An update for this device alone is not staged.
+        if message.topic == own_topic {
+            notice.percent = 100;
+        }
+
+        // The state published on connect already says so.
+        if notice.version == VERSION {
+            continue;
+        }
+        let skip_reason = if updater.trial.is_some() {
+            Some("health check running")
+        } else if updater.rejected == Some(notice.sha256) {
+            Some("failed before")
+        } else {
+            notice.skip_reason(VERSION, &device, &settings.ota_source)
+        };
+        if let Some(reason) = skip_reason {
+            log::info!("ota: skipping version={} reason={}", notice.version, reason);
+            let state = report(Phase::Skipped, &notice, reason);
+            client.publish(&state_topic, state.as_bytes(), true)?;
+            continue;
+        }
+
+        log::warn!(
+            "ota: installing version={} url={}",
+            notice.version,
+            notice.url
+        );
+        let state = report(Phase::Downloading, &notice, "");
+        client.publish(&state_topic, state.as_bytes(), true)?;
+        match install(&notice, watchdog).and_then(|()| updater.installed(&notice)) {
+            Ok(()) => {
+                log::warn!("ota: version={} installed, restarting", notice.version);
+                let state = report(Phase::Restarting, &notice, "");
+                // The download may have outlasted the keep-alive.
+                if let Err(err) = client.publish(&state_topic, state.as_bytes(), true) {
+                    log::warn!("ota: reporting the restart error={:#}", err);
+                }
+                thread::sleep(Duration::from_secs(1));
+                unsafe { esp_idf_sys::esp_restart() };
+            }
+            Err(err) => {
+                log::error!("ota: installing version={} error={:#}", notice.version, err);
+                let state = report(Phase::Failed, &notice, &format!("{:#}", err));
+                updater.rejected = Some(notice.sha256);
+                client.publish(&state_topic, state.as_bytes(), true)?;
+            }
+        }
+    }
+    log::info!("ota: settings changed, reconnecting");
+    Ok(())
+}
+
+/// Downloads the image into the next OTA slot and makes it the boot slot
+/// if its SHA-256 matches. ESP-IDF checks the image itself on top.
+fn install(notice: &Notice, watchdog: &Watchdog) -> error::Result<()> {
+    let partition = unsafe { esp_idf_sys::esp_ota_get_next_update_partition(std::ptr::null()) };
+    if partition.is_null() {
+        bail!(Code::Config, "no ota slot, flash partitions-ota.csv");
+    }
+    let slot_size = unsafe { (*partition).size } as usize;
+
+    let connection = EspHttpConnection::new(&HttpConfiguration {
+        timeout: Some(Duration::from_secs(30)),
+        crt_bundle_attach: Some(esp_idf_sys::esp_crt_bundle_attach),
+        ..Default::default()
+    })
+    .context("create esp http connection")?;
+    let mut client = Client::wrap(connection);
+    let request = client
+        .request(Method::Get, &notice.url, &[])
+        .context("create get request")?;
+    let mut response = request.submit().context("do get request")?;
+    let status = response.status();
+    if !(200..300).contains(&status) {
+        bail!(Code::Http, "download http status code={}", status);
+    }
+    let content_length = response
+        .header("content-length")
+        .and_then(|l| l.parse().ok());
+    if content_length.is_some_and(|len: usize| len > slot_size) {
+        bail!(
+            Code::Config,
+            "image is larger than the {}B ota slot",
+            slot_size
+        );
+    }
+
+    let mut handle = 0;
+    esp!(unsafe {
+        esp_idf_sys::esp_ota_begin(
+            partition,
+            esp_idf_sys::OTA_SIZE_UNKNOWN as usize,
+            &mut handle,
+        )
+    })
+    .context("begin ota")?;
+    let written = download(&mut response, handle, watchdog);
+    let digest = match written {
+        Ok(digest) => digest,
+        Err(err) => {
+            unsafe { esp_idf_sys::esp_ota_abort(handle) };
+            return Err(err);
+        }
+    };
+    if digest != notice.sha256 {
+        unsafe { esp_idf_sys::esp_ota_abort(handle) };
+        bail!(
+            Code::Protocol,
+            "sha256 mismatch, downloaded {}",
+            sha256::hex(&digest)
+        );
+    }
+    esp!(unsafe { esp_idf_sys::esp_ota_end(handle) }).context("check image")?;
+    esp!(unsafe { esp_idf_sys::esp_ota_set_boot_partition(partition) })
+        .context("set boot partition")?;
+    Ok(())
+}
+
+/// Writes the body to the OTA slot, returns its SHA-256.
+fn download(
+    body: &mut Response<&mut EspHttpConnection>,
+    handle: esp_idf_sys::esp_ota_handle_t,
+    watchdog: &Watchdog,
+) -> error::Result<[u8; 32]> {
+    let mut sha = Sha256::default();
+    let mut buf = vec![0u8; CHUNK_LEN];
+    let mut total = 0;
+    loop {
+        watchdog.feed();
+        let len = body.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        sha.update(&buf[..len]);
+        esp!(unsafe { esp_idf_sys::esp_ota_write(handle, buf.as_ptr().cast(), len) })
+            .context("write ota slot")?;
+        total += len;
+    }
+    log::info!("ota: downloaded {}B", total);
+    Ok(sha.finish())
+}
@@ -0,0 +1,123 @@
+//! OTLP/HTTP JSON encoding of [`Point`]s, for an OpenTelemetry collector's
+//! `/v1/metrics` endpoint.
+
+use serde_json::{json, Value as Json};
+
+use crate::point::{Point, Value};
+
+/// `service.name` of the resource all metrics belong to.
+const SERVICE_NAME: &str = "esp-sensor";
+
+/// Encodes points as an `ExportMetricsServiceRequest`. Every numeric or
+/// boolean field becomes a gauge named `measurement.field` with the tags
+/// as attributes, points of the same gauge become its data points.
+/// Points without a timestamp get `now`, in nanoseconds since the Unix
+/// epoch. String fields and values that are not finite are skipped.
+pub fn encode(points: &[Point], now: i64) -> Vec<u8> {
+    let mut metrics: Vec<(String, Vec<Json>)> = Vec::new();
+    for point in points {
+        let attributes: Vec<_> = point
+            .tags
+            .iter()
+            .map(|(key, value)| json!({ "key": key, "value": { "stringValue": value } }))
+            .collect();
+        // Integers are strings in the JSON mapping of protobuf.
+        let time = point.timestamp.unwrap_or(now).to_string();
+        for (key, value) in &point.fields {
+            let (kind, value) = match value {
+                Value::Float(v) if v.is_finite() => ("asDouble", json!(v)),
+                Value::Integer(v) => ("asInt", json!(v.to_string())),
+                Value::UInteger(v) => ("asInt", json!(v.to_string())),
+                Value::Bool(v) => ("asInt", json!(u8::from(*v).to_string())),
+                Value::Float(_) | Value::String(_) => continue,
+            };
+            let data_point = json!({
+                "attributes": attributes,
+                "timeUnixNano": time,
+                kind: value,
+            });
+
+            let name = format!("{}.{}", point.measurement, key);
+            match metrics.iter_mut().find(|(n, _)| *n == name) {
+                Some((_, data_points)) => data_points.push(data_point),
+                None => metrics.push((name, vec![data_point])),
+            }
+        }
+    }
+
+    let metrics: Vec<_> = metrics
+        .into_iter()
+        .map(|(name, data_points)| json!({ "name": name, "gauge": { "dataPoints": data_points } }))
+        .collect();
+    let service = json!({ "key": "service.name", "value": { "stringValue": SERVICE_NAME } });
+    let request = json!({
+        "resourceMetrics": [{
+            "resource": { "attributes": [service] },
+            "scopeMetrics": [{
+                "scope": { "name": SERVICE_NAME },
+                "metrics": metrics,
+            }],
+        }],
+    });
+    request.to_string().into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metrics(body: &[u8]) -> Json {
+        let request: Json = serde_json::from_slice(body).unwrap();
+        request["resourceMetrics"][0]["scopeMetrics"][0]["metrics"].clone()
+    }
+
+    #[test]
+    fn encodes_fields_as_gauges() {
+        let points = [Point::new("dht")
+            .tag("device", "kitchen")
+            .field("temperature", 21.5f32)
+            .field("errors", 3i64)
+            .field("relay", true)
+            .field("note", "skipped")
+            .timestamp(1_700_000_000_000_000_000)];
+        let metrics = metrics(&encode(&points, 5));
+
+        assert_eq!(metrics.as_array().unwrap().len(), 3);
+        assert_eq!(
+            metrics[0],
+            json!({
+                "name": "dht.temperature",
+                "gauge": { "dataPoints": [{
+                    "attributes": [{ "key": "device", "value": { "stringValue": "kitchen" } }],
+                    "timeUnixNano": "1700000000000000000",
+                    "asDouble": 21.5,
+                }] },
+            })
+        );
+        assert_eq!(metrics[1]["gauge"]["dataPoints"][0]["asInt"], "3");
+        assert_eq!(metrics[2]["name"], "dht.relay");
+        assert_eq!(metrics[2]["gauge"]["dataPoints"][0]["asInt"], "1");
+    }
+
+    #[test]
+    fn groups_data_points_of_a_gauge() {
+        let points = [
+            Point::new("rack")
+                .tag("rack_u", "1")
+                .field("temperature", 22.),
+            Point::new("rack")
+                .tag("rack_u", "42")
+                .field("temperature", 31.),
+            Point::new("rack_delta").field("delta", f64::NAN),
+        ];
+        let metrics = metrics(&encode(&points, 5));
+
+        assert_eq!(metrics.as_array().unwrap().len(), 1);
+        let data_points = &metrics[0]["gauge"]["dataPoints"];
+        assert_eq!(
+            data_points[1]["attributes"][0]["value"]["stringValue"],
+            "42"
+        );
+        assert_eq!(data_points[1]["timeUnixNano"], "5");
+    }
+}
@@ -0,0 +1,253 @@
+//! Points waiting to be written, in priority classes. When the link is
+//! slow or down the queue fills up: batches are taken highest class first
+//! and a full queue drops the least important points, so alerts go out
+//! before readings and diagnostics never crowd out data.
+
+use std::collections::VecDeque;
+
+use crate::point::Point;
+
+/// Most points kept, each a couple hundred bytes of heap.
+pub const CAPACITY: usize = 64;
+/// Most points written in one request, so a backlog goes out in requests
+/// a slow link can finish.
+pub const MAX_BATCH: usize = 16;
+
+/// Priority classes, most important first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Class {
+    /// Alert events, contact changes and crash reports.
+    Alert,
+    /// Readings not tried yet.
+    Reading,
+    /// Readings from a batch that failed, sent again after the new ones.
+    History,
+    /// Telemetry, heartbeats and task and sender statistics.
+    Diagnostics,
+}
+
+impl Class {
+    pub const ALL: [Class; 4] = [
+        Class::Alert,
+        Class::Reading,
+        Class::History,
+        Class::Diagnostics,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Class::Alert => "alert",
+            Class::Reading => "reading",
+            Class::History => "history",
+            Class::Diagnostics => "diagnostics",
+        }
+    }
+
+    /// What a point becomes when its batch fails.
+    fn retried(self) -> Class {
+        match self {
+            Class::Reading => Class::History,
+            class => class,
+        }
+    }
+}
+
+pub struct Outbox {
+    queues: [VecDeque<Point>; 4],
+    /// The batch being written, with the class of each point.
+    batch: Vec<Point>,
+    classes: Vec<Class>,
+    capacity: usize,
+    dropped: [u32; 4],
+}
+
+impl Default for Outbox {
+    fn default() -> Self {
+        Outbox::new(CAPACITY)
+    }
+}
+
+impl Outbox {
+    pub fn new(capacity: usize) -> Self {
+        Outbox {
+            queues: Default::default(),
+            batch: Vec::new(),
+            classes: Vec::new(),
+            capacity,
+            dropped: [0; 4],
+        }
+    }
+
+    /// Queues `points`, stamping those without a timestamp with `now` in ns
+    /// so a point written late keeps the time it was taken. When full, the
+    /// oldest point of the least important class makes room, unless that
+    /// class is more important than `class`, then the new point is dropped.
+    pub fn extend(
+        &mut self,
+        class: Class,
+        points: impl IntoIterator<Item = Point>,
+        now: Option<i64>,
+    ) {
+        for mut point in points {
+            if point.timestamp.is_none() {
+                point.timestamp = now;
+            }
+            if self.len() >= self.capacity {
+                let least =
+                    (Class::ALL.into_iter().rev()).find(|c| !self.queues[*c as usize].is_empty());
+                match least {
+                    Some(least) if least >= class => {
+                        self.queues[least as usize].pop_front();
+                        self.dropped[least as usize] += 1;
+                    }
+                    _ => {
+                        self.dropped[class as usize] += 1;
+                        continue;
+                    }
+                }
+            }
+            self.queues[class as usize].push_back(point);
+        }
+    }
+
+    /// Queued points, including the batch being written.
+    pub fn len(&self) -> usize {
+        self.queues.iter().map(VecDeque::len).sum::<usize>() + self.batch.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether points wait behind a batch that went out, to be sent right
+    /// away rather than with the next reading.
+    pub fn has_backlog(&self) -> bool {
+        self.batch.is_empty() && !self.is_empty()
+    }
+
+    /// The next batch of at most `max` points, highest class first. A
+    /// batch not confirmed with [`Outbox::sent`] is queued again first,
+    /// ahead of newer points of its class.
+    pub fn next_batch(&mut self, max: usize) -> &[Point] {
+        self.requeue();
+        for class in Class::ALL {
+            let queue = &mut self.queues[class as usize];
+            while self.batch.len() < max {
+                let Some(point) = queue.pop_front() else {
+                    break;
+                };
+                self.batch.push(point);
+                self.classes.push(class);
+            }
+        }
+        &self.batch
+    }
+
+    /// Confirms the batch was written.
+    pub fn sent(&mut self) {
+        self.batch.clear();
+        self.classes.clear();
+    }
+
+    fn requeue(&mut self) {
+        while let (Some(point), Some(class)) = (self.batch.pop(), self.classes.pop()) {
+            self.queues[class.retried() as usize].push_front(point);
+        }
+    }
+
+    /// Points queued and dropped per class, as an `outbox` point.
+    pub fn point(&self, tags: &[(String, String)]) -> Point {
+        let mut queued = [0; 4];
+        for class in &self.classes {
+            queued[*class as usize] += 1;
+        }
+        let mut point = Point::new("outbox").tags(tags);
+        for class in Class::ALL {
+            let queued = queued[class as usize] + self.queues[class as usize].len();
+            point = point
+                .field(format!("queued_{}", class.name()), queued as u32)
+                .field(
+                    format!("dropped_{}", class.name()),
+                    self.dropped[class as usize],
+                );
+        }
+        point
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(name: &str) -> Point {
+        Point::new(name).field("value", 1u32)
+    }
+
+    fn names(batch: &[Point]) -> Vec<&str> {
+        batch.iter().map(|p| p.measurement.as_str()).collect()
+    }
+
+    #[test]
+    fn takes_the_highest_class_first() {
+        let mut outbox = Outbox::new(8);
+        outbox.extend(Class::Diagnostics, [point("device")], None);
+        outbox.extend(Class::Reading, [point("room1"), point("room2")], None);
+        outbox.extend(Class::Alert, [point("alert")], None);
+
+        assert_eq!(names(outbox.next_batch(3)), ["alert", "room1", "room2"]);
+        assert!(!outbox.has_backlog());
+        outbox.sent();
+        assert!(outbox.has_backlog());
+        assert_eq!(names(outbox.next_batch(3)), ["device"]);
+        outbox.sent();
+        assert!(outbox.is_empty());
+        assert!(!outbox.has_backlog());
+    }
+
+    #[test]
+    fn sends_failed_readings_after_new_ones() {
+        let mut outbox = Outbox::new(8);
+        outbox.extend(Class::Reading, [point("old")], None);
+        outbox.extend(Class::Diagnostics, [point("device")], None);
+        assert_eq!(names(outbox.next_batch(4)), ["old", "device"]);
+
+        // Not confirmed, the batch failed.
+        outbox.extend(Class::Reading, [point("new")], None);
+        assert_eq!(outbox.len(), 3);
+        assert_eq!(names(outbox.next_batch(4)), ["new", "old", "device"]);
+    }
+
+    #[test]
+    fn keeps_the_time_of_a_resent_point() {
+        let mut outbox = Outbox::new(8);
+        let taken = point("room").timestamp(1);
+        outbox.extend(Class::Reading, [taken, point("heartbeat")], Some(2));
+        assert_eq!(outbox.next_batch(4).len(), 2);
+
+        // The batch failed and goes out again with newer points.
+        outbox.extend(Class::Reading, [point("new")], Some(3));
+        let times: Vec<_> = outbox.next_batch(4).iter().map(|p| p.timestamp).collect();
+        assert_eq!(times, [Some(3), Some(1), Some(2)]);
+    }
+
+    #[test]
+    fn drops_the_least_important_when_full() {
+        let mut outbox = Outbox::new(2);
+        outbox.extend(Class::Diagnostics, [point("device")], None);
+        outbox.extend(Class::Reading, [point("room1"), point("room2")], None);
+        outbox.extend(Class::Alert, [point("alert")], None);
+        // Nothing less important than a diagnostic is left to make room.
+        outbox.extend(Class::Diagnostics, [point("task")], None);
+
+        assert_eq!(names(outbox.next_batch(4)), ["alert", "room2"]);
+        let point = outbox.point(&[]);
+        let field = |name: &str| {
+            let (_, value) = point.fields.iter().find(|(key, _)| key == name).unwrap();
+            value.clone()
+        };
+        assert_eq!(field("dropped_diagnostics"), 2u32.into());
+        assert_eq!(field("dropped_reading"), 1u32.into());
+        assert_eq!(field("queued_alert"), 1u32.into());
+        assert_eq!(field("queued_reading"), 1u32.into());
+    }
+}
@@ -0,0 +1,62 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "pir")]
+use esp_idf_hal::gpio::{self, Input, PinDriver};
+
+#[cfg(feature = "pir")]
+use crate::{health, settings::Store, watchdog::Watchdog};
+
+#[cfg(feature = "pir")]
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Room occupancy from the PIR sensor, shared with the display and sender.
+pub struct Occupancy {
+    last_motion: Mutex<Instant>,
+}
+
+impl Default for Occupancy {
+    /// Boot counts as motion, so the display starts awake.
+    fn default() -> Self {
+        Occupancy {
+            last_motion: Mutex::new(Instant::now()),
+        }
+    }
+}
+
+impl Occupancy {
+    /// Occupied while motion was seen within `hold`. Always occupied
+    /// without the `pir` feature.
+    pub fn is_occupied(&self, hold: Duration) -> bool {
+        !cfg!(feature = "pir") || self.last_motion.lock().unwrap().elapsed() < hold
+    }
+
+    #[cfg(feature = "pir")]
+    fn motion(&self) {
+        *self.last_motion.lock().unwrap() = Instant::now();
+    }
+}
+
+/// Polls the (active high) PIR output and records motion.
+#[cfg(feature = "pir")]
+pub fn run<P: gpio::InputPin>(store: &Store, occupancy: &Occupancy, pin: PinDriver<'_, P, Input>) {
+    let watchdog = Watchdog::subscribe("pir");
+    let health = health::register("pir");
+    let mut occupied = true;
+
+    loop {
+        health.tick();
+        watchdog.sleep(POLL_INTERVAL);
+
+        if pin.is_high() {
+            occupancy.motion();
+        }
+        let hold = Duration::from_secs(u64::from(store.get().pir_hold_secs));
+        if occupancy.is_occupied(hold) != occupied {
+            occupied = !occupied;
+            log::info!("pir: {}", if occupied { "occupied" } else { "vacant" });
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use std::fmt::{self, Display, Write};
+
+use influxdb_line_protocol::builder::{AfterField, AfterMeasurement, LineProtocolBuilder};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Float(f64),
+    Integer(i64),
+    UInteger(u64),
+    Bool(bool),
+    String(String),
+}
+
+impl From<f32> for Value {
+    fn from(value: f32) -> Self {
+        Self::Float(f64::from(value))
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Self::Float(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Self::Integer(value)
+    }
+}
+
+impl From<u64> for Value {
+    fn from(value: u64) -> Self {
+        Self::UInteger(value)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(value: u32) -> Self {
+        Self::UInteger(u64::from(value))
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Self::String(value.into())
+    }
+}
+
+/// A single line protocol point. Without a timestamp the server assigns
+/// the time of arrival.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Point {
+    pub measurement: String,
+    pub tags: Vec<(String, String)>,
+    pub fields: Vec<(String, Value)>,
+    /// Nanoseconds since the Unix epoch.
+    pub timestamp: Option<i64>,
+}
+
+impl Point {
+    pub fn new(measurement: impl Into<String>) -> Self {
+        Self {
+            measurement: measurement.into(),
+            tags: Vec::new(),
+            fields: Vec::new(),
+            timestamp: None,
+        }
+    }
+
+    pub fn tag(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.tags.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn tags(mut self, tags: &[(String, String)]) -> Self {
+        self.tags.extend_from_slice(tags);
+        self
+    }
+
+    pub fn field(mut self, key: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.fields.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn timestamp(mut self, nanos: i64) -> Self {
+        self.timestamp = Some(nanos);
+        self
+    }
+}
+
+/// Encodes points as line protocol. Points without fields are skipped since
+/// the protocol requires at least one.
+pub fn encode(points: &[Point]) -> Vec<u8> {
+    let mut body = Vec::new();
+    encode_into(points, &mut body);
+    body
+}
+
+/// Encodes points into `body`, replacing what it held. The buffer is
+/// reserved to [`encoded_len`] first, so one kept across batches grows to
+/// the largest batch and is then reused without allocating.
+pub fn encode_into(points: &[Point], body: &mut Vec<u8>) {
+    body.clear();
+    body.reserve(encoded_len(points));
+    for point in points {
+        encode_line(point, body);
+    }
+}
+
+/// Appends the line of one point to `body`, nothing for a point without
+/// fields. Encoding a batch line by line keeps only one line in RAM.
+pub fn encode_line(point: &Point, body: &mut Vec<u8>) {
+    let Some(((key, value), rest)) = point.fields.split_first() else {
+        log::warn!("point: skipping {} without fields", point.measurement);
+        return;
+    };
+
+    let builder = LineProtocolBuilder::new_with(std::mem::take(body));
+    let mut line = builder.measurement(&point.measurement);
+    for (key, value) in &point.tags {
+        line = line.tag(key, value);
+    }
+
+    let mut line = first_field(line, key, value);
+    for (key, value) in rest {
+        line = next_field(line, key, value);
+    }
+    let builder = match point.timestamp {
+        Some(timestamp) => line.timestamp(timestamp).close_line(),
+        None => line.close_line(),
+    };
+    *body = builder.build();
+}
+
+/// The exact length of [`encode`]'s output, computed without allocating.
+pub fn encoded_len(points: &[Point]) -> usize {
+    points
+        .iter()
+        .filter(|point| !point.fields.is_empty())
+        .map(|point| {
+            let tags: usize = (point.tags.iter())
+                .map(|(key, value)| 2 + escaped_len(key, ",= ") + escaped_len(value, ",= "))
+                .sum();
+            let fields: usize = (point.fields.iter())
+                .map(|(key, value)| 2 + escaped_len(key, ",= ") + value_len(value))
+                .sum();
+            let timestamp = point.timestamp.map_or(0, |t| 1 + display_len(t));
+            escaped_len(&point.measurement, ", ") + tags + fields + timestamp + 1
+        })
+        .sum()
+}
+
+fn value_len(value: &Value) -> usize {
+    match value {
+        Value::Float(v) => display_len(v),
+        Value::Integer(v) => display_len(v) + 1,
+        Value::UInteger(v) => display_len(v) + 1,
+        Value::Bool(v) => display_len(v),
+        Value::String(v) => escaped_len(v, "\"\\") + 2,
+    }
+}
+
+/// The length of `s` with a backslash before each of `special`.
+fn escaped_len(s: &str, special: &str) -> usize {
+    s.len() + s.bytes().filter(|b| special.as_bytes().contains(b)).count()
+}
+
+fn display_len(value: impl Display) -> usize {
+    struct Counter(usize);
+
+    impl Write for Counter {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            self.0 += s.len();
+            Ok(())
+        }
+    }
+
+    let mut counter = Counter(0);
+    write!(counter, "{}", value).unwrap();
+    counter.0
+}
+
+fn first_field(
+    line: LineProtocolBuilder<Vec<u8>, AfterMeasurement>,
+    key: &str,
+    value: &Value,
+) -> LineProtocolBuilder<Vec<u8>, AfterField> {
+    match value {
+        Value::Float(v) => line.field(key, *v),
+        Value::Integer(v) => line.field(key, *v),
+        Value::UInteger(v) => line.field(key, *v),
+        Value::Bool(v) => line.field(key, *v),
+        Value::String(v) => line.field(key, v.as_str()),
+    }
+}
+
+fn next_field(
+    line: LineProtocolBuilder<Vec<u8>, AfterField>,
+    key: &str,
+    value: &Value,
+) -> LineProtocolBuilder<Vec<u8>, AfterField> {
+    match value {
+        Value::Float(v) => line.field(key, *v),
+        Value::Integer(v) => line.field(key, *v),
+        Value::UInteger(v) => line.field(key, *v),
+        Value::Bool(v) => line.field(key, *v),
+        Value::String(v) => line.field(key, v.as_str()),
+    }
+}
@@ -0,0 +1,214 @@
+#[cfg(feature = "ble")]
+use std::{ffi::c_void, ptr, slice};
+use std::{
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::error::{self, bail, Context};
+use crate::point::Point;
+#[cfg(feature = "ble")]
+use crate::{ble, health, settings::Store, watchdog::Watchdog};
+
+/// A beacon not heard for this long is reported absent.
+const ABSENT_AFTER: Duration = Duration::from_secs(60);
+#[cfg(feature = "ble")]
+const TICK: Duration = Duration::from_secs(10);
+/// Apple company id followed by the iBeacon type and length.
+#[cfg(feature = "ble")]
+const IBEACON_PREFIX: [u8; 4] = [0x4C, 0x00, 0x02, 0x15];
+
+static TRACKED: Mutex<Vec<Tracked>> = Mutex::new(Vec::new());
+
+struct Tracked {
+    beacon: Beacon,
+    /// Time and RSSI of the latest advertisement.
+    last_seen: Option<(Instant, i8)>,
+}
+
+// Only matched against advertisements with the `ble` feature.
+#[cfg_attr(not(feature = "ble"), allow(dead_code))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Id {
+    Mac([u8; 6]),
+    Ibeacon([u8; 16]),
+}
+
+/// Watched device, written as `name=aa:bb:cc:dd:ee:ff` for a MAC address
+/// or `name=<uuid>` for an iBeacon proximity UUID.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Beacon {
+    pub name: String,
+    #[cfg_attr(not(feature = "ble"), allow(dead_code))]
+    id: Id,
+}
+
+impl FromStr for Beacon {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((name, id)) = s.split_once('=') else {
+            bail!("beacon {:?} has no name", s);
+        };
+        let name = name.trim();
+        if name.is_empty() {
+            bail!("beacon {:?} has an empty name", s);
+        }
+
+        let id = id.trim();
+        let id = if id.contains(':') {
+            let mut mac = [0u8; 6];
+            let parts: Vec<&str> = id.split(':').collect();
+            if parts.len() != mac.len() {
+                bail!("mac address {:?} does not have 6 bytes", id);
+            }
+            for (byte, part) in mac.iter_mut().zip(parts) {
+                *byte = u8::from_str_radix(part, 16).context("parse mac address")?;
+            }
+            Id::Mac(mac)
+        } else {
+            let hex = id.replace('-', "");
+            let mut uuid = [0u8; 16];
+            if hex.len() != uuid.len() * 2 || !hex.is_ascii() {
+                bail!("uuid {:?} does not have 16 bytes", id);
+            }
+            for (i, byte) in uuid.iter_mut().enumerate() {
+                *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).context("parse uuid")?;
+            }
+            Id::Ibeacon(uuid)
+        };
+
+        Ok(Beacon {
+            name: name.into(),
+            id,
+        })
+    }
+}
+
+/// Parses the comma separated `ble_beacons` setting.
+pub fn parse_beacons(spec: &str) -> error::Result<Vec<Beacon>> {
+    spec.split(',')
+        .filter(|beacon| !beacon.trim().is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+/// A `presence` point per watched beacon with `present` and, while
+/// present, the latest `rssi`.
+pub fn points(tags: &[(String, String)]) -> Vec<Point> {
+    let tracked = TRACKED.lock().unwrap();
+    tracked
+        .iter()
+        .map(|tracked| {
+            let point = Point::new("presence")
+                .tag("beacon", tracked.beacon.name.as_str())
+                .tags(tags);
+            match tracked.last_seen {
+                Some((at, rssi)) if at.elapsed() < ABSENT_AFTER => {
+                    point.field("present", true).field("rssi", i64::from(rssi))
+                }
+                _ => point.field("present", false),
+            }
+        })
+        .collect()
+}
+
+/// Replaces the watched beacons when the setting changed.
+#[cfg(feature = "ble")]
+fn sync(beacons: Vec<Beacon>) {
+    let mut tracked = TRACKED.lock().unwrap();
+    if tracked.iter().map(|t| &t.beacon).eq(beacons.iter()) {
+        return;
+    }
+    log::info!("presence: watching {} beacons", beacons.len());
+    *tracked = beacons
+        .into_iter()
+        .map(|beacon| Tracked {
+            beacon,
+            last_seen: None,
+        })
+        .collect();
+}
+
+/// Keeps a passive scan running while `ble_beacons` is set.
+#[cfg(feature = "ble")]
+pub fn run(store: &Store) {
+    let watchdog = Watchdog::subscribe("presence");
+    let health = health::register("presence");
+
+    loop {
+        health.tick();
+        // The store only accepts valid beacons.
+        let beacons = parse_beacons(&store.get().ble_beacons).unwrap_or_default();
+        let scan = !beacons.is_empty();
+        sync(beacons);
+
+        if scan && unsafe { esp_idf_sys::ble_gap_disc_active() } == 0 {
+            if let Err(err) = start_scan() {
+                log::error!("presence: starting scan error={:?}", err);
+            }
+        }
+        watchdog.sleep(TICK);
+    }
+}
+
+#[cfg(feature = "ble")]
+fn start_scan() -> error::Result<()> {
+    let mut params = esp_idf_sys::ble_gap_disc_params::default();
+    params.set_passive(1);
+    ble::check(unsafe {
+        esp_idf_sys::ble_gap_disc(
+            esp_idf_sys::BLE_OWN_ADDR_PUBLIC as u8,
+            i32::MAX,
+            &params,
+            Some(on_gap_event),
+            ptr::null_mut(),
+        )
+    })
+}
+
+/// Runs on the NimBLE host task for every received advertisement.
+#[cfg(feature = "ble")]
+unsafe extern "C" fn on_gap_event(event: *mut esp_idf_sys::ble_gap_event, _: *mut c_void) -> i32 {
+    if u32::from((*event).type_) != esp_idf_sys::BLE_GAP_EVENT_DISC {
+        return 0;
+    }
+    let disc = &(*event).__bindgen_anon_1.disc;
+    let data = slice::from_raw_parts(disc.data, usize::from(disc.length_data));
+
+    // NimBLE stores the address least significant byte first.
+    let mut mac = disc.addr.val;
+    mac.reverse();
+    let uuid = ibeacon_uuid(data);
+
+    let mut tracked = TRACKED.lock().unwrap();
+    for tracked in tracked.iter_mut() {
+        let seen = match tracked.beacon.id {
+            Id::Mac(id) => id == mac,
+            Id::Ibeacon(id) => uuid == Some(id),
+        };
+        if seen {
+            tracked.last_seen = Some((Instant::now(), disc.rssi));
+        }
+    }
+    0
+}
+
+/// Proximity UUID of an iBeacon advertisement.
+#[cfg(feature = "ble")]
+fn ibeacon_uuid(mut data: &[u8]) -> Option<[u8; 16]> {
+    while let [len, rest @ ..] = data {
+        let len = usize::from(*len);
+        if len == 0 || len > rest.len() {
+            return None;
+        }
+        let (structure, next) = rest.split_at(len);
+        // Manufacturer specific data.
+        if structure[0] == 0xFF && structure[1..].starts_with(&IBEACON_PREFIX) {
+            return structure.get(5..21)?.try_into().ok();
+        }
+        data = next;
+    }
+    None
+}
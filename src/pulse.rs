@@ -0,0 +1,394 @@
+#[cfg(feature = "pulse")]
+use std::time::Duration;
+use std::{sync::Mutex, time::Instant};
+
+#[cfg(feature = "pulse")]
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+
+use crate::error::{self, bail, Context};
+use crate::point::Point;
+#[cfg(feature = "pulse")]
+use crate::{health, watchdog::Watchdog};
+
+/// More inputs than this are rejected, chips have four pulse counter units
+/// or more.
+const MAX_INPUTS: usize = 4;
+/// The hardware filter is clocked from the 80 MHz APB clock with at most
+/// 1023 cycles.
+pub const MAX_FILTER_NS: u32 = 12_700;
+#[cfg(feature = "pulse")]
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Totals are written to NVS at most this often to spare the flash.
+#[cfg(feature = "pulse")]
+const SAVE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+#[cfg(feature = "pulse")]
+const NAMESPACE: &str = "pulses";
+
+/// Pulse input, written as `name:gpio` or `name:gpio~filter_ns`, e.g.
+/// `energy:6~1000`. Pulses shorter than the filter are ignored. The name is
+/// also the NVS key of its total, so at most 15 characters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Input {
+    pub name: String,
+    pub pin: i32,
+    pub filter_ns: u32,
+}
+
+/// Parses comma separated pulse inputs, empty means none.
+pub fn parse_inputs(spec: &str) -> error::Result<Vec<Input>> {
+    let mut inputs: Vec<Input> = Vec::new();
+    for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let Some((name, rest)) = entry.split_once(':') else {
+            bail!("pulse input {:?} is not name:gpio", entry);
+        };
+        let name = name.trim();
+        if name.is_empty()
+            || name.len() > 15
+            || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            bail!(
+                "pulse input name {:?} must be up to 15 letters, digits and _",
+                name
+            );
+        }
+        let (pin, filter_ns) = match rest.split_once('~') {
+            Some((pin, filter)) => (pin, filter.trim().parse().context("parse filter")?),
+            None => (rest, 0),
+        };
+        let pin: i32 = pin
+            .trim()
+            .parse()
+            .with_context(|| format!("parse gpio of pulse input {:?}", name))?;
+        if !(0..esp_idf_sys::gpio_num_t_GPIO_NUM_MAX as i32).contains(&pin) {
+            bail!("gpio{} does not exist on this chip", pin);
+        }
+        if filter_ns > MAX_FILTER_NS {
+            bail!("pulse filter {}ns is above {}ns", filter_ns, MAX_FILTER_NS);
+        }
+        if inputs.iter().any(|i| i.name == name) {
+            bail!("pulse input {:?} is defined more than once", name);
+        }
+        inputs.push(Input {
+            name: name.into(),
+            pin,
+            filter_ns,
+        });
+    }
+    if inputs.len() > MAX_INPUTS {
+        bail!("at most {} pulse inputs are supported", MAX_INPUTS);
+    }
+    Ok(inputs)
+}
+
+/// Counts rising edges on the pulse counter, one unit per input.
+#[cfg(all(
+    any(feature = "pulse", feature = "weather"),
+    esp_idf_soc_pcnt_supported
+))]
+pub struct Counter {
+    units: Vec<esp_idf_sys::pcnt_unit_handle_t>,
+    /// Count of each unit at the previous call.
+    last: Vec<i32>,
+}
+
+// SAFETY: the unit handles are only used by the thread owning the counter.
+#[cfg(all(
+    any(feature = "pulse", feature = "weather"),
+    esp_idf_soc_pcnt_supported
+))]
+unsafe impl Send for Counter {}
+
+#[cfg(all(
+    any(feature = "pulse", feature = "weather"),
+    esp_idf_soc_pcnt_supported
+))]
+impl Counter {
+    /// Units are cleared once past this count, well before their limit.
+    const CLEAR_AT: i32 = 16384;
+
+    pub fn new(inputs: &[(i32, u32)]) -> error::Result<Counter> {
+        use esp_idf_sys::*;
+
+        let mut units = Vec::with_capacity(inputs.len());
+        for &(pin, filter_ns) in inputs {
+            let mut unit = std::ptr::null_mut();
+            unsafe {
+                esp!(pcnt_new_unit(
+                    &pcnt_unit_config_t {
+                        low_limit: -1,
+                        high_limit: i16::MAX.into(),
+                        ..Default::default()
+                    },
+                    &mut unit
+                ))
+                .with_context(|| format!("create pcnt unit for gpio{}", pin))?;
+                if filter_ns > 0 {
+                    esp!(pcnt_unit_set_glitch_filter(
+                        unit,
+                        &pcnt_glitch_filter_config_t {
+                            max_glitch_ns: filter_ns
+                        }
+                    ))?;
+                }
+
+                let mut channel = std::ptr::null_mut();
+                esp!(pcnt_new_channel(
+                    unit,
+                    &pcnt_chan_config_t {
+                        edge_gpio_num: pin,
+                        level_gpio_num: -1,
+                        ..Default::default()
+                    },
+                    &mut channel
+                ))
+                .context("create pcnt channel")?;
+                esp!(pcnt_channel_set_edge_action(
+                    channel,
+                    pcnt_channel_edge_action_t_PCNT_CHANNEL_EDGE_ACTION_INCREASE,
+                    pcnt_channel_edge_action_t_PCNT_CHANNEL_EDGE_ACTION_HOLD
+                ))?;
+
+                esp!(pcnt_unit_enable(unit))?;
+                esp!(pcnt_unit_clear_count(unit))?;
+                esp!(pcnt_unit_start(unit))?;
+            }
+            units.push(unit);
+        }
+        Ok(Counter {
+            last: vec![0; units.len()],
+            units,
+        })
+    }
+
+    /// Pulses per input since the last call. Must be called more often than
+    /// every 16383 pulses.
+    pub fn take(&mut self) -> error::Result<Vec<u32>> {
+        use esp_idf_sys::esp;
+
+        let mut pulses = Vec::with_capacity(self.units.len());
+        for (&unit, last) in self.units.iter().zip(&mut self.last) {
+            let mut count = 0;
+            unsafe { esp!(esp_idf_sys::pcnt_unit_get_count(unit, &mut count))? };
+            pulses.push((count - *last) as u32);
+            *last = count;
+            // Clearing rather than on every call keeps the pulses arriving
+            // between reading and clearing to one in 16384.
+            if count >= Self::CLEAR_AT {
+                unsafe { esp!(esp_idf_sys::pcnt_unit_clear_count(unit))? };
+                *last = 0;
+            }
+        }
+        Ok(pulses)
+    }
+}
+
+/// Interrupt counters of chips without a pulse counter, such as the
+/// ESP32-C3, indexed by the `slot` passed to the handler.
+#[cfg(all(
+    any(feature = "pulse", feature = "weather"),
+    not(esp_idf_soc_pcnt_supported)
+))]
+static COUNTS: [std::sync::atomic::AtomicU32; 8] =
+    [const { std::sync::atomic::AtomicU32::new(0) }; 8];
+#[cfg(all(
+    any(feature = "pulse", feature = "weather"),
+    not(esp_idf_soc_pcnt_supported)
+))]
+static LAST_EDGE_US: [std::sync::atomic::AtomicU32; 8] =
+    [const { std::sync::atomic::AtomicU32::new(0) }; 8];
+#[cfg(all(
+    any(feature = "pulse", feature = "weather"),
+    not(esp_idf_soc_pcnt_supported)
+))]
+static FILTER_US: [std::sync::atomic::AtomicU32; 8] =
+    [const { std::sync::atomic::AtomicU32::new(0) }; 8];
+#[cfg(all(
+    any(feature = "pulse", feature = "weather"),
+    not(esp_idf_soc_pcnt_supported)
+))]
+static NEXT_SLOT: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+#[cfg(all(
+    any(feature = "pulse", feature = "weather"),
+    not(esp_idf_soc_pcnt_supported)
+))]
+unsafe extern "C" fn on_edge(slot: *mut std::ffi::c_void) {
+    use std::sync::atomic::Ordering;
+
+    let slot = slot as usize;
+    let now = esp_idf_sys::esp_timer_get_time() as u32;
+    let last = LAST_EDGE_US[slot].swap(now, Ordering::Relaxed);
+    if now.wrapping_sub(last) >= FILTER_US[slot].load(Ordering::Relaxed) {
+        COUNTS[slot].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Without a pulse counter the rising edges are counted in an interrupt
+/// handler, and the filter drops edges following the previous one too
+/// closely instead.
+#[cfg(all(
+    any(feature = "pulse", feature = "weather"),
+    not(esp_idf_soc_pcnt_supported)
+))]
+pub struct Counter {
+    slots: Vec<usize>,
+}
+
+#[cfg(all(
+    any(feature = "pulse", feature = "weather"),
+    not(esp_idf_soc_pcnt_supported)
+))]
+impl Counter {
+    pub fn new(inputs: &[(i32, u32)]) -> error::Result<Counter> {
+        use esp_idf_sys::*;
+        use std::sync::atomic::Ordering;
+
+        let mut slots = Vec::with_capacity(inputs.len());
+        for &(pin, filter_ns) in inputs {
+            let slot = NEXT_SLOT.fetch_add(1, Ordering::Relaxed);
+            if slot >= COUNTS.len() {
+                bail!("no interrupt counter left for gpio{}", pin);
+            }
+            FILTER_US[slot].store(filter_ns.div_ceil(1000), Ordering::Relaxed);
+            unsafe {
+                esp!(gpio_reset_pin(pin))?;
+                esp!(gpio_set_direction(pin, gpio_mode_t_GPIO_MODE_INPUT))?;
+                esp!(gpio_set_intr_type(pin, gpio_int_type_t_GPIO_INTR_POSEDGE))?;
+                // Already installed is fine.
+                let err = gpio_install_isr_service(0);
+                if err != ESP_ERR_INVALID_STATE {
+                    esp!(err)?;
+                }
+                esp!(gpio_isr_handler_add(pin, Some(on_edge), slot as *mut _))
+                    .with_context(|| format!("add interrupt handler for gpio{}", pin))?;
+                esp!(gpio_intr_enable(pin))?;
+            }
+            slots.push(slot);
+        }
+        Ok(Counter { slots })
+    }
+
+    /// Pulses per input since the last call.
+    pub fn take(&mut self) -> error::Result<Vec<u32>> {
+        Ok(self
+            .slots
+            .iter()
+            .map(|&slot| COUNTS[slot].swap(0, std::sync::atomic::Ordering::Relaxed))
+            .collect())
+    }
+}
+
+struct Total {
+    name: String,
+    total: u64,
+    /// Total and time of the last point, for the rate.
+    reported: u64,
+    reported_at: Instant,
+}
+
+/// Pulse totals shared with the sender.
+#[derive(Default)]
+pub struct Pulses {
+    totals: Mutex<Vec<Total>>,
+}
+
+impl Pulses {
+    /// A `pulses` point per input with the `total` since first use and the
+    /// `rate` in pulses per second since the previous point.
+    pub fn points(&self, tags: &[(String, String)]) -> Vec<Point> {
+        let mut totals = self.totals.lock().unwrap();
+        totals
+            .iter_mut()
+            .map(|t| {
+                let elapsed = t.reported_at.elapsed().as_secs_f32();
+                let rate = (t.total - t.reported) as f32 / elapsed.max(1.);
+                t.reported = t.total;
+                t.reported_at = Instant::now();
+                Point::new("pulses")
+                    .tag("counter", &t.name)
+                    .tags(tags)
+                    .field("total", t.total)
+                    .field("rate", rate)
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "pulse")]
+    fn add(&self, index: usize, pulses: u32) {
+        self.totals.lock().unwrap()[index].total += u64::from(pulses);
+    }
+}
+
+/// Counts the `pulse_inputs` and keeps their totals, which are persisted in
+/// NVS every few minutes.
+#[cfg(feature = "pulse")]
+pub fn run(
+    pulses: &Pulses,
+    inputs: &[Input],
+    mut counter: Counter,
+    partition: EspDefaultNvsPartition,
+) {
+    let watchdog = Watchdog::subscribe("pulse");
+    let health = health::register("pulse");
+    let mut nvs = match EspNvs::new(partition, NAMESPACE, true) {
+        Ok(nvs) => Some(nvs),
+        Err(err) => {
+            log::error!("pulse: opening nvs error={:?}", err);
+            None
+        }
+    };
+
+    *pulses.totals.lock().unwrap() = inputs
+        .iter()
+        .map(|input| {
+            let total = nvs
+                .as_ref()
+                .and_then(|nvs| nvs.get_u64(&input.name).ok().flatten())
+                .unwrap_or(0);
+            log::info!("pulse: {} starts at {}", input.name, total);
+            Total {
+                name: input.name.clone(),
+                total,
+                reported: total,
+                reported_at: Instant::now(),
+            }
+        })
+        .collect();
+
+    let mut saved_at = Instant::now();
+    let mut dirty = false;
+    loop {
+        health.tick();
+        watchdog.sleep(POLL_INTERVAL);
+
+        match counter.take() {
+            Ok(counts) => {
+                for (index, pulses_since) in counts.into_iter().enumerate() {
+                    if pulses_since > 0 {
+                        pulses.add(index, pulses_since);
+                        dirty = true;
+                    }
+                }
+            }
+            Err(err) => log::error!("pulse: reading counters error={:?}", err),
+        }
+
+        if dirty && saved_at.elapsed() >= SAVE_INTERVAL {
+            if let Some(nvs) = &mut nvs {
+                if let Err(err) = save(nvs, pulses) {
+                    log::error!("pulse: saving totals error={:?}", err);
+                }
+            }
+            saved_at = Instant::now();
+            dirty = false;
+        }
+    }
+}
+
+#[cfg(feature = "pulse")]
+fn save(nvs: &mut EspNvs<NvsDefault>, pulses: &Pulses) -> error::Result<()> {
+    for total in pulses.totals.lock().unwrap().iter() {
+        nvs.set_u64(&total.name, total.total)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,197 @@
+use std::sync::Mutex;
+#[cfg(feature = "rack")]
+use std::time::Duration;
+
+#[cfg(feature = "rack")]
+use embedded_hal::{
+    blocking::delay::DelayUs,
+    digital::v2::{InputPin, OutputPin},
+};
+
+#[cfg(feature = "rack")]
+use crate::{
+    alert::{Alerts, Event},
+    ds18b20, health,
+    onewire::{format_rom, parse_rom, OneWire},
+    settings::Store,
+    watchdog::Watchdog,
+};
+use crate::{layout, point::Point};
+
+#[cfg(feature = "rack")]
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
+/// Alerts clear once the temperature is this far below their limit.
+#[cfg(feature = "rack")]
+const HYSTERESIS: f32 = 1.;
+/// Name of the alert raised for the difference between top and bottom.
+#[cfg(feature = "rack")]
+const DELTA_ALERT: &str = "rack_delta";
+
+/// Latest readings of the rack, shared with the sender.
+#[derive(Default)]
+pub struct Rack {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Rack unit and temperature in °C of each sensor that was read.
+    readings: Vec<(u32, f32)>,
+    /// Whether the readings changed since the previous points.
+    fresh: bool,
+}
+
+impl Rack {
+    /// A `rack` point with the `rack_u` tag and the `temperature` of each
+    /// sensor, and a `rack_delta` point with the `delta` between the top
+    /// and the bottom sensor. Empty without new readings.
+    pub fn points(&self, tags: &[(String, String)]) -> Vec<Point> {
+        let mut state = self.state.lock().unwrap();
+        if !state.fresh {
+            return Vec::new();
+        }
+        state.fresh = false;
+
+        let mut points: Vec<_> = state
+            .readings
+            .iter()
+            .map(|&(u, temperature)| {
+                Point::new("rack")
+                    .tag("rack_u", u.to_string())
+                    .tags(tags)
+                    .field("temperature", temperature)
+            })
+            .collect();
+        if let Some(delta) = layout::delta(&state.readings) {
+            points.push(Point::new("rack_delta").tags(tags).field("delta", delta));
+        }
+        points
+    }
+}
+
+/// Raises or clears `name` at `max` with [`HYSTERESIS`], returns whether it
+/// is active.
+#[cfg(feature = "rack")]
+fn check(
+    name: &str,
+    value: Option<f32>,
+    max: f32,
+    was_active: bool,
+    events: &mut Vec<Event>,
+) -> bool {
+    let Some(value) = value else {
+        return was_active;
+    };
+    let active = if was_active {
+        value > max - HYSTERESIS
+    } else {
+        value >= max
+    };
+    if active != was_active {
+        if active {
+            log::warn!("rack: {} at {:.1}°C reached {}°C", name, value, max);
+        } else {
+            log::info!("rack: {} back to {:.1}°C", name, value);
+        }
+        events.push(Event {
+            name: name.into(),
+            urgent: false,
+            raised: active,
+            value,
+            notified: false,
+        });
+    }
+    active
+}
+
+/// Reads the DS18B20s of `rack_sensors` every 10 seconds. A `rack_<zone>`
+/// alert is raised while a sensor in a zone of `rack_zones` reaches its
+/// limit, and the `rack_delta` alert while the top is `rack_delta` °C
+/// warmer than the bottom. Sensors on the bus that are missing from
+/// `rack_sensors`, other than the aquarium probe, are logged at boot.
+#[cfg(feature = "rack")]
+pub fn run<P, D, E>(store: &Store, rack: &Rack, alerts: &Alerts, bus: &Mutex<OneWire<P, D>>)
+where
+    P: InputPin<Error = E> + OutputPin<Error = E>,
+    D: DelayUs<u16>,
+    E: std::fmt::Debug,
+{
+    let watchdog = Watchdog::subscribe("rack");
+    let health = health::register("rack");
+    // The store only accepts valid sensors and zones.
+    let sensors = layout::parse_sensors(&store.get().rack_sensors).unwrap_or_default();
+    let probe = parse_rom(&store.get().aquarium_probe).ok();
+    let found = bus.lock().unwrap().search();
+    match found {
+        Ok(found) => {
+            for rom in found.iter().filter(|rom| rom[0] == ds18b20::FAMILY) {
+                if !sensors.iter().any(|(r, _)| r == rom) && probe.as_ref() != Some(rom) {
+                    log::warn!("rack: ds18b20 {} is not in rack_sensors", format_rom(rom));
+                }
+            }
+        }
+        Err(err) => log::error!("rack: searching the bus error={:?}", err),
+    }
+
+    // Names of the active alerts.
+    let mut active: Vec<String> = Vec::new();
+    loop {
+        health.tick();
+        watchdog.sleep(SAMPLE_INTERVAL);
+
+        let settings = store.get();
+        let sensors = layout::parse_sensors(&settings.rack_sensors).unwrap_or_default();
+        let mut readings = Vec::with_capacity(sensors.len());
+        {
+            // Held through the conversion, which also starts the aquarium probe.
+            let mut bus = bus.lock().unwrap();
+            if let Err(err) = ds18b20::start_conversion(&mut bus) {
+                log::error!("rack: starting conversion error={:?}", err);
+                continue;
+            }
+            watchdog.sleep(Duration::from_millis(ds18b20::CONVERSION_MS));
+            for (rom, u) in &sensors {
+                match ds18b20::read(&mut bus, Some(rom)) {
+                    Ok(Some(temperature)) => readings.push((*u, temperature)),
+                    Ok(None) => {}
+                    Err(err) => log::error!("rack: reading u{} error={:?}", u, err),
+                }
+            }
+        }
+        readings.sort_by_key(|&(u, _)| u);
+
+        let mut events = Vec::new();
+        let mut now_active = Vec::new();
+        let zones = layout::parse_zones(&settings.rack_zones).unwrap_or_default();
+        for zone in &zones {
+            let name = format!("rack_{}", zone.name);
+            let was_active = active.contains(&name);
+            if check(
+                &name,
+                zone.hottest(&readings),
+                zone.max,
+                was_active,
+                &mut events,
+            ) {
+                now_active.push(name);
+            }
+        }
+        if settings.rack_delta > 0 {
+            let was_active = active.iter().any(|name| name == DELTA_ALERT);
+            let max = settings.rack_delta as f32;
+            let delta = layout::delta(&readings);
+            if check(DELTA_ALERT, delta, max, was_active, &mut events) {
+                now_active.push(DELTA_ALERT.into());
+            }
+        }
+        // Alerts of removed zones or a disabled delta clear silently.
+        active = now_active;
+
+        *rack.state.lock().unwrap() = State {
+            readings,
+            fresh: true,
+        };
+        let published = active.iter().map(|name| (name.clone(), false)).collect();
+        alerts.publish("rack", published, events);
+    }
+}
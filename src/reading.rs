@@ -0,0 +1,51 @@
+use std::fmt::Display;
+
+use crate::point::Point;
+
+/// DHT22 reading, passed from the sensor task to every subscriber.
+#[derive(Debug, Clone, Copy)]
+pub struct SensorData {
+    pub temperature: f32,
+    pub humidity: f32,
+}
+
+impl SensorData {
+    pub fn is_correct(&self) -> bool {
+        let humidity_correct = self.humidity > 0.0 && self.humidity < 100.0;
+        let temperature_correct = self.temperature >= -40.0 && self.temperature <= 80.0;
+
+        humidity_correct && temperature_correct
+    }
+}
+
+impl Display for SensorData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "temperature={:2.1}°C humidity={:2.1}%",
+            self.temperature, self.humidity
+        ))
+    }
+}
+
+/// Builds the sensor point with the given measurement, tags and field
+/// names. Empty field names leave the field out.
+pub fn sensor_point(
+    measurement: &str,
+    temperature_field: &str,
+    humidity_field: &str,
+    tags: &[(String, String)],
+    data: SensorData,
+    timestamp: Option<i64>,
+) -> Point {
+    let mut point = Point::new(measurement).tag("sensor", "dht22").tags(tags);
+    if !humidity_field.is_empty() {
+        point = point.field(humidity_field, data.humidity);
+    }
+    if !temperature_field.is_empty() {
+        point = point.field(temperature_field, data.temperature);
+    }
+    match timestamp {
+        Some(nanos) => point.timestamp(nanos),
+        None => point,
+    }
+}
@@ -0,0 +1,117 @@
+//! Boot readiness. The init steps run side by side in their tasks and mark
+//! themselves done here, so a task waits for exactly the step it needs
+//! instead of sleeping a fixed time, and the boot is logged step by step.
+
+use std::{
+    sync::{Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Step {
+    /// The first valid reading.
+    Sensor,
+    Display,
+    Wifi,
+    /// The first batch written.
+    Sent,
+    /// The first batch InfluxDB accepted.
+    Written,
+}
+
+impl Step {
+    pub fn name(self) -> &'static str {
+        match self {
+            Step::Sensor => "sensor",
+            Step::Display => "display",
+            Step::Wifi => "wifi",
+            Step::Sent => "sent",
+            Step::Written => "written",
+        }
+    }
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+pub struct Ready {
+    /// Created first thing at boot, the steps are timed from here.
+    boot: Instant,
+    done: Mutex<u8>,
+    changed: Condvar,
+}
+
+impl Default for Ready {
+    fn default() -> Self {
+        Ready {
+            boot: Instant::now(),
+            done: Mutex::new(0),
+            changed: Condvar::new(),
+        }
+    }
+}
+
+impl Ready {
+    /// Marks `step` done and wakes its waiters. Only the first mark is
+    /// logged, later ones are ignored.
+    pub fn mark(&self, step: Step) {
+        let mut done = self.done.lock().unwrap();
+        if *done & step.bit() == 0 {
+            *done |= step.bit();
+            log::info!(
+                "ready: {} after {}ms",
+                step.name(),
+                self.boot.elapsed().as_millis()
+            );
+            self.changed.notify_all();
+        }
+    }
+
+    pub fn is_done(&self, step: Step) -> bool {
+        *self.done.lock().unwrap() & step.bit() != 0
+    }
+
+    /// Waits at most `timeout` for `step`, returns whether it is done.
+    pub fn wait(&self, step: Step, timeout: Duration) -> bool {
+        let done = self.done.lock().unwrap();
+        let (done, _) = self
+            .changed
+            .wait_timeout_while(done, timeout, |done| *done & step.bit() == 0)
+            .unwrap();
+        *done & step.bit() != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use super::*;
+
+    #[test]
+    fn marks_steps_independently() {
+        let ready = Ready::default();
+        ready.mark(Step::Wifi);
+        ready.mark(Step::Wifi);
+        assert!(ready.is_done(Step::Wifi));
+        assert!(!ready.is_done(Step::Sensor));
+        assert!(!ready.wait(Step::Sensor, Duration::from_millis(10)));
+        assert!(ready.wait(Step::Wifi, Duration::ZERO));
+    }
+
+    #[test]
+    fn wakes_waiters() {
+        let ready = Arc::new(Ready::default());
+        let marker = {
+            let ready = ready.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                ready.mark(Step::Sensor);
+            })
+        };
+        assert!(ready.wait(Step::Sensor, Duration::from_secs(5)));
+        marker.join().unwrap();
+    }
+}
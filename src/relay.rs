@@ -0,0 +1,204 @@
+use std::{str::FromStr, sync::Mutex};
+
+#[cfg(feature = "relay")]
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "relay")]
+use esp_idf_hal::gpio::{self, Output, PinDriver};
+
+use crate::alert::Rule;
+use crate::error::{self, bail};
+#[cfg(feature = "relay")]
+use crate::{
+    alert::Engine, broadcast, frost::Frost, health, script, settings::Store, watchdog::Watchdog,
+    SensorData,
+};
+
+/// Without a reading for this many sensor intervals the relay is switched off.
+#[cfg(feature = "relay")]
+const STALE_INTERVALS: u32 = 3;
+#[cfg(feature = "relay")]
+const TICK: Duration = Duration::from_secs(5);
+
+/// Parses the `relay_control` setting. It uses the alert rule syntax
+/// without a name, e.g. `temperature<20~0.5` switches a heater on below 20
+/// and off above 20.5. Empty disables the controller.
+pub fn parse_control(spec: &str) -> error::Result<Option<Rule>> {
+    if spec.trim().is_empty() {
+        return Ok(None);
+    }
+    format!("relay={}", spec).parse().map(Some)
+}
+
+/// Formats a rule back into the `relay_control` syntax.
+#[cfg_attr(not(feature = "encoder"), allow(dead_code))]
+pub fn format_control(rule: &Rule) -> String {
+    let spec = rule.to_string();
+    spec.strip_prefix("relay=").unwrap_or(&spec).into()
+}
+
+/// Manual override of the controller.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Mode {
+    #[default]
+    Auto,
+    On,
+    Off,
+}
+
+impl FromStr for Mode {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Mode::Auto),
+            "on" => Ok(Mode::On),
+            "off" => Ok(Mode::Off),
+            _ => bail!("relay mode {:?} is not one of auto, on, off", s),
+        }
+    }
+}
+
+impl Mode {
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Auto => "auto",
+            Mode::On => "on",
+            Mode::Off => "off",
+        }
+    }
+}
+
+/// Relay state shared with the sender, console and HTTP server.
+#[derive(Default)]
+pub struct Relay {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    mode: Mode,
+    on: bool,
+}
+
+impl Relay {
+    pub fn is_on(&self) -> bool {
+        self.state.lock().unwrap().on
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.state.lock().unwrap().mode
+    }
+
+    /// The override is kept until reboot.
+    pub fn set_mode(&self, mode: Mode) {
+        log::info!("relay: mode {}", mode.name());
+        self.state.lock().unwrap().mode = mode;
+    }
+
+    #[cfg(feature = "relay")]
+    fn set_on(&self, on: bool) {
+        self.state.lock().unwrap().on = on;
+    }
+}
+
+/// Drives the relay from `relay_control` with setpoint and hysteresis.
+///
+/// Once an `automation` rule matched, the relay follows the last matching
+/// rule instead and keeps that state until another rule switches it.
+/// A frost warning switches it on too, to run a heater.
+///
+/// For safety the relay is switched off when readings stop arriving, and
+/// after being on for `relay_max_on` seconds. It then stays off until the
+/// controller itself turns off.
+#[cfg(feature = "relay")]
+pub fn run<P: gpio::OutputPin>(
+    mut sub: broadcast::Receiver<SensorData>,
+    store: &Store,
+    relay: &Relay,
+    frost: &Frost,
+    mut pin: PinDriver<'_, P, Output>,
+) {
+    let watchdog = Watchdog::subscribe("relay");
+    let health = health::register("relay");
+    let mut spec = String::new();
+    let mut engine = Engine::new(Vec::new());
+    let mut automation = String::new();
+    let mut rules = Vec::new();
+    let mut scripted: Option<bool> = None;
+    let mut last_reading: Option<Instant> = None;
+    let mut on_since: Option<Instant> = None;
+    let mut locked_out = false;
+
+    loop {
+        health.tick();
+        let settings = store.get();
+        if settings.relay_control != spec {
+            spec = settings.relay_control.clone();
+            // The store only accepts valid rules.
+            let rule = parse_control(&spec).ok().flatten();
+            engine = Engine::new(rule.into_iter().collect());
+            log::info!("relay: control {:?}", spec);
+        }
+        if settings.automation != automation {
+            automation = settings.automation.clone();
+            rules = script::parse_rules(&automation).unwrap_or_default();
+            scripted = None;
+            log::info!("relay: automation {:?}", automation);
+        }
+
+        match watchdog.recv_timeout(&mut sub, TICK) {
+            Ok(data) => {
+                engine.update(&data);
+                if let Some(on) = script::relay_demand(&rules, &data) {
+                    if scripted != Some(on) {
+                        log::info!(
+                            "relay: automation requests {}",
+                            if on { "on" } else { "off" }
+                        );
+                    }
+                    scripted = Some(on);
+                }
+                last_reading = Some(Instant::now());
+            }
+            Err(broadcast::RecvError::Closed) => return,
+            Err(_) => {}
+        }
+
+        let interval = Duration::from_secs(u64::from(settings.read_sensor_interval_secs));
+        let stale =
+            !matches!(last_reading, Some(last) if last.elapsed() < interval * STALE_INTERVALS);
+        let demand = match relay.mode() {
+            Mode::On => true,
+            Mode::Off => false,
+            Mode::Auto => {
+                (scripted.unwrap_or(!engine.active().is_empty()) || frost.is_warning()) && !stale
+            }
+        };
+        if !demand {
+            locked_out = false;
+        }
+
+        let max_on = Duration::from_secs(u64::from(settings.relay_max_on_secs));
+        if let Some(since) = on_since {
+            if !max_on.is_zero() && since.elapsed() >= max_on && !locked_out {
+                log::warn!(
+                    "relay: on for {:?}, switching off until demand stops",
+                    max_on
+                );
+                locked_out = true;
+            }
+        }
+
+        let on = demand && !locked_out;
+        if on != on_since.is_some() {
+            log::info!("relay: switching {}", if on { "on" } else { "off" });
+            on_since = on.then(Instant::now);
+        }
+        let result = if on { pin.set_high() } else { pin.set_low() };
+        if let Err(err) = result {
+            log::error!("relay: setting pin error={:?}", err);
+        }
+        relay.set_on(on);
+    }
+}
@@ -0,0 +1,115 @@
+use std::time::{Duration, Instant};
+
+use embedded_svc::{
+    http::{client::Client, Method},
+    utils::io,
+};
+use esp_idf_svc::http::client::{Configuration as HttpConfiguration, EspHttpConnection};
+
+use crate::error::{self, bail, Context};
+use crate::settings::{Key, Store};
+
+const MAX_DOCUMENT_LEN: usize = 2048;
+
+/// Periodically pulls a JSON settings document from `config_url`.
+///
+/// The document is a flat object using the same keys as the console, e.g.
+/// `{"interval": 60, "influx_bucket": "garage"}`. A document with an unknown
+/// key or an invalid value is rejected as a whole.
+#[derive(Default)]
+pub struct Puller {
+    etag: Option<String>,
+    last_pull: Option<Instant>,
+}
+
+impl Puller {
+    /// Pulls the document if `config_url` is set and the pull interval elapsed.
+    pub fn poll(&mut self, store: &Store) {
+        let settings = store.get();
+        if settings.config_url.is_empty() {
+            return;
+        }
+
+        let interval = Duration::from_secs(u64::from(settings.config_pull_interval_secs));
+        if matches!(self.last_pull, Some(last) if last.elapsed() < interval) {
+            return;
+        }
+        self.last_pull = Some(Instant::now());
+
+        if let Err(err) = self.pull(&settings.config_url, store) {
+            log::error!("remote_config: pull failed error={:?}", err);
+        }
+    }
+
+    fn pull(&mut self, url: &str, store: &Store) -> error::Result<()> {
+        let connection = EspHttpConnection::new(&HttpConfiguration {
+            timeout: Some(Duration::from_secs(30)),
+            ..Default::default()
+        })
+        .context("create esp http connection")?;
+        let mut client = Client::wrap(connection);
+
+        let mut headers = vec![("accept", "application/json")];
+        if let Some(etag) = &self.etag {
+            headers.push(("if-none-match", etag.as_str()));
+        }
+
+        log::trace!("remote_config: fetching url={}", url);
+        let request = client
+            .request(Method::Get, url, &headers)
+            .context("create get request")?;
+        let mut response = request.submit().context("do get request")?;
+
+        let status = response.status();
+        if status == 304 {
+            log::trace!("remote_config: not modified");
+            return Ok(());
+        }
+        if !(200..300).contains(&status) {
+            bail!("http status code={}", status);
+        }
+
+        let etag = response.header("etag").map(String::from);
+        // One byte past the limit tells a document of exactly the limit from a larger one.
+        let mut buf = vec![0u8; MAX_DOCUMENT_LEN + 1];
+        let len = io::try_read_full(&mut response, &mut buf).map_err(|e| e.0)?;
+        if len > MAX_DOCUMENT_LEN {
+            bail!("document is larger than {} bytes", MAX_DOCUMENT_LEN);
+        }
+
+        let changes = parse(&buf[..len])?;
+        let changes: Vec<(Key, &str)> = changes
+            .iter()
+            .filter(|(key, value)| store.value(*key) != *value)
+            .map(|(key, value)| (*key, value.as_str()))
+            .collect();
+
+        if changes.is_empty() {
+            log::info!("remote_config: settings are up to date");
+        } else {
+            store.update(&changes).context("apply remote settings")?;
+            log::info!("remote_config: applied {} changes", changes.len());
+        }
+
+        self.etag = etag;
+        Ok(())
+    }
+}
+
+fn parse(document: &[u8]) -> error::Result<Vec<(Key, String)>> {
+    let object: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_slice(document).context("parse json document")?;
+
+    let mut changes = Vec::with_capacity(object.len());
+    for (name, value) in object {
+        let key: Key = name.parse()?;
+        let value = match value {
+            serde_json::Value::String(s) => s,
+            serde_json::Value::Number(n) => n.to_string(),
+            other => bail!("unsupported value for {}: {}", key, other),
+        };
+        changes.push((key, value));
+    }
+
+    Ok(changes)
+}
@@ -0,0 +1,297 @@
+//! Firmware update announcements and the rollout state reported back, see
+//! `ota.rs`. An announcement names a version, where to download it and its
+//! SHA-256, and optionally the percentage of the fleet it is meant for, so
+//! a release can go to a few devices first and to the rest once their
+//! states look fine. A new image has to pass a health check before it is
+//! kept, otherwise the device rolls back to the previous one.
+
+use std::str::FromStr;
+
+use serde_json::{json, Value as Json};
+
+use crate::error::{self, bail, Context};
+use crate::ready::Step;
+use crate::sha256;
+
+/// An update announcement, a JSON object published to `ota_topic`:
+/// `{"version":"0.2.0","url":"https://.../esp_sensor.bin","sha256":"<hex>","percent":10}`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notice {
+    pub version: String,
+    pub url: String,
+    pub sha256: [u8; 32],
+    /// Share of the devices that install it, 100 when left out.
+    pub percent: u8,
+}
+
+impl Notice {
+    pub fn parse(payload: &[u8]) -> error::Result<Notice> {
+        let object: Json = serde_json::from_slice(payload).context("parse ota notice")?;
+        let text = |key: &str| object.get(key).and_then(Json::as_str).unwrap_or_default();
+        let version = text("version");
+        if version.is_empty() {
+            bail!("ota notice without a version");
+        }
+        let url = text("url");
+        // The broker is not trusted, the image server has to be.
+        if !url.starts_with("https://") {
+            bail!("ota notice url {:?} must start with https://", url);
+        }
+        let Some(sha256) = sha256::parse_hex(text("sha256")) else {
+            bail!("ota notice sha256 must be 64 hex digits");
+        };
+        let percent = match object.get("percent") {
+            None => 100,
+            Some(percent) => match percent.as_u64() {
+                Some(percent @ 0..=100) => percent as u8,
+                _ => bail!("ota notice percent must be 0 to 100"),
+            },
+        };
+        Ok(Notice {
+            version: version.into(),
+            url: url.into(),
+            sha256,
+            percent,
+        })
+    }
+
+    /// Why the device does not install it, `None` when it does. Whether a
+    /// device is in a rollout depends only on its id, so raising the
+    /// percentage keeps the devices that already have the update in.
+    pub fn skip_reason(
+        &self,
+        running: &str,
+        device_id: &str,
+        source: &str,
+    ) -> Option<&'static str> {
+        if self.version == running {
+            Some("already running")
+        } else if !self.url.starts_with(source) {
+            Some("not from ota_source")
+        } else if bucket(device_id) >= self.percent {
+            Some("outside the rollout")
+        } else {
+            None
+        }
+    }
+}
+
+/// Checks `ota_source`, the prefix of the image URLs a device installs,
+/// e.g. `https://releases.example.com/esp-sensor/`. Anyone who can publish
+/// to the broker can announce an image, so this pins them to a server of
+/// the owner. Empty allows any HTTPS URL.
+pub fn check_source(source: &str) -> error::Result<()> {
+    if !source.is_empty() && !source.starts_with("https://") {
+        bail!("ota_source {:?} must start with https://", source);
+    }
+    Ok(())
+}
+
+/// The rollout bucket of a device, 0 to 99, from the FNV-1a hash of its id.
+pub fn bucket(device_id: &str) -> u8 {
+    let hash = device_id.bytes().fold(0x811c_9dc5u32, |hash, byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    });
+    (hash % 100) as u8
+}
+
+/// Where a device is in an update, published retained to
+/// `<ota_topic>/<device>/state`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Running, waiting for announcements.
+    Idle,
+    /// An announcement it does not install.
+    Skipped,
+    Downloading,
+    /// Downloaded and verified, restarting into it.
+    Restarting,
+    Failed,
+    /// Running a new image that has not passed the health check yet.
+    Verifying,
+    /// Back on the previous image after the new one failed the health
+    /// check or restarted during it.
+    RolledBack,
+}
+
+impl Phase {
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Idle => "idle",
+            Phase::Skipped => "skipped",
+            Phase::Downloading => "downloading",
+            Phase::Restarting => "restarting",
+            Phase::Failed => "failed",
+            Phase::Verifying => "verifying",
+            Phase::RolledBack => "rolled_back",
+        }
+    }
+}
+
+/// What a new image has to reach within `ota_window` to be kept. Not
+/// restarting in that time, e.g. on a panic, is always checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Check {
+    /// Wi-Fi connected.
+    Wifi,
+    /// A batch accepted by InfluxDB.
+    Write,
+}
+
+impl Check {
+    pub fn name(self) -> &'static str {
+        match self {
+            Check::Wifi => "wifi",
+            Check::Write => "write",
+        }
+    }
+
+    /// The boot step that passes the check.
+    pub fn step(self) -> Step {
+        match self {
+            Check::Wifi => Step::Wifi,
+            Check::Write => Step::Written,
+        }
+    }
+}
+
+impl FromStr for Check {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wifi" => Ok(Check::Wifi),
+            "write" => Ok(Check::Write),
+            _ => bail!("unknown check {:?}, expected wifi or write", s),
+        }
+    }
+}
+
+/// Parses comma separated checks, e.g. `wifi,write`. Empty for none.
+pub fn parse_checks(s: &str) -> error::Result<Vec<Check>> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|check| !check.is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+/// The state message: the running version, the phase and, during an
+/// update, the version announced or rolled back and why it was skipped or
+/// failed.
+pub fn report(phase: Phase, running: &str, target: Option<&str>, detail: &str) -> String {
+    let mut report = json!({
+        "state": phase.name(),
+        "running": running,
+    });
+    if let Some(target) = target {
+        report["target"] = json!(target);
+    }
+    if !detail.is_empty() {
+        report["detail"] = json!(detail);
+    }
+    report.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIGEST: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    fn notice(percent: u8) -> Notice {
+        Notice {
+            version: "0.2.0".into(),
+            url: "https://example.com/esp_sensor.bin".into(),
+            sha256: sha256::parse_hex(DIGEST).unwrap(),
+            percent,
+        }
+    }
+
+    #[test]
+    fn parses_notices() {
+        let payload = format!(
+            r#"{{"version":"0.2.0","url":"https://example.com/esp_sensor.bin","sha256":"{}","percent":10}}"#,
+            DIGEST
+        );
+        assert_eq!(Notice::parse(payload.as_bytes()).unwrap(), notice(10));
+
+        let payload = payload.replace(r#","percent":10"#, "");
+        assert_eq!(Notice::parse(payload.as_bytes()).unwrap().percent, 100);
+        assert!(Notice::parse(payload.replace("https", "ftp").as_bytes()).is_err());
+        assert!(Notice::parse(payload.replace("https", "http").as_bytes()).is_err());
+        assert!(Notice::parse(payload.replace(DIGEST, "abc").as_bytes()).is_err());
+        assert!(Notice::parse(payload.replace("0.2.0", "").as_bytes()).is_err());
+        let payload = payload.replace('}', r#","percent":101}"#);
+        assert!(Notice::parse(payload.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn stages_by_device() {
+        let devices: Vec<String> = (0..1000).map(|i| format!("esp-{:06x}", i)).collect();
+        let included = |percent| {
+            devices
+                .iter()
+                .filter(|device| notice(percent).skip_reason("0.1.0", device, "").is_none())
+                .count()
+        };
+        assert_eq!(included(0), 0);
+        assert_eq!(included(100), devices.len());
+        assert!((50..150).contains(&included(10)));
+        // A device in a stage stays in the larger ones.
+        for device in &devices {
+            if bucket(device) < 10 {
+                assert_eq!(notice(50).skip_reason("0.1.0", device, ""), None);
+            }
+        }
+        assert_eq!(
+            notice(100).skip_reason("0.2.0", "esp-000001", ""),
+            Some("already running")
+        );
+        assert_eq!(
+            notice(100).skip_reason("0.1.0", "esp-000001", "https://example.com/"),
+            None
+        );
+        assert_eq!(
+            notice(100).skip_reason("0.1.0", "esp-000001", "https://example.org/"),
+            Some("not from ota_source")
+        );
+        assert!(check_source("https://example.com/").is_ok());
+        assert!(check_source("").is_ok());
+        assert!(check_source("http://example.com/").is_err());
+    }
+
+    #[test]
+    fn reports_states() {
+        let report: Json = serde_json::from_str(&report(
+            Phase::Failed,
+            "0.1.0",
+            Some("0.2.0"),
+            "sha256 mismatch",
+        ))
+        .unwrap();
+        assert_eq!(
+            report,
+            json!({
+                "state": "failed",
+                "running": "0.1.0",
+                "target": "0.2.0",
+                "detail": "sha256 mismatch",
+            })
+        );
+        assert_eq!(
+            super::report(Phase::Idle, "0.1.0", None, ""),
+            r#"{"running":"0.1.0","state":"idle"}"#
+        );
+    }
+
+    #[test]
+    fn parses_checks() {
+        assert_eq!(
+            parse_checks("wifi, write").unwrap(),
+            [Check::Wifi, Check::Write]
+        );
+        assert_eq!(parse_checks("").unwrap(), []);
+        assert!(parse_checks("wifi,panic").is_err());
+    }
+}
@@ -0,0 +1,155 @@
+use std::{ptr, sync::Mutex, time::Duration};
+
+use esp_idf_hal::{delay::BLOCK, i2c::I2cDriver};
+
+use crate::{
+    error::{self, bail},
+    health, schedule,
+    watchdog::Watchdog,
+};
+
+const ADDRESS: u8 = 0x68;
+const REG_SECONDS: u8 = 0x00;
+const REG_STATUS: u8 = 0x0F;
+/// Oscillator stopped, the time is not valid.
+const STATUS_OSF: u8 = 0x80;
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// DS3231 real time clock, kept in UTC. It shares the I2C bus with the
+/// other I2C devices.
+pub struct Ds3231<'a, 'd> {
+    i2c: &'a Mutex<I2cDriver<'d>>,
+}
+
+impl<'a, 'd> Ds3231<'a, 'd> {
+    pub fn new(i2c: &'a Mutex<I2cDriver<'d>>) -> Self {
+        Self { i2c }
+    }
+
+    /// Seconds since the Unix epoch, `None` if the clock lost its time.
+    pub fn read(&mut self) -> error::Result<Option<i64>> {
+        let mut i2c = self.i2c.lock().unwrap();
+        let mut status = [0u8; 1];
+        i2c.write_read(ADDRESS, &[REG_STATUS], &mut status, BLOCK)?;
+        if status[0] & STATUS_OSF != 0 {
+            return Ok(None);
+        }
+
+        let mut regs = [0u8; 7];
+        i2c.write_read(ADDRESS, &[REG_SECONDS], &mut regs, BLOCK)?;
+        if regs[2] & 0x40 != 0 {
+            bail!("rtc is in 12 hour mode");
+        }
+        let seconds = from_bcd(regs[0] & 0x7F);
+        let minutes = from_bcd(regs[1] & 0x7F);
+        let hours = from_bcd(regs[2] & 0x3F);
+        let day = from_bcd(regs[4] & 0x3F);
+        let month = from_bcd(regs[5] & 0x1F);
+        let year = 2000 + from_bcd(regs[6]);
+
+        let days = days_from_civil(i64::from(year), i64::from(month), i64::from(day));
+        Ok(Some(
+            days * 86400 + i64::from(hours) * 3600 + i64::from(minutes) * 60 + i64::from(seconds),
+        ))
+    }
+
+    /// Sets the clock and clears the oscillator stop flag.
+    pub fn write(&mut self, unix_secs: i64) -> error::Result<()> {
+        let (year, month, day) = civil_from_days(unix_secs.div_euclid(86400));
+        let secs = unix_secs.rem_euclid(86400);
+        // Day of the week, 1 is Monday; 1970-01-01 was a Thursday.
+        let weekday = (unix_secs.div_euclid(86400) + 3).rem_euclid(7) + 1;
+        if !(2000..2100).contains(&year) {
+            bail!("year {} is out of the rtc range", year);
+        }
+
+        let mut i2c = self.i2c.lock().unwrap();
+        i2c.write(
+            ADDRESS,
+            &[
+                REG_SECONDS,
+                to_bcd((secs % 60) as u8),
+                to_bcd((secs / 60 % 60) as u8),
+                to_bcd((secs / 3600) as u8),
+                weekday as u8,
+                to_bcd(day as u8),
+                to_bcd(month as u8),
+                to_bcd((year - 2000) as u8),
+            ],
+            BLOCK,
+        )?;
+        i2c.write(ADDRESS, &[REG_STATUS, 0], BLOCK)?;
+        Ok(())
+    }
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0F)
+}
+
+fn to_bcd(value: u8) -> u8 {
+    (value / 10) << 4 | value % 10
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Inverse of [`days_from_civil`].
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Sets the system clock from the RTC, for boots without network.
+pub fn restore(rtc: &mut Ds3231<'_, '_>) {
+    match rtc.read() {
+        Ok(Some(unix_secs)) => {
+            let tv = esp_idf_sys::timeval {
+                tv_sec: unix_secs as _,
+                tv_usec: 0,
+            };
+            unsafe { esp_idf_sys::settimeofday(&tv, ptr::null()) };
+            log::info!("rtc: restored system time {}", unix_secs);
+        }
+        Ok(None) => log::warn!("rtc: clock lost its time, waiting for sntp"),
+        Err(err) => log::error!("rtc: reading error={:?}", err),
+    }
+}
+
+/// Writes the time to the RTC after every SNTP synchronization.
+pub fn run(mut rtc: Ds3231<'_, '_>) {
+    let watchdog = Watchdog::subscribe("rtc");
+    let health = health::register("rtc");
+
+    loop {
+        health.tick();
+        // Reading the status resets it, so each sync is seen once.
+        let status = unsafe { esp_idf_sys::sntp_get_sync_status() };
+        if status == esp_idf_sys::sntp_sync_status_t_SNTP_SYNC_STATUS_COMPLETED {
+            match schedule::unix_time() {
+                Some(now) => match rtc.write(now.as_secs() as i64) {
+                    Ok(()) => log::info!("rtc: set from sntp"),
+                    Err(err) => log::error!("rtc: writing error={:?}", err),
+                },
+                None => log::warn!("rtc: sntp completed but the clock is not set"),
+            }
+        }
+        watchdog.sleep(CHECK_INTERVAL);
+    }
+}
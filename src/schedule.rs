@@ -0,0 +1,159 @@
+use std::{
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    error::{self, bail, Context},
+    settings::Settings,
+    sun,
+};
+
+/// Clock values before 2024-01-01 mean neither SNTP nor the RTC set it yet.
+const MIN_VALID_UNIX_SECS: u64 = 1_704_067_200;
+
+/// Time since the Unix epoch, or `None` while the clock is not set.
+pub fn unix_time() -> Option<Duration> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+    (now.as_secs() >= MIN_VALID_UNIX_SECS).then_some(now)
+}
+
+/// Checks a POSIX TZ string such as `CET-1CEST,M3.5.0,M10.5.0/3`. Only the
+/// standard time name and offset are checked, the C library parses the
+/// daylight saving rules.
+pub fn parse_timezone(tz: &str) -> error::Result<()> {
+    if tz.contains(char::is_whitespace) {
+        bail!("timezone {:?} contains whitespace", tz);
+    }
+    let offset = match tz.strip_prefix('<') {
+        Some(quoted) => {
+            let (_, offset) = quoted
+                .split_once('>')
+                .with_context(|| format!("timezone {:?} is missing >", tz))?;
+            offset
+        }
+        None => {
+            let name_len = tz
+                .find(|c: char| !c.is_ascii_alphabetic())
+                .unwrap_or(tz.len());
+            if name_len < 3 {
+                bail!("timezone {:?} needs a name of at least 3 letters", tz);
+            }
+            &tz[name_len..]
+        }
+    };
+    if !offset
+        .trim_start_matches(['+', '-'])
+        .starts_with(|c: char| c.is_ascii_digit())
+    {
+        bail!("timezone {:?} has no utc offset", tz);
+    }
+    Ok(())
+}
+
+/// Sets the timezone of local time.
+pub fn apply_timezone(tz: &str) {
+    std::env::set_var("TZ", tz);
+    unsafe { esp_idf_sys::tzset() };
+    log::info!("schedule: timezone {}", tz);
+}
+
+/// Minutes since local midnight, or `None` while the clock is not set.
+pub fn local_minutes() -> Option<u32> {
+    let now = unix_time()?.as_secs() as esp_idf_sys::time_t;
+    let mut tm: esp_idf_sys::tm = unsafe { std::mem::zeroed() };
+    unsafe { esp_idf_sys::localtime_r(&now, &mut tm) };
+    Some(tm.tm_hour as u32 * 60 + tm.tm_min as u32)
+}
+
+/// Local date as `YYYYMMDD`, or `None` while the clock is not set.
+#[cfg_attr(not(feature = "weather"), allow(dead_code))]
+pub fn local_date() -> Option<u32> {
+    let now = unix_time()?.as_secs() as esp_idf_sys::time_t;
+    let mut tm: esp_idf_sys::tm = unsafe { std::mem::zeroed() };
+    unsafe { esp_idf_sys::localtime_r(&now, &mut tm) };
+    Some((tm.tm_year as u32 + 1900) * 10_000 + (tm.tm_mon as u32 + 1) * 100 + tm.tm_mday as u32)
+}
+
+/// Daily time window written as `HH:MM-HH:MM` in local time. It may wrap
+/// around midnight, e.g. `22:00-07:00`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    start: u32,
+    end: u32,
+}
+
+impl Window {
+    pub fn contains(&self, minutes: u32) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&minutes)
+        } else {
+            minutes >= self.start || minutes < self.end
+        }
+    }
+
+    /// Whether the local time is inside the window. Unknown time counts as
+    /// outside, so nothing is suppressed before SNTP synced.
+    pub fn is_now(&self) -> bool {
+        local_minutes().is_some_and(|minutes| self.contains(minutes))
+    }
+}
+
+impl FromStr for Window {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((start, end)) = s.split_once('-') else {
+            bail!("time window {:?} is not HH:MM-HH:MM", s);
+        };
+        Ok(Window {
+            start: parse_time(start)?,
+            end: parse_time(end)?,
+        })
+    }
+}
+
+fn parse_time(s: &str) -> error::Result<u32> {
+    let Some((hours, minutes)) = s.trim().split_once(':') else {
+        bail!("time {:?} is not HH:MM", s);
+    };
+    let hours: u32 = hours.parse().context("parse hours")?;
+    let minutes: u32 = minutes.parse().context("parse minutes")?;
+    if hours > 23 || minutes > 59 {
+        bail!("time {:?} is out of range", s);
+    }
+    Ok(hours * 60 + minutes)
+}
+
+/// When alert outputs are quiet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuietHours {
+    Window(Window),
+    /// Between sunset and sunrise at `location`.
+    Dark,
+}
+
+/// Parses the `quiet_hours` setting, a window or `dark`. Empty disables
+/// quiet hours.
+pub fn parse_quiet_hours(spec: &str) -> error::Result<Option<QuietHours>> {
+    match spec.trim() {
+        "" => Ok(None),
+        "dark" => Ok(Some(QuietHours::Dark)),
+        window => window
+            .parse()
+            .map(|window| Some(QuietHours::Window(window))),
+    }
+}
+
+/// Whether non-urgent alert outputs are suppressed right now.
+pub fn is_quiet(settings: &Settings) -> bool {
+    // The store only accepts valid values.
+    match parse_quiet_hours(&settings.quiet_hours) {
+        Ok(Some(QuietHours::Window(window))) => window.is_now(),
+        Ok(Some(QuietHours::Dark)) => matches!(
+            sun::parse_location(&settings.location),
+            Ok(Some(location)) if location.is_dark() == Some(true)
+        ),
+        _ => false,
+    }
+}
@@ -0,0 +1,256 @@
+// Rules are parsed to validate settings, but only the relay runs them.
+#![cfg_attr(not(feature = "relay"), allow(dead_code))]
+
+use std::str::FromStr;
+
+use crate::{
+    alert::{Field, Values},
+    error::{self, bail, Context},
+    SensorData,
+};
+
+const SYMBOLS: [&str; 11] = ["&&", "||", "<=", ">=", "==", "!=", "<", ">", "!", "(", ")"];
+
+/// Automation rule, written as `condition -> action`.
+///
+/// The condition compares reading fields and numbers with `<`, `<=`, `>`,
+/// `>=`, `==` and `!=`, combined with `&&`, `||`, `!` and parentheses, e.g.
+/// `temperature > 28 && humidity < 30 -> relay on`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    condition: Expr,
+    action: Action,
+}
+
+impl Rule {
+    pub fn matches(&self, data: &SensorData) -> bool {
+        self.condition.eval(data)
+    }
+}
+
+impl FromStr for Rule {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((condition, action)) = s.split_once("->") else {
+            bail!("automation rule {:?} has no -> action", s);
+        };
+
+        let mut parser = Parser {
+            tokens: tokenize(condition)?,
+            pos: 0,
+        };
+        let condition = parser.or()?;
+        if let Some(token) = parser.next() {
+            bail!("unexpected {:?} in {:?}", token, s);
+        }
+
+        Ok(Rule {
+            condition,
+            action: action.parse()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Relay(bool),
+}
+
+impl FromStr for Action {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_whitespace().collect::<Vec<_>>().as_slice() {
+            ["relay", "on"] => Ok(Action::Relay(true)),
+            ["relay", "off"] => Ok(Action::Relay(false)),
+            _ => bail!("unknown action {:?}, expected relay on|off", s.trim()),
+        }
+    }
+}
+
+/// Parses comma separated automation rules, empty means none.
+pub fn parse_rules(spec: &str) -> error::Result<Vec<Rule>> {
+    spec.split(',')
+        .filter(|rule| !rule.trim().is_empty())
+        .map(|rule| {
+            rule.parse()
+                .with_context(|| format!("parse rule {:?}", rule))
+        })
+        .collect()
+}
+
+/// Relay state requested by the last matching rule, if any.
+pub fn relay_demand(rules: &[Rule], data: &SensorData) -> Option<bool> {
+    rules
+        .iter()
+        .filter(|rule| rule.matches(data))
+        .map(|rule| match rule.action {
+            Action::Relay(on) => on,
+        })
+        .last()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Compare(Operand, Cmp, Operand),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, data: &SensorData) -> bool {
+        match self {
+            Expr::Compare(lhs, cmp, rhs) => cmp.eval(lhs.value(data), rhs.value(data)),
+            Expr::Not(expr) => !expr.eval(data),
+            Expr::And(lhs, rhs) => lhs.eval(data) && rhs.eval(data),
+            Expr::Or(lhs, rhs) => lhs.eval(data) || rhs.eval(data),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operand {
+    Field(Field),
+    Number(f32),
+}
+
+impl Operand {
+    fn value(self, data: &SensorData) -> f32 {
+        match self {
+            // Fields the reading does not have are NaN, only `!=` holds.
+            Operand::Field(field) => data.get(field).unwrap_or(f32::NAN),
+            Operand::Number(n) => n,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Cmp {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl Cmp {
+    fn eval(self, lhs: f32, rhs: f32) -> bool {
+        match self {
+            Cmp::Lt => lhs < rhs,
+            Cmp::Le => lhs <= rhs,
+            Cmp::Gt => lhs > rhs,
+            Cmp::Ge => lhs >= rhs,
+            Cmp::Eq => lhs == rhs,
+            Cmp::Ne => lhs != rhs,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Number(f32),
+    Symbol(&'static str),
+}
+
+fn tokenize(s: &str) -> error::Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+    while !rest.is_empty() {
+        if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(*symbol)) {
+            tokens.push(Token::Symbol(*symbol));
+            rest = &rest[symbol.len()..];
+        } else {
+            let end = rest
+                .find(|c: char| c.is_whitespace() || "&|<>=!()".contains(c))
+                .unwrap_or(rest.len());
+            if end == 0 {
+                bail!("unexpected {:?}", rest);
+            }
+            let word = &rest[..end];
+            tokens.push(match word.parse() {
+                Ok(n) => Token::Number(n),
+                Err(_) => Token::Word(word.into()),
+            });
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser, `!` binds tighter than `&&`, which binds
+/// tighter than `||`.
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        let found = matches!(self.tokens.get(self.pos), Some(Token::Symbol(s)) if *s == symbol);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn or(&mut self) -> error::Result<Expr> {
+        let mut expr = self.and()?;
+        while self.eat("||") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> error::Result<Expr> {
+        let mut expr = self.unary()?;
+        while self.eat("&&") {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> error::Result<Expr> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("(") {
+            let expr = self.or()?;
+            if !self.eat(")") {
+                bail!("missing )");
+            }
+            return Ok(expr);
+        }
+
+        let lhs = self.operand()?;
+        let cmp = match self.next() {
+            Some(Token::Symbol("<")) => Cmp::Lt,
+            Some(Token::Symbol("<=")) => Cmp::Le,
+            Some(Token::Symbol(">")) => Cmp::Gt,
+            Some(Token::Symbol(">=")) => Cmp::Ge,
+            Some(Token::Symbol("==")) => Cmp::Eq,
+            Some(Token::Symbol("!=")) => Cmp::Ne,
+            other => bail!("expected a comparison, found {:?}", other),
+        };
+        let rhs = self.operand()?;
+        Ok(Expr::Compare(lhs, cmp, rhs))
+    }
+
+    fn operand(&mut self) -> error::Result<Operand> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Operand::Number(n)),
+            Some(Token::Word(word)) => Ok(Operand::Field(word.parse()?)),
+            other => bail!("expected a field or number, found {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use embedded_hal::blocking::i2c::{Read, Write};
+
+pub const ADDRESS: u8 = 0x25;
+
+/// Continuous differential pressure, averaged until read.
+const START_CONTINUOUS: [u8; 2] = [0x36, 0x15];
+/// Temperature in °C per count.
+const TEMPERATURE_SCALE: f32 = 200.;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error<E> {
+    /// Also a read before the first measurement, the sensor does not
+    /// acknowledge it.
+    I2c(E),
+    Crc,
+}
+
+/// Starts continuous measurements, the first is ready after 8 ms.
+pub fn start<I, E>(i2c: &mut I) -> Result<(), Error<E>>
+where
+    I: Write<Error = E>,
+{
+    i2c.write(ADDRESS, &START_CONTINUOUS).map_err(Error::I2c)
+}
+
+/// Differential pressure in Pa and temperature in °C, averaged since the
+/// previous read. The scale factor comes with each reading, so the 125 and
+/// 500 Pa models read alike.
+pub fn read<I, E>(i2c: &mut I) -> Result<(f32, f32), Error<E>>
+where
+    I: Read<Error = E>,
+{
+    let mut data = [0u8; 9];
+    i2c.read(ADDRESS, &mut data).map_err(Error::I2c)?;
+    let mut words = [0i16; 3];
+    for (word, chunk) in words.iter_mut().zip(data.chunks(3)) {
+        if crc8(&chunk[..2]) != chunk[2] {
+            return Err(Error::Crc);
+        }
+        *word = i16::from_be_bytes([chunk[0], chunk[1]]);
+    }
+    let [pressure, temperature, scale] = words;
+    if scale <= 0 {
+        return Err(Error::Crc);
+    }
+    Ok((
+        f32::from(pressure) / f32::from(scale),
+        f32::from(temperature) / TEMPERATURE_SCALE,
+    ))
+}
+
+/// CRC-8 of the Sensirion sensors, polynomial 0x31 starting from 0xFF.
+fn crc8(bytes: &[u8]) -> u8 {
+    let mut crc = 0xFFu8;
+    for &byte in bytes {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                crc << 1 ^ 0x31
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal_mock::eh0::i2c::{Mock, Transaction};
+
+    fn reading(words: [i16; 3]) -> Vec<u8> {
+        words
+            .iter()
+            .flat_map(|w| {
+                let [high, low] = w.to_be_bytes();
+                [high, low, crc8(&[high, low])]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn crc_matches_the_datasheet() {
+        assert_eq!(crc8(&[0xBE, 0xEF]), 0x92);
+    }
+
+    #[test]
+    fn reads_pressure_and_temperature() {
+        let mut i2c = Mock::new(&[
+            Transaction::write(ADDRESS, START_CONTINUOUS.to_vec()),
+            Transaction::read(ADDRESS, reading([600, 4600, 60])),
+            Transaction::read(ADDRESS, reading([-30, 4600, 60])),
+        ]);
+        start(&mut i2c).unwrap();
+        assert_eq!(read(&mut i2c).unwrap(), (10., 23.));
+        assert_eq!(read(&mut i2c).unwrap(), (-0.5, 23.));
+        i2c.done();
+    }
+
+    #[test]
+    fn rejects_corrupted_readings() {
+        let mut data = reading([600, 4600, 60]);
+        data[4] ^= 1;
+        let mut i2c = Mock::new(&[Transaction::read(ADDRESS, data)]);
+        assert_eq!(read(&mut i2c), Err(Error::Crc));
+        i2c.done();
+    }
+}
@@ -0,0 +1,112 @@
+use crate::reading::SensorData;
+
+/// Segments of the hex digits 0-F.
+const HEX: [u8; 16] = [
+    0x3F, 0x06, 0x5B, 0x4F, 0x66, 0x6D, 0x7D, 0x07, 0x7F, 0x6F, 0x77, 0x7C, 0x39, 0x5E, 0x79, 0x71,
+];
+/// The colon of clock modules is wired to the point of the second digit.
+const COLON: u8 = 0x80;
+/// Decimal point of a digit on modules with points.
+const POINT: u8 = 0x80;
+const MINUS: u8 = 0x40;
+
+/// Four digit seven segment frame, one byte per digit with bit 0 for
+/// segment A to bit 6 for segment G.
+pub type Frame = [u8; 4];
+
+/// Every segment lit.
+pub const ALL: Frame = [0xFF; 4];
+pub const PASS: Frame = [0x73, 0x77, 0x6D, 0x6D];
+
+fn hex(digits: [u8; 4]) -> Frame {
+    digits.map(|digit| HEX[usize::from(digit & 0xF)])
+}
+
+/// A letter followed by a two digit number, e.g. `E042`.
+fn code(letter: u8, number: u8) -> Frame {
+    hex([letter, number / 100 % 10, number / 10 % 10, number % 10])
+}
+
+/// Error code as `E0xx`.
+pub fn error(code: u8) -> Frame {
+    self::code(0xE, code % 100)
+}
+
+/// Remaining seconds until a factory reset as `F0NN`.
+pub fn countdown(remaining_secs: u8) -> Frame {
+    code(0xF, remaining_secs % 100)
+}
+
+/// Number of active alerts as `A0NN`, at most 99.
+pub fn alerts(count: usize) -> Frame {
+    code(0xA, count.min(99) as u8)
+}
+
+/// Local time as `HH:MM`, from minutes since midnight.
+pub fn clock(minutes: u32, colon: bool) -> Frame {
+    let (hours, minutes) = ((minutes / 60 % 24) as u8, (minutes % 60) as u8);
+    let mut frame = hex([hours / 10, hours % 10, minutes / 10, minutes % 10]);
+    if colon {
+        frame[1] |= COLON;
+    }
+    frame
+}
+
+/// Setpoint with one decimal, e.g. ` 20.5` or `-18.0`.
+pub fn setpoint(value: f32) -> Frame {
+    let tenths = ((value.abs() * 10.).round() as usize).min(999);
+    [
+        if value < 0. { MINUS } else { 0 },
+        if tenths >= 100 { HEX[tenths / 100] } else { 0 },
+        HEX[tenths / 10 % 10] | POINT,
+        HEX[tenths % 10],
+    ]
+}
+
+/// Whole degrees and percent of a reading, two digits each.
+pub fn reading(data: SensorData) -> Frame {
+    let two_digits = |value: f32| {
+        let value = value as u32;
+        [(value / 10 % 10) as u8, (value % 10) as u8]
+    };
+    let [t1, t0] = two_digits(data.temperature);
+    let [h1, h0] = two_digits(data.humidity);
+    hex([t1, t0, h1, h0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shows_codes() {
+        // E, 0, 4, 2
+        assert_eq!(error(42), [0x79, 0x3F, 0x66, 0x5B]);
+        assert_eq!(countdown(7), [0x71, 0x3F, 0x3F, 0x07]);
+        assert_eq!(alerts(150), [0x77, 0x3F, 0x6F, 0x6F]);
+    }
+
+    #[test]
+    fn shows_clock_with_blinking_colon() {
+        let frame = clock(9 * 60 + 41, true);
+        assert_eq!(frame, [0x3F, 0x6F | COLON, 0x66, 0x06]);
+        assert_eq!(clock(9 * 60 + 41, false)[1], 0x6F);
+    }
+
+    #[test]
+    fn shows_setpoint() {
+        assert_eq!(setpoint(20.5), [0, 0x5B, 0x3F | POINT, 0x6D]);
+        assert_eq!(setpoint(-8.0), [MINUS, 0, 0x7F | POINT, 0x3F]);
+        // Rounded to one decimal and capped at 99.9.
+        assert_eq!(setpoint(1234.56), [0, 0x6F, 0x6F | POINT, 0x6F]);
+    }
+
+    #[test]
+    fn shows_reading() {
+        let data = SensorData {
+            temperature: 23.7,
+            humidity: 48.2,
+        };
+        assert_eq!(reading(data), [0x5B, 0x4F, 0x66, 0x7F]);
+    }
+}
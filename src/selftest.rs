@@ -0,0 +1,171 @@
+use std::{fmt::Display, str::FromStr, thread, time::Duration};
+
+use esp_idf_hal::{
+    delay,
+    gpio::{self, PinDriver},
+};
+use esp_idf_svc::{
+    nvs::{EspDefaultNvsPartition, EspNvs},
+    ping::{self, EspPing},
+    wifi::EspWifi,
+};
+
+use crate::dht;
+use crate::error::{self, bail, Context};
+
+const NAMESPACE: &str = "selftest";
+const SENSOR_ATTEMPTS: u32 = 3;
+/// The DHT22 needs two seconds between reads, and after power-on.
+const SENSOR_DELAY: Duration = Duration::from_millis(2500);
+
+/// How the power-on self test is run, set with the `selftest` setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Off,
+    /// Run the checks and report failures, but keep going.
+    On,
+    /// Do not start normal operation after a critical failure.
+    Strict,
+}
+
+impl FromStr for Mode {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Mode::Off),
+            "on" => Ok(Mode::On),
+            "strict" => Ok(Mode::Strict),
+            _ => bail!("self test mode {:?} is not one of off, on, strict", s),
+        }
+    }
+}
+
+/// Checks of the self test. The numeric value is shown on the display as `E0xx`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Check {
+    Sensor = 20,
+    #[cfg(feature = "display")]
+    Display = 21,
+    Flash = 22,
+    Network = 23,
+}
+
+impl Check {
+    /// Normal operation is pointless with this check failing.
+    pub fn is_critical(self) -> bool {
+        matches!(self, Check::Sensor | Check::Flash)
+    }
+}
+
+impl Display for Check {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Check::Sensor => "sensor",
+            #[cfg(feature = "display")]
+            Check::Display => "display",
+            Check::Flash => "flash",
+            Check::Network => "network",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Default)]
+pub struct Report {
+    failures: Vec<Check>,
+}
+
+impl Report {
+    pub fn record(&mut self, check: Check, result: error::Result<()>) {
+        match result {
+            Ok(()) => log::info!("selftest: {} passed", check),
+            Err(err) => {
+                log::error!("selftest: {} failed error={:?}", check, err);
+                self.failures.push(check);
+            }
+        }
+    }
+
+    pub fn first_failure(&self) -> Option<Check> {
+        self.failures.first().copied()
+    }
+
+    pub fn critical_failure(&self) -> Option<Check> {
+        self.failures.iter().copied().find(|c| c.is_critical())
+    }
+
+    pub fn log_summary(&self) {
+        if self.failures.is_empty() {
+            log::info!("selftest: all checks passed");
+        } else {
+            log::error!("selftest: failed checks {:?}", self.failures);
+        }
+    }
+}
+
+/// Reads the DHT22 until it returns a plausible value.
+pub fn sensor<P: gpio::InputPin + gpio::OutputPin>(
+    pin: &mut PinDriver<'_, P, gpio::InputOutput>,
+) -> error::Result<()> {
+    for attempt in 1..=SENSOR_ATTEMPTS {
+        thread::sleep(SENSOR_DELAY);
+        match dht::read(pin, &mut delay::Ets) {
+            Ok(data) => {
+                if data.is_correct() {
+                    log::info!("selftest: sensor data={}", data);
+                    return Ok(());
+                }
+                log::warn!("selftest: attempt {} got invalid data={}", attempt, data);
+            }
+            Err(err) => log::warn!("selftest: attempt {} error={:?}", attempt, err),
+        }
+    }
+
+    bail!("no valid reading after {} attempts", SENSOR_ATTEMPTS)
+}
+
+/// Writes a value to NVS and reads it back.
+pub fn flash(partition: EspDefaultNvsPartition) -> error::Result<()> {
+    let mut nvs = EspNvs::new(partition, NAMESPACE, true).context("open selftest namespace")?;
+    let probe = unsafe { esp_idf_sys::esp_random() };
+    nvs.set_u32("probe", probe).context("write probe")?;
+    let read = nvs.get_u32("probe").context("read probe")?;
+    nvs.remove("probe").context("remove probe")?;
+
+    if read != Some(probe) {
+        bail!("wrote {} but read back {:?}", probe, read);
+    }
+    Ok(())
+}
+
+/// Pings the gateway of the station interface.
+pub fn network(wifi: &EspWifi<'_>) -> error::Result<()> {
+    let gateway = wifi
+        .sta_netif()
+        .get_ip_info()
+        .context("get ip info")?
+        .subnet
+        .gateway;
+    let summary = EspPing::default()
+        .ping(
+            gateway,
+            &ping::Configuration {
+                count: 3,
+                ..Default::default()
+            },
+        )
+        .context("ping gateway")?;
+
+    log::info!(
+        "selftest: ping {} received {}/{} in {:?}",
+        gateway,
+        summary.received,
+        summary.transmitted,
+        summary.time
+    );
+    if summary.received == 0 {
+        bail!("gateway {} does not answer pings", gateway);
+    }
+    Ok(())
+}
@@ -0,0 +1,40 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs};
+
+use crate::error::{self, Context};
+use crate::point::Point;
+
+const NAMESPACE: &str = "sequence";
+const BOOT_KEY: &str = "boot";
+const FIELD: &str = "point_seq";
+
+/// Numbers every sent point with a `point_seq` field.
+///
+/// The high 32 bits are a boot counter persisted in NVS and the low 32 bits
+/// count points since boot, so the value only grows, across reboots too.
+/// Gaps mean lost points and repeats mean retried or replayed ones.
+pub struct Sequence {
+    next: AtomicU64,
+}
+
+impl Sequence {
+    /// Increments the persisted boot counter.
+    pub fn load(partition: EspDefaultNvsPartition) -> error::Result<Self> {
+        let mut nvs = EspNvs::new(partition, NAMESPACE, true).context("open sequence namespace")?;
+        let boot = nvs.get_u32(BOOT_KEY)?.unwrap_or(0).wrapping_add(1);
+        nvs.set_u32(BOOT_KEY, boot)?;
+        log::info!("sequence: boot {}", boot);
+
+        Ok(Self {
+            next: AtomicU64::new(u64::from(boot) << 32),
+        })
+    }
+
+    pub fn stamp(&self, points: &mut [Point]) {
+        let first = self.next.fetch_add(points.len() as u64, Ordering::Relaxed);
+        for (seq, point) in (first..).zip(points) {
+            point.fields.push((FIELD.into(), seq.into()));
+        }
+    }
+}
@@ -0,0 +1,329 @@
+use std::sync::Arc;
+
+use embedded_svc::{http::Method, io::Write, utils::io};
+use esp_idf_svc::http::server::{Configuration, EspHttpServer};
+
+use crate::{
+    device, error, health, logging, relay,
+    settings::{Key, Store},
+    thing, validation, Shared,
+};
+
+/// Starts the device HTTP server. It stops when the returned value is dropped.
+pub fn start(
+    store: Arc<Store>,
+    pins: Vec<(&'static str, i32)>,
+    shared: Shared,
+) -> error::Result<EspHttpServer> {
+    let mut server = EspHttpServer::new(&Configuration::default())?;
+
+    let status_store = store.clone();
+    let status_shared = shared.clone();
+    server.fn_handler("/status", Method::Get, move |request| {
+        #[cfg_attr(not(feature = "build-info"), allow(unused_mut))]
+        let mut body = serde_json::json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "uptime_secs": device::uptime().as_secs(),
+            "free_heap": unsafe { esp_idf_sys::esp_get_free_heap_size() },
+            "reset_reason": device::reset_reason(),
+            "profile": status_store.profile(),
+            "last_panic": status_shared.crash_log.last(),
+            "alerts": status_shared.alerts.active(),
+        });
+        #[cfg(feature = "build-info")]
+        {
+            body["build"] = crate::build_info::json();
+        }
+
+        let mut response =
+            request.into_response(200, None, &[("content-type", "application/json")])?;
+        response.write_all(body.to_string().as_bytes())?;
+        Ok(())
+    })?;
+
+    let diagnostics_store = store.clone();
+    server.fn_handler("/diagnostics", Method::Get, move |request| {
+        let problems = validation::validate(&diagnostics_store.get(), &pins);
+        let problems: Vec<_> = problems
+            .iter()
+            .map(|p| serde_json::json!({ "code": p.code as u8, "message": p.message }))
+            .collect();
+        let tasks: Vec<_> = health::tasks()
+            .iter()
+            .map(|t| {
+                serde_json::json!({
+                    "name": t.name,
+                    "stack_free": t.stack_free,
+                    "iterations": t.iterations,
+                })
+            })
+            .collect();
+        let body = serde_json::json!({
+            "ok": problems.is_empty(),
+            "problems": problems,
+            "tasks": tasks,
+        });
+
+        let mut response =
+            request.into_response(200, None, &[("content-type", "application/json")])?;
+        response.write_all(body.to_string().as_bytes())?;
+        Ok(())
+    })?;
+
+    let log_store = store.clone();
+    server.fn_handler("/log_levels", Method::Get, move |request| {
+        let mut response = request.into_ok_response()?;
+        response.write_all(log_store.get().log_levels.as_bytes())?;
+        Ok(())
+    })?;
+
+    let log_store = store.clone();
+    // Body is the full spec, e.g. `esp_sensor=debug,wifi=warn`.
+    server.fn_handler("/log_levels", Method::Post, move |mut request| {
+        let mut buf = [0u8; 256];
+        let len = io::try_read_full(&mut request, &mut buf).map_err(|e| e.0)?;
+        let spec = std::str::from_utf8(&buf[..len])?.trim();
+
+        match log_store.set(Key::LogLevels, spec) {
+            Ok(()) => {
+                request.into_ok_response()?.write_all(b"ok")?;
+            }
+            Err(err) => {
+                let mut response = request.into_status_response(400)?;
+                response.write_all(format!("{:#}", err).as_bytes())?;
+            }
+        }
+        Ok(())
+    })?;
+
+    #[cfg(feature = "gas")]
+    {
+        let gas_store = store.clone();
+        let gas = shared.gas.clone();
+        // Calibrates the gas sensor, which must be in clean air.
+        server.fn_handler("/gas/calibrate", Method::Post, move |request| {
+            match gas.calibrate(&gas_store) {
+                Ok(r0) => {
+                    let body = serde_json::json!({ "r0": r0 });
+                    let mut response = request.into_response(
+                        200,
+                        None,
+                        &[("content-type", "application/json")],
+                    )?;
+                    response.write_all(body.to_string().as_bytes())?;
+                }
+                Err(err) => {
+                    let mut response = request.into_status_response(409)?;
+                    response.write_all(format!("{:#}", err).as_bytes())?;
+                }
+            }
+            Ok(())
+        })?;
+    }
+
+    #[cfg(feature = "hive")]
+    {
+        let hive_store = store.clone();
+        let hive = shared.hive.clone();
+        // Tares the hive scale, which must be empty.
+        server.fn_handler("/hive/tare", Method::Post, move |request| {
+            match hive.tare(&hive_store) {
+                Ok(tare) => {
+                    let body = serde_json::json!({ "tare": tare });
+                    let mut response = request.into_response(
+                        200,
+                        None,
+                        &[("content-type", "application/json")],
+                    )?;
+                    response.write_all(body.to_string().as_bytes())?;
+                }
+                Err(err) => {
+                    let mut response = request.into_status_response(409)?;
+                    response.write_all(format!("{:#}", err).as_bytes())?;
+                }
+            }
+            Ok(())
+        })?;
+
+        let hive_store = store.clone();
+        let hive = shared.hive.clone();
+        // Body is the known weight on the tared scale in kg, e.g. `10`.
+        server.fn_handler("/hive/calibrate", Method::Post, move |mut request| {
+            let mut buf = [0u8; 16];
+            let len = io::try_read_full(&mut request, &mut buf).map_err(|e| e.0)?;
+            let kg = std::str::from_utf8(&buf[..len])?.trim().parse::<f32>();
+
+            match kg
+                .map_err(error::Error::from)
+                .and_then(|kg| hive.calibrate(&hive_store, kg))
+            {
+                Ok(counts) => {
+                    let body = serde_json::json!({ "counts_per_kg": counts });
+                    let mut response = request.into_response(
+                        200,
+                        None,
+                        &[("content-type", "application/json")],
+                    )?;
+                    response.write_all(body.to_string().as_bytes())?;
+                }
+                Err(err) => {
+                    let mut response = request.into_status_response(409)?;
+                    response.write_all(format!("{:#}", err).as_bytes())?;
+                }
+            }
+            Ok(())
+        })?;
+    }
+
+    #[cfg(feature = "aquarium")]
+    {
+        let aquarium_store = store.clone();
+        let aquarium = shared.aquarium.clone();
+        // Body is the pH of the buffer solution the probe is in, e.g. `7`.
+        server.fn_handler("/aquarium/ph", Method::Post, move |mut request| {
+            let mut buf = [0u8; 16];
+            let len = io::try_read_full(&mut request, &mut buf).map_err(|e| e.0)?;
+            let ph = std::str::from_utf8(&buf[..len])?.trim().parse::<f32>();
+
+            match ph
+                .map_err(error::Error::from)
+                .and_then(|ph| aquarium.calibrate_ph(&aquarium_store, ph))
+            {
+                Ok(spec) => {
+                    let body = serde_json::json!({ "ph_calibration": spec });
+                    let mut response = request.into_response(
+                        200,
+                        None,
+                        &[("content-type", "application/json")],
+                    )?;
+                    response.write_all(body.to_string().as_bytes())?;
+                }
+                Err(err) => {
+                    let mut response = request.into_status_response(409)?;
+                    response.write_all(format!("{:#}", err).as_bytes())?;
+                }
+            }
+            Ok(())
+        })?;
+    }
+
+    #[cfg(feature = "hvac")]
+    {
+        let hvac = shared.hvac.clone();
+        // Starts the filter runtime over after a filter change.
+        server.fn_handler("/hvac/filter", Method::Post, move |request| {
+            hvac.reset_filter();
+            request.into_ok_response()?.write_all(b"ok")?;
+            Ok(())
+        })?;
+    }
+
+    // The Thing Description, at the root for WebThings gateways and at the
+    // W3C discovery path.
+    for uri in ["/", "/.well-known/wot"] {
+        let thing_store = store.clone();
+        server.fn_handler(uri, Method::Get, move |request| {
+            let body = thing::description(&thing_store.get().device_id());
+            let mut response =
+                request.into_response(200, None, &[("content-type", "application/td+json")])?;
+            response.write_all(body.to_string().as_bytes())?;
+            Ok(())
+        })?;
+    }
+
+    let names = std::iter::once(None).chain(thing::PROPERTIES.map(Some));
+    for name in names {
+        let uri = match name {
+            Some(name) => format!("/properties/{}", name),
+            None => "/properties".into(),
+        };
+        let state = shared.state.clone();
+        server.fn_handler(&uri, Method::Get, move |request| {
+            match state
+                .latest()
+                .and_then(|latest| thing::properties(latest.data, name))
+            {
+                Some(body) => {
+                    let mut response = request.into_response(
+                        200,
+                        None,
+                        &[("content-type", "application/json")],
+                    )?;
+                    response.write_all(body.to_string().as_bytes())?;
+                }
+                None => {
+                    let mut response = request.into_status_response(503)?;
+                    response.write_all(b"no reading yet")?;
+                }
+            }
+            Ok(())
+        })?;
+    }
+
+    let pins_store = store.clone();
+    server.fn_handler("/pins", Method::Get, move |request| {
+        let mut response = request.into_ok_response()?;
+        response.write_all(pins_store.get().pins.as_bytes())?;
+        Ok(())
+    })?;
+
+    // Body is the full spec, e.g. `dht22=4,relay=6`. Applied after a reboot.
+    server.fn_handler("/pins", Method::Post, move |mut request| {
+        let mut buf = [0u8; 256];
+        let len = io::try_read_full(&mut request, &mut buf).map_err(|e| e.0)?;
+        let spec = std::str::from_utf8(&buf[..len])?.trim();
+
+        match store.set(Key::Pins, spec) {
+            Ok(()) => {
+                request
+                    .into_ok_response()?
+                    .write_all(b"ok, reboot to apply")?;
+            }
+            Err(err) => {
+                let mut response = request.into_status_response(400)?;
+                response.write_all(format!("{:#}", err).as_bytes())?;
+            }
+        }
+        Ok(())
+    })?;
+
+    server.fn_handler("/logs", Method::Get, move |request| {
+        let mut response = request.into_response(200, None, &[("content-type", "text/plain")])?;
+        response.write_all(&logging::recent())?;
+        Ok(())
+    })?;
+
+    let relay = shared.relay.clone();
+    server.fn_handler("/relay", Method::Get, move |request| {
+        let body = serde_json::json!({ "on": relay.is_on(), "mode": relay.mode().name() });
+        let mut response =
+            request.into_response(200, None, &[("content-type", "application/json")])?;
+        response.write_all(body.to_string().as_bytes())?;
+        Ok(())
+    })?;
+
+    // Body is `on`, `off` or `auto`.
+    let relay = shared.relay.clone();
+    server.fn_handler("/relay", Method::Post, move |mut request| {
+        let mut buf = [0u8; 16];
+        let len = io::try_read_full(&mut request, &mut buf).map_err(|e| e.0)?;
+        let mode = std::str::from_utf8(&buf[..len])?
+            .trim()
+            .parse::<relay::Mode>();
+
+        match mode {
+            Ok(mode) => {
+                relay.set_mode(mode);
+                request.into_ok_response()?.write_all(b"ok")?;
+            }
+            Err(err) => {
+                let mut response = request.into_status_response(400)?;
+                response.write_all(format!("{:#}", err).as_bytes())?;
+            }
+        }
+        Ok(())
+    })?;
+
+    log::info!("server: listening");
+    Ok(server)
+}
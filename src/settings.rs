@@ -0,0 +1,1593 @@
+use std::{
+    fmt::{Debug, Display},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Mutex, RwLock,
+    },
+};
+
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+
+use crate::{
+    alert, aquarium, board, bthome, buzzer, contacts, deadband, device,
+    error::{self, bail, Code, Context},
+    exposure, fan, gas, hvac, knxnet, layout, logging, lora, mqtt, nats, noise, onewire, presence,
+    pulse, relay, rollout, schedule, script, selftest, sun, CONFIG,
+};
+
+const NAMESPACE: &str = "settings";
+const MAX_STR_LEN: usize = 256;
+const PROFILE_KEY: &str = "profile";
+const PROFILES_KEY: &str = "profiles";
+const DEFAULT_PROFILE: &str = "default";
+/// NVS namespaces are limited to 15 characters, including the `p_` prefix.
+const MAX_PROFILE_LEN: usize = 13;
+
+/// Runtime configuration. Values come from the NVS namespace when present,
+/// otherwise from the compiled `cfg.toml` defaults.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub ssid: String,
+    pub password: Secret,
+    pub addr: String,
+    pub influx_token: Secret,
+    pub influx_org: String,
+    pub influx_bucket: String,
+    /// OTLP/HTTP metrics endpoint, e.g. `http://collector:4318/v1/metrics`, empty disables it.
+    pub otlp_url: String,
+    /// Authorization header of the OTLP endpoint, e.g. `Bearer <token>`, empty sends none.
+    pub otlp_auth: Secret,
+    /// Grafana base URL, e.g. `http://grafana:3000`, empty disables Grafana Live pushes.
+    pub grafana_url: String,
+    /// Service account token with the Grafana Live publish permission.
+    pub grafana_token: Secret,
+    /// Stream id of the pushes, the channels are `stream/<id>/<measurement>`.
+    pub grafana_stream: String,
+    /// StatsD server as `host:port`, e.g. `127.0.0.1:8125`, empty disables it.
+    pub statsd_addr: String,
+    /// Prefix of the StatsD metric names, empty for none.
+    pub statsd_prefix: String,
+    /// KNXnet/IP gateway as `host` or `host:port`, empty disables it.
+    pub knx_gateway: String,
+    /// Group address the temperature is written to, see [`knxnet::parse_group`], empty for none.
+    pub knx_temperature: String,
+    /// Group address the humidity is written to, empty for none.
+    pub knx_humidity: String,
+    /// NATS server as `nats://host:port`, empty disables it.
+    pub nats_url: String,
+    /// Token of the NATS server, empty sends none.
+    pub nats_token: Secret,
+    /// Subject template of the messages, see [`nats::messages`].
+    pub nats_subject: String,
+    /// Seconds to wait for the JetStream acknowledgement of each message, 0 publishes without.
+    pub nats_ack_secs: u32,
+    /// MQTT broker as `mqtt://host:port`, empty disables it.
+    pub mqtt_url: String,
+    /// Credentials of the MQTT broker, empty sends none.
+    pub mqtt_user: String,
+    pub mqtt_password: Secret,
+    /// Topic of the update announcements, see [`rollout::Notice`].
+    pub ota_topic: String,
+    /// Where images may be downloaded from, see [`rollout::check_source`].
+    pub ota_source: String,
+    /// What a new image has to reach to be kept, see [`rollout::Check`].
+    pub ota_checks: String,
+    /// Time a new image has to pass the checks in without restarting.
+    pub ota_window_secs: u32,
+    pub read_sensor_interval_secs: u32,
+    pub config_url: String,
+    pub config_pull_interval_secs: u32,
+    pub measurement: String,
+    /// Static tags as comma separated `key=value` pairs.
+    pub tags: String,
+    /// Empty means a MAC derived id, see [`Settings::device_id`].
+    pub device_id: String,
+    /// Field names, an empty name disables the field.
+    pub temperature_field: String,
+    pub humidity_field: String,
+    pub telemetry_interval_secs: u32,
+    /// Where stored core dumps are uploaded to, empty disables uploads.
+    pub coredump_url: String,
+    /// Comma separated `target=level` pairs, see [`logging::parse`].
+    pub log_levels: String,
+    pub heartbeat_interval_secs: u32,
+    /// Change in °C and %RH that sends a reading at once, "0" to not watch
+    /// the value. With both "0" every reading is sent, see [`deadband`].
+    pub delta_temp: String,
+    pub delta_humidity: String,
+    /// Longest time between two sent readings while they stay within the
+    /// deltas.
+    pub keepalive_secs: u32,
+    /// Power-on self test mode, see [`selftest::Mode`].
+    pub selftest: String,
+    /// HTTP client buffer sizes in bytes, 0 keeps the esp-idf default.
+    pub http_rx_buffer: u32,
+    pub http_tx_buffer: u32,
+    /// Timeout of the write connection, covering connect, send and receive.
+    pub http_timeout_secs: u32,
+    /// Comma separated threshold rules, see [`alert::Rule`].
+    pub alert_rules: String,
+    /// Beep pattern, see [`buzzer::parse_pattern`].
+    pub buzzer_pattern: String,
+    /// Relay controller rule, see [`relay::parse_control`].
+    pub relay_control: String,
+    /// Longest time the relay stays on, 0 is unlimited.
+    pub relay_max_on_secs: u32,
+    /// Fan curve, see [`fan::parse_curve`].
+    pub fan_curve: String,
+    /// ntfy topic URL for alert messages, e.g. `https://ntfy.sh/my-topic`.
+    pub ntfy_url: String,
+    pub ntfy_token: Secret,
+    /// Pushover application token and user key.
+    pub pushover_token: Secret,
+    pub pushover_user: Secret,
+    /// Shortest time between two messages about the same alert.
+    pub notify_interval_secs: u32,
+    /// Telegram bot token and the chat it talks to.
+    pub telegram_token: Secret,
+    pub telegram_chat: String,
+    /// Local time window without buzzer and push messages, e.g. `22:00-07:00`,
+    /// or `dark`, see [`schedule::QuietHours`].
+    pub quiet_hours: String,
+    /// Automation rules, see [`script::Rule`].
+    pub automation: String,
+    /// BTHome advertising, see [`bthome::Mode`]. Read at boot.
+    pub bthome: String,
+    /// Watched BLE devices, see [`presence::Beacon`].
+    pub ble_beacons: String,
+    /// LoRa role, see [`lora::Role`]. Read at boot.
+    pub lora_role: String,
+    /// LoRa frequency in Hz, 868.1 MHz in Europe, 915 MHz in the US. Read at boot.
+    pub lora_freq_hz: u32,
+    /// ESP-NOW role, see [`lora::Role`]. Read at boot.
+    pub espnow_role: String,
+    /// Wi-Fi channel of nodes and relays, the one of the gateway's access
+    /// point. Read at boot.
+    pub espnow_channel: u32,
+    /// POSIX TZ string of local time, e.g. `CET-1CEST,M3.5.0,M10.5.0/3`.
+    pub timezone: String,
+    /// Seconds the clock page is shown after 10 seconds of the reading, 0 disables it.
+    pub display_clock_secs: u32,
+    /// Latitude and longitude, see [`sun::Location`].
+    pub location: String,
+    /// Comma separated `name=gpio` overrides of the board pins, e.g.
+    /// `dht22=4,relay=6`. Read at boot.
+    pub pins: String,
+    /// Seconds the room stays occupied after the last PIR motion.
+    pub pir_hold_secs: u32,
+    /// Door and window contacts, see [`contacts::Contact`]. Read at boot.
+    pub contacts: String,
+    /// ADC reading at which the leak probe is wet, 0 reads it as a digital
+    /// input that is low while wet. Read at boot.
+    pub leak_threshold: u32,
+    /// Pulse inputs, see [`pulse::Input`]. Read at boot.
+    pub pulse_inputs: String,
+    /// Wind speed in mm/s per anemometer pulse per second, 667 for the common 2.4 km/h per Hz.
+    pub wind_factor: u32,
+    /// Rain in µm per tip of the rain gauge.
+    pub rain_tip: u32,
+    /// Gas sensor, see [`gas::Model`].
+    pub gas_sensor: String,
+    /// Load resistor of the gas sensor module in ohms.
+    pub gas_load: u32,
+    /// ADC reading of the gas sensor supply through the divider of its output.
+    pub gas_supply: u32,
+    /// Gas sensor resistance in clean air in ohms, 0 until calibrated.
+    pub gas_r0: u32,
+    /// Heater warm-up after boot before the gas sensor resistance is used.
+    pub gas_warmup_secs: u32,
+    /// Synthetic points per second of the send benchmark, 0 sends readings as usual.
+    pub bench_rate: u32,
+    /// Points per request of the send benchmark.
+    pub bench_batch: u32,
+    /// 1 also writes the benchmark summaries as `bench` points.
+    pub bench_report: u32,
+    /// Seconds between the disruptions of the soak test, 0 disables it.
+    pub soak_interval_secs: u32,
+    /// Length of the server outages of the soak test.
+    pub soak_outage_secs: u32,
+    /// Raw reading of the empty hive scale.
+    pub hive_tare: String,
+    /// Temperature at the hive scale tare in °C, empty if unknown.
+    pub hive_tare_temp: String,
+    /// Hive scale counts per kg, 0 until calibrated.
+    pub hive_scale: String,
+    /// Drift of the hive scale in grams per °C.
+    pub hive_tempco: String,
+    /// Threshold rules on the aquarium probes, see [`alert::Rule`].
+    pub aquarium_alerts: String,
+    /// pH probe calibration, see [`aquarium::parse_calibration`].
+    pub ph_calibration: String,
+    /// ROM code of the aquarium DS18B20, empty when it is alone on the bus.
+    pub aquarium_probe: String,
+    /// Calibration factor of the TDS probe in thousandths.
+    pub tds_factor: u32,
+    /// Current of the CT clamp in amps at 1 V output, 30 for the SCT-013-030.
+    pub ct_amps: u32,
+    /// Currents below this many mA read as 0, the noise floor of the ADC.
+    pub ct_noise_ma: u32,
+    /// Mains voltage the apparent power is computed at.
+    pub mains_voltage: u32,
+    /// Output of the dust sensor in mV in clean air.
+    pub dust_zero_mv: u32,
+    /// Ratio of the divider on the dust sensor output in thousandths, 1000 without one.
+    pub dust_divider: u32,
+    /// Window of each noise level in ms, at least 125.
+    pub noise_window_ms: u32,
+    /// Sensitivity of the microphone in dBFS at 94 dB SPL, -26 for the INMP441.
+    pub mic_sensitivity: String,
+    /// CO alarm levels, see [`exposure::Level`].
+    pub co_levels: String,
+    /// Output of the CO sensor in mV in clean air.
+    pub co_zero_mv: u32,
+    /// Output change of the CO sensor in µV per ppm.
+    pub co_uv_per_ppm: u32,
+    /// Seconds between push messages while the CO alarm sounds.
+    pub co_repeat_secs: u32,
+    /// How far ahead the frost warning looks, in seconds.
+    pub frost_lead_secs: u32,
+    /// Predicted temperature in °C that raises the frost warning.
+    pub frost_temp: String,
+    /// Hours of mould growth conditions that make a risk of 100.
+    pub mold_hours: u32,
+    /// Mould risk that raises the `mold` alert, 0 disables it.
+    pub mold_alert: u32,
+    /// Pressure sensors across the HVAC filter, see [`hvac::Sensor`], read at boot.
+    pub hvac_sensor: String,
+    /// Pressure drop in Pa the sensors read with the fan off, subtracted from each reading.
+    pub hvac_offset: String,
+    /// Pressure drop in Pa from which the fan counts as running.
+    pub hvac_fan_pa: u32,
+    /// Pressure drop in Pa of a clogged filter.
+    pub hvac_clogged_pa: u32,
+    /// Fan runtime in hours after which the filter is due, 0 disables it.
+    pub hvac_filter_hours: u32,
+    /// ROM codes of the rack sensors with their rack units, see [`layout::parse_sensors`].
+    pub rack_sensors: String,
+    /// Rack zones with their temperature limits, see [`layout::parse_zones`].
+    pub rack_zones: String,
+    /// Difference in °C between the top and bottom sensor that raises `rack_delta`, 0 disables it.
+    pub rack_delta: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            ssid: CONFIG.ssid.into(),
+            password: CONFIG.password.into(),
+            addr: CONFIG.addr.into(),
+            influx_token: CONFIG.influx_token.into(),
+            influx_org: CONFIG.influx_org.into(),
+            influx_bucket: CONFIG.influx_bucket.into(),
+            otlp_url: CONFIG.otlp_url.into(),
+            otlp_auth: CONFIG.otlp_auth.into(),
+            grafana_url: CONFIG.grafana_url.into(),
+            grafana_token: CONFIG.grafana_token.into(),
+            grafana_stream: CONFIG.grafana_stream.into(),
+            statsd_addr: CONFIG.statsd_addr.into(),
+            statsd_prefix: CONFIG.statsd_prefix.into(),
+            knx_gateway: CONFIG.knx_gateway.into(),
+            knx_temperature: CONFIG.knx_temperature.into(),
+            knx_humidity: CONFIG.knx_humidity.into(),
+            nats_url: CONFIG.nats_url.into(),
+            nats_token: CONFIG.nats_token.into(),
+            nats_subject: CONFIG.nats_subject.into(),
+            nats_ack_secs: CONFIG.nats_ack_secs,
+            mqtt_url: CONFIG.mqtt_url.into(),
+            mqtt_user: CONFIG.mqtt_user.into(),
+            mqtt_password: CONFIG.mqtt_password.into(),
+            ota_topic: CONFIG.ota_topic.into(),
+            ota_source: CONFIG.ota_source.into(),
+            ota_checks: CONFIG.ota_checks.into(),
+            ota_window_secs: CONFIG.ota_window_secs,
+            read_sensor_interval_secs: CONFIG.read_sensor_interval_secs,
+            config_url: CONFIG.config_url.into(),
+            config_pull_interval_secs: CONFIG.config_pull_interval_secs,
+            measurement: CONFIG.measurement.into(),
+            tags: CONFIG.tags.into(),
+            device_id: CONFIG.device_id.into(),
+            temperature_field: CONFIG.temperature_field.into(),
+            humidity_field: CONFIG.humidity_field.into(),
+            telemetry_interval_secs: CONFIG.telemetry_interval_secs,
+            coredump_url: CONFIG.coredump_url.into(),
+            log_levels: CONFIG.log_levels.into(),
+            heartbeat_interval_secs: CONFIG.heartbeat_interval_secs,
+            delta_temp: CONFIG.delta_temp.into(),
+            delta_humidity: CONFIG.delta_humidity.into(),
+            keepalive_secs: CONFIG.keepalive_secs,
+            selftest: CONFIG.selftest.into(),
+            http_rx_buffer: CONFIG.http_rx_buffer,
+            http_tx_buffer: CONFIG.http_tx_buffer,
+            http_timeout_secs: CONFIG.http_timeout_secs,
+            alert_rules: CONFIG.alert_rules.into(),
+            buzzer_pattern: CONFIG.buzzer_pattern.into(),
+            relay_control: CONFIG.relay_control.into(),
+            relay_max_on_secs: CONFIG.relay_max_on_secs,
+            fan_curve: CONFIG.fan_curve.into(),
+            ntfy_url: CONFIG.ntfy_url.into(),
+            ntfy_token: CONFIG.ntfy_token.into(),
+            pushover_token: CONFIG.pushover_token.into(),
+            pushover_user: CONFIG.pushover_user.into(),
+            notify_interval_secs: CONFIG.notify_interval_secs,
+            telegram_token: CONFIG.telegram_token.into(),
+            telegram_chat: CONFIG.telegram_chat.into(),
+            quiet_hours: CONFIG.quiet_hours.into(),
+            automation: CONFIG.automation.into(),
+            bthome: CONFIG.bthome.into(),
+            ble_beacons: CONFIG.ble_beacons.into(),
+            lora_role: CONFIG.lora_role.into(),
+            lora_freq_hz: CONFIG.lora_freq_hz,
+            espnow_role: CONFIG.espnow_role.into(),
+            espnow_channel: CONFIG.espnow_channel,
+            timezone: CONFIG.timezone.into(),
+            display_clock_secs: CONFIG.display_clock_secs,
+            location: CONFIG.location.into(),
+            pins: CONFIG.pins.into(),
+            pir_hold_secs: CONFIG.pir_hold_secs,
+            contacts: CONFIG.contacts.into(),
+            leak_threshold: CONFIG.leak_threshold,
+            pulse_inputs: CONFIG.pulse_inputs.into(),
+            wind_factor: CONFIG.wind_factor,
+            rain_tip: CONFIG.rain_tip,
+            gas_sensor: CONFIG.gas_sensor.into(),
+            gas_load: CONFIG.gas_load,
+            gas_supply: CONFIG.gas_supply,
+            gas_r0: CONFIG.gas_r0,
+            gas_warmup_secs: CONFIG.gas_warmup_secs,
+            bench_rate: CONFIG.bench_rate,
+            bench_batch: CONFIG.bench_batch,
+            bench_report: CONFIG.bench_report,
+            soak_interval_secs: CONFIG.soak_interval_secs,
+            soak_outage_secs: CONFIG.soak_outage_secs,
+            hive_tare: CONFIG.hive_tare.into(),
+            hive_tare_temp: CONFIG.hive_tare_temp.into(),
+            hive_scale: CONFIG.hive_scale.into(),
+            hive_tempco: CONFIG.hive_tempco.into(),
+            aquarium_alerts: CONFIG.aquarium_alerts.into(),
+            ph_calibration: CONFIG.ph_calibration.into(),
+            aquarium_probe: CONFIG.aquarium_probe.into(),
+            tds_factor: CONFIG.tds_factor,
+            ct_amps: CONFIG.ct_amps,
+            ct_noise_ma: CONFIG.ct_noise_ma,
+            mains_voltage: CONFIG.mains_voltage,
+            dust_zero_mv: CONFIG.dust_zero_mv,
+            dust_divider: CONFIG.dust_divider,
+            noise_window_ms: CONFIG.noise_window_ms,
+            mic_sensitivity: CONFIG.mic_sensitivity.into(),
+            co_levels: CONFIG.co_levels.into(),
+            co_zero_mv: CONFIG.co_zero_mv,
+            co_uv_per_ppm: CONFIG.co_uv_per_ppm,
+            co_repeat_secs: CONFIG.co_repeat_secs,
+            frost_lead_secs: CONFIG.frost_lead_secs,
+            frost_temp: CONFIG.frost_temp.into(),
+            mold_hours: CONFIG.mold_hours,
+            mold_alert: CONFIG.mold_alert,
+            hvac_sensor: CONFIG.hvac_sensor.into(),
+            hvac_offset: CONFIG.hvac_offset.into(),
+            hvac_fan_pa: CONFIG.hvac_fan_pa,
+            hvac_clogged_pa: CONFIG.hvac_clogged_pa,
+            hvac_filter_hours: CONFIG.hvac_filter_hours,
+            rack_sensors: CONFIG.rack_sensors.into(),
+            rack_zones: CONFIG.rack_zones.into(),
+            rack_delta: CONFIG.rack_delta,
+        }
+    }
+}
+
+/// A value that is never printed by `Debug`, so settings can be logged.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(value: &str) -> Self {
+        Self(value.into())
+    }
+}
+
+impl Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0.is_empty() {
+            f.write_str("<empty>")
+        } else {
+            f.write_str("<redacted>")
+        }
+    }
+}
+
+/// A single changeable setting. The name is used both by the console/remote
+/// interfaces and as the NVS key, so it must stay within 15 characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Ssid,
+    Password,
+    Addr,
+    InfluxToken,
+    InfluxOrg,
+    InfluxBucket,
+    OtlpUrl,
+    OtlpAuth,
+    GrafanaUrl,
+    GrafanaToken,
+    GrafanaStream,
+    StatsdAddr,
+    StatsdPrefix,
+    KnxGateway,
+    KnxTemperature,
+    KnxHumidity,
+    NatsUrl,
+    NatsToken,
+    NatsSubject,
+    NatsAck,
+    MqttUrl,
+    MqttUser,
+    MqttPassword,
+    OtaTopic,
+    OtaSource,
+    OtaChecks,
+    OtaWindow,
+    ReadSensorInterval,
+    ConfigUrl,
+    ConfigPullInterval,
+    Measurement,
+    Tags,
+    DeviceId,
+    TemperatureField,
+    HumidityField,
+    TelemetryInterval,
+    CoredumpUrl,
+    LogLevels,
+    HeartbeatInterval,
+    DeltaTemp,
+    DeltaHumidity,
+    Keepalive,
+    SelfTest,
+    HttpRxBuffer,
+    HttpTxBuffer,
+    HttpTimeout,
+    AlertRules,
+    BuzzerPattern,
+    RelayControl,
+    RelayMaxOn,
+    FanCurve,
+    NtfyUrl,
+    NtfyToken,
+    PushoverToken,
+    PushoverUser,
+    NotifyInterval,
+    TelegramToken,
+    TelegramChat,
+    QuietHours,
+    Automation,
+    Bthome,
+    BleBeacons,
+    LoraRole,
+    LoraFrequency,
+    EspnowRole,
+    EspnowChannel,
+    Timezone,
+    DisplayClock,
+    Location,
+    Pins,
+    PirHold,
+    Contacts,
+    LeakThreshold,
+    PulseInputs,
+    WindFactor,
+    RainTip,
+    GasSensor,
+    GasLoad,
+    GasSupply,
+    GasR0,
+    GasWarmup,
+    BenchRate,
+    BenchBatch,
+    BenchReport,
+    SoakInterval,
+    SoakOutage,
+    HiveTare,
+    HiveTareTemp,
+    HiveScale,
+    HiveTempco,
+    AquariumAlerts,
+    PhCalibration,
+    AquariumProbe,
+    TdsFactor,
+    CtAmps,
+    CtNoise,
+    MainsVoltage,
+    DustZero,
+    DustDivider,
+    NoiseWindow,
+    MicSensitivity,
+    CoLevels,
+    CoZero,
+    CoSensitivity,
+    CoRepeat,
+    FrostLead,
+    FrostTemp,
+    MoldHours,
+    MoldAlert,
+    HvacSensor,
+    HvacOffset,
+    HvacFan,
+    HvacClogged,
+    HvacFilter,
+    RackSensors,
+    RackZones,
+    RackDelta,
+}
+
+impl Key {
+    pub const ALL: [Key; 117] = [
+        Key::Ssid,
+        Key::Password,
+        Key::Addr,
+        Key::InfluxToken,
+        Key::InfluxOrg,
+        Key::InfluxBucket,
+        Key::OtlpUrl,
+        Key::OtlpAuth,
+        Key::GrafanaUrl,
+        Key::GrafanaToken,
+        Key::GrafanaStream,
+        Key::StatsdAddr,
+        Key::StatsdPrefix,
+        Key::KnxGateway,
+        Key::KnxTemperature,
+        Key::KnxHumidity,
+        Key::NatsUrl,
+        Key::NatsToken,
+        Key::NatsSubject,
+        Key::NatsAck,
+        Key::MqttUrl,
+        Key::MqttUser,
+        Key::MqttPassword,
+        Key::OtaTopic,
+        Key::OtaSource,
+        Key::OtaChecks,
+        Key::OtaWindow,
+        Key::ReadSensorInterval,
+        Key::ConfigUrl,
+        Key::ConfigPullInterval,
+        Key::Measurement,
+        Key::Tags,
+        Key::DeviceId,
+        Key::TemperatureField,
+        Key::HumidityField,
+        Key::TelemetryInterval,
+        Key::CoredumpUrl,
+        Key::LogLevels,
+        Key::HeartbeatInterval,
+        Key::DeltaTemp,
+        Key::DeltaHumidity,
+        Key::Keepalive,
+        Key::SelfTest,
+        Key::HttpRxBuffer,
+        Key::HttpTxBuffer,
+        Key::HttpTimeout,
+        Key::AlertRules,
+        Key::BuzzerPattern,
+        Key::RelayControl,
+        Key::RelayMaxOn,
+        Key::FanCurve,
+        Key::NtfyUrl,
+        Key::NtfyToken,
+        Key::PushoverToken,
+        Key::PushoverUser,
+        Key::NotifyInterval,
+        Key::TelegramToken,
+        Key::TelegramChat,
+        Key::QuietHours,
+        Key::Automation,
+        Key::Bthome,
+        Key::BleBeacons,
+        Key::LoraRole,
+        Key::LoraFrequency,
+        Key::EspnowRole,
+        Key::EspnowChannel,
+        Key::Timezone,
+        Key::DisplayClock,
+        Key::Location,
+        Key::Pins,
+        Key::PirHold,
+        Key::Contacts,
+        Key::LeakThreshold,
+        Key::PulseInputs,
+        Key::WindFactor,
+        Key::RainTip,
+        Key::GasSensor,
+        Key::GasLoad,
+        Key::GasSupply,
+        Key::GasR0,
+        Key::GasWarmup,
+        Key::BenchRate,
+        Key::BenchBatch,
+        Key::BenchReport,
+        Key::SoakInterval,
+        Key::SoakOutage,
+        Key::HiveTare,
+        Key::HiveTareTemp,
+        Key::HiveScale,
+        Key::HiveTempco,
+        Key::AquariumAlerts,
+        Key::PhCalibration,
+        Key::AquariumProbe,
+        Key::TdsFactor,
+        Key::CtAmps,
+        Key::CtNoise,
+        Key::MainsVoltage,
+        Key::DustZero,
+        Key::DustDivider,
+        Key::NoiseWindow,
+        Key::MicSensitivity,
+        Key::CoLevels,
+        Key::CoZero,
+        Key::CoSensitivity,
+        Key::CoRepeat,
+        Key::FrostLead,
+        Key::FrostTemp,
+        Key::MoldHours,
+        Key::MoldAlert,
+        Key::HvacSensor,
+        Key::HvacOffset,
+        Key::HvacFan,
+        Key::HvacClogged,
+        Key::HvacFilter,
+        Key::RackSensors,
+        Key::RackZones,
+        Key::RackDelta,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Key::Ssid => "ssid",
+            Key::Password => "password",
+            Key::Addr => "addr",
+            Key::InfluxToken => "influx_token",
+            Key::InfluxOrg => "influx_org",
+            Key::InfluxBucket => "influx_bucket",
+            Key::OtlpUrl => "otlp_url",
+            Key::OtlpAuth => "otlp_auth",
+            Key::GrafanaUrl => "grafana_url",
+            Key::GrafanaToken => "grafana_token",
+            Key::GrafanaStream => "grafana_stream",
+            Key::StatsdAddr => "statsd_addr",
+            Key::StatsdPrefix => "statsd_prefix",
+            Key::KnxGateway => "knx_gateway",
+            Key::KnxTemperature => "knx_temperature",
+            Key::KnxHumidity => "knx_humidity",
+            Key::NatsUrl => "nats_url",
+            Key::NatsToken => "nats_token",
+            Key::NatsSubject => "nats_subject",
+            Key::NatsAck => "nats_ack",
+            Key::MqttUrl => "mqtt_url",
+            Key::MqttUser => "mqtt_user",
+            Key::MqttPassword => "mqtt_password",
+            Key::OtaTopic => "ota_topic",
+            Key::OtaSource => "ota_source",
+            Key::OtaChecks => "ota_checks",
+            Key::OtaWindow => "ota_window",
+            Key::ReadSensorInterval => "interval",
+            Key::ConfigUrl => "config_url",
+            Key::ConfigPullInterval => "config_interval",
+            Key::Measurement => "measurement",
+            Key::Tags => "tags",
+            Key::DeviceId => "device_id",
+            Key::TemperatureField => "temp_field",
+            Key::HumidityField => "humidity_field",
+            Key::TelemetryInterval => "telemetry_int",
+            Key::CoredumpUrl => "coredump_url",
+            Key::LogLevels => "log_levels",
+            Key::HeartbeatInterval => "heartbeat_int",
+            Key::DeltaTemp => "delta_temp",
+            Key::DeltaHumidity => "delta_humidity",
+            Key::Keepalive => "keepalive",
+            Key::SelfTest => "selftest",
+            Key::HttpRxBuffer => "http_rx_buf",
+            Key::HttpTxBuffer => "http_tx_buf",
+            Key::HttpTimeout => "http_timeout",
+            Key::AlertRules => "alert_rules",
+            Key::BuzzerPattern => "buzzer_pattern",
+            Key::RelayControl => "relay_control",
+            Key::RelayMaxOn => "relay_max_on",
+            Key::FanCurve => "fan_curve",
+            Key::NtfyUrl => "ntfy_url",
+            Key::NtfyToken => "ntfy_token",
+            Key::PushoverToken => "pushover_token",
+            Key::PushoverUser => "pushover_user",
+            Key::NotifyInterval => "notify_int",
+            Key::TelegramToken => "telegram_token",
+            Key::TelegramChat => "telegram_chat",
+            Key::QuietHours => "quiet_hours",
+            Key::Automation => "automation",
+            Key::Bthome => "bthome",
+            Key::BleBeacons => "ble_beacons",
+            Key::LoraRole => "lora_role",
+            Key::LoraFrequency => "lora_freq",
+            Key::EspnowRole => "espnow_role",
+            Key::EspnowChannel => "espnow_channel",
+            Key::Timezone => "timezone",
+            Key::DisplayClock => "display_clock",
+            Key::Location => "location",
+            Key::Pins => "pins",
+            Key::PirHold => "pir_hold",
+            Key::Contacts => "contacts",
+            Key::LeakThreshold => "leak_thresh",
+            Key::PulseInputs => "pulse_inputs",
+            Key::WindFactor => "wind_factor",
+            Key::RainTip => "rain_tip",
+            Key::GasSensor => "gas_sensor",
+            Key::GasLoad => "gas_load",
+            Key::GasSupply => "gas_supply",
+            Key::GasR0 => "gas_r0",
+            Key::GasWarmup => "gas_warmup",
+            Key::BenchRate => "bench_rate",
+            Key::BenchBatch => "bench_batch",
+            Key::BenchReport => "bench_report",
+            Key::SoakInterval => "soak_interval",
+            Key::SoakOutage => "soak_outage",
+            Key::HiveTare => "hive_tare",
+            Key::HiveTareTemp => "hive_tare_temp",
+            Key::HiveScale => "hive_scale",
+            Key::HiveTempco => "hive_tempco",
+            Key::AquariumAlerts => "aquarium_alerts",
+            Key::PhCalibration => "ph_cal",
+            Key::AquariumProbe => "aquarium_probe",
+            Key::TdsFactor => "tds_factor",
+            Key::CtAmps => "ct_amps",
+            Key::CtNoise => "ct_noise",
+            Key::MainsVoltage => "mains_volts",
+            Key::DustZero => "dust_zero",
+            Key::DustDivider => "dust_divider",
+            Key::NoiseWindow => "noise_window",
+            Key::MicSensitivity => "mic_sens",
+            Key::CoLevels => "co_levels",
+            Key::CoZero => "co_zero",
+            Key::CoSensitivity => "co_sens",
+            Key::CoRepeat => "co_repeat",
+            Key::FrostLead => "frost_lead",
+            Key::FrostTemp => "frost_temp",
+            Key::MoldHours => "mold_hours",
+            Key::MoldAlert => "mold_alert",
+            Key::HvacSensor => "hvac_sensor",
+            Key::HvacOffset => "hvac_offset",
+            Key::HvacFan => "hvac_fan",
+            Key::HvacClogged => "hvac_clogged",
+            Key::HvacFilter => "hvac_filter",
+            Key::RackSensors => "rack_sensors",
+            Key::RackZones => "rack_zones",
+            Key::RackDelta => "rack_delta",
+        }
+    }
+
+    /// Credentials, never shown by the console or remote interfaces.
+    pub fn is_secret(self) -> bool {
+        matches!(
+            self,
+            Key::Password
+                | Key::InfluxToken
+                | Key::OtlpAuth
+                | Key::GrafanaToken
+                | Key::NatsToken
+                | Key::MqttPassword
+                | Key::NtfyToken
+                | Key::PushoverToken
+                | Key::PushoverUser
+                | Key::TelegramToken
+        )
+    }
+
+    /// Wi-Fi and sink settings differ between profiles.
+    fn is_profile_scoped(self) -> bool {
+        matches!(
+            self,
+            Key::Ssid
+                | Key::Password
+                | Key::Addr
+                | Key::InfluxToken
+                | Key::InfluxOrg
+                | Key::InfluxBucket
+                | Key::OtlpUrl
+                | Key::OtlpAuth
+                | Key::GrafanaUrl
+                | Key::GrafanaToken
+                | Key::GrafanaStream
+                | Key::StatsdAddr
+                | Key::StatsdPrefix
+                | Key::KnxGateway
+                | Key::KnxTemperature
+                | Key::KnxHumidity
+                | Key::NatsUrl
+                | Key::NatsToken
+                | Key::NatsSubject
+                | Key::NatsAck
+                | Key::MqttUrl
+                | Key::MqttUser
+                | Key::MqttPassword
+        )
+    }
+
+    /// Numeric settings are stored as `u32` in NVS, the rest as strings.
+    fn is_u32(self) -> bool {
+        matches!(
+            self,
+            Key::ReadSensorInterval
+                | Key::ConfigPullInterval
+                | Key::TelemetryInterval
+                | Key::HeartbeatInterval
+                | Key::Keepalive
+                | Key::HttpRxBuffer
+                | Key::HttpTxBuffer
+                | Key::HttpTimeout
+                | Key::RelayMaxOn
+                | Key::NotifyInterval
+                | Key::LoraFrequency
+                | Key::EspnowChannel
+                | Key::DisplayClock
+                | Key::PirHold
+                | Key::LeakThreshold
+                | Key::WindFactor
+                | Key::RainTip
+                | Key::GasLoad
+                | Key::GasSupply
+                | Key::GasR0
+                | Key::GasWarmup
+                | Key::BenchRate
+                | Key::BenchBatch
+                | Key::BenchReport
+                | Key::SoakInterval
+                | Key::SoakOutage
+                | Key::TdsFactor
+                | Key::CtAmps
+                | Key::CtNoise
+                | Key::MainsVoltage
+                | Key::DustZero
+                | Key::DustDivider
+                | Key::NoiseWindow
+                | Key::CoZero
+                | Key::CoSensitivity
+                | Key::CoRepeat
+                | Key::FrostLead
+                | Key::MoldHours
+                | Key::MoldAlert
+                | Key::HvacFan
+                | Key::HvacClogged
+                | Key::HvacFilter
+                | Key::RackDelta
+                | Key::NatsAck
+                | Key::OtaWindow
+        )
+    }
+}
+
+impl Display for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Key {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match Key::ALL.into_iter().find(|k| k.name() == s) {
+            Some(key) => Ok(key),
+            None => bail!("unknown setting {:?}", s),
+        }
+    }
+}
+
+impl Settings {
+    fn apply(&mut self, key: Key, value: &str) -> error::Result<()> {
+        match key {
+            Key::Ssid => self.ssid = value.into(),
+            Key::Password => self.password = value.into(),
+            Key::Addr => self.addr = value.into(),
+            Key::InfluxToken => self.influx_token = value.into(),
+            Key::InfluxOrg => self.influx_org = value.into(),
+            Key::InfluxBucket => self.influx_bucket = value.into(),
+            Key::OtlpUrl => self.otlp_url = value.into(),
+            Key::OtlpAuth => self.otlp_auth = value.into(),
+            Key::GrafanaUrl => self.grafana_url = value.into(),
+            Key::GrafanaToken => self.grafana_token = value.into(),
+            Key::GrafanaStream => self.grafana_stream = value.into(),
+            Key::StatsdAddr => self.statsd_addr = value.into(),
+            Key::StatsdPrefix => self.statsd_prefix = value.into(),
+            Key::KnxGateway => self.knx_gateway = value.into(),
+            Key::KnxTemperature => {
+                parse_group(value)?;
+                self.knx_temperature = value.into();
+            }
+            Key::KnxHumidity => {
+                parse_group(value)?;
+                self.knx_humidity = value.into();
+            }
+            Key::NatsUrl => self.nats_url = value.into(),
+            Key::NatsToken => self.nats_token = value.into(),
+            Key::NatsSubject => {
+                nats::check_subject(value)?;
+                self.nats_subject = value.into();
+            }
+            Key::NatsAck => self.nats_ack_secs = parse_u32(key, value)?,
+            Key::MqttUrl => self.mqtt_url = value.into(),
+            Key::MqttUser => self.mqtt_user = value.into(),
+            Key::MqttPassword => self.mqtt_password = value.into(),
+            Key::OtaTopic => {
+                mqtt::check_topic(value)?;
+                self.ota_topic = value.into();
+            }
+            Key::OtaSource => {
+                rollout::check_source(value)?;
+                self.ota_source = value.into();
+            }
+            Key::OtaChecks => {
+                rollout::parse_checks(value)?;
+                self.ota_checks = value.into();
+            }
+            Key::OtaWindow => self.ota_window_secs = parse_secs(key, value)?,
+            Key::ReadSensorInterval => self.read_sensor_interval_secs = parse_secs(key, value)?,
+            Key::ConfigUrl => self.config_url = value.into(),
+            Key::ConfigPullInterval => self.config_pull_interval_secs = parse_secs(key, value)?,
+            Key::Measurement => {
+                if value.is_empty() {
+                    bail!("measurement must not be empty");
+                }
+                self.measurement = value.into();
+            }
+            Key::Tags => {
+                parse_tags(value)?;
+                self.tags = value.into();
+            }
+            Key::DeviceId => self.device_id = value.into(),
+            Key::TemperatureField => self.temperature_field = value.into(),
+            Key::HumidityField => self.humidity_field = value.into(),
+            Key::TelemetryInterval => self.telemetry_interval_secs = parse_secs(key, value)?,
+            Key::CoredumpUrl => self.coredump_url = value.into(),
+            Key::LogLevels => {
+                logging::parse(value)?;
+                self.log_levels = value.into();
+            }
+            Key::HeartbeatInterval => self.heartbeat_interval_secs = parse_secs(key, value)?,
+            Key::DeltaTemp => {
+                parse_f32(key, value)?;
+                self.delta_temp = value.into();
+            }
+            Key::DeltaHumidity => {
+                parse_f32(key, value)?;
+                self.delta_humidity = value.into();
+            }
+            Key::Keepalive => self.keepalive_secs = parse_secs(key, value)?,
+            Key::SelfTest => {
+                value.parse::<selftest::Mode>()?;
+                self.selftest = value.into();
+            }
+            Key::HttpRxBuffer => self.http_rx_buffer = parse_u32(key, value)?,
+            Key::HttpTxBuffer => self.http_tx_buffer = parse_u32(key, value)?,
+            Key::HttpTimeout => self.http_timeout_secs = parse_secs(key, value)?,
+            Key::AlertRules => {
+                alert::parse_rules(value)?;
+                self.alert_rules = value.into();
+            }
+            Key::BuzzerPattern => {
+                buzzer::parse_pattern(value)?;
+                self.buzzer_pattern = value.into();
+            }
+            Key::RelayControl => {
+                relay::parse_control(value)?;
+                self.relay_control = value.into();
+            }
+            Key::RelayMaxOn => self.relay_max_on_secs = parse_u32(key, value)?,
+            Key::FanCurve => {
+                fan::parse_curve(value)?;
+                self.fan_curve = value.into();
+            }
+            Key::NtfyUrl => self.ntfy_url = value.into(),
+            Key::NtfyToken => self.ntfy_token = value.into(),
+            Key::PushoverToken => self.pushover_token = value.into(),
+            Key::PushoverUser => self.pushover_user = value.into(),
+            Key::NotifyInterval => self.notify_interval_secs = parse_secs(key, value)?,
+            Key::TelegramToken => self.telegram_token = value.into(),
+            Key::TelegramChat => self.telegram_chat = value.into(),
+            Key::QuietHours => {
+                schedule::parse_quiet_hours(value)?;
+                self.quiet_hours = value.into();
+            }
+            Key::Automation => {
+                script::parse_rules(value)?;
+                self.automation = value.into();
+            }
+            Key::Bthome => {
+                value.parse::<bthome::Mode>()?;
+                self.bthome = value.into();
+            }
+            Key::BleBeacons => {
+                presence::parse_beacons(value)?;
+                self.ble_beacons = value.into();
+            }
+            Key::LoraRole => {
+                value.parse::<lora::Role>()?;
+                self.lora_role = value.into();
+            }
+            Key::LoraFrequency => self.lora_freq_hz = parse_u32(key, value)?,
+            Key::EspnowRole => {
+                value.parse::<lora::Role>()?;
+                self.espnow_role = value.into();
+            }
+            Key::EspnowChannel => {
+                let channel = parse_u32(key, value)?;
+                if !(1..=13).contains(&channel) {
+                    bail!("{} must be between 1 and 13", key);
+                }
+                self.espnow_channel = channel;
+            }
+            Key::Timezone => {
+                schedule::parse_timezone(value)?;
+                self.timezone = value.into();
+            }
+            Key::DisplayClock => self.display_clock_secs = parse_u32(key, value)?,
+            Key::Location => {
+                sun::parse_location(value)?;
+                self.location = value.into();
+            }
+            Key::Pins => {
+                board::BOARD.with_pins(value)?;
+                self.pins = value.into();
+            }
+            Key::PirHold => self.pir_hold_secs = parse_secs(key, value)?,
+            Key::Contacts => {
+                contacts::parse_contacts(value)?;
+                self.contacts = value.into();
+            }
+            Key::LeakThreshold => self.leak_threshold = parse_u32(key, value)?,
+            Key::PulseInputs => {
+                pulse::parse_inputs(value)?;
+                self.pulse_inputs = value.into();
+            }
+            Key::WindFactor => self.wind_factor = parse_u32(key, value)?,
+            Key::RainTip => self.rain_tip = parse_u32(key, value)?,
+            Key::GasSensor => {
+                value.parse::<gas::Model>()?;
+                self.gas_sensor = value.into();
+            }
+            Key::GasLoad => self.gas_load = parse_u32(key, value)?,
+            Key::GasSupply => self.gas_supply = parse_u32(key, value)?,
+            Key::GasR0 => self.gas_r0 = parse_u32(key, value)?,
+            Key::GasWarmup => self.gas_warmup_secs = parse_secs(key, value)?,
+            Key::BenchRate => self.bench_rate = parse_u32(key, value)?,
+            Key::BenchBatch => self.bench_batch = parse_u32(key, value)?,
+            Key::BenchReport => self.bench_report = parse_u32(key, value)?,
+            Key::SoakInterval => self.soak_interval_secs = parse_secs(key, value)?,
+            Key::SoakOutage => self.soak_outage_secs = parse_secs(key, value)?,
+            Key::HiveTare => {
+                value
+                    .parse::<i32>()
+                    .with_context(|| format!("parse {}", key))?;
+                self.hive_tare = value.into();
+            }
+            Key::HiveTareTemp => {
+                if !value.is_empty() {
+                    parse_f32(key, value)?;
+                }
+                self.hive_tare_temp = value.into();
+            }
+            Key::HiveScale => {
+                parse_f32(key, value)?;
+                self.hive_scale = value.into();
+            }
+            Key::HiveTempco => {
+                parse_f32(key, value)?;
+                self.hive_tempco = value.into();
+            }
+            Key::AquariumAlerts => {
+                alert::parse_rules(value)?;
+                self.aquarium_alerts = value.into();
+            }
+            Key::PhCalibration => {
+                aquarium::parse_calibration(value)?;
+                self.ph_calibration = value.into();
+            }
+            Key::AquariumProbe => {
+                if !value.trim().is_empty() {
+                    onewire::parse_rom(value)?;
+                }
+                self.aquarium_probe = value.into();
+            }
+            Key::TdsFactor => self.tds_factor = parse_u32(key, value)?,
+            Key::CtAmps => self.ct_amps = parse_u32(key, value)?,
+            Key::CtNoise => self.ct_noise_ma = parse_u32(key, value)?,
+            Key::MainsVoltage => self.mains_voltage = parse_u32(key, value)?,
+            Key::DustZero => self.dust_zero_mv = parse_u32(key, value)?,
+            Key::DustDivider => self.dust_divider = parse_u32(key, value)?,
+            Key::NoiseWindow => {
+                let ms = parse_u32(key, value)?;
+                if ms < noise::MIN_WINDOW_MS {
+                    bail!("{} must be at least {} ms", key, noise::MIN_WINDOW_MS);
+                }
+                self.noise_window_ms = ms;
+            }
+            Key::MicSensitivity => {
+                parse_f32(key, value)?;
+                self.mic_sensitivity = value.into();
+            }
+            Key::CoLevels => {
+                exposure::parse_levels(value)?;
+                self.co_levels = value.into();
+            }
+            Key::CoZero => self.co_zero_mv = parse_u32(key, value)?,
+            Key::CoSensitivity => self.co_uv_per_ppm = parse_u32(key, value)?,
+            Key::CoRepeat => self.co_repeat_secs = parse_secs(key, value)?,
+            Key::FrostLead => self.frost_lead_secs = parse_secs(key, value)?,
+            Key::FrostTemp => {
+                parse_f32(key, value)?;
+                self.frost_temp = value.into();
+            }
+            Key::MoldHours => self.mold_hours = parse_nonzero(key, value, "hour")?,
+            Key::MoldAlert => {
+                let risk = parse_u32(key, value)?;
+                if risk > 100 {
+                    bail!("{} must be at most 100", key);
+                }
+                self.mold_alert = risk;
+            }
+            Key::HvacSensor => {
+                value.parse::<hvac::Sensor>()?;
+                self.hvac_sensor = value.into();
+            }
+            Key::HvacOffset => {
+                parse_f32(key, value)?;
+                self.hvac_offset = value.into();
+            }
+            Key::HvacFan => self.hvac_fan_pa = parse_u32(key, value)?,
+            Key::HvacClogged => self.hvac_clogged_pa = parse_nonzero(key, value, "Pa")?,
+            Key::HvacFilter => self.hvac_filter_hours = parse_u32(key, value)?,
+            Key::RackSensors => {
+                layout::parse_sensors(value)?;
+                self.rack_sensors = value.into();
+            }
+            Key::RackZones => {
+                layout::parse_zones(value)?;
+                self.rack_zones = value.into();
+            }
+            Key::RackDelta => self.rack_delta = parse_u32(key, value)?,
+        }
+        Ok(())
+    }
+
+    fn get(&self, key: Key) -> String {
+        match key {
+            Key::Ssid => self.ssid.clone(),
+            Key::Password => self.password.expose().into(),
+            Key::Addr => self.addr.clone(),
+            Key::InfluxToken => self.influx_token.expose().into(),
+            Key::InfluxOrg => self.influx_org.clone(),
+            Key::InfluxBucket => self.influx_bucket.clone(),
+            Key::OtlpUrl => self.otlp_url.clone(),
+            Key::OtlpAuth => self.otlp_auth.expose().into(),
+            Key::GrafanaUrl => self.grafana_url.clone(),
+            Key::GrafanaToken => self.grafana_token.expose().into(),
+            Key::GrafanaStream => self.grafana_stream.clone(),
+            Key::StatsdAddr => self.statsd_addr.clone(),
+            Key::StatsdPrefix => self.statsd_prefix.clone(),
+            Key::KnxGateway => self.knx_gateway.clone(),
+            Key::KnxTemperature => self.knx_temperature.clone(),
+            Key::KnxHumidity => self.knx_humidity.clone(),
+            Key::NatsUrl => self.nats_url.clone(),
+            Key::NatsToken => self.nats_token.expose().into(),
+            Key::NatsSubject => self.nats_subject.clone(),
+            Key::NatsAck => self.nats_ack_secs.to_string(),
+            Key::MqttUrl => self.mqtt_url.clone(),
+            Key::MqttUser => self.mqtt_user.clone(),
+            Key::MqttPassword => self.mqtt_password.expose().into(),
+            Key::OtaTopic => self.ota_topic.clone(),
+            Key::OtaSource => self.ota_source.clone(),
+            Key::OtaChecks => self.ota_checks.clone(),
+            Key::OtaWindow => self.ota_window_secs.to_string(),
+            Key::ReadSensorInterval => self.read_sensor_interval_secs.to_string(),
+            Key::ConfigUrl => self.config_url.clone(),
+            Key::ConfigPullInterval => self.config_pull_interval_secs.to_string(),
+            Key::Measurement => self.measurement.clone(),
+            Key::Tags => self.tags.clone(),
+            Key::DeviceId => self.device_id.clone(),
+            Key::TemperatureField => self.temperature_field.clone(),
+            Key::HumidityField => self.humidity_field.clone(),
+            Key::TelemetryInterval => self.telemetry_interval_secs.to_string(),
+            Key::CoredumpUrl => self.coredump_url.clone(),
+            Key::LogLevels => self.log_levels.clone(),
+            Key::HeartbeatInterval => self.heartbeat_interval_secs.to_string(),
+            Key::DeltaTemp => self.delta_temp.clone(),
+            Key::DeltaHumidity => self.delta_humidity.clone(),
+            Key::Keepalive => self.keepalive_secs.to_string(),
+            Key::SelfTest => self.selftest.clone(),
+            Key::HttpRxBuffer => self.http_rx_buffer.to_string(),
+            Key::HttpTxBuffer => self.http_tx_buffer.to_string(),
+            Key::HttpTimeout => self.http_timeout_secs.to_string(),
+            Key::AlertRules => self.alert_rules.clone(),
+            Key::BuzzerPattern => self.buzzer_pattern.clone(),
+            Key::RelayControl => self.relay_control.clone(),
+            Key::RelayMaxOn => self.relay_max_on_secs.to_string(),
+            Key::FanCurve => self.fan_curve.clone(),
+            Key::NtfyUrl => self.ntfy_url.clone(),
+            Key::NtfyToken => self.ntfy_token.expose().into(),
+            Key::PushoverToken => self.pushover_token.expose().into(),
+            Key::PushoverUser => self.pushover_user.expose().into(),
+            Key::NotifyInterval => self.notify_interval_secs.to_string(),
+            Key::TelegramToken => self.telegram_token.expose().into(),
+            Key::TelegramChat => self.telegram_chat.clone(),
+            Key::QuietHours => self.quiet_hours.clone(),
+            Key::Automation => self.automation.clone(),
+            Key::Bthome => self.bthome.clone(),
+            Key::BleBeacons => self.ble_beacons.clone(),
+            Key::LoraRole => self.lora_role.clone(),
+            Key::LoraFrequency => self.lora_freq_hz.to_string(),
+            Key::EspnowRole => self.espnow_role.clone(),
+            Key::EspnowChannel => self.espnow_channel.to_string(),
+            Key::Timezone => self.timezone.clone(),
+            Key::DisplayClock => self.display_clock_secs.to_string(),
+            Key::Location => self.location.clone(),
+            Key::Pins => self.pins.clone(),
+            Key::PirHold => self.pir_hold_secs.to_string(),
+            Key::Contacts => self.contacts.clone(),
+            Key::LeakThreshold => self.leak_threshold.to_string(),
+            Key::PulseInputs => self.pulse_inputs.clone(),
+            Key::WindFactor => self.wind_factor.to_string(),
+            Key::RainTip => self.rain_tip.to_string(),
+            Key::GasSensor => self.gas_sensor.clone(),
+            Key::GasLoad => self.gas_load.to_string(),
+            Key::GasSupply => self.gas_supply.to_string(),
+            Key::GasR0 => self.gas_r0.to_string(),
+            Key::GasWarmup => self.gas_warmup_secs.to_string(),
+            Key::BenchRate => self.bench_rate.to_string(),
+            Key::BenchBatch => self.bench_batch.to_string(),
+            Key::BenchReport => self.bench_report.to_string(),
+            Key::SoakInterval => self.soak_interval_secs.to_string(),
+            Key::SoakOutage => self.soak_outage_secs.to_string(),
+            Key::HiveTare => self.hive_tare.clone(),
+            Key::HiveTareTemp => self.hive_tare_temp.clone(),
+            Key::HiveScale => self.hive_scale.clone(),
+            Key::HiveTempco => self.hive_tempco.clone(),
+            Key::AquariumAlerts => self.aquarium_alerts.clone(),
+            Key::PhCalibration => self.ph_calibration.clone(),
+            Key::AquariumProbe => self.aquarium_probe.clone(),
+            Key::TdsFactor => self.tds_factor.to_string(),
+            Key::CtAmps => self.ct_amps.to_string(),
+            Key::CtNoise => self.ct_noise_ma.to_string(),
+            Key::MainsVoltage => self.mains_voltage.to_string(),
+            Key::DustZero => self.dust_zero_mv.to_string(),
+            Key::DustDivider => self.dust_divider.to_string(),
+            Key::NoiseWindow => self.noise_window_ms.to_string(),
+            Key::MicSensitivity => self.mic_sensitivity.clone(),
+            Key::CoLevels => self.co_levels.clone(),
+            Key::CoZero => self.co_zero_mv.to_string(),
+            Key::CoSensitivity => self.co_uv_per_ppm.to_string(),
+            Key::CoRepeat => self.co_repeat_secs.to_string(),
+            Key::FrostLead => self.frost_lead_secs.to_string(),
+            Key::FrostTemp => self.frost_temp.clone(),
+            Key::MoldHours => self.mold_hours.to_string(),
+            Key::MoldAlert => self.mold_alert.to_string(),
+            Key::HvacSensor => self.hvac_sensor.clone(),
+            Key::HvacOffset => self.hvac_offset.clone(),
+            Key::HvacFan => self.hvac_fan_pa.to_string(),
+            Key::HvacClogged => self.hvac_clogged_pa.to_string(),
+            Key::HvacFilter => self.hvac_filter_hours.to_string(),
+            Key::RackSensors => self.rack_sensors.clone(),
+            Key::RackZones => self.rack_zones.clone(),
+            Key::RackDelta => self.rack_delta.to_string(),
+        }
+    }
+
+    /// Configured device id or one derived from the factory MAC address.
+    pub fn device_id(&self) -> String {
+        if self.device_id.is_empty() {
+            device::mac_id()
+        } else {
+            self.device_id.clone()
+        }
+    }
+
+    /// Static tags attached to every point, including the device id.
+    pub fn tags(&self) -> Vec<(String, String)> {
+        let mut tags = parse_tags(&self.tags).unwrap_or_default();
+        tags.push(("device".into(), self.device_id()));
+        tags
+    }
+
+    pub fn delta(&self) -> deadband::Delta {
+        deadband::Delta {
+            temperature: self.delta_temp.parse().unwrap_or(0.),
+            humidity: self.delta_humidity.parse().unwrap_or(0.),
+        }
+    }
+}
+
+fn parse_tags(value: &str) -> error::Result<Vec<(String, String)>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((k, v)) if !k.trim().is_empty() && !v.trim().is_empty() => {
+                Ok((k.trim().into(), v.trim().into()))
+            }
+            _ => bail!("tag {:?} is not a key=value pair", pair),
+        })
+        .collect()
+}
+
+fn parse_u32(key: Key, value: &str) -> error::Result<u32> {
+    value.parse().with_context(|| format!("parse {}", key))
+}
+
+fn parse_f32(key: Key, value: &str) -> error::Result<f32> {
+    value.parse().with_context(|| format!("parse {}", key))
+}
+
+fn parse_secs(key: Key, value: &str) -> error::Result<u32> {
+    parse_nonzero(key, value, "second")
+}
+
+/// A count of `unit`, at least one.
+fn parse_nonzero(key: Key, value: &str, unit: &str) -> error::Result<u32> {
+    let count = parse_u32(key, value)?;
+    if count == 0 {
+        bail!("{} must be at least 1 {}", key, unit);
+    }
+    Ok(count)
+}
+
+/// Checks a KNX group address, empty disables it.
+fn parse_group(value: &str) -> error::Result<()> {
+    if !value.is_empty() {
+        knxnet::parse_group(value)?;
+    }
+    Ok(())
+}
+
+/// Settings shared between tasks and persisted in NVS.
+///
+/// Wi-Fi and sink settings belong to the active profile and live in a
+/// per-profile namespace, everything else is shared by all profiles.
+pub struct Store {
+    partition: EspDefaultNvsPartition,
+    nvs: Mutex<Namespaces>,
+    current: RwLock<Settings>,
+    revision: AtomicU32,
+}
+
+struct Namespaces {
+    global: EspNvs<NvsDefault>,
+    profile: EspNvs<NvsDefault>,
+    profile_name: String,
+}
+
+impl Namespaces {
+    fn for_key(&mut self, key: Key) -> &mut EspNvs<NvsDefault> {
+        if key.is_profile_scoped() {
+            &mut self.profile
+        } else {
+            &mut self.global
+        }
+    }
+}
+
+impl Store {
+    pub fn load(partition: EspDefaultNvsPartition) -> error::Result<Self> {
+        let global = EspNvs::new(partition.clone(), NAMESPACE, true)
+            .context("open settings namespace")
+            .code(Code::Storage)?;
+
+        let mut buf = [0u8; MAX_STR_LEN];
+        let profile_name = global
+            .get_str(PROFILE_KEY, &mut buf)?
+            .unwrap_or(DEFAULT_PROFILE)
+            .to_string();
+        let profile = open_profile(&partition, &profile_name)?;
+        log::info!("settings: using profile {}", profile_name);
+
+        let namespaces = Namespaces {
+            global,
+            profile,
+            profile_name,
+        };
+        let settings = read(&namespaces)?;
+
+        Ok(Self {
+            partition,
+            nvs: Mutex::new(namespaces),
+            current: RwLock::new(settings),
+            revision: AtomicU32::new(0),
+        })
+    }
+
+    /// Returns a snapshot of the current settings.
+    pub fn get(&self) -> Settings {
+        self.current.read().unwrap().clone()
+    }
+
+    pub fn value(&self, key: Key) -> String {
+        self.current.read().unwrap().get(key)
+    }
+
+    /// Incremented on every change, lets tasks notice they hold stale settings.
+    pub fn revision(&self) -> u32 {
+        self.revision.load(Ordering::Acquire)
+    }
+
+    /// Validates, persists and applies a new value.
+    pub fn set(&self, key: Key, value: &str) -> error::Result<()> {
+        self.update(&[(key, value)])
+    }
+
+    /// Validates all changes first and only then persists and applies them,
+    /// so an invalid entry leaves the settings untouched.
+    pub fn update(&self, changes: &[(Key, &str)]) -> error::Result<()> {
+        let mut nvs = self.nvs.lock().unwrap();
+        let mut updated = self.get();
+        for &(key, value) in changes {
+            if value.len() >= MAX_STR_LEN {
+                bail!(Code::Config, "value for {} is too long", key);
+            }
+            updated.apply(key, value).code(Code::Config)?;
+        }
+
+        for &(key, value) in changes {
+            let ns = nvs.for_key(key);
+            if key.is_u32() {
+                ns.set_u32(key.name(), value.parse()?).code(Code::Storage)?;
+            } else {
+                ns.set_str(key.name(), value).code(Code::Storage)?;
+            }
+            log::info!("settings: updated {}", key);
+        }
+        if changes.iter().any(|(key, _)| *key == Key::LogLevels) {
+            logging::apply(&updated.log_levels);
+        }
+        if changes.iter().any(|(key, _)| *key == Key::Timezone) {
+            schedule::apply_timezone(&updated.timezone);
+        }
+        *self.current.write().unwrap() = updated;
+        self.revision.fetch_add(1, Ordering::AcqRel);
+
+        Ok(())
+    }
+
+    /// Removes the stored value so the compiled default is used again.
+    pub fn reset(&self, key: Key) -> error::Result<()> {
+        self.nvs.lock().unwrap().for_key(key).remove(key.name())?;
+
+        let default = Settings::default().get(key);
+        self.current.write().unwrap().apply(key, &default)?;
+        self.revision.fetch_add(1, Ordering::AcqRel);
+        if key == Key::LogLevels {
+            logging::apply(&default);
+        }
+        if key == Key::Timezone {
+            schedule::apply_timezone(&default);
+        }
+
+        log::info!("settings: reset {} to default", key);
+        Ok(())
+    }
+
+    pub fn profile(&self) -> String {
+        self.nvs.lock().unwrap().profile_name.clone()
+    }
+
+    /// Known profile names, the default profile is always first.
+    pub fn profiles(&self) -> error::Result<Vec<String>> {
+        let nvs = self.nvs.lock().unwrap();
+        let mut buf = [0u8; MAX_STR_LEN];
+        let stored = nvs.global.get_str(PROFILES_KEY, &mut buf)?.unwrap_or("");
+
+        let mut profiles = vec![DEFAULT_PROFILE.to_string()];
+        profiles.extend(
+            stored
+                .split(',')
+                .filter(|p| !p.is_empty() && *p != DEFAULT_PROFILE)
+                .map(String::from),
+        );
+        Ok(profiles)
+    }
+
+    /// Activates a profile, creating it if it does not exist yet. New profiles
+    /// start from the compiled defaults.
+    pub fn use_profile(&self, name: &str) -> error::Result<()> {
+        if name.is_empty()
+            || name.len() > MAX_PROFILE_LEN
+            || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            bail!(
+                "profile name must be 1..={} ascii letters, digits or '_'",
+                MAX_PROFILE_LEN
+            );
+        }
+
+        let mut profiles = self.profiles()?;
+        let mut nvs = self.nvs.lock().unwrap();
+        let profile = open_profile(&self.partition, name)?;
+
+        if !profiles.iter().any(|p| p == name) {
+            profiles.push(name.into());
+            nvs.global.set_str(PROFILES_KEY, &profiles[1..].join(","))?;
+        }
+        nvs.global.set_str(PROFILE_KEY, name)?;
+        nvs.profile = profile;
+        nvs.profile_name = name.into();
+
+        *self.current.write().unwrap() = read(&nvs)?;
+        self.revision.fetch_add(1, Ordering::AcqRel);
+
+        log::info!("settings: switched to profile {}", name);
+        Ok(())
+    }
+
+    /// Activates the profile after the current one, wrapping around.
+    pub fn next_profile(&self) -> error::Result<()> {
+        let profiles = self.profiles()?;
+        let current = self.profile();
+        let index = profiles.iter().position(|p| *p == current).unwrap_or(0);
+        let next = &profiles[(index + 1) % profiles.len()];
+        self.use_profile(next)
+    }
+}
+
+fn open_profile(
+    partition: &EspDefaultNvsPartition,
+    name: &str,
+) -> error::Result<EspNvs<NvsDefault>> {
+    let namespace = if name == DEFAULT_PROFILE {
+        NAMESPACE.to_string()
+    } else {
+        format!("p_{}", name)
+    };
+
+    EspNvs::new(partition.clone(), &namespace, true)
+        .with_context(|| format!("open profile namespace {}", namespace))
+}
+
+fn read(nvs: &Namespaces) -> error::Result<Settings> {
+    let mut settings = Settings::default();
+    let mut buf = [0u8; MAX_STR_LEN];
+    for key in Key::ALL {
+        let ns = if key.is_profile_scoped() {
+            &nvs.profile
+        } else {
+            &nvs.global
+        };
+        let stored = if key.is_u32() {
+            ns.get_u32(key.name())?.map(|v| v.to_string())
+        } else {
+            ns.get_str(key.name(), &mut buf)?.map(String::from)
+        };
+
+        if let Some(value) = stored {
+            log::info!("settings: loaded {} from nvs", key);
+            if let Err(err) = settings.apply(key, &value) {
+                log::error!("settings: ignoring stored {} error={:?}", key, err);
+            }
+        }
+    }
+
+    Ok(settings)
+}
+
+/// Warns when secrets end up in plain text: compiled into the image or
+/// stored in an NVS partition without encryption.
+pub fn check_secrets_storage() {
+    let defaults = Settings::default();
+    if Key::ALL
+        .into_iter()
+        .any(|key| key.is_secret() && !defaults.get(key).is_empty())
+    {
+        log::warn!("settings: secrets are compiled into the firmware image, provision them into nvs instead");
+    }
+    if cfg!(not(esp_idf_nvs_encryption)) {
+        log::warn!("settings: nvs encryption is disabled, secrets are stored in plain text");
+    }
+}
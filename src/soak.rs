@@ -0,0 +1,151 @@
+use std::{
+    fmt::Display,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::{self, bail},
+    point::Point,
+    settings::Settings,
+};
+
+/// End of the simulated server outage, checked before every write.
+static OUTAGE_UNTIL: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// Fails writes during a simulated server outage.
+pub fn check_outage() -> error::Result<()> {
+    if matches!(*OUTAGE_UNTIL.lock().unwrap(), Some(until) if Instant::now() < until) {
+        bail!("soak: simulated server outage");
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Disruption {
+    /// The sender drops Wi-Fi and goes through its reconnect.
+    WifiDrop,
+    /// Every write fails for `soak_outage` seconds.
+    ServerOutage,
+}
+
+impl Disruption {
+    fn name(self) -> &'static str {
+        match self {
+            Disruption::WifiDrop => "wifi_drop",
+            Disruption::ServerOutage => "server_outage",
+        }
+    }
+}
+
+impl Display for Disruption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A disruption waiting for the first successful write.
+struct Ongoing {
+    disruption: Disruption,
+    started: Instant,
+    /// Readings published and written when it started.
+    published: u64,
+    written: u64,
+}
+
+/// Disrupts the sender every `soak_interval` seconds, alternating between
+/// dropping Wi-Fi and a server outage, and measures how long the first
+/// successful write takes afterwards and how many readings never made it.
+///
+/// Every recovery is logged and sent as a `soak` point with the
+/// `disruption` tag and `recovery_ms`, `lost` and `cycle` fields.
+#[derive(Default)]
+pub struct Soak {
+    next_at: Option<Instant>,
+    next: Option<Disruption>,
+    ongoing: Option<Ongoing>,
+    /// Readings whose point was written.
+    written: u64,
+    cycles: u32,
+    lost_total: u64,
+    recovery_max: Duration,
+    pending: Option<(Disruption, Duration, u64)>,
+}
+
+impl Soak {
+    /// Starts the disruption that is due, given the number of readings
+    /// published so far.
+    pub fn due(&mut self, settings: &Settings, published: u64) -> Option<Disruption> {
+        if settings.soak_interval_secs == 0 {
+            self.next_at = None;
+            return None;
+        }
+        let interval = Duration::from_secs(u64::from(settings.soak_interval_secs));
+        let next_at = *self
+            .next_at
+            .get_or_insert_with(|| Instant::now() + interval);
+        if self.ongoing.is_some() || Instant::now() < next_at {
+            return None;
+        }
+
+        let disruption = self.next.unwrap_or(Disruption::WifiDrop);
+        self.next = Some(match disruption {
+            Disruption::WifiDrop => Disruption::ServerOutage,
+            Disruption::ServerOutage => Disruption::WifiDrop,
+        });
+        self.next_at = Some(Instant::now() + interval);
+        self.ongoing = Some(Ongoing {
+            disruption,
+            started: Instant::now(),
+            published,
+            written: self.written,
+        });
+        if disruption == Disruption::ServerOutage {
+            let outage = Duration::from_secs(u64::from(settings.soak_outage_secs));
+            *OUTAGE_UNTIL.lock().unwrap() = Some(Instant::now() + outage);
+        }
+        log::warn!("soak: starting {}", disruption);
+        Some(disruption)
+    }
+
+    /// Records a write and whether it carried a reading. The first
+    /// successful one ends an ongoing disruption.
+    pub fn written(&mut self, status: u16, reading: bool, published: u64) {
+        if !(200..300).contains(&status) {
+            return;
+        }
+        self.written += u64::from(reading);
+        let Some(ongoing) = self.ongoing.take() else {
+            return;
+        };
+
+        let recovery = ongoing.started.elapsed();
+        let lost = (published - ongoing.published).saturating_sub(self.written - ongoing.written);
+        self.cycles += 1;
+        self.lost_total += lost;
+        self.recovery_max = self.recovery_max.max(recovery);
+        log::warn!(
+            "soak: {} recovered in {:.1}s lost={} cycle={} lost_total={} slowest={:.1}s",
+            ongoing.disruption,
+            recovery.as_secs_f32(),
+            lost,
+            self.cycles,
+            self.lost_total,
+            self.recovery_max.as_secs_f32()
+        );
+        self.pending = Some((ongoing.disruption, recovery, lost));
+    }
+
+    /// The point of the last recovery, once.
+    pub fn take_point(&mut self, tags: &[(String, String)]) -> Option<Point> {
+        let (disruption, recovery, lost) = self.pending.take()?;
+        Some(
+            Point::new("soak")
+                .tag("disruption", disruption.name())
+                .tags(tags)
+                .field("recovery_ms", recovery.as_millis() as u64)
+                .field("lost", lost)
+                .field("cycle", self.cycles),
+        )
+    }
+}
@@ -0,0 +1,108 @@
+//! The latest reading and the values derived from it, for the tasks that
+//! only ever show or check the current value: the display, the HTTP server,
+//! BTHome, the alert rules, Modbus, KNX and the console. Unlike a broadcast
+//! receiver a slow reader cannot lag, it just sees the newest reading.
+
+use std::{
+    sync::{Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{forecast, reading::SensorData};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Snapshot {
+    pub data: SensorData,
+    pub dew_point: f32,
+    /// Counts readings since boot, tells a new snapshot from a seen one.
+    pub sequence: u32,
+    pub at: Instant,
+}
+
+#[derive(Default)]
+pub struct State {
+    latest: Mutex<Option<Snapshot>>,
+    changed: Condvar,
+}
+
+impl State {
+    /// Replaces the latest reading and wakes the waiting readers.
+    pub fn update(&self, data: SensorData) {
+        let mut latest = self.latest.lock().unwrap();
+        let sequence = latest.map_or(0, |s| s.sequence.wrapping_add(1));
+        *latest = Some(Snapshot {
+            data,
+            dew_point: forecast::dew_point(data.temperature, data.humidity),
+            sequence,
+            at: Instant::now(),
+        });
+        self.changed.notify_all();
+    }
+
+    /// The latest snapshot, `None` before the first reading.
+    pub fn latest(&self) -> Option<Snapshot> {
+        *self.latest.lock().unwrap()
+    }
+
+    /// Waits at most `timeout` for a snapshot other than `seen`, the
+    /// sequence of the last one the caller handled, and returns it.
+    pub fn wait_newer(&self, seen: Option<u32>, timeout: Duration) -> Option<Snapshot> {
+        let latest = self.latest.lock().unwrap();
+        let (latest, _) = self
+            .changed
+            .wait_timeout_while(latest, timeout, |latest| latest.map(|s| s.sequence) == seen)
+            .unwrap();
+        latest.filter(|s| Some(s.sequence) != seen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use super::*;
+
+    fn reading(temperature: f32) -> SensorData {
+        SensorData {
+            temperature,
+            humidity: 50.,
+        }
+    }
+
+    #[test]
+    fn keeps_the_latest_reading() {
+        let state = State::default();
+        assert!(state.latest().is_none());
+        for temperature in [1., 2., 3.] {
+            state.update(reading(temperature));
+        }
+        let latest = state.latest().unwrap();
+        assert_eq!(latest.data.temperature, 3.);
+        assert_eq!(latest.sequence, 2);
+        assert!((latest.dew_point - forecast::dew_point(3., 50.)).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn waits_for_a_newer_reading() {
+        let state = Arc::new(State::default());
+        assert!(state.wait_newer(None, Duration::ZERO).is_none());
+        state.update(reading(20.));
+        let first = state.wait_newer(None, Duration::ZERO).unwrap();
+        assert!(state
+            .wait_newer(Some(first.sequence), Duration::from_millis(10))
+            .is_none());
+
+        let writer = {
+            let state = state.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                state.update(reading(21.));
+            })
+        };
+        let next = state
+            .wait_newer(Some(first.sequence), Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(next.data.temperature, 21.);
+        writer.join().unwrap();
+    }
+}
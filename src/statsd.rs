@@ -0,0 +1,109 @@
+//! StatsD encoding of [`Point`]s as DogStatsD gauges, which the Datadog
+//! agent and the Prometheus statsd-exporter both accept.
+
+use crate::point::{Point, Value};
+
+/// Largest datagram, stays within the Ethernet MTU without fragments.
+pub const MAX_PACKET: usize = 1432;
+
+/// Encodes every numeric or boolean field as a gauge named
+/// `prefix.measurement.field` with the tags as `key:value` DogStatsD tags,
+/// packed one per line into datagrams of at most `max_packet` bytes. String
+/// fields and values that are not finite are skipped.
+pub fn encode(points: &[Point], prefix: &str, max_packet: usize) -> Vec<Vec<u8>> {
+    let mut packets = Vec::new();
+    let mut packet = Vec::new();
+    for point in points {
+        let tags: Vec<_> = point
+            .tags
+            .iter()
+            .map(|(key, value)| format!("{}:{}", sanitize(key), sanitize(value)))
+            .collect();
+        for (key, value) in &point.fields {
+            let value = match value {
+                Value::Float(v) if v.is_finite() => v.to_string(),
+                Value::Integer(v) => v.to_string(),
+                Value::UInteger(v) => v.to_string(),
+                Value::Bool(v) => u8::from(*v).to_string(),
+                Value::Float(_) | Value::String(_) => continue,
+            };
+            let mut name = format!("{}.{}", point.measurement, key);
+            if !prefix.is_empty() {
+                name = format!("{}.{}", prefix, name);
+            }
+            let mut line = format!("{}:{}|g", sanitize(&name), value);
+            if !tags.is_empty() {
+                line.push_str("|#");
+                line.push_str(&tags.join(","));
+            }
+
+            if !packet.is_empty() && packet.len() + 1 + line.len() > max_packet {
+                packets.push(std::mem::take(&mut packet));
+            }
+            if !packet.is_empty() {
+                packet.push(b'\n');
+            }
+            packet.extend_from_slice(line.as_bytes());
+        }
+    }
+    if !packet.is_empty() {
+        packets.push(packet);
+    }
+    packets
+}
+
+/// Replaces the separators of the format and whitespace with `_`.
+fn sanitize(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            ':' | '|' | '@' | ',' | '#' => '_',
+            c if c.is_whitespace() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_gauges_with_tags() {
+        let points = [Point::new("dht")
+            .tag("device", "kitchen")
+            .tag("room", "living room")
+            .field("temperature", 21.5f32)
+            .field("errors", 3i64)
+            .field("relay", true)
+            .field("note", "skipped")
+            .field("broken", f64::NAN)];
+        let packets = encode(&points, "home", MAX_PACKET);
+        assert_eq!(
+            String::from_utf8(packets.concat()).unwrap(),
+            "home.dht.temperature:21.5|g|#device:kitchen,room:living_room\n\
+             home.dht.errors:3|g|#device:kitchen,room:living_room\n\
+             home.dht.relay:1|g|#device:kitchen,room:living_room"
+        );
+    }
+
+    #[test]
+    fn omits_an_empty_prefix_and_tags() {
+        let points = [Point::new("rack|1").field("temperature", 22.)];
+        assert_eq!(
+            encode(&points, "", MAX_PACKET),
+            [b"rack_1.temperature:22|g"]
+        );
+    }
+
+    #[test]
+    fn splits_packets_at_whole_lines() {
+        let points: Vec<_> = (0..10)
+            .map(|i| Point::new("m").field(format!("f{}", i), i as i64))
+            .collect();
+        // Each line is `m.fN:N|g`, 8 bytes and a newline.
+        let packets = encode(&points, "", 30);
+        assert_eq!(packets.len(), 4);
+        assert_eq!(packets[0], b"m.f0:0|g\nm.f1:1|g\nm.f2:2|g");
+        assert!(packets.iter().all(|p| p.len() <= 30));
+    }
+}
@@ -0,0 +1,108 @@
+use std::{f64::consts::PI, str::FromStr};
+
+use crate::error::{self, bail, Context};
+use crate::schedule;
+
+/// Zenith of sunrise and sunset, including refraction and the solar disc.
+const ZENITH_DEG: f64 = 90.833;
+const MINUTES_PER_DAY: f64 = 1440.;
+
+/// Position on earth in degrees, written as `latitude,longitude`, e.g.
+/// `50.45,30.52`. East and north are positive.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Location {
+    latitude: f64,
+    longitude: f64,
+}
+
+impl FromStr for Location {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((latitude, longitude)) = s.split_once(',') else {
+            bail!("location {:?} is not latitude,longitude", s);
+        };
+        let latitude: f64 = latitude.trim().parse().context("parse latitude")?;
+        let longitude: f64 = longitude.trim().parse().context("parse longitude")?;
+        if !(-90. ..=90.).contains(&latitude) || !(-180. ..=180.).contains(&longitude) {
+            bail!("location {:?} is out of range", s);
+        }
+        Ok(Location {
+            latitude,
+            longitude,
+        })
+    }
+}
+
+/// Parses the `location` setting, empty means unknown.
+pub fn parse_location(spec: &str) -> error::Result<Option<Location>> {
+    if spec.trim().is_empty() {
+        return Ok(None);
+    }
+    spec.parse().map(Some)
+}
+
+/// Daylight on one day.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Daylight {
+    /// Polar day.
+    Always,
+    /// Polar night.
+    Never,
+    /// Sunrise and sunset in minutes since UTC midnight, wrapped into a day.
+    Between(f64, f64),
+}
+
+impl Location {
+    /// Sunrise and sunset after the NOAA approximation, good to about a
+    /// minute away from the poles.
+    pub fn daylight(&self, day_of_year: u32) -> Daylight {
+        let gamma = 2. * PI / 365. * f64::from(day_of_year);
+        let eqtime = 229.18
+            * (0.000075 + 0.001868 * gamma.cos()
+                - 0.032077 * gamma.sin()
+                - 0.014615 * (2. * gamma).cos()
+                - 0.040849 * (2. * gamma).sin());
+        let declination = 0.006918 - 0.399912 * gamma.cos() + 0.070257 * gamma.sin()
+            - 0.006758 * (2. * gamma).cos()
+            + 0.000907 * (2. * gamma).sin()
+            - 0.002697 * (3. * gamma).cos()
+            + 0.00148 * (3. * gamma).sin();
+
+        let latitude = self.latitude.to_radians();
+        let cos_hour_angle = ZENITH_DEG.to_radians().cos() / (latitude.cos() * declination.cos())
+            - latitude.tan() * declination.tan();
+        if cos_hour_angle > 1. {
+            return Daylight::Never;
+        }
+        if cos_hour_angle < -1. {
+            return Daylight::Always;
+        }
+
+        let hour_angle = cos_hour_angle.acos().to_degrees();
+        let sunrise = 720. - 4. * (self.longitude + hour_angle) - eqtime;
+        let sunset = 720. - 4. * (self.longitude - hour_angle) - eqtime;
+        Daylight::Between(
+            sunrise.rem_euclid(MINUTES_PER_DAY),
+            sunset.rem_euclid(MINUTES_PER_DAY),
+        )
+    }
+
+    /// Whether the sun is down now, `None` while the clock is not set.
+    pub fn is_dark(&self) -> Option<bool> {
+        let now = schedule::unix_time()?.as_secs() as esp_idf_sys::time_t;
+        let mut tm: esp_idf_sys::tm = unsafe { std::mem::zeroed() };
+        unsafe { esp_idf_sys::gmtime_r(&now, &mut tm) };
+        let minutes = f64::from(tm.tm_hour * 60 + tm.tm_min);
+
+        Some(match self.daylight(tm.tm_yday as u32) {
+            Daylight::Always => false,
+            Daylight::Never => true,
+            // Away from Greenwich the day may wrap past UTC midnight.
+            Daylight::Between(sunrise, sunset) if sunrise <= sunset => {
+                !(sunrise..sunset).contains(&minutes)
+            }
+            Daylight::Between(sunrise, sunset) => (sunset..sunrise).contains(&minutes),
+        })
+    }
+}
@@ -0,0 +1,179 @@
+use std::time::Duration;
+
+use embedded_svc::{
+    http::{client::Client, Method},
+    io::{Read, Write},
+    utils::io,
+};
+use esp_idf_svc::http::client::{Configuration as HttpConfiguration, EspHttpConnection};
+
+use crate::{
+    alert::Alerts,
+    broadcast,
+    error::{self, bail, Context},
+    health,
+    settings::Store,
+    watchdog::Watchdog,
+    SensorData,
+};
+
+const API_URL: &str = "https://api.telegram.org";
+/// Seconds Telegram holds a `getUpdates` request open waiting for messages.
+/// Has to stay well below the task watchdog timeout.
+const LONG_POLL_SECS: u32 = 25;
+/// Updates are fetched one at a time so they fit this buffer.
+const MAX_RESPONSE_LEN: usize = 4096;
+
+/// Sends `text` to `chat_id`.
+pub fn send_message(token: &str, chat_id: &str, text: &str) -> error::Result<()> {
+    let url = format!("{}/bot{}/sendMessage", API_URL, token);
+    let body = serde_json::json!({ "chat_id": chat_id, "text": text }).to_string();
+    let headers = [("content-type", "application/json")];
+    request(Method::Post, &url, &headers, body.as_bytes(), 15)?;
+    Ok(())
+}
+
+/// Answers `/status` in the configured chat with the latest reading and the
+/// active alerts, long polling for messages.
+pub fn run(mut sub: broadcast::Receiver<SensorData>, store: &Store, alerts: &Alerts) {
+    let watchdog = Watchdog::subscribe("telegram");
+    let health = health::register("telegram");
+    let mut latest = None;
+    let mut offset: Option<i64> = None;
+
+    loop {
+        health.tick();
+        watchdog.feed();
+        while let Ok(data) = sub.try_recv() {
+            latest = Some(data);
+        }
+
+        let settings = store.get();
+        let token = settings.telegram_token.expose();
+        if token.is_empty() || settings.telegram_chat.is_empty() {
+            watchdog.sleep(Duration::from_secs(60));
+            continue;
+        }
+
+        let result = poll(token, &mut offset).and_then(|commands| {
+            for (chat, text) in commands {
+                if chat != settings.telegram_chat {
+                    log::warn!("telegram: ignoring message from chat {}", chat);
+                    continue;
+                }
+                if text.split_whitespace().next() == Some("/status") {
+                    let reply = status(&settings.device_id(), latest, &alerts.active());
+                    send_message(token, &chat, &reply)?;
+                }
+            }
+            Ok(())
+        });
+        if let Err(err) = result {
+            // Also the case while Wi-Fi is down.
+            log::debug!("telegram: polling error={:?}", err);
+            watchdog.sleep(Duration::from_secs(30));
+        }
+    }
+}
+
+fn status(device_id: &str, latest: Option<SensorData>, alerts: &[String]) -> String {
+    let reading = match latest {
+        Some(data) => data.to_string(),
+        None => "no reading yet".into(),
+    };
+    let alerts = if alerts.is_empty() {
+        "none".into()
+    } else {
+        alerts.join(", ")
+    };
+    format!("{}\n{}\nactive alerts: {}", device_id, reading, alerts)
+}
+
+/// Fetches the next update and returns `(chat id, text)` of its message.
+fn poll(token: &str, offset: &mut Option<i64>) -> error::Result<Vec<(String, String)>> {
+    let mut url = format!(
+        "{}/bot{}/getUpdates?limit=1&timeout={}&allowed_updates=%5B%22message%22%5D",
+        API_URL, token, LONG_POLL_SECS
+    );
+    if let Some(offset) = offset {
+        url += &format!("&offset={}", offset);
+    }
+
+    let body = request(Method::Get, &url, &[], &[], LONG_POLL_SECS + 10)?;
+    let document: serde_json::Value = match serde_json::from_slice(&body) {
+        Ok(document) => document,
+        Err(err) => {
+            // Most likely a message too long for the buffer, skip it.
+            if let Some(id) = first_update_id(&body) {
+                *offset = Some(id + 1);
+            }
+            bail!("parse updates error={:?}", err);
+        }
+    };
+
+    let mut messages = Vec::new();
+    for update in document["result"].as_array().into_iter().flatten() {
+        if let Some(id) = update["update_id"].as_i64() {
+            *offset = Some(id + 1);
+        }
+        let message = &update["message"];
+        if let (Some(chat), Some(text)) = (message["chat"]["id"].as_i64(), message["text"].as_str())
+        {
+            messages.push((chat.to_string(), text.to_string()));
+        }
+    }
+    Ok(messages)
+}
+
+fn first_update_id(body: &[u8]) -> Option<i64> {
+    let body = std::str::from_utf8(body).ok()?;
+    let rest = &body[body.find("\"update_id\":")? + "\"update_id\":".len()..];
+    let end = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    rest[..end].parse().ok()
+}
+
+fn request(
+    method: Method,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+    timeout_secs: u32,
+) -> error::Result<Vec<u8>> {
+    let connection = EspHttpConnection::new(&HttpConfiguration {
+        timeout: Some(Duration::from_secs(u64::from(timeout_secs))),
+        crt_bundle_attach: Some(esp_idf_sys::esp_crt_bundle_attach),
+        ..Default::default()
+    })
+    .context("create esp http connection")?;
+    let mut client = Client::wrap(connection);
+
+    let content_length = body.len().to_string();
+    let mut all_headers = headers.to_vec();
+    all_headers.push(("content-length", &content_length));
+
+    // The URL contains the bot token, so it is never logged.
+    let mut request = client
+        .request(method, url, &all_headers)
+        .context("create request")?;
+    request.write_all(body)?;
+    request.flush()?;
+    let mut response = request.submit().context("do request")?;
+
+    let status = response.status();
+    let mut buf = vec![0u8; MAX_RESPONSE_LEN];
+    let len = io::try_read_full(&mut response, &mut buf).map_err(|e| e.0)?;
+    buf.truncate(len);
+    let mut rest = [0u8; 64];
+    while response.read(&mut rest)? > 0 {}
+
+    if !(200..300).contains(&status) {
+        bail!(
+            "http status code={} body={:?}",
+            status,
+            String::from_utf8_lossy(&buf)
+        );
+    }
+    Ok(buf)
+}
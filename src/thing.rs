@@ -0,0 +1,100 @@
+//! A Web Thing description of the sensor and its properties, for WebThings
+//! gateways, Node-RED and other W3C Web of Things consumers.
+
+use serde_json::{json, Value as Json};
+
+use crate::reading::SensorData;
+
+/// Names of the properties, each served at `/properties/<name>`.
+pub const PROPERTIES: [&str; 2] = ["temperature", "humidity"];
+
+/// The Thing Description of a device. Properties link to
+/// `/properties/<name>` both as WebThings `links` and as W3C `forms`.
+pub fn description(device_id: &str) -> Json {
+    let property = |name: &str, kind: &str, title: &str, unit: &str| {
+        let href = format!("/properties/{}", name);
+        json!({
+            "@type": kind,
+            "title": title,
+            "type": "number",
+            "unit": unit,
+            "readOnly": true,
+            "links": [{ "rel": "property", "href": href }],
+            "forms": [{ "href": href, "op": ["readproperty"] }],
+        })
+    };
+    let mut humidity = property("humidity", "HumidityProperty", "Humidity", "percent");
+    humidity["minimum"] = json!(0);
+    humidity["maximum"] = json!(100);
+
+    json!({
+        "@context": ["https://www.w3.org/2019/wot/td/v1", "https://webthings.io/schemas/"],
+        "@type": ["TemperatureSensor", "HumiditySensor"],
+        "id": format!("urn:dev:ops:esp-sensor-{}", device_id),
+        "title": device_id,
+        "description": "Temperature and humidity sensor",
+        "securityDefinitions": { "nosec_sc": { "scheme": "nosec" } },
+        "security": "nosec_sc",
+        "properties": {
+            "temperature": property(
+                "temperature",
+                "TemperatureProperty",
+                "Temperature",
+                "degree celsius"
+            ),
+            "humidity": humidity,
+        },
+        "links": [{ "rel": "properties", "href": "/properties" }],
+        "forms": [{ "href": "/properties", "op": ["readallproperties"] }],
+    })
+}
+
+/// The value of one property, or of all with `None`, as a JSON object of
+/// property names. `None` for an unknown property.
+pub fn properties(data: SensorData, name: Option<&str>) -> Option<Json> {
+    let all = json!({
+        "temperature": data.temperature,
+        "humidity": data.humidity,
+    });
+    match name {
+        None => Some(all),
+        Some(name) => all.get(name).map(|value| json!({ name: value })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes_the_properties() {
+        let description = description("kitchen");
+        assert_eq!(description["title"], "kitchen");
+        for name in PROPERTIES {
+            let property = &description["properties"][name];
+            assert_eq!(property["readOnly"], true);
+            assert_eq!(
+                property["links"][0]["href"],
+                format!("/properties/{}", name)
+            );
+        }
+        assert_eq!(description["properties"]["humidity"]["maximum"], 100);
+    }
+
+    #[test]
+    fn reads_properties() {
+        let data = SensorData {
+            temperature: 21.5,
+            humidity: 40.,
+        };
+        assert_eq!(
+            properties(data, None).unwrap(),
+            json!({ "temperature": 21.5, "humidity": 40. })
+        );
+        assert_eq!(
+            properties(data, Some("humidity")).unwrap(),
+            json!({ "humidity": 40. })
+        );
+        assert!(properties(data, Some("pressure")).is_none());
+    }
+}
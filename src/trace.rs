@@ -0,0 +1,272 @@
+use std::{
+    ffi::CStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use esp_idf_sys::{esp, esp_partition_t};
+
+use crate::error::{self, bail, Context};
+use crate::SensorData;
+
+/// Label of the data partition holding the trace, see `partitions.csv`.
+const PARTITION: &CStr = c"trace";
+const SECTOR_LEN: usize = 4096;
+/// Seconds since the start of the recording, then the temperature and
+/// humidity in tenths as the DHT22 reports them.
+const RECORD_LEN: usize = 8;
+/// Offset of an erased record, the end of the trace.
+const ERASED: u32 = u32::MAX;
+
+/// Time skipped by accelerated replays since boot.
+static SKIPPED_MS: AtomicU64 = AtomicU64::new(0);
+
+/// Clock of rules with a minimum duration. It runs ahead of the monotonic
+/// clock by the time replays skipped, so durations hold at any speed.
+pub fn now() -> Instant {
+    Instant::now() + Duration::from_millis(SKIPPED_MS.load(Ordering::Relaxed))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    #[default]
+    Idle,
+    Record,
+    /// Replays at `speed` times the recorded pace.
+    Replay {
+        speed: u32,
+    },
+}
+
+#[derive(Default)]
+struct State {
+    mode: Mode,
+    /// Index of the next record to write or replay.
+    next: usize,
+    started: Option<Instant>,
+    /// Offset of the last replayed record.
+    last_offset: u32,
+}
+
+/// Records raw readings to the `trace` flash partition and replays them in
+/// place of the sensor, through alerts, relay, fan and display.
+#[derive(Default)]
+pub struct Trace {
+    state: Mutex<State>,
+}
+
+impl Trace {
+    pub fn mode(&self) -> Mode {
+        self.state.lock().unwrap().mode
+    }
+
+    pub fn replaying(&self) -> bool {
+        matches!(self.mode(), Mode::Replay { .. })
+    }
+
+    /// Starts a new recording, replacing the stored one.
+    pub fn start_recording(&self) -> error::Result<()> {
+        let partition = partition()?;
+        erase_sector(partition, 0)?;
+        *self.state.lock().unwrap() = State {
+            mode: Mode::Record,
+            started: Some(Instant::now()),
+            ..Default::default()
+        };
+        log::info!("trace: recording");
+        Ok(())
+    }
+
+    /// Starts replaying the stored recording, returns its length.
+    pub fn start_replay(&self, speed: u32) -> error::Result<usize> {
+        if speed == 0 {
+            bail!("speed must be at least 1");
+        }
+        let len = len(partition()?)?;
+        if len == 0 {
+            bail!("no trace recorded");
+        }
+        *self.state.lock().unwrap() = State {
+            mode: Mode::Replay { speed },
+            ..Default::default()
+        };
+        log::info!("trace: replaying {} readings at {}x", len, speed);
+        Ok(len)
+    }
+
+    pub fn stop(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.mode != Mode::Idle {
+            log::info!("trace: stopped {:?} at reading {}", state.mode, state.next);
+        }
+        state.mode = Mode::Idle;
+    }
+
+    /// Number of stored readings.
+    pub fn recorded(&self) -> error::Result<usize> {
+        len(partition()?)
+    }
+
+    /// Appends a sensor reading while recording.
+    pub fn record(&self, data: SensorData) {
+        let mut state = self.state.lock().unwrap();
+        if state.mode != Mode::Record {
+            return;
+        }
+        let offset = state.started.map_or(0, |s| s.elapsed().as_secs() as u32);
+        if let Err(err) = write(state.next, offset, data) {
+            log::error!("trace: recording stopped error={:?}", err);
+            state.mode = Mode::Idle;
+            return;
+        }
+        state.next += 1;
+    }
+
+    /// The next replayed reading and how long to wait before passing it on.
+    /// `None` when not replaying, the replay stops after the last reading.
+    pub fn next_replayed(&self) -> Option<(SensorData, Duration)> {
+        let mut state = self.state.lock().unwrap();
+        let Mode::Replay { speed } = state.mode else {
+            return None;
+        };
+        let record = partition().and_then(|partition| read(partition, state.next));
+        let (offset, data) = match record {
+            Ok(Some(record)) => record,
+            Ok(None) => {
+                log::info!("trace: replay finished after {} readings", state.next);
+                state.mode = Mode::Idle;
+                return None;
+            }
+            Err(err) => {
+                log::error!("trace: replay stopped error={:?}", err);
+                state.mode = Mode::Idle;
+                return None;
+            }
+        };
+
+        let recorded = Duration::from_secs(u64::from(offset.saturating_sub(state.last_offset)));
+        let wait = recorded / speed;
+        SKIPPED_MS.fetch_add((recorded - wait).as_millis() as u64, Ordering::Relaxed);
+        state.last_offset = offset;
+        state.next += 1;
+        Some((data, wait))
+    }
+}
+
+fn partition() -> error::Result<*const esp_partition_t> {
+    let partition = unsafe {
+        esp_idf_sys::esp_partition_find_first(
+            esp_idf_sys::esp_partition_type_t_ESP_PARTITION_TYPE_DATA,
+            esp_idf_sys::esp_partition_subtype_t_ESP_PARTITION_SUBTYPE_ANY,
+            PARTITION.as_ptr(),
+        )
+    };
+    if partition.is_null() {
+        bail!("no {:?} partition", PARTITION);
+    }
+    Ok(partition)
+}
+
+fn capacity(partition: *const esp_partition_t) -> usize {
+    unsafe { (*partition).size as usize / RECORD_LEN }
+}
+
+fn erase_sector(partition: *const esp_partition_t, sector: usize) -> error::Result<()> {
+    esp!(unsafe {
+        esp_idf_sys::esp_partition_erase_range(partition, sector * SECTOR_LEN, SECTOR_LEN)
+    })
+    .context("erase trace sector")
+}
+
+fn write(index: usize, offset: u32, data: SensorData) -> error::Result<()> {
+    let partition = partition()?;
+    let capacity = capacity(partition);
+    if index >= capacity {
+        bail!("trace partition is full");
+    }
+    let at = index * RECORD_LEN;
+    // Erasing a sector ahead leaves an erased record after the last one,
+    // rather than a previous recording.
+    let next_sector = at / SECTOR_LEN + 1;
+    if at % SECTOR_LEN == 0 && next_sector * SECTOR_LEN < capacity * RECORD_LEN {
+        erase_sector(partition, next_sector)?;
+    }
+    let record = encode(offset, data);
+    esp!(unsafe {
+        esp_idf_sys::esp_partition_write(partition, at, record.as_ptr().cast(), RECORD_LEN)
+    })
+    .context("write trace record")
+}
+
+fn read(
+    partition: *const esp_partition_t,
+    index: usize,
+) -> error::Result<Option<(u32, SensorData)>> {
+    if index >= capacity(partition) {
+        return Ok(None);
+    }
+    let mut record = [0u8; RECORD_LEN];
+    esp!(unsafe {
+        esp_idf_sys::esp_partition_read(
+            partition,
+            index * RECORD_LEN,
+            record.as_mut_ptr().cast(),
+            RECORD_LEN,
+        )
+    })
+    .context("read trace record")?;
+    Ok(decode(record))
+}
+
+/// Index of the first erased record, read a sector at a time.
+fn len(partition: *const esp_partition_t) -> error::Result<usize> {
+    let mut sector = [0u8; SECTOR_LEN];
+    let capacity = capacity(partition);
+    for start in (0..capacity * RECORD_LEN).step_by(SECTOR_LEN) {
+        esp!(unsafe {
+            esp_idf_sys::esp_partition_read(
+                partition,
+                start,
+                sector.as_mut_ptr().cast(),
+                SECTOR_LEN,
+            )
+        })
+        .context("read trace sector")?;
+        let erased = sector
+            .chunks_exact(RECORD_LEN)
+            .position(|record| decode(record.try_into().unwrap()).is_none());
+        if let Some(position) = erased {
+            return Ok(start / RECORD_LEN + position);
+        }
+    }
+    Ok(capacity)
+}
+
+fn encode(offset: u32, data: SensorData) -> [u8; RECORD_LEN] {
+    let temperature = (data.temperature * 10.).round() as i16;
+    let humidity = (data.humidity * 10.).round() as u16;
+    let mut record = [0u8; RECORD_LEN];
+    record[..4].copy_from_slice(&offset.to_le_bytes());
+    record[4..6].copy_from_slice(&temperature.to_le_bytes());
+    record[6..].copy_from_slice(&humidity.to_le_bytes());
+    record
+}
+
+fn decode(record: [u8; RECORD_LEN]) -> Option<(u32, SensorData)> {
+    let offset = u32::from_le_bytes(record[..4].try_into().unwrap());
+    if offset == ERASED {
+        return None;
+    }
+    let temperature = i16::from_le_bytes([record[4], record[5]]);
+    let humidity = u16::from_le_bytes([record[6], record[7]]);
+    Some((
+        offset,
+        SensorData {
+            temperature: f32::from(temperature) / 10.,
+            humidity: f32::from(humidity) / 10.,
+        },
+    ))
+}
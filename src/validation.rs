@@ -0,0 +1,245 @@
+use std::fmt::Display;
+
+use crate::{mqtt, nats, settings::Settings};
+
+const PLACEHOLDER: &str = "<CHANGEME>";
+const MIN_INTERVAL_SECS: u32 = 5;
+const MAX_INTERVAL_SECS: u32 = 24 * 60 * 60;
+
+/// Problem categories. The numeric value is shown on the display as `E0xx`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Code {
+    WifiSsid = 1,
+    WifiPassword = 2,
+    InfluxAddr = 3,
+    InfluxToken = 4,
+    InfluxTarget = 5,
+    Interval = 6,
+    ConfigUrl = 7,
+    Tags = 8,
+    PinConflict = 9,
+    Fields = 10,
+    Notify = 11,
+    OtlpUrl = 12,
+    StatsdAddr = 13,
+    NatsUrl = 14,
+    Grafana = 15,
+    MqttUrl = 16,
+}
+
+impl Code {
+    /// Wi-Fi can not be joined at all with this problem.
+    pub fn blocks_wifi(self) -> bool {
+        matches!(self, Code::WifiSsid | Code::WifiPassword)
+    }
+
+    /// Data can not be delivered to InfluxDB, or to any sink with bad tags or
+    /// fields. A bad OTLP, Grafana Live, StatsD or NATS setting only turns
+    /// that sink off.
+    pub fn blocks_sending(self) -> bool {
+        matches!(
+            self,
+            Code::InfluxAddr | Code::InfluxToken | Code::InfluxTarget | Code::Tags | Code::Fields
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub code: Code,
+    pub message: String,
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "E{:03} {}", self.code as u8, self.message)
+    }
+}
+
+/// Checks settings and pin assignments, returning every problem found.
+pub fn validate(settings: &Settings, pins: &[(&'static str, i32)]) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    let mut report = |code, message: String| problems.push(Problem { code, message });
+
+    if is_unset(&settings.ssid) {
+        report(Code::WifiSsid, "wi-fi name is not set".into());
+    } else if settings.ssid.len() > 32 {
+        report(Code::WifiSsid, "wi-fi name is longer than 32 bytes".into());
+    }
+    if is_unset(settings.password.expose()) {
+        report(Code::WifiPassword, "wi-fi password is not set".into());
+    } else if settings.password.expose().len() > 64 {
+        report(
+            Code::WifiPassword,
+            "wi-fi password is longer than 64 bytes".into(),
+        );
+    }
+
+    if cfg!(feature = "influx") {
+        if let Err(err) = check_url(&settings.addr) {
+            report(Code::InfluxAddr, format!("addr {}", err));
+        }
+    }
+    if cfg!(feature = "otlp") && !settings.otlp_url.is_empty() {
+        if let Err(err) = check_url(&settings.otlp_url) {
+            report(Code::OtlpUrl, format!("otlp_url {}", err));
+        }
+    }
+    if cfg!(feature = "grafana") && !settings.grafana_url.is_empty() {
+        if let Err(err) = check_url(&settings.grafana_url) {
+            report(Code::Grafana, format!("grafana_url {}", err));
+        }
+        let stream = &settings.grafana_stream;
+        if stream.is_empty()
+            || !stream
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            report(
+                Code::Grafana,
+                "grafana_stream must be letters, digits, - and _".into(),
+            );
+        }
+        if settings.grafana_token.expose().is_empty() {
+            report(Code::Grafana, "grafana_token is not set".into());
+        }
+    }
+    if cfg!(feature = "statsd") && !settings.statsd_addr.is_empty() {
+        let parts = settings.statsd_addr.rsplit_once(':');
+        if !parts.is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok()) {
+            report(
+                Code::StatsdAddr,
+                "statsd_addr must be written as host:port".into(),
+            );
+        }
+    }
+    if cfg!(feature = "nats") && !settings.nats_url.is_empty() {
+        if let Err(err) = nats::parse_url(&settings.nats_url) {
+            report(Code::NatsUrl, format!("nats_url {:#}", err));
+        }
+    }
+    if cfg!(feature = "ota") && !settings.mqtt_url.is_empty() {
+        if let Err(err) = mqtt::parse_url(&settings.mqtt_url) {
+            report(Code::MqttUrl, format!("mqtt_url {:#}", err));
+        }
+    }
+    if !settings.config_url.is_empty() {
+        if let Err(err) = check_url(&settings.config_url) {
+            report(Code::ConfigUrl, format!("config_url {}", err));
+        }
+    }
+    if !settings.coredump_url.is_empty() {
+        if let Err(err) = check_url(&settings.coredump_url) {
+            report(Code::ConfigUrl, format!("coredump_url {}", err));
+        }
+    }
+
+    if !settings.ntfy_url.is_empty() {
+        if let Err(err) = check_url(&settings.ntfy_url) {
+            report(Code::Notify, format!("ntfy_url {}", err));
+        }
+    }
+    if settings.pushover_token.expose().is_empty() != settings.pushover_user.expose().is_empty() {
+        report(
+            Code::Notify,
+            "pushover_token and pushover_user must be set together".into(),
+        );
+    }
+
+    if cfg!(feature = "influx") {
+        if is_unset(settings.influx_token.expose()) {
+            report(Code::InfluxToken, "influx_token is not set".into());
+        } else if settings
+            .influx_token
+            .expose()
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control())
+        {
+            report(
+                Code::InfluxToken,
+                "influx_token must not contain whitespace".into(),
+            );
+        }
+        if is_unset(&settings.influx_org) {
+            report(Code::InfluxTarget, "influx_org is not set".into());
+        }
+        if is_unset(&settings.influx_bucket) {
+            report(Code::InfluxTarget, "influx_bucket is not set".into());
+        }
+    }
+
+    let interval = settings.read_sensor_interval_secs;
+    if !(MIN_INTERVAL_SECS..=MAX_INTERVAL_SECS).contains(&interval) {
+        report(
+            Code::Interval,
+            format!(
+                "interval {}s is outside of {}..={}s",
+                interval, MIN_INTERVAL_SECS, MAX_INTERVAL_SECS
+            ),
+        );
+    }
+
+    let tags = settings.tags();
+    for (i, (key, _)) in tags.iter().enumerate() {
+        if key == "sensor" || tags[..i].iter().any(|(k, _)| k == key) {
+            report(Code::Tags, format!("tag {:?} is set more than once", key));
+        }
+    }
+
+    if settings.temperature_field.is_empty() && settings.humidity_field.is_empty() {
+        report(Code::Fields, "all fields are disabled".into());
+    } else if settings.temperature_field == settings.humidity_field {
+        report(
+            Code::Fields,
+            format!("field name {:?} is used twice", settings.temperature_field),
+        );
+    }
+
+    for (i, (name, pin)) in pins.iter().enumerate() {
+        if let Some((other, _)) = pins[..i].iter().find(|(_, p)| p == pin) {
+            report(
+                Code::PinConflict,
+                format!("{} and {} both use gpio{}", other, name, pin),
+            );
+        }
+    }
+
+    problems
+}
+
+/// The first problem with `code`, for a sink that is turned off on its own.
+#[cfg(any(
+    feature = "otlp",
+    feature = "grafana",
+    feature = "statsd",
+    feature = "nats"
+))]
+pub fn find(problems: &[Problem], code: Code) -> Option<&Problem> {
+    problems.iter().find(|p| p.code == code)
+}
+
+fn is_unset(value: &str) -> bool {
+    value.trim().is_empty() || value == PLACEHOLDER
+}
+
+fn check_url(url: &str) -> Result<(), String> {
+    if url == PLACEHOLDER {
+        return Err("is not set".into());
+    }
+
+    let rest = url
+        .strip_prefix("http://")
+        .or_else(|| url.strip_prefix("https://"))
+        .ok_or_else(|| format!("{:?} must start with http:// or https://", url))?;
+    let authority = rest.split(['/', '?']).next().unwrap_or_default();
+    let host = match authority.rsplit_once(':') {
+        Some((host, port)) if port.parse::<u16>().is_ok() => host,
+        Some(_) => return Err(format!("{:?} has an invalid port", url)),
+        None => authority,
+    };
+    if host.is_empty() || host.contains(char::is_whitespace) {
+        return Err(format!("{:?} has an invalid host", url));
+    }
+
+    Ok(())
+}
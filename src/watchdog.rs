@@ -0,0 +1,165 @@
+use std::{
+    marker::PhantomData,
+    ptr,
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    broadcast::{self, RecvError},
+    state::{Snapshot, State},
+};
+
+/// Longest time a subscribed task blocks without feeding the watchdog. Must
+/// stay well below `CONFIG_ESP_TASK_WDT_TIMEOUT_S`.
+const FEED_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Subscription of the current task to the ESP-IDF task watchdog. The task
+/// is unsubscribed on drop. Not `Send` because the subscription belongs to
+/// the FreeRTOS task that created it.
+pub struct Watchdog {
+    name: &'static str,
+    subscribed: bool,
+    _task_bound: PhantomData<*const ()>,
+}
+
+impl Watchdog {
+    /// Subscribes the current task. If that fails the error is logged and
+    /// the task keeps running unsupervised.
+    pub fn subscribe(name: &'static str) -> Self {
+        let err = unsafe { esp_idf_sys::esp_task_wdt_add(ptr::null_mut()) };
+        let subscribed = err == esp_idf_sys::ESP_OK;
+        if subscribed {
+            log::info!("watchdog: subscribed {}", name);
+        } else {
+            log::error!("watchdog: subscribing {} error={}", name, err);
+        }
+
+        Self {
+            name,
+            subscribed,
+            _task_bound: PhantomData,
+        }
+    }
+
+    pub fn feed(&self) {
+        if !self.subscribed {
+            return;
+        }
+
+        let err = unsafe { esp_idf_sys::esp_task_wdt_reset() };
+        if err != esp_idf_sys::ESP_OK {
+            log::error!("watchdog: feeding {} error={}", self.name, err);
+        }
+    }
+
+    /// Sleeps for `duration`, feeding the watchdog in between.
+    pub fn sleep(&self, duration: Duration) {
+        let deadline = Instant::now() + duration;
+        loop {
+            self.feed();
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return;
+            }
+            thread::sleep(remaining.min(FEED_INTERVAL));
+        }
+    }
+
+    /// Waits for a message on `wake` for at most `timeout`, feeding the
+    /// watchdog in between. Returns `true` if woken up by a message.
+    pub fn wait(&self, wake: &mpsc::Receiver<()>, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            self.feed();
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return false;
+            }
+
+            match wake.recv_timeout(remaining.min(FEED_INTERVAL)) {
+                Ok(()) => return true,
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => thread::sleep(remaining.min(FEED_INTERVAL)),
+            }
+        }
+    }
+
+    /// Receives the next broadcast value for at most `timeout`, feeding the
+    /// watchdog while waiting. Lagging is logged and otherwise ignored, so
+    /// the error is `Timeout`, `Woken` or `Closed`.
+    pub fn recv_timeout<T: Clone>(
+        &self,
+        sub: &mut broadcast::Receiver<T>,
+        timeout: Duration,
+    ) -> Result<T, RecvError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            self.feed();
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match sub.recv_timeout(remaining.min(FEED_INTERVAL)) {
+                Ok(value) => return Ok(value),
+                Err(RecvError::Lagged(skipped)) => {
+                    log::warn!("watchdog: {} skipped {} values", self.name, skipped);
+                }
+                Err(RecvError::Timeout) if !remaining.is_zero() => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Receives the next broadcast value, feeding the watchdog while
+    /// waiting. Returns `None` once the sender is gone.
+    pub fn recv<T: Clone>(&self, sub: &mut broadcast::Receiver<T>) -> Option<T> {
+        loop {
+            match self.recv_timeout(sub, FEED_INTERVAL) {
+                Ok(value) => return Some(value),
+                Err(RecvError::Closed) => return None,
+                Err(_) => {}
+            }
+        }
+    }
+
+    /// Waits at most `timeout` for a snapshot newer than `seen`, feeding the
+    /// watchdog while waiting.
+    pub fn wait_newer(
+        &self,
+        state: &State,
+        seen: Option<u32>,
+        timeout: Duration,
+    ) -> Option<Snapshot> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            self.feed();
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let snapshot = state.wait_newer(seen, remaining.min(FEED_INTERVAL));
+            if snapshot.is_some() || remaining.is_zero() {
+                return snapshot;
+            }
+        }
+    }
+
+    /// Waits for the next snapshot after `seen`, feeding the watchdog while
+    /// waiting.
+    pub fn next(&self, state: &State, seen: Option<u32>) -> Snapshot {
+        loop {
+            if let Some(snapshot) = self.wait_newer(state, seen, FEED_INTERVAL) {
+                return snapshot;
+            }
+        }
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        if !self.subscribed {
+            return;
+        }
+
+        let err = unsafe { esp_idf_sys::esp_task_wdt_delete(ptr::null_mut()) };
+        if err != esp_idf_sys::ESP_OK {
+            log::error!("watchdog: unsubscribing {} error={}", self.name, err);
+        }
+    }
+}
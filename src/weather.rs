@@ -0,0 +1,257 @@
+#[cfg(feature = "weather")]
+use std::{collections::VecDeque, time::Duration};
+use std::{sync::Mutex, time::Instant};
+
+#[cfg(feature = "weather")]
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+
+use crate::error;
+use crate::point::Point;
+#[cfg(feature = "weather")]
+use crate::{adc, board::Board, health, pulse, schedule, settings::Store, watchdog::Watchdog};
+
+#[cfg(feature = "weather")]
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+/// Gusts are the highest wind speed averaged over this many samples, the
+/// 3 second gust of the WMO.
+#[cfg(feature = "weather")]
+const GUST_SAMPLES: usize = 3;
+/// The rain of the day is written to NVS at most this often.
+#[cfg(feature = "weather")]
+const SAVE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+#[cfg(feature = "weather")]
+const NAMESPACE: &str = "weather";
+
+/// Pull-up between the wind vane and 3.3 V.
+#[cfg(feature = "weather")]
+const VANE_PULL_UP_OHMS: f32 = 10_000.;
+/// Direction in degrees and resistance of the wind vane of the common
+/// SparkFun and Misol weather meters.
+#[cfg(feature = "weather")]
+const VANE_OHMS: [(f32, f32); 16] = [
+    (0., 33_000.),
+    (22.5, 6_570.),
+    (45., 8_200.),
+    (67.5, 891.),
+    (90., 1_000.),
+    (112.5, 688.),
+    (135., 2_200.),
+    (157.5, 1_410.),
+    (180., 3_900.),
+    (202.5, 3_140.),
+    (225., 16_000.),
+    (247.5, 14_120.),
+    (270., 120_000.),
+    (292.5, 42_120.),
+    (315., 64_900.),
+    (337.5, 21_880.),
+];
+
+/// Direction in degrees of the vane position closest to a raw reading.
+#[cfg(feature = "weather")]
+fn vane_direction(raw: i32) -> f32 {
+    let ratio = raw as f32 / 4095.;
+    let distance = |ohms: f32| (ohms / (ohms + VANE_PULL_UP_OHMS) - ratio).abs();
+    VANE_OHMS
+        .iter()
+        .min_by(|a, b| distance(a.1).total_cmp(&distance(b.1)))
+        .map_or(0., |&(degrees, _)| degrees)
+}
+
+/// Wind and rain since the previous point, shared with the sender.
+#[derive(Default)]
+pub struct Weather {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Start of the current point, `None` without a weather station.
+    since: Option<Instant>,
+    /// Metres of wind passed by.
+    wind_run: f32,
+    /// Highest gust in m/s.
+    gust: f32,
+    /// Sum of the unit vectors towards the wind, east and north.
+    vane: (f32, f32),
+    /// Rain since local midnight.
+    rain_mm: f32,
+}
+
+impl Weather {
+    /// A `weather` point with the average `wind_speed` and highest
+    /// `wind_gust` in m/s since the previous point, the average
+    /// `wind_direction` in degrees from north and the `rain` in mm since
+    /// local midnight. `None` without the weather station.
+    pub fn point(&self, tags: &[(String, String)]) -> Option<Point> {
+        let mut state = self.state.lock().unwrap();
+        let elapsed = state.since?.elapsed().as_secs_f32().max(1.);
+        let mut point = Point::new("weather")
+            .tags(tags)
+            .field("wind_speed", state.wind_run / elapsed)
+            .field("wind_gust", state.gust)
+            .field("rain", state.rain_mm);
+        let (east, north) = state.vane;
+        if east != 0. || north != 0. {
+            let degrees = east.atan2(north).to_degrees().rem_euclid(360.);
+            point = point.field("wind_direction", degrees);
+        }
+
+        state.since = Some(Instant::now());
+        state.wind_run = 0.;
+        state.gust = 0.;
+        state.vane = (0., 0.);
+        Some(point)
+    }
+}
+
+/// Rain of one day, persisted so that a reboot does not reset it.
+#[cfg(feature = "weather")]
+struct Rain {
+    nvs: Option<EspNvs<NvsDefault>>,
+    /// Local date as `YYYYMMDD`, 0 while unknown.
+    date: u32,
+    tips: u32,
+    saved_at: Instant,
+    dirty: bool,
+}
+
+#[cfg(feature = "weather")]
+impl Rain {
+    fn load(partition: EspDefaultNvsPartition) -> Rain {
+        let nvs = match EspNvs::new(partition, NAMESPACE, true) {
+            Ok(nvs) => Some(nvs),
+            Err(err) => {
+                log::error!("weather: opening nvs error={:?}", err);
+                None
+            }
+        };
+        let get = |key| {
+            nvs.as_ref()
+                .and_then(|nvs| nvs.get_u32(key).ok().flatten())
+                .unwrap_or(0)
+        };
+        Rain {
+            date: get("rain_date"),
+            tips: get("rain_tips"),
+            nvs,
+            saved_at: Instant::now(),
+            dirty: false,
+        }
+    }
+
+    /// Adds tips, starting over on a new local day.
+    fn add(&mut self, tips: u32) {
+        if let Some(today) = schedule::local_date() {
+            if today != self.date {
+                if self.date != 0 {
+                    log::info!("weather: new day, {} tips on {}", self.tips, self.date);
+                }
+                self.date = today;
+                self.tips = 0;
+                self.dirty = true;
+            }
+        }
+        if tips > 0 {
+            self.tips += tips;
+            self.dirty = true;
+        }
+        if self.dirty && self.saved_at.elapsed() >= SAVE_INTERVAL {
+            if let Err(err) = self.save() {
+                log::error!("weather: saving rain error={:?}", err);
+            }
+            self.saved_at = Instant::now();
+            self.dirty = false;
+        }
+    }
+
+    fn save(&mut self) -> error::Result<()> {
+        if let Some(nvs) = &mut self.nvs {
+            nvs.set_u32("rain_date", self.date)?;
+            nvs.set_u32("rain_tips", self.tips)?;
+        }
+        Ok(())
+    }
+}
+
+/// Samples the anemometer, rain gauge and wind vane every second.
+#[cfg(feature = "weather")]
+pub fn run(store: &Store, weather: &Weather, board: &Board, partition: EspDefaultNvsPartition) {
+    use esp_idf_sys::*;
+
+    let watchdog = Watchdog::subscribe("weather");
+    let health = health::register("weather");
+    // Reed switches bounce for longer than any filter, a capacitor across
+    // them takes care of that.
+    let inputs = [
+        (board.anemometer, pulse::MAX_FILTER_NS),
+        (board.rain_gauge, pulse::MAX_FILTER_NS),
+    ];
+    let mut counter = match pulse::Counter::new(&inputs) {
+        Ok(counter) => counter,
+        Err(err) => {
+            log::error!("weather: init error={:?}", err);
+            return;
+        }
+    };
+    for (pin, _) in inputs {
+        if let Err(err) =
+            esp!(unsafe { gpio_set_pull_mode(pin, gpio_pull_mode_t_GPIO_PULLUP_ONLY) })
+        {
+            log::warn!("weather: no pull-up on gpio{} error={:?}", pin, err);
+        }
+    }
+    let vane = adc::Channel::new(board.wind_vane)
+        .map_err(|err| log::error!("weather: no wind vane error={:?}", err))
+        .ok();
+
+    let mut rain = Rain::load(partition);
+    weather.state.lock().unwrap().since = Some(Instant::now());
+    // Wind run in metres and seconds of the last few samples.
+    let mut recent: VecDeque<(f32, f32)> = VecDeque::with_capacity(GUST_SAMPLES);
+    let mut sampled_at = Instant::now();
+    loop {
+        health.tick();
+        watchdog.sleep(SAMPLE_INTERVAL);
+
+        let counts = match counter.take() {
+            Ok(counts) => counts,
+            Err(err) => {
+                log::error!("weather: reading counters error={:?}", err);
+                continue;
+            }
+        };
+        let settings = store.get();
+        let wind_run = counts[0] as f32 * settings.wind_factor as f32 / 1000.;
+        let secs = sampled_at.elapsed().as_secs_f32();
+        sampled_at = Instant::now();
+        if recent.len() == GUST_SAMPLES {
+            recent.pop_front();
+        }
+        recent.push_back((wind_run, secs));
+        rain.add(counts[1]);
+
+        let direction = vane.as_ref().and_then(|vane| match vane.read() {
+            Ok(raw) => Some(vane_direction(raw).to_radians()),
+            Err(err) => {
+                log::error!("weather: reading wind vane error={:?}", err);
+                None
+            }
+        });
+
+        let mut state = weather.state.lock().unwrap();
+        state.wind_run += wind_run;
+        if recent.len() == GUST_SAMPLES {
+            let (run, secs) = recent
+                .iter()
+                .fold((0., 0.), |(run, secs), (r, s)| (run + r, secs + s));
+            state.gust = state.gust.max(run / secs);
+        }
+        // Calm air leaves the vane anywhere, it does not count.
+        if let Some(direction) = direction.filter(|_| wind_run > 0.) {
+            state.vane.0 += direction.sin();
+            state.vane.1 += direction.cos();
+        }
+        state.rain_mm = rain.tips as f32 * settings.rain_tip as f32 / 1000.;
+    }
+}